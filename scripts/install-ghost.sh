@@ -17,15 +17,26 @@ opkg install kmod-tun kmod-nft-tproxy kmod-nf-tproxy \
 mkdir -p $NG_DIR/{bin,config,logs,cache,scripts,geodata,zapret}
 
 cat > $NG_DIR/config/config.toml << 'CONF'
+[proxy]
 sni = "ebanking.bmi.ir"
-protocol = "auto"
-cdn = "cloudflare"
-dpi_mode = "ghost"
-max_latency = 300
-port_hopping = true
-enable_zapret = true
-enable_goodbyedpi = true
-enable_warp = false
+protocol = "reality"
+# uuid = ""          # همراه با کلید عمومی REALITY سرور پر شود
+# public_key = ""    # pbk
+cdn_type = "cloudflare"
+max_latency_ms = 300
+enable_port_hopping = true
+
+[anti_ai]
+mode = "ghost"
+
+[zapret]
+enabled = true
+
+[goodbyedpi]
+enabled = true
+
+[warp]
+enabled = false
 CONF
 
 # TPROXY setup script
@@ -99,7 +110,7 @@ start() {
 }
 stop() {
     /opt/network-ghost/scripts/tproxy-cleanup.sh
-    [ -f $PID ] && kill $(cat $PID) 2>/dev/null; rm -f $PID
+    $PROG stop 2>/dev/null || { [ -f $PID ] && kill $(cat $PID) 2>/dev/null; }; rm -f $PID
 }
 restart() { stop; sleep 1; start; }
 INIT
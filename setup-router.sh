@@ -170,6 +170,22 @@ echo -e "${YELLOW}[5/16]${NC} Installing configuration..."
 # Generate Auto-UUID
 NEW_UUID=$(cat /proc/sys/kernel/random/uuid 2>/dev/null || uuidgen 2>/dev/null || echo "12345678-1234-1234-1234-1234567890ab")
 
+# REALITY needs the server public key (pbk): GHOST_PUBLIC_KEY=... or asked here.
+# Without it the config is written as an unfilled template (no uuid yet),
+# which check-config accepts with a warning.
+PUBLIC_KEY="${GHOST_PUBLIC_KEY:-}"
+if [ -z "${PUBLIC_KEY}" ] && [ -t 0 ]; then
+    printf "REALITY server public key (pbk, empty to fill in later): "
+    read -r PUBLIC_KEY || PUBLIC_KEY=""
+fi
+if [ -n "${PUBLIC_KEY}" ]; then
+    CONFIG_UUID="${NEW_UUID}"
+    PUBLIC_KEY_LINE="public_key = \"${PUBLIC_KEY}\""
+else
+    CONFIG_UUID=""
+    PUBLIC_KEY_LINE="# public_key = \"\"   # REALITY pbk; set it together with uuid = \"${NEW_UUID}\""
+fi
+
 # Copy config if exists in package
 if [ -f "${PACKAGE_DIR}/config/config.toml" ]; then
     cp "${PACKAGE_DIR}/config/config.toml" "${CONFIG_DIR}/"
@@ -191,7 +207,8 @@ port = 443
 protocol = "reality"
 cdn_type = "cloudflare"
 sni = "ebanking.bmi.ir"
-uuid = "${NEW_UUID}"
+uuid = "${CONFIG_UUID}"
+${PUBLIC_KEY_LINE}
 utls_fingerprint = "chrome"
 max_latency_ms = 250
 auto_switch = true
@@ -214,29 +231,26 @@ enable_padding = true
 enable_timing = true
 enable_decoy = false
 
-[matryoshka]
-enabled = true
-max_layers = 20
-layers = ["shadowtls", "reality", "smux"]
-
 [dns]
-servers = ["9.9.9.9:53", "dns.google.com:53", "1.1.1.1", "8.8.8.8"]
+servers = ["9.9.9.9:53", "1.1.1.1:53", "8.8.8.8:53"]
 enable_doh = true
 enable_doq = true
 cache_size = 1000
-
-[dashboard]
-enabled = true
-port = 9090
-bind = "0.0.0.0"
-
-[logging]
-level = "info"
-file = "/var/log/network-ghost/ghost.log"
-max_size_mb = 10
-max_files = 5
 EOF
-    echo -e "${GREEN}✅ Default config created with auto-generated UUID: ${CYAN}${NEW_UUID}${NC}"
+    if [ -n "${CONFIG_UUID}" ]; then
+        echo -e "${GREEN}✅ Default config created with auto-generated UUID: ${CYAN}${NEW_UUID}${NC}"
+    else
+        echo -e "${YELLOW}⚠️ Default config created without a REALITY public key — set public_key and uuid before starting${NC}"
+    fi
+fi
+
+# Refuse to deploy a config the binary cannot use
+if [ -x "${INSTALL_DIR}/network-ghost" ]; then
+    if ! "${INSTALL_DIR}/network-ghost" --config "${CONFIG_DIR}/config.toml" check-config; then
+        echo -e "${RED}❌ ${CONFIG_DIR}/config.toml is invalid — fix the errors above and re-run${NC}"
+        exit 1
+    fi
+    echo -e "${GREEN}✅ Configuration validated${NC}"
 fi
 
 # Copy CDN list if exists
@@ -882,6 +896,7 @@ echo ""
 echo -e "  ${CYAN}2. Set your server details in config:${NC}"
 echo -e "     - server: Your proxy server IP"
 echo -e "     - uuid: Your UUID (auto-generated: ${NEW_UUID})"
+echo -e "     - public_key: REALITY public key (pbk) of the server"
 echo ""
 echo -e "  ${CYAN}3. Start the service:${NC}"
 echo -e "     systemctl start network-ghost"
@@ -28,21 +28,20 @@ const MAX_INTER_PACKET_DELAY_MS: u64 = 50;
 const STATS_WINDOW_MS: u64 = 5000;
 const HISTORY_CAPACITY: usize = 1000;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum AntiAiMode {
     Normal,
     Aggressive,
     Stealth,
+    #[default]
     Adaptive,
     Ghost,
 }
 
-impl Default for AntiAiMode {
-    fn default() -> Self { Self::Adaptive }
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub enum TrafficProfile {
+    #[default]
     WebBrowsing,
     VideoStreaming,
     FileDownload,
@@ -52,10 +51,6 @@ pub enum TrafficProfile {
     VoiceCall,
 }
 
-impl Default for TrafficProfile {
-    fn default() -> Self { Self::WebBrowsing }
-}
-
 pub struct AntiAiDpi {
     mode: std::sync::Mutex<AntiAiMode>,
     profile: std::sync::Mutex<TrafficProfile>,
@@ -135,7 +130,7 @@ impl AntiAiDpi {
             }
             AntiAiMode::Adaptive => {
                 let counter = self.packet_counter.load(Ordering::Relaxed);
-                if counter % 5 == 0 {
+                if counter.is_multiple_of(5) {
                     self.simulate_network_jitter(&mut rng, &profile)
                 } else {
                     rng.gen_range(MIN_INTER_PACKET_DELAY_MS..MAX_INTER_PACKET_DELAY_MS)
@@ -377,7 +372,7 @@ impl AntiAiDpi {
 
         let is_detected = variance < 100.0 && mode != AntiAiMode::Ghost;
         let confidence = if is_detected {
-            (100.0 - variance.sqrt()).max(0.0).min(100.0) as f32 / 100.0
+            (100.0 - variance.sqrt()).clamp(0.0, 100.0) as f32 / 100.0
         } else { 0.1 };
 
         DetectionAnalysis {
@@ -4,9 +4,13 @@ use std::time::Duration;
 use anyhow::{Context, Result};
 use tokio::time::timeout;
 use tracing::{error, info, warn};
+use network_ghost_v5::{
+    config::{GhostConfig, DEFAULT_CONFIG_PATH},
+    control::{ControlClient, DEFAULT_SOCKET_PATH},
+    subscription::SubscriptionFetcher,
+};
 
 const LOCK_FILE: &str = "/tmp/network-ghost-updater.lock";
-const PROXY_FILE: &str = "/opt/network-ghost/sub/proxies.txt";
 const LOG_DIR: &str = "/opt/network-ghost/logs";
 const MAX_RUN_SECS: u64 = 600;
 
@@ -82,7 +86,7 @@ async fn run_update() -> Result<()> {
     if let Some(checker) = checker_path {
         info!("🔍 اجرای proxy-checker...");
         let status = tokio::process::Command::new(&checker)
-            .args(&[
+            .args([
                 "--max-concurrent", "30",
                 "--timeout", "6",
                 "--max-ping-ms", "300",
@@ -101,9 +105,42 @@ async fn run_update() -> Result<()> {
         warn!("⚠️ proxy-checker یافت نشد");
     }
 
+    // به‌روزرسانی اشتراک‌ها
+    refresh_subscriptions().await;
+
     Ok(())
 }
 
+/// دریافت اشتراک‌ها و اطلاع به daemon برای بارگذاری استخر جدید
+async fn refresh_subscriptions() {
+    let config = match GhostConfig::load_or_default(DEFAULT_CONFIG_PATH) {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("⚠️ پیکربندی خوانده نشد: {:#}", e);
+            return;
+        }
+    };
+    if config.subscription.urls.is_empty() {
+        return;
+    }
+
+    info!("📥 به‌روزرسانی {} اشتراک...", config.subscription.urls.len());
+    match SubscriptionFetcher::new(config.subscription).refresh_and_save().await {
+        Ok(_) => notify_daemon().await,
+        Err(e) => warn!("⚠️ به‌روزرسانی اشتراک ناموفق — استخر قبلی حفظ شد: {:#}", e),
+    }
+}
+
+/// درخواست بارگذاری استخر جدید از daemon (اگر در حال اجرا باشد)
+async fn notify_daemon() {
+    let result = match ControlClient::connect(DEFAULT_SOCKET_PATH).await {
+        Ok(mut client) => client.call("reload-pool", serde_json::Value::Null).await,
+        Err(_) => return,
+    };
+    if let Err(e) = result {
+        warn!("⚠️ بارگذاری استخر در daemon ناموفق: {:#}", e);
+    }
+}
 async fn check_internet() -> bool {
     use tokio::net::TcpStream;
     use tokio::time::timeout;
@@ -1,10 +1,18 @@
 //! Network Ghost Daemon — پروسه پس‌زمینه
 
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 use anyhow::Result;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::time::interval;
-use tracing::{error, info};
-use network_ghost_v5::{NetworkGhostEngine, types::ProxyConfig};
+use tracing::{error, info, warn};
+use network_ghost_v5::{
+    NetworkGhostEngine,
+    config::{GhostConfig, DEFAULT_CONFIG_PATH},
+    config_check::load_checked,
+    control::{ControlServer, DEFAULT_SOCKET_PATH},
+    event_journal::EventJournal,
+    profile::{self, UplinkWatcher},
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -17,22 +25,54 @@ async fn main() -> Result<()> {
 
     info!("🌙 Network Ghost Daemon v5.0 شروع شد");
 
-    // ذخیره PID
-    let pid = std::process::id();
-    tokio::fs::write("/tmp/network-ghost.pid", pid.to_string()).await?;
+    let mut config = GhostConfig::load_or_default(DEFAULT_CONFIG_PATH)?;
+    if let Some(name) = profile::apply(&mut config, None).await? {
+        info!("📋 پروفایل: {}", name);
+    }
+    let journal = config.journal.clone();
+    let engine = Arc::new(NetworkGhostEngine::new(config).await?);
+
+    // ژورنال رویدادها (events.jsonl) برای بررسی بعدی
+    if journal.enabled {
+        match EventJournal::open(&journal) {
+            Ok(journal) => {
+                journal.spawn(engine.subscribe());
+            }
+            Err(e) => warn!("⚠️ ژورنال رویدادها در دسترس نیست: {:#}", e),
+        }
+    }
 
-    let config = ProxyConfig::default();
-    let engine = NetworkGhostEngine::new(config).await?;
+    // سوکت کنترل (status/stop/rescan/reload-config/... از CLI)
+    let control = ControlServer::new(engine.clone(), DEFAULT_SOCKET_PATH).with_config_loader(load_reloaded);
+    let shutdown = control.shutdown_signal();
+    control.spawn()?;
 
     // شروع تانل
     if let Err(e) = engine.start().await {
         error!("❌ خطا در شروع تانل: {}", e);
     }
 
-    // حلقه نگهداری (watchdog)
+    // حلقه نگهداری (watchdog) + بارگذاری مجدد با SIGHUP یا تغییر uplink
     let mut tick = interval(Duration::from_secs(30));
+    let mut hangup = signal(SignalKind::hangup())?;
+    let mut uplink = UplinkWatcher::new();
     loop {
-        tick.tick().await;
+        tokio::select! {
+            _ = tick.tick() => {
+                if uplink.changed() {
+                    reload(&engine).await;
+                }
+            }
+            _ = shutdown.notified() => {
+                info!("🛑 توقف با دستور stop");
+                return Ok(());
+            }
+            _ = hangup.recv() => {
+                info!("🔁 SIGHUP — بارگذاری مجدد {}", DEFAULT_CONFIG_PATH);
+                reload(&engine).await;
+                continue;
+            }
+        }
         let state = engine.get_state().await;
         if !state.active {
             info!("🔄 تانل غیر فعال — تلاش برای راه‌اندازی مجدد...");
@@ -42,3 +82,22 @@ async fn main() -> Result<()> {
         }
     }
 }
+
+/// خواندن دوباره فایل پیکربندی (با انتخاب دوباره پروفایل)
+async fn load_reloaded() -> Result<GhostConfig> {
+    let mut config = load_checked(DEFAULT_CONFIG_PATH)?;
+    profile::apply(&mut config, None).await?;
+    Ok(config)
+}
+
+/// بارگذاری مجدد فایل پیکربندی در موتور
+async fn reload(engine: &NetworkGhostEngine) {
+    match load_reloaded().await {
+        Ok(config) => {
+            if let Err(e) = engine.reload_config(config).await {
+                error!("❌ بارگذاری مجدد ناموفق: {:#}", e);
+            }
+        }
+        Err(e) => error!("❌ پیکربندی جدید رد شد، پیکربندی فعلی حفظ می‌شود: {:#}", e),
+    }
+}
@@ -11,7 +11,6 @@ use colored::Colorize;
 
 const DEFAULT_MAX_CONCURRENT: usize = 50;
 const DEFAULT_TIMEOUT_SECONDS: u64 = 6;
-const CHECK_URL: &str = "https://ipp.nscl.ir";
 const MAX_PING_DEFAULT: u64 = 300;
 
 const GOOD_ISPS: &[&str] = &[
@@ -225,7 +224,6 @@ fn detect_isp_by_ip(host: &str) -> String {
         return match octets {
             [104, 16..=31, _, _] => "Cloudflare".to_string(),
             [172, 64..=71, _, _] => "Cloudflare".to_string(),
-            [104, 21..=31, _, _] => "Cloudflare".to_string(),
             [8, 8, _, _]         => "Google".to_string(),
             [34, ..] | [35, ..] | [142, ..] => "Google Cloud".to_string(),
             [52, ..] | [54, ..] | [18, ..]  => "Amazon AWS".to_string(),
@@ -3,17 +3,19 @@
 use anyhow::Result;
 use clap::Parser;
 use tracing::info;
-use network_ghost_v5::{NetworkGhostEngine, types::{ProxyConfig, CdnType}};
+use network_ghost_v5::{NetworkGhostEngine, config::{GhostConfig, DEFAULT_CONFIG_PATH}};
 
 #[derive(Debug, Parser)]
 #[command(name = "scanner", version = "5.0.0")]
 struct Cli {
     #[arg(long, default_value = "cloudflare")]
     cdn: String,
-    #[arg(long, default_value = "100")]
-    max_ips: usize,
+    #[arg(long)]
+    max_ips: Option<usize>,
     #[arg(long)]
     output: Option<std::path::PathBuf>,
+    #[arg(short, long, default_value = DEFAULT_CONFIG_PATH)]
+    config: std::path::PathBuf,
 }
 
 #[tokio::main]
@@ -28,9 +30,13 @@ async fn main() -> Result<()> {
     let cli = Cli::parse();
     info!("🔍 شروع اسکن IP — CDN: {}", cli.cdn);
 
-    let config = ProxyConfig::default();
+    let mut config = GhostConfig::load_or_default(&cli.config)?;
+    if let Some(max_ips) = cli.max_ips {
+        config.scanner.max_ips = max_ips;
+    }
     let engine = NetworkGhostEngine::new(config).await?;
-    let ips = engine.get_clean_ips().await;
+    // نتایج در کش مشترک IPهای تمیز هم ذخیره می‌شوند
+    let ips = engine.refresh_clean_ips().await?;
 
     info!("✅ {} IP تمیز یافت شد", ips.len());
 
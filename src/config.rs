@@ -1,6 +1,437 @@
 //! Configuration Management
 //!
-//! Re-exports canonical types from the types module for backwards compatibility.
+//! Typed loader for `config.toml`. Every table of the shipped file maps onto a
+//! field of [`GhostConfig`]; tables that configure an existing engine
+//! deserialize straight into that engine's config type.
+
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    anti_ai_dpi::AntiAiMode,
+    event_journal::JournalConfig,
+    goodbyedpi::GoodbyeDpiConfig,
+    inbound::InboundConfig,
+    ipq40xx_offload::Ipq40xxConfig,
+    profile::Uplink,
+    router_manager::TproxyConfig,
+    scanner::ScannerConfig,
+    subscription::SubscriptionConfig,
+    warp_client::WarpConfig,
+    zapret_bypass::{ZapretConfig, ZapretStrategy},
+};
 
 // Re-export the canonical ProxyConfig and related types from types module
 pub use crate::types::{CdnType, ProtocolType, ProxyConfig};
+
+/// Default location of the config file on the router
+pub const DEFAULT_CONFIG_PATH: &str = "/opt/network-ghost/config/config.toml";
+
+// ==================== ROOT ====================
+
+/// Full contents of `config.toml`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GhostConfig {
+    /// `[general]`
+    pub general: GeneralSection,
+    /// `[proxy]`
+    pub proxy: ProxyConfig,
+    /// `[scanner]`
+    pub scanner: ScannerConfig,
+    /// `[anti_ai]`
+    pub anti_ai: AntiAiSection,
+    /// `[dns]`
+    pub dns: DnsSection,
+    /// `[zapret]`
+    pub zapret: ZapretSection,
+    /// `[goodbyedpi]`
+    pub goodbyedpi: GoodbyeDpiSection,
+    /// `[network]`
+    pub network: NetworkSection,
+    /// `[warp]`
+    pub warp: WarpSection,
+    /// `[tproxy]`
+    pub tproxy: TproxyConfig,
+    /// `[hardware]`
+    pub hardware: Ipq40xxConfig,
+    /// `[subscription]`
+    pub subscription: SubscriptionConfig,
+    /// `[inbound]`
+    pub inbound: InboundConfig,
+    /// `[journal]`
+    pub journal: JournalConfig,
+    /// `[profiles.<name>]`
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl GhostConfig {
+    /// Load and parse a config file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        Self::from_toml_str(&content)
+            .with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    /// Load a config file, falling back to defaults when it does not exist
+    pub fn load_or_default(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if path.exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    /// Parse config from a TOML string
+    pub fn from_toml_str(content: &str) -> Result<Self> {
+        let mut config: Self = toml::from_str(content)?;
+        config.normalize();
+        Ok(config)
+    }
+
+    /// Fill in values that the file leaves empty on purpose
+    fn normalize(&mut self) {
+        self.proxy.uuid = self.proxy.uuid.trim().to_string();
+        if self.proxy.alternative_ports.is_empty() {
+            self.proxy.alternative_ports = crate::types::ALTERNATIVE_PORTS.to_vec();
+        }
+    }
+
+    /// Overlay the named profile onto this config
+    pub fn apply_profile(&mut self, name: &str) -> Result<()> {
+        let profile = self
+            .profiles
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("Unknown profile `{}`", name))?;
+
+        if let Some(protocol) = profile.protocol {
+            self.proxy.protocol = protocol;
+        }
+        if let Some(sni) = profile.sni {
+            self.proxy.sni = sni;
+        }
+        if let Some(cdn) = profile.cdn {
+            self.proxy.cdn_type = cdn;
+        }
+        if let Some(strategy) = profile.zapret_strategy {
+            self.zapret.config.strategy = strategy;
+        }
+        if let Some(mode) = profile.dpi_mode {
+            self.anti_ai.enabled = true;
+            self.anti_ai.mode = mode;
+        }
+        Ok(())
+    }
+
+    /// Profile selected by the uplink: a gateway match wins over an ASN match
+    pub fn match_profile(&self, uplink: &Uplink) -> Option<&str> {
+        let by_gateway = uplink.gateway.and_then(|gateway| {
+            self.profiles
+                .iter()
+                .find(|(_, p)| p.gateways.contains(&gateway))
+        });
+        let by_asn = || {
+            uplink
+                .asn
+                .and_then(|asn| self.profiles.iter().find(|(_, p)| p.asn.contains(&asn)))
+        };
+        by_gateway.or_else(by_asn).map(|(name, _)| name.as_str())
+    }
+
+    /// First usable DNS upstream, as `ip:port`
+    ///
+    /// Hostname entries (written by older setup scripts) are skipped.
+    pub fn primary_dns(&self) -> String {
+        self.dns
+            .servers
+            .iter()
+            .find_map(|server| dns_upstream(server))
+            .map(|addr| addr.to_string())
+            .unwrap_or_else(|| DnsSection::default().servers[0].clone())
+    }
+}
+
+// ==================== SECTIONS ====================
+
+/// `[general]` — process-wide settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GeneralSection {
+    /// Soft memory limit (MB)
+    pub memory_limit_mb: u32,
+    /// Interval for persisting runtime state (seconds)
+    pub auto_save_interval: u64,
+    /// Restart the tunnel when it goes down
+    pub enable_watchdog: bool,
+    /// Profile to apply: a name from `[profiles]`, `auto` to pick by uplink,
+    /// or empty for none
+    pub profile: String,
+}
+
+impl Default for GeneralSection {
+    fn default() -> Self {
+        Self {
+            memory_limit_mb: 120,
+            auto_save_interval: 300,
+            enable_watchdog: true,
+            profile: String::new(),
+        }
+    }
+}
+
+/// `[anti_ai]` — Anti-AI DPI engine
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AntiAiSection {
+    /// Enable the engine
+    pub enabled: bool,
+    /// Operating mode
+    pub mode: AntiAiMode,
+    /// Entropy padding
+    pub enable_padding: bool,
+    /// Timing jitter
+    pub enable_timing: bool,
+    /// Decoy traffic
+    pub enable_decoy: bool,
+}
+
+impl Default for AntiAiSection {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            mode: AntiAiMode::Adaptive,
+            enable_padding: true,
+            enable_timing: true,
+            enable_decoy: true,
+        }
+    }
+}
+
+/// Address of a `[dns] servers` entry; a bare IP uses port 53
+pub fn dns_upstream(server: &str) -> Option<SocketAddr> {
+    let server = server.trim();
+    server
+        .parse::<SocketAddr>()
+        .ok()
+        .or_else(|| server.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, 53)))
+}
+
+/// `[dns]` — resolver upstreams
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DnsSection {
+    /// Upstream servers (`ip:port` or a bare IP for port 53), first one is primary
+    pub servers: Vec<String>,
+    /// Enable DNS-over-HTTPS
+    pub enable_doh: bool,
+    /// Enable DNS-over-QUIC
+    pub enable_doq: bool,
+    /// Cache entries
+    pub cache_size: usize,
+}
+
+impl Default for DnsSection {
+    fn default() -> Self {
+        Self {
+            servers: vec![
+                "1.1.1.1:53".to_string(),
+                "8.8.8.8:53".to_string(),
+                "9.9.9.9:53".to_string(),
+            ],
+            enable_doh: true,
+            enable_doq: true,
+            cache_size: 2048,
+        }
+    }
+}
+
+/// `[zapret]` — [`ZapretConfig`] plus an on/off switch
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ZapretSection {
+    /// Enable the engine
+    pub enabled: bool,
+    /// Engine settings
+    #[serde(flatten)]
+    pub config: ZapretConfig,
+}
+
+impl Default for ZapretSection {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            config: ZapretConfig::default(),
+        }
+    }
+}
+
+/// `[goodbyedpi]` — [`GoodbyeDpiConfig`] plus an on/off switch
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GoodbyeDpiSection {
+    /// Enable the engine
+    pub enabled: bool,
+    /// Engine settings
+    #[serde(flatten)]
+    pub config: GoodbyeDpiConfig,
+}
+
+impl Default for GoodbyeDpiSection {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            config: GoodbyeDpiConfig::default(),
+        }
+    }
+}
+
+/// UDP-over-TCP policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UdpOverTcp {
+    /// Only when native UDP is blocked
+    #[default]
+    Auto,
+    /// Always tunnel UDP over TCP
+    Always,
+    /// Never tunnel UDP over TCP
+    Never,
+}
+
+/// `[network]` — socket tuning
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkSection {
+    /// Enable TCP Fast Open
+    pub tcp_fast_open: bool,
+    /// UDP-over-TCP policy
+    pub udp_over_tcp: UdpOverTcp,
+    /// Send window (bytes)
+    pub send_window: u32,
+    /// Receive window (bytes)
+    pub recv_window: u32,
+}
+
+impl Default for NetworkSection {
+    fn default() -> Self {
+        Self {
+            tcp_fast_open: true,
+            udp_over_tcp: UdpOverTcp::Auto,
+            send_window: 8 * 1024 * 1024,
+            recv_window: 8 * 1024 * 1024,
+        }
+    }
+}
+
+/// `[warp]` — [`WarpConfig`] plus an on/off switch
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WarpSection {
+    /// Enable WARP
+    pub enabled: bool,
+    /// WARP settings
+    #[serde(flatten)]
+    pub config: WarpConfig,
+}
+
+/// `[profiles.<name>]` — overrides for one uplink (ISP)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    /// Tunnel protocol
+    pub protocol: Option<ProtocolType>,
+    /// SNI
+    pub sni: Option<String>,
+    /// Zapret strategy
+    pub zapret_strategy: Option<ZapretStrategy>,
+    /// Anti-AI DPI mode
+    pub dpi_mode: Option<AntiAiMode>,
+    /// CDN
+    pub cdn: Option<CdnType>,
+    /// WAN ASNs that select this profile in `auto` mode
+    pub asn: Vec<u32>,
+    /// Default gateways that select this profile in `auto` mode
+    pub gateways: Vec<IpAddr>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        goodbyedpi::GoodbyeDpiMode, warp_client::WarpAccountType,
+        zapret_bypass::ZapretStrategy,
+    };
+
+    const SHIPPED: &str = include_str!("../config/config.toml");
+
+    #[test]
+    fn test_shipped_config_parses() {
+        let config = GhostConfig::from_toml_str(SHIPPED).unwrap();
+
+        assert_eq!(config.proxy.protocol, ProtocolType::Reality);
+        assert_eq!(config.proxy.cdn_type, CdnType::Cloudflare);
+        assert_eq!(config.proxy.max_latency_ms, 250);
+        assert!(config.proxy.uuid.is_empty());
+        assert_eq!(config.scanner.max_ips, 150);
+        assert_eq!(config.scanner.scan_interval, 300);
+        assert_eq!(config.anti_ai.mode, AntiAiMode::Adaptive);
+        assert_eq!(config.zapret.config.strategy, ZapretStrategy::Auto);
+        assert_eq!(config.zapret.config.fragment_size, Some(40));
+        assert!(config.zapret.config.use_nfqueue);
+        assert_eq!(config.goodbyedpi.config.mode, GoodbyeDpiMode::Iranian);
+        assert_eq!(config.goodbyedpi.config.dns_server, "1.1.1.1");
+        assert!(!config.warp.enabled);
+        assert_eq!(config.warp.config.account_type, WarpAccountType::Free);
+        assert_eq!(config.tproxy.listen_port, 7892);
+        assert!(config.hardware.enable_bbr);
+        assert_eq!(config.network.udp_over_tcp, UdpOverTcp::Auto);
+        assert_eq!(config.primary_dns(), "1.1.1.1:53");
+    }
+
+    #[test]
+    fn test_missing_sections_use_defaults() {
+        let config = GhostConfig::from_toml_str("[proxy]\nsni = \"aparat.com\"\n").unwrap();
+        assert_eq!(config.proxy.sni, "aparat.com");
+        assert_eq!(config.proxy.port, 443);
+        assert_eq!(config.tproxy.listen_port, 7892);
+        assert!(config.zapret.enabled);
+    }
+
+    #[test]
+    fn test_profile_selection_and_overlay() {
+        let mut config = GhostConfig::from_toml_str(SHIPPED).unwrap();
+        let uplink = |asn, gateway: Option<&str>| Uplink {
+            asn: Some(asn),
+            gateway: gateway.map(|g| g.parse().unwrap()),
+            ..Uplink::default()
+        };
+
+        assert_eq!(config.match_profile(&uplink(44244, None)), Some("irancell"));
+        assert_eq!(config.match_profile(&uplink(197207, None)), Some("mci"));
+        assert_eq!(config.match_profile(&uplink(1, None)), None);
+
+        config.profiles.get_mut("shatel").unwrap().gateways = vec!["192.168.8.1".parse().unwrap()];
+        assert_eq!(config.match_profile(&uplink(44244, Some("192.168.8.1"))), Some("shatel"));
+
+        config.apply_profile("mci").unwrap();
+        let mci = &config.profiles["mci"];
+        assert_eq!(Some(&config.proxy.sni), mci.sni.as_ref());
+        assert_eq!(Some(config.zapret.config.strategy), mci.zapret_strategy);
+        assert!(config.apply_profile("nope").is_err());
+    }
+
+    #[test]
+    fn test_type_error_is_reported() {
+        assert!(GhostConfig::from_toml_str("[proxy]\nport = \"https\"\n").is_err());
+    }
+}
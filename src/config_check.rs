@@ -0,0 +1,767 @@
+//! Config Validation
+//!
+//! Backs `network-ghost check-config`: reports unknown keys, type errors,
+//! out-of-range values and conflicting options in `config.toml`, each with the
+//! line it came from, so provisioning can refuse to deploy a broken file.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    config::{dns_upstream, GhostConfig},
+    engine::relays_connections,
+    reality::{parse_public_key, parse_short_id},
+    types::{ProtocolSettings, ProtocolType},
+    utils::parse_cidr,
+    warp_client::WarpAccountType,
+};
+
+/// Keys that are valid but absent from a serialized default config
+/// (`Option` fields that default to `None`, and serde aliases)
+const EXTRA_KEYS: &[(&str, &[&str])] = &[
+    ("proxy", &["private_key", "public_key", "short_id", "fallback_port", "settings", "chain"]),
+    ("zapret", &["fragment_size", "fragment_size_https", "enable_fake_packets"]),
+    ("warp", &["license_key", "team_name", "custom_endpoint"]),
+    ("subscription", &["proxy"]),
+    ("inbound", &["username", "password"]),
+    ("proxy.settings", &["flow", "spider_x", "obfs", "obfs_password", "pin_sha256", "plugin"]),
+    ("proxy.settings.transport", &["path", "host", "service_name", "header_type"]),
+];
+
+/// Placeholder `[proxy] server` of the config setup-router.sh writes
+const TEMPLATE_SERVER: &str = "YOUR_SERVER_IP";
+
+/// Sections older config files carry that are no longer read, with where
+/// their settings live now
+const LEGACY_SECTIONS: &[(&str, &str)] = &[
+    ("matryoshka", "layers are set by `[proxy] chain`"),
+    ("dashboard", ""),
+    ("logging", ""),
+    ("transport", "padding and mux are set by `[proxy] chain`"),
+    ("spoofing", "the SNI is `[proxy] sni`"),
+];
+
+/// Keys of a `[profiles.<name>]` table
+const PROFILE_KEYS: &[&str] = &["protocol", "sni", "zapret_strategy", "dpi_mode", "cdn", "asn", "gateways"];
+
+/// Alternative spellings a key may use in the file
+const KEY_ALIASES: &[(&str, &str, &str)] = &[
+    ("zapret", "fragment_size", "fragment_size_https"),
+    ("zapret", "enable_fake", "enable_fake_packets"),
+];
+
+// ==================== DIAGNOSTICS ====================
+
+/// Diagnostic severity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The config must not be deployed
+    Error,
+    /// Suspicious but usable
+    Warning,
+}
+
+/// A single finding in a config file
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    /// Severity
+    pub severity: Severity,
+    /// 1-based line number, when known
+    pub line: Option<usize>,
+    /// Dotted key path (e.g. `zapret.fake_ttl`)
+    pub key: Option<String>,
+    /// Human-readable description
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        match &self.key {
+            Some(key) => write!(f, "{}: {}: {}", level, key, self.message),
+            None => write!(f, "{}: {}", level, self.message),
+        }
+    }
+}
+
+/// Whether any diagnostic is an error
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(|d| d.severity == Severity::Error)
+}
+
+// ==================== ENTRY POINTS ====================
+
+/// Validate a config file on disk
+pub fn check_config_file(path: impl AsRef<Path>) -> Result<Vec<Diagnostic>> {
+    let path = path.as_ref();
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+    Ok(check_config_str(&content))
+}
+
+/// Load a config file, refusing it if validation reports any error.
+///
+/// Used for reloads, where a broken file must not replace a working config.
+pub fn load_checked(path: impl AsRef<Path>) -> Result<GhostConfig> {
+    let path = path.as_ref();
+    let diagnostics = check_config_file(path)?;
+    if has_errors(&diagnostics) {
+        let errors: Vec<String> = diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+            .map(|d| match d.line {
+                Some(line) => format!("{}:{}: {}", path.display(), line, d),
+                None => format!("{}: {}", path.display(), d),
+            })
+            .collect();
+        anyhow::bail!("Config rejected:\n{}", errors.join("\n"));
+    }
+    GhostConfig::load(path)
+}
+
+/// Validate config file contents
+pub fn check_config_str(content: &str) -> Vec<Diagnostic> {
+    let mut report = Report::new(content);
+
+    // Stage 1: TOML syntax
+    let table = match content.parse::<toml::Table>() {
+        Ok(table) => table,
+        Err(e) => {
+            report.at_offset(e.span().map(|s| s.start), e.message());
+            return report.finish();
+        }
+    };
+
+    // Stage 2: unknown sections / keys
+    report.unknown_keys(&table);
+
+    // Stage 3: types
+    let config = match GhostConfig::from_toml_str(content) {
+        Ok(config) => config,
+        Err(e) => {
+            match e.downcast_ref::<toml::de::Error>() {
+                Some(de) => report.at_offset(de.span().map(|s| s.start), de.message()),
+                None => report.at_offset(None, &e.to_string()),
+            }
+            return report.finish();
+        }
+    };
+
+    // Stage 4: ranges and conflicts
+    check_values(&config, &mut report);
+
+    report.finish()
+}
+
+// ==================== REPORT ====================
+
+/// Collects diagnostics and resolves their line numbers
+struct Report<'a> {
+    content: &'a str,
+    lines: HashMap<(String, String), usize>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Report<'a> {
+    fn new(content: &'a str) -> Self {
+        Self {
+            content,
+            lines: index_lines(content),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    fn finish(mut self) -> Vec<Diagnostic> {
+        self.diagnostics.sort_by_key(|d| d.line.unwrap_or(usize::MAX));
+        self.diagnostics
+    }
+
+    /// Line of `key` inside `[section]` (or of the header when `key` is empty)
+    fn line_of(&self, section: &str, key: &str) -> Option<usize> {
+        let lookup = |k: &str| self.lines.get(&(section.to_string(), k.to_string())).copied();
+        lookup(key).or_else(|| {
+            KEY_ALIASES
+                .iter()
+                .filter(|(s, k, _)| *s == section && *k == key)
+                .find_map(|(_, _, alias)| lookup(alias))
+        })
+    }
+
+    fn at_offset(&mut self, offset: Option<usize>, message: &str) {
+        let line = offset.map(|o| self.content[..o.min(self.content.len())].matches('\n').count() + 1);
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            line,
+            key: None,
+            message: message.trim().to_string(),
+        });
+    }
+
+    /// Line of one element of a (possibly multi-line) array under `key`
+    fn line_of_value(&self, section: &str, key: &str, value: &str) -> Option<usize> {
+        let start = self.line_of(section, key)?;
+        let quoted = format!("\"{}\"", value);
+        self.content
+            .lines()
+            .enumerate()
+            .skip(start - 1)
+            .take_while(|(i, l)| *i + 1 == start || !l.trim_start().starts_with('['))
+            .find(|(_, l)| l.contains(&quoted))
+            .map(|(i, _)| i + 1)
+    }
+
+    fn push(&mut self, severity: Severity, section: &str, key: &str, message: String) {
+        let line = self.line_of(section, key).or_else(|| self.line_of(section, ""));
+        self.push_at(line, severity, section, key, message);
+    }
+
+    fn push_at(&mut self, line: Option<usize>, severity: Severity, section: &str, key: &str, message: String) {
+        let key = if key.is_empty() {
+            format!("[{}]", section)
+        } else if section.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", section, key)
+        };
+        self.diagnostics.push(Diagnostic { severity, line, key: Some(key), message });
+    }
+
+    fn error(&mut self, section: &str, key: &str, message: impl Into<String>) {
+        self.push(Severity::Error, section, key, message.into());
+    }
+
+    fn warning(&mut self, section: &str, key: &str, message: impl Into<String>) {
+        self.push(Severity::Warning, section, key, message.into());
+    }
+
+    /// Error for every element of `values` that is not a CIDR range
+    fn check_cidrs(&mut self, section: &str, key: &str, values: &[String]) {
+        for value in values {
+            if let Err(e) = parse_cidr(value) {
+                let line = self.line_of_value(section, key, value).or_else(|| self.line_of(section, key));
+                let message = format!("`{}` is not a CIDR range (e.g. 10.8.0.0/16): {:#}", value, e);
+                self.push_at(line, Severity::Error, section, key, message);
+            }
+        }
+    }
+
+    fn unknown_keys(&mut self, table: &toml::Table) {
+        let mut schema = match toml::Table::try_from(GhostConfig::default()) {
+            Ok(schema) => schema,
+            Err(_) => return,
+        };
+        // `[proxy.settings]` only has a schema for the protocol its `type` names
+        let settings = table.get("proxy").and_then(|p| p.get("settings")).and_then(settings_schema);
+        if let (Some(settings), Some(proxy)) = (settings, schema.get_mut("proxy").and_then(|p| p.as_table_mut())) {
+            proxy.insert("settings".to_string(), settings);
+        }
+
+        for (section, value) in table {
+            if section == "profiles" {
+                self.unknown_profile_keys(value);
+                continue;
+            }
+            let Some(known) = schema.get(section) else {
+                match value {
+                    toml::Value::Table(_) if LEGACY_SECTIONS.iter().any(|(s, _)| s == section) => {
+                        let hint = LEGACY_SECTIONS.iter().find(|(s, _)| s == section).map_or("", |(_, h)| *h);
+                        let mut message = "legacy section is ignored and will stop being accepted".to_string();
+                        if !hint.is_empty() {
+                            message = format!("{}; {}", message, hint);
+                        }
+                        self.warning(section, "", message)
+                    }
+                    toml::Value::Table(_) => self.error(section, "", "unknown section"),
+                    _ => self.error("", section, "unknown top-level key (did you mean to put it in a section?)"),
+                }
+                continue;
+            };
+            if let (Some(keys), Some(known)) = (value.as_table(), known.as_table()) {
+                self.unknown_table_keys(section, keys, known);
+            }
+        }
+    }
+
+    /// Unknown keys of `table` (at dotted `path`), recursing into nested tables
+    fn unknown_table_keys(&mut self, path: &str, table: &toml::Table, known: &toml::Table) {
+        let extra = EXTRA_KEYS
+            .iter()
+            .find(|(s, _)| *s == path)
+            .map(|(_, keys)| *keys)
+            .unwrap_or_default();
+        for (key, value) in table {
+            match (known.get(key), value) {
+                (Some(toml::Value::Table(known)), toml::Value::Table(nested)) => {
+                    self.unknown_table_keys(&format!("{}.{}", path, key), nested, known);
+                }
+                (Some(_), _) => {}
+                (None, _) if extra.contains(&key.as_str()) => {}
+                (None, _) => self.error(path, key, "unknown key"),
+            }
+        }
+    }
+}
+
+/// Serialized defaults of the `[proxy.settings]` variant named by its `type`
+fn settings_schema(settings: &toml::Value) -> Option<toml::Value> {
+    let kind = settings.get("type")?.clone();
+    let defaults: ProtocolSettings = toml::Table::from_iter([("type".to_string(), kind)]).try_into().ok()?;
+    toml::Value::try_from(defaults).ok()
+}
+
+impl Report<'_> {
+    fn unknown_profile_keys(&mut self, profiles: &toml::Value) {
+        let Some(profiles) = profiles.as_table() else {
+            return;
+        };
+        for (name, profile) in profiles {
+            let section = format!("profiles.{}", name);
+            let Some(profile) = profile.as_table() else {
+                self.error("profiles", name, "profile must be a table");
+                continue;
+            };
+            for key in profile.keys() {
+                if !PROFILE_KEYS.contains(&key.as_str()) {
+                    self.error(&section, key, "unknown key");
+                }
+            }
+        }
+    }
+}
+
+/// Map every `(section, key)` and section header to its 1-based line
+fn index_lines(content: &str) -> HashMap<(String, String), usize> {
+    let mut lines = HashMap::new();
+    let mut section = String::new();
+
+    for (i, raw) in content.lines().enumerate() {
+        let line = raw.trim();
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[').and_then(|l| l.split(']').next()) {
+            section = header.trim().to_string();
+            lines.entry((section.clone(), String::new())).or_insert(i + 1);
+            continue;
+        }
+        if let Some((key, _)) = line.split_once('=') {
+            let key = key.trim().trim_matches('"').to_string();
+            lines.entry((section.clone(), key)).or_insert(i + 1);
+        }
+    }
+
+    lines
+}
+
+// ==================== VALUE CHECKS ====================
+
+fn check_values(config: &GhostConfig, r: &mut Report<'_>) {
+    // [general]
+    let profile = &config.general.profile;
+    if !profile.is_empty() && profile != crate::profile::AUTO && !config.profiles.contains_key(profile) {
+        r.error("general", "profile", format!("no [profiles.{}] section", profile));
+    }
+
+    // [proxy]
+    let proxy = &config.proxy;
+    if proxy.port == 0 {
+        r.error("proxy", "port", "port must be between 1 and 65535");
+    }
+    if proxy.fallback_port == Some(0) {
+        r.error("proxy", "fallback_port", "port must be between 1 and 65535");
+    }
+    if proxy.alternative_ports.contains(&0) {
+        r.error("proxy", "alternative_ports", "port must be between 1 and 65535");
+    }
+    if proxy.sni.trim().is_empty() {
+        r.error("proxy", "sni", "must not be empty");
+    }
+    if !proxy.uuid.is_empty() && uuid::Uuid::parse_str(&proxy.uuid).is_err() {
+        r.error("proxy", "uuid", format!("`{}` is not a valid UUID", proxy.uuid));
+    }
+    if proxy.max_latency_ms == 0 {
+        r.error("proxy", "max_latency_ms", "must be greater than 0");
+    }
+    match proxy.public_key.as_deref() {
+        Some(key) => {
+            if let Err(e) = parse_public_key(key) {
+                r.error("proxy", "public_key", e.to_string());
+            }
+        }
+        // An unfilled template (no uuid, or the placeholder server setup
+        // scripts write) cannot connect yet, so only warn
+        None if proxy.protocol == ProtocolType::Reality
+            && (proxy.uuid.is_empty() || proxy.server == TEMPLATE_SERVER) =>
+        {
+            r.warning("proxy", "public_key", "protocol `reality` needs the server public key and a uuid");
+        }
+        None if proxy.protocol == ProtocolType::Reality => {
+            r.error("proxy", "public_key", "protocol `reality` needs the server public key (connections are refused without it)");
+        }
+        None => {}
+    }
+    if let Err(e) = parse_short_id(proxy.short_id.as_deref().unwrap_or_default()) {
+        r.error("proxy", "short_id", e.to_string());
+    }
+    let settings_match = match (&proxy.protocol, &proxy.settings) {
+        (ProtocolType::Trojan, Some(ProtocolSettings::Trojan(_)))
+        | (ProtocolType::Hysteria2, Some(ProtocolSettings::Hysteria2(_)))
+        | (ProtocolType::Tuic, Some(ProtocolSettings::Tuic(_)))
+        | (ProtocolType::Shadowsocks, Some(ProtocolSettings::Shadowsocks(_)))
+        | (ProtocolType::Reality | ProtocolType::Vless, None | Some(ProtocolSettings::Vless(_))) => true,
+        (ProtocolType::Auto, _) => true,
+        (ProtocolType::Trojan | ProtocolType::Shadowsocks, None) => false,
+        (_, None) => true,
+        _ => false,
+    };
+    if !settings_match {
+        r.error(
+            "proxy",
+            "settings",
+            format!("[proxy.settings] does not match protocol `{:?}`", proxy.protocol),
+        );
+    }
+
+    // [scanner]
+    let scanner = &config.scanner;
+    if scanner.max_ips == 0 {
+        r.error("scanner", "max_ips", "must be greater than 0");
+    }
+    if scanner.concurrency == 0 {
+        r.error("scanner", "concurrency", "must be greater than 0");
+    }
+    if scanner.connect_timeout_ms == 0 {
+        r.error("scanner", "connect_timeout_ms", "must be greater than 0");
+    }
+    if scanner.tls_timeout_ms < scanner.connect_timeout_ms {
+        r.warning(
+            "scanner",
+            "tls_timeout_ms",
+            "is shorter than connect_timeout_ms; TLS probes will time out first",
+        );
+    }
+
+    // [dns]
+    if config.dns.servers.is_empty() {
+        r.error("dns", "servers", "at least one server is required");
+    }
+    for server in &config.dns.servers {
+        if dns_upstream(server).is_none() {
+            r.warning("dns", "servers", format!("`{}` is not an IP address and is skipped", server));
+        }
+    }
+    if !config.dns.servers.is_empty() && config.dns.servers.iter().all(|s| dns_upstream(s).is_none()) {
+        r.error("dns", "servers", "no server is an IP address (`ip:port`, or a bare IP for port 53)");
+    }
+
+    // [zapret]
+    let zapret = &config.zapret.config;
+    if zapret.fake_ttl == 0 {
+        r.error("zapret", "fake_ttl", "must be at least 1");
+    }
+    if zapret.fragment_size == Some(0) {
+        r.error("zapret", "fragment_size", "must be greater than 0");
+    }
+    if zapret.target_ports.contains(&0) {
+        r.error("zapret", "target_ports", "port must be between 1 and 65535");
+    }
+    if zapret.use_nfqueue && zapret.target_ports.is_empty() {
+        r.error("zapret", "use_nfqueue", "use_nfqueue requires non-empty target_ports");
+    }
+    if config.zapret.enabled && !zapret.http_bypass && !zapret.https_bypass && !zapret.quic_bypass {
+        r.warning("zapret", "enabled", "enabled but http, https and quic bypass are all off");
+    }
+
+    // [goodbyedpi]
+    let gdpi = &config.goodbyedpi.config;
+    if gdpi.http_fragment && gdpi.http_fragment_size == 0 {
+        r.error("goodbyedpi", "http_fragment_size", "must be greater than 0 when http_fragment is on");
+    }
+    if gdpi.https_fragment && gdpi.https_fragment_size == 0 {
+        r.error("goodbyedpi", "https_fragment_size", "must be greater than 0 when https_fragment is on");
+    }
+    if gdpi.wrong_seq_ttl == 0 {
+        r.error("goodbyedpi", "wrong_seq_ttl", "must be at least 1");
+    }
+    if gdpi.target_ports.contains(&0) {
+        r.error("goodbyedpi", "target_ports", "port must be between 1 and 65535");
+    }
+    if gdpi.dns_redirect && gdpi.dns_server.parse::<IpAddr>().is_err() {
+        r.error(
+            "goodbyedpi",
+            "dns_server",
+            format!("`{}` is not an IP address (required by dns_redirect)", gdpi.dns_server),
+        );
+    }
+
+    // [network]
+    if config.network.send_window == 0 {
+        r.error("network", "send_window", "must be greater than 0");
+    }
+    if config.network.recv_window == 0 {
+        r.error("network", "recv_window", "must be greater than 0");
+    }
+
+    // [warp]
+    let warp = &config.warp.config;
+    if !(1280..=1500).contains(&warp.mtu) {
+        r.warning("warp", "mtu", format!("{} is outside the usual 1280-1500 range", warp.mtu));
+    }
+    if let Some(endpoint) = &warp.custom_endpoint {
+        if endpoint.parse::<SocketAddr>().is_err() {
+            r.error("warp", "custom_endpoint", format!("`{}` is not an ip:port address", endpoint));
+        }
+    }
+    for dns in &warp.dns {
+        if dns.parse::<IpAddr>().is_err() {
+            r.error("warp", "dns", format!("`{}` is not an IP address", dns));
+        }
+    }
+    if config.warp.enabled && warp.account_type == WarpAccountType::Plus && warp.license_key.is_none() {
+        r.error("warp", "account_type", "account_type `plus` requires license_key");
+    }
+    if config.warp.enabled && warp.account_type == WarpAccountType::ZeroTrust && warp.team_name.is_none() {
+        r.error("warp", "account_type", "account_type `zero_trust` requires team_name");
+    }
+
+    // [tproxy]
+    let tproxy = &config.tproxy;
+    if tproxy.listen_port == 0 {
+        r.error("tproxy", "listen_port", "port must be between 1 and 65535");
+    }
+    if tproxy.dns_port == 0 {
+        r.error("tproxy", "dns_port", "port must be between 1 and 65535");
+    }
+    if tproxy.routing_mark == tproxy.mark {
+        r.error("tproxy", "routing_mark", "routing_mark must differ from mark");
+    }
+    if tproxy.listen_port != 0 && tproxy.listen_port == tproxy.dns_port {
+        r.error("tproxy", "dns_port", "dns_port must differ from listen_port");
+    }
+    r.check_cidrs("tproxy", "bypass_cidrs", &tproxy.bypass_cidrs);
+
+    // [subscription]
+    let subscription = &config.subscription;
+    for url in &subscription.urls {
+        if !["https://", "http://", "file://"].iter().any(|s| url.starts_with(s)) {
+            r.error("subscription", "urls", format!("`{}` must be an http(s):// or file:// URL", url));
+        }
+    }
+    if let Some(proxy) = &subscription.proxy {
+        if url::Url::parse(proxy).is_err() {
+            r.error("subscription", "proxy", format!("`{}` is not a proxy URL", proxy));
+        }
+    }
+    if subscription.timeout_secs == 0 {
+        r.error("subscription", "timeout_secs", "must be greater than 0");
+    }
+
+    // [inbound]
+    let inbound = &config.inbound;
+    let ports = [inbound.socks_port, inbound.http_port, inbound.mixed_port];
+    if inbound.enabled && ports.iter().all(|&p| p == 0) {
+        r.warning("inbound", "enabled", "no listener port set (socks_port, http_port, mixed_port)");
+    }
+    let mut seen = Vec::new();
+    for (key, port) in [("socks_port", inbound.socks_port), ("http_port", inbound.http_port), ("mixed_port", inbound.mixed_port)] {
+        if port != 0 && seen.contains(&port) {
+            r.error("inbound", key, format!("port {} is used by another listener", port));
+        }
+        seen.push(port);
+    }
+    if inbound.username.is_some() != inbound.password.is_some() {
+        r.error("inbound", "username", "`username` and `password` must be set together");
+    }
+    if inbound.enabled && !inbound.listen.is_loopback() && inbound.username.is_none() {
+        r.warning("inbound", "listen", format!("proxies on {} accept clients without authentication", inbound.listen));
+    }
+
+    // Inbound and TPROXY connections are relayed over the chain
+    let proxy = &config.proxy;
+    let listens = (inbound.enabled && ports.iter().any(|&p| p != 0)) || config.tproxy.enabled;
+    match (&proxy.chain, &proxy.protocol) {
+        _ if !listens => {}
+        (None, ProtocolType::Auto) => r.warning(
+            "proxy",
+            "protocol",
+            "`auto` may pick a protocol that cannot relay [inbound]/[tproxy] connections yet (only reality and vless can)",
+        ),
+        (Some(spec), _) if !relays_connections(proxy) => r.error(
+            "proxy",
+            "chain",
+            format!("chain `{}` has no reality layer to relay [inbound]/[tproxy] connections", spec),
+        ),
+        (None, protocol) if !relays_connections(proxy) => r.error(
+            "proxy",
+            "protocol",
+            format!(
+                "protocol `{:?}` cannot relay [inbound]/[tproxy] connections yet; use reality or vless, or disable them",
+                protocol
+            ),
+        ),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHIPPED: &str = include_str!("../config/config.toml");
+
+    #[test]
+    fn test_shipped_config_has_no_errors() {
+        let diagnostics = check_config_str(SHIPPED);
+        assert!(!has_errors(&diagnostics), "{:?}", diagnostics);
+    }
+
+    /// The config `setup-router.sh` writes when the package has none
+    fn setup_router_config(uuid: &str, public_key_line: &str) -> String {
+        let script = include_str!("../setup-router.sh");
+        let start = script.find("<< EOF\n").unwrap() + "<< EOF\n".len();
+        let end = start + script[start..].find("\nEOF\n").unwrap();
+        script[start..end]
+            .replace("${VERSION}", "5.0.0")
+            .replace("${CONFIG_UUID}", uuid)
+            .replace("${PUBLIC_KEY_LINE}", public_key_line)
+    }
+
+    #[test]
+    fn test_setup_router_config_has_no_errors() {
+        let template = check_config_str(&setup_router_config("", "# public_key = \"\""));
+        assert!(!has_errors(&template), "{:?}", template);
+
+        let uuid = "0c7a2c7e-3e4f-4d6b-9c1a-2f5b8e9d1a3c";
+        let filled = setup_router_config(uuid, "public_key = \"SbVKOEMjK0sIlbwg4akyBg5mL5KZwwB-ed4eEE7YnRc\"");
+        let filled = check_config_str(&filled);
+        assert!(!has_errors(&filled), "{:?}", filled);
+    }
+
+    #[test]
+    fn test_config_from_older_setup_script_is_accepted() {
+        // What setup-router.sh wrote before the legacy sections were dropped
+        let content = r#"
+[general]
+memory_limit_mb = 120
+
+[proxy]
+server = "YOUR_SERVER_IP"
+protocol = "reality"
+uuid = "0c7a2c7e-3e4f-4d6b-9c1a-2f5b8e9d1a3c"
+enable_matryoshka = true
+
+[matryoshka]
+enabled = true
+layers = ["shadowtls", "reality", "smux"]
+
+[dns]
+servers = ["9.9.9.9:53", "dns.google.com:53", "1.1.1.1", "8.8.8.8"]
+
+[dashboard]
+port = 9090
+
+[logging]
+level = "info"
+"#;
+        let diagnostics = check_config_str(content);
+        assert!(!has_errors(&diagnostics), "{:?}", diagnostics);
+        let legacy = diagnostics.iter().filter(|d| d.message.starts_with("legacy section")).count();
+        assert_eq!(legacy, 3);
+        let skipped: Vec<_> = diagnostics.iter().filter(|d| d.key.as_deref() == Some("dns.servers")).collect();
+        assert_eq!(skipped.len(), 1, "{:?}", skipped);
+        assert_eq!(skipped[0].line, Some(16));
+
+        let config = GhostConfig::from_toml_str(content).unwrap();
+        assert_eq!(config.primary_dns(), "9.9.9.9:53");
+        assert_eq!(crate::config::dns_upstream("1.1.1.1").unwrap().to_string(), "1.1.1.1:53");
+    }
+
+    #[test]
+    fn test_unknown_key_and_range_errors_have_lines() {
+        let content = "[zapret]\nfake_ttl = 0\nfake_tll = 8\n\n[scanner]\nmax_ips = 0\n";
+        let diagnostics = check_config_str(content);
+
+        let unknown = diagnostics.iter().find(|d| d.key.as_deref() == Some("zapret.fake_tll")).unwrap();
+        assert_eq!(unknown.line, Some(3));
+        let ttl = diagnostics.iter().find(|d| d.key.as_deref() == Some("zapret.fake_ttl")).unwrap();
+        assert_eq!(ttl.line, Some(2));
+        let max_ips = diagnostics.iter().find(|d| d.key.as_deref() == Some("scanner.max_ips")).unwrap();
+        assert_eq!(max_ips.line, Some(6));
+    }
+
+    #[test]
+    fn test_reality_requires_valid_public_key() {
+        let uuid = "uuid = \"0c7a2c7e-3e4f-4d6b-9c1a-2f5b8e9d1a3c\"\n";
+        let missing = check_config_str(&format!("[proxy]\nprotocol = \"reality\"\n{}", uuid));
+        let error = missing.iter().find(|d| d.key.as_deref() == Some("proxy.public_key")).unwrap();
+        assert_eq!(error.severity, Severity::Error);
+
+        let malformed = check_config_str(&format!("[proxy]\n{}public_key = \"abc\"\nshort_id = \"xyz\"\n", uuid));
+        assert_eq!(malformed.iter().filter(|d| d.severity == Severity::Error).count(), 2, "{:?}", malformed);
+    }
+
+    #[test]
+    fn test_inbound_needs_a_relaying_protocol() {
+        let error = |content: &str| {
+            check_config_str(content)
+                .into_iter()
+                .find(|d| d.severity == Severity::Error && d.message.contains("relay"))
+                .and_then(|d| d.key)
+        };
+        assert_eq!(error("[proxy]\nprotocol = \"hysteria2\"\n").as_deref(), Some("proxy.protocol"));
+        assert_eq!(error("[proxy]\nchain = \"tcp > smux\"\n").as_deref(), Some("proxy.chain"));
+        assert_eq!(error("[proxy]\nprotocol = \"hysteria2\"\n\n[inbound]\nenabled = false\n"), None);
+        assert_eq!(error("[proxy]\nprotocol = \"vless\"\n"), None);
+    }
+
+    #[test]
+    fn test_malformed_cidr_points_at_its_line() {
+        let content = "[tproxy]\nbypass_cidrs = [\n  \"10.8.0.0/16\",\n  \"10.9.0.0/33\",\n  \"fd00::/8\",\n  \"not-an-ip\",\n]\n";
+        let diagnostics = check_config_str(content);
+        let lines: Vec<_> = diagnostics
+            .iter()
+            .filter(|d| d.key.as_deref() == Some("tproxy.bypass_cidrs"))
+            .map(|d| d.line)
+            .collect();
+        assert_eq!(lines, vec![Some(4), Some(6)], "{:?}", diagnostics);
+    }
+
+    #[test]
+    fn test_unknown_keys_in_nested_tables() {
+        let content = "[proxy]\nprotocol = \"trojan\"\n\n[proxy.settings]\ntype = \"trojan\"\npasword = \"x\"\n\n[proxy.settings.transport]\nnetwork = \"ws\"\npaht = \"/ws\"\nhost = \"cdn.example.com\"\n";
+        let diagnostics = check_config_str(content);
+        let unknown: Vec<_> = diagnostics
+            .iter()
+            .filter(|d| d.message == "unknown key")
+            .map(|d| (d.key.as_deref().unwrap(), d.line))
+            .collect();
+        assert_eq!(
+            unknown,
+            vec![("proxy.settings.pasword", Some(6)), ("proxy.settings.transport.paht", Some(10))],
+            "{:?}",
+            diagnostics
+        );
+    }
+
+    #[test]
+    fn test_type_error_points_at_line() {
+        let diagnostics = check_config_str("[proxy]\nsni = \"a.ir\"\nport = 70000\n");
+        assert!(has_errors(&diagnostics));
+        assert_eq!(diagnostics[0].line, Some(3));
+    }
+
+    #[test]
+    fn test_nfqueue_without_ports_conflicts() {
+        let diagnostics = check_config_str("[zapret]\nuse_nfqueue = true\ntarget_ports = []\n");
+        assert!(diagnostics.iter().any(|d| d.key.as_deref() == Some("zapret.use_nfqueue")));
+    }
+}
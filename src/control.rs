@@ -0,0 +1,427 @@
+//! Control Socket
+//!
+//! سوکت کنترل JSON-RPC 2.0 روی Unix socket (هر پیام یک خط JSON). daemon این
+//! سوکت را سرو می‌کند و دستورات CLI (`status`، `stop`، ...) کلاینت آن هستند.
+//!
+//! متدها: `status`، `stop`، `rescan`، `switch-ip`، `set-dpi-mode`،
+//! `reload-pool`، `reload-config` و `events` (پس از پاسخ، رویدادها به صورت
+//! notification با متد `event` ارسال می‌شوند تا کلاینت قطع شود).
+
+use std::{
+    future::Future,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::{broadcast, Notify},
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    anti_ai_dpi::AntiAiMode, config::GhostConfig, engine::NetworkGhostEngine, types::EngineEvent,
+};
+
+/// مسیر پیش‌فرض سوکت کنترل
+pub const DEFAULT_SOCKET_PATH: &str = "/var/run/network-ghost.sock";
+
+// ==================== PROTOCOL ====================
+
+/// درخواست JSON-RPC
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    /// همیشه `2.0`
+    pub jsonrpc: String,
+    /// شناسه درخواست
+    #[serde(default)]
+    pub id: Value,
+    /// نام متد
+    pub method: String,
+    /// پارامترها
+    #[serde(default)]
+    pub params: Value,
+}
+
+/// خطای JSON-RPC
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcError {
+    /// کد خطا
+    pub code: i64,
+    /// پیام خطا
+    pub message: String,
+}
+
+impl RpcError {
+    const PARSE_ERROR: i64 = -32700;
+    const METHOD_NOT_FOUND: i64 = -32601;
+    const INVALID_PARAMS: i64 = -32602;
+    const ENGINE_ERROR: i64 = -32000;
+
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+}
+
+/// پاسخ JSON-RPC
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Response {
+    /// همیشه `2.0`
+    pub jsonrpc: String,
+    /// شناسه درخواست
+    pub id: Value,
+    /// نتیجه
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    /// خطا
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl Response {
+    fn reply(id: Value, outcome: std::result::Result<Value, RpcError>) -> Self {
+        let (result, error) = match outcome {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self { jsonrpc: "2.0".to_string(), id, result, error }
+    }
+}
+
+/// پارامتر `set-dpi-mode`
+#[derive(Debug, Deserialize)]
+struct SetDpiModeParams {
+    mode: AntiAiMode,
+}
+
+// ==================== SERVER ====================
+
+/// خواندن دوباره فایل پیکربندی برای `reload-config`؛ مسیر فایل، پروفایل و
+/// فلگ‌های CLI فقط نزد پروسه میزبان است
+pub type ConfigLoader = Arc<dyn Fn() -> BoxFuture<'static, Result<GhostConfig>> + Send + Sync>;
+
+/// سرور سوکت کنترل
+pub struct ControlServer {
+    engine: Arc<NetworkGhostEngine>,
+    path: PathBuf,
+    shutdown: Arc<Notify>,
+    config_loader: Option<ConfigLoader>,
+}
+
+impl ControlServer {
+    /// ساخت سرور برای موتور
+    pub fn new(engine: Arc<NetworkGhostEngine>, path: impl Into<PathBuf>) -> Self {
+        Self {
+            engine,
+            path: path.into(),
+            shutdown: Arc::new(Notify::new()),
+            config_loader: None,
+        }
+    }
+
+    /// فعال کردن `reload-config` با همان بارگذاری که SIGHUP استفاده می‌کند
+    pub fn with_config_loader<F, Fut>(mut self, loader: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<GhostConfig>> + Send + 'static,
+    {
+        self.config_loader = Some(Arc::new(move || Box::pin(loader())));
+        self
+    }
+
+    /// با متد `stop` بیدار می‌شود تا پروسه میزبان خارج شود
+    pub fn shutdown_signal(&self) -> Arc<Notify> {
+        self.shutdown.clone()
+    }
+
+    /// گوش دادن روی سوکت و سرویس‌دهی به کلاینت‌ها (در پس‌زمینه)
+    pub fn spawn(self) -> Result<tokio::task::JoinHandle<()>> {
+        let listener = bind(&self.path)?;
+        info!("🎛️ سوکت کنترل: {}", self.path.display());
+
+        let server = Arc::new(self);
+        Ok(tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let server = server.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = server.handle_client(stream).await {
+                                debug!("Control client error: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => warn!("⚠️ خطای سوکت کنترل: {}", e),
+                }
+            }
+        }))
+    }
+
+    async fn handle_client(&self, stream: UnixStream) -> Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let request: Request = match serde_json::from_str(&line) {
+                Ok(request) => request,
+                Err(e) => {
+                    let error = RpcError::new(RpcError::PARSE_ERROR, e.to_string());
+                    write_line(&mut writer, &Response::reply(Value::Null, Err(error))).await?;
+                    continue;
+                }
+            };
+
+            if request.method == "events" {
+                // اشتراک قبل از پاسخ تا هیچ رویدادی از دست نرود
+                let events = self.engine.subscribe();
+                let ack = Response::reply(request.id, Ok(json!({ "subscribed": true })));
+                write_line(&mut writer, &ack).await?;
+                return stream_events(events, &mut writer).await;
+            }
+
+            let outcome = self.dispatch(&request.method, request.params).await;
+            write_line(&mut writer, &Response::reply(request.id, outcome)).await?;
+        }
+        Ok(())
+    }
+
+    async fn dispatch(&self, method: &str, params: Value) -> std::result::Result<Value, RpcError> {
+        let engine_error = |e: anyhow::Error| RpcError::new(RpcError::ENGINE_ERROR, format!("{:#}", e));
+
+        match method {
+            "status" => Ok(json!(self.engine.get_state().await)),
+            "stop" => {
+                self.engine.stop("Stopped via control socket").await.map_err(engine_error)?;
+                self.shutdown.notify_one();
+                Ok(json!({ "stopped": true }))
+            }
+            "rescan" => {
+                let clean_ips = self.engine.refresh_clean_ips().await.map_err(engine_error)?;
+                Ok(json!({ "clean_ips": clean_ips.len() }))
+            }
+            "switch-ip" => {
+                let ip = self.engine.switch_ip().await.map_err(engine_error)?;
+                Ok(json!({ "ip": ip }))
+            }
+            "set-dpi-mode" => {
+                let params: SetDpiModeParams = serde_json::from_value(params)
+                    .map_err(|e| RpcError::new(RpcError::INVALID_PARAMS, e.to_string()))?;
+                self.engine.set_dpi_mode(params.mode).await;
+                Ok(json!({ "mode": params.mode }))
+            }
+            "reload-pool" => {
+                let nodes = self.engine.reload_node_pool().await.map_err(engine_error)?;
+                Ok(json!({ "nodes": nodes }))
+            }
+            "reload-config" => {
+                let loader = self.config_loader.as_ref().ok_or_else(|| {
+                    RpcError::new(RpcError::METHOD_NOT_FOUND, "reload-config is not enabled on this socket")
+                })?;
+                let config = loader()
+                    .await
+                    .context("New config rejected, keeping the current one")
+                    .map_err(engine_error)?;
+                let report = self.engine.reload_config(config).await.map_err(engine_error)?;
+                Ok(json!(report))
+            }
+            other => Err(RpcError::new(
+                RpcError::METHOD_NOT_FOUND,
+                format!("Unknown method: {}", other),
+            )),
+        }
+    }
+}
+
+/// ساخت listener؛ سوکت قدیمی (از اجرای قبلی) حذف می‌شود
+fn bind(path: &Path) -> Result<UnixListener> {
+    if path.exists() {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            bail!("Another instance is already serving {}", path.display());
+        }
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).ok();
+    }
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind control socket {}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660))?;
+    Ok(listener)
+}
+
+async fn stream_events(
+    mut events: broadcast::Receiver<EngineEvent>,
+    writer: &mut (impl AsyncWriteExt + Unpin),
+) -> Result<()> {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                debug!("Control client lagged, {} events skipped", skipped);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        };
+        let notification = json!({ "jsonrpc": "2.0", "method": "event", "params": event });
+        write_line(writer, &notification).await?;
+    }
+}
+
+async fn write_line(writer: &mut (impl AsyncWriteExt + Unpin), value: &impl Serialize) -> Result<()> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+    Ok(())
+}
+
+// ==================== CLIENT ====================
+
+/// کلاینت سوکت کنترل
+pub struct ControlClient {
+    lines: tokio::io::Lines<BufReader<tokio::net::unix::OwnedReadHalf>>,
+    writer: tokio::net::unix::OwnedWriteHalf,
+    next_id: u64,
+}
+
+impl ControlClient {
+    /// اتصال به سوکت
+    pub async fn connect(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let stream = UnixStream::connect(path).await.with_context(|| {
+            format!("Cannot reach the daemon at {} (is it running?)", path.display())
+        })?;
+        let (reader, writer) = stream.into_split();
+        Ok(Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+            next_id: 1,
+        })
+    }
+
+    /// فراخوانی یک متد
+    pub async fn call(&mut self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = Request {
+            jsonrpc: "2.0".to_string(),
+            id: json!(id),
+            method: method.to_string(),
+            params,
+        };
+        write_line(&mut self.writer, &request).await?;
+
+        let line = self
+            .lines
+            .next_line()
+            .await?
+            .ok_or_else(|| anyhow!("Control socket closed"))?;
+        let response: Response = serde_json::from_str(&line).context("Invalid control response")?;
+        match (response.result, response.error) {
+            (_, Some(error)) => bail!("{} (code {})", error.message, error.code),
+            (Some(result), None) => Ok(result),
+            (None, None) => Ok(Value::Null),
+        }
+    }
+
+    /// اشتراک رویدادها؛ هر رویداد تا بسته شدن سوکت به `on_event` داده می‌شود
+    pub async fn events(mut self, mut on_event: impl FnMut(EngineEvent)) -> Result<()> {
+        self.call("events", Value::Null).await?;
+        while let Some(line) = self.lines.next_line().await? {
+            let notification: Value = serde_json::from_str(&line)?;
+            match serde_json::from_value(notification["params"].clone()) {
+                Ok(event) => on_event(event),
+                Err(e) => debug!("Unknown event: {}", e),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine::ConfigReloadReport, types::TunnelState};
+
+    #[tokio::test]
+    async fn test_control_round_trip() {
+        let path = std::env::temp_dir().join(format!("ghost-control-{}.sock", std::process::id()));
+        let engine = Arc::new(NetworkGhostEngine::new(GhostConfig::default()).await.unwrap());
+        let server = ControlServer::new(engine.clone(), &path);
+        let shutdown = server.shutdown_signal();
+        let task = server.spawn().unwrap();
+
+        let mut client = ControlClient::connect(&path).await.unwrap();
+        let state: TunnelState =
+            serde_json::from_value(client.call("status", Value::Null).await.unwrap()).unwrap();
+        assert!(!state.active);
+
+        client.call("set-dpi-mode", json!({ "mode": "stealth" })).await.unwrap();
+        assert_eq!(engine.current_dpi_mode(), AntiAiMode::Stealth);
+
+        let err = client.call("set-dpi-mode", json!({ "mode": "loud" })).await.unwrap_err();
+        assert!(err.to_string().contains("-32602"), "{}", err);
+        let err = client.call("reboot", Value::Null).await.unwrap_err();
+        assert!(err.to_string().contains("-32601"), "{}", err);
+
+        client.call("stop", Value::Null).await.unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(1), shutdown.notified())
+            .await
+            .unwrap();
+
+        task.abort();
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_reload_config_round_trip() {
+        let path = std::env::temp_dir().join(format!("ghost-reload-{}.sock", std::process::id()));
+        let engine = Arc::new(NetworkGhostEngine::new(GhostConfig::default()).await.unwrap());
+        let valid = Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let loader_valid = valid.clone();
+        let server = ControlServer::new(engine.clone(), &path).with_config_loader(move || {
+            let valid = loader_valid.load(std::sync::atomic::Ordering::SeqCst);
+            async move {
+                if !valid {
+                    bail!("line 3: expected `=`");
+                }
+                let mut config = GhostConfig::default();
+                config.proxy.sni = "reloaded.example.com".to_string();
+                config.proxy.max_latency_ms += 100;
+                Ok(config)
+            }
+        });
+        let task = server.spawn().unwrap();
+
+        let mut client = ControlClient::connect(&path).await.unwrap();
+        let report: ConfigReloadReport =
+            serde_json::from_value(client.call("reload-config", Value::Null).await.unwrap()).unwrap();
+        assert!(report.applied.contains(&"proxy.sni = reloaded.example.com".to_string()), "{:?}", report);
+        assert_eq!(report.restart_required, vec!["proxy.max_latency_ms".to_string()]);
+
+        valid.store(false, std::sync::atomic::Ordering::SeqCst);
+        let err = client.call("reload-config", Value::Null).await.unwrap_err();
+        assert!(err.to_string().contains("line 3"), "{}", err);
+        assert!(err.to_string().contains("-32000"), "{}", err);
+
+        // سوکت بدون loader متد را نمی‌شناسد
+        task.abort();
+        std::fs::remove_file(&path).ok();
+        let task = ControlServer::new(engine, &path).spawn().unwrap();
+        let mut client = ControlClient::connect(&path).await.unwrap();
+        let err = client.call("reload-config", Value::Null).await.unwrap_err();
+        assert!(err.to_string().contains("-32601"), "{}", err);
+
+        task.abort();
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -110,6 +110,8 @@ impl DaeGenerator {
             ProtocolType::Trojan => "trojan",
             ProtocolType::Vless => "vless",
             ProtocolType::Warp => "warp",
+            ProtocolType::Shadowsocks => "shadowsocks",
+            ProtocolType::Auto => "vless",
             ProtocolType::Cascade { .. } => "vless",
         };
 
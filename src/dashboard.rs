@@ -7,6 +7,8 @@ use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 use tracing::info;
 
+use crate::types::TrafficRates;
+
 /// تنظیمات Dashboard
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DashboardConfig {
@@ -44,6 +46,12 @@ pub struct TunnelInfo {
     pub rx_bytes: u64,
     /// TX bytes
     pub tx_bytes: u64,
+    /// نرخ ترافیک (۱/۱۰/۶۰ ثانیه)
+    pub rates: TrafficRates,
+    /// تعداد اتصال‌ها
+    pub connections: u64,
+    /// درصد از دست رفتن پکت
+    pub packet_loss_pct: f32,
     /// تأخیر
     pub latency_ms: u64,
 }
@@ -70,6 +78,9 @@ impl DashboardServer {
                 uptime_secs: 0,
                 rx_bytes: 0,
                 tx_bytes: 0,
+                rates: TrafficRates::default(),
+                connections: 0,
+                packet_loss_pct: 0.0,
                 latency_ms: 0,
             })),
         }
@@ -8,8 +8,8 @@ use tracing::debug;
 
 /// DNS over QUIC Client
 pub struct DnsOverQuic {
-    /// آدرس سرور DNS
-    server: SocketAddr,
+    /// آدرس سرور DNS (قابل تغییر در حین اجرا)
+    server: std::sync::RwLock<SocketAddr>,
     /// سوکت UDP
     socket: Option<UdpSocket>,
 }
@@ -20,11 +20,24 @@ impl DnsOverQuic {
         let addr: SocketAddr = server.parse().context("Invalid DNS server address")?;
         
         Ok(Self {
-            server: addr,
+            server: std::sync::RwLock::new(addr),
             socket: None,
         })
     }
 
+    /// آدرس سرور فعلی
+    pub fn server(&self) -> SocketAddr {
+        *self.server.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// تغییر سرور upstream بدون ساخت مجدد کلاینت
+    pub fn set_server(&self, server: &str) -> Result<()> {
+        let addr: SocketAddr = server.parse().context("Invalid DNS server address")?;
+        *self.server.write().unwrap_or_else(|e| e.into_inner()) = addr;
+        debug!("🔁 DNS upstream → {}", addr);
+        Ok(())
+    }
+
     /// resolve نام دامنه
     pub async fn resolve(&self, domain: &str) -> Result<Vec<IpAddr>> {
         debug!("🔍 Resolving: {}", domain);
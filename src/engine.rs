@@ -1,6 +1,8 @@
 //! Network Ghost Engine - Core orchestration engine
 
 use std::{
+    collections::HashMap,
+    future::Future,
     net::{IpAddr, SocketAddr},
     sync::Arc,
     time::{Duration, Instant},
@@ -8,21 +10,40 @@ use std::{
 
 use anyhow::{Context, Result};
 use tokio::{
-    sync::{Mutex, RwLock, broadcast},
+    sync::{Mutex, RwLock, broadcast, watch},
     time::{interval, timeout},
 };
-use tracing::{error, info, warn};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{debug, error, info, warn};
 
 use crate::{
-    anti_ai_dpi::AntiAiDpi,
+    anti_ai_dpi::{AntiAiDpi, AntiAiMode},
     circuit_breaker::CircuitBreaker,
+    config::{GhostConfig, UdpOverTcp},
     dae_generator::DaeGenerator,
     dashboard::{DashboardConfig, DashboardServer, TunnelInfo},
     dns_over_quic::DnsOverQuic,
-    fingerprint::FingerprintManager,
-    matryoshka::MatryoshkaDialer,
+    fingerprint::{FingerprintManager, FingerprintType},
+    goodbyedpi::GoodbyeDpiEngine,
+    hysteria2::Hysteria2,
+    inbound::InboundServer,
+    ip_pool::{IpCache, IpPool},
+    ipq40xx_offload::Ipq40xxManager,
+    matryoshka::{ChainTrace, LayerType, MatryoshkaDialer, TunnelStream},
     port_hopper::PortHopper,
+    protocol_probe::{hysteria2_config, tuic_config, ProtocolSelector},
+    router_manager::TproxyConfig,
     scanner::TlsScanner,
+    share_link,
+    tproxy::TproxyServer,
+    traffic::{CountingDatagram, CountingStream, TrafficMeter, TrafficTap},
+    transport::{BoxedDatagram, DatagramDialer, DatagramTransport, UdpDialer},
+    tuic::Tuic,
+    udp_over_tcp::{self, UotClient},
+    subscription::NodePool,
+    warp_client::WarpConfig,
+    zapret_bypass::ZapretEngine,
+    utils,
     types::{
         CdnType, EngineEvent, ProtocolType, ProxyConfig, ScanResult, TunnelState,
         ALTERNATIVE_PORTS,
@@ -35,10 +56,14 @@ pub struct NetworkGhostEngine {
     config: Arc<RwLock<ProxyConfig>>,
     /// Tunnel state
     state: Arc<RwLock<TunnelState>>,
-    /// Clean IP list
-    clean_ips: Arc<Mutex<Vec<ScanResult>>>,
+    /// Ranked clean IPs, with failed ones quarantined
+    clean_ips: Arc<Mutex<IpPool>>,
+    /// Subscription nodes to fail over to when no clean IP is left
+    node_pool: Arc<Mutex<NodePool>>,
     /// Circuit Breaker
     circuit_breaker: Arc<CircuitBreaker>,
+    /// Protocol prober for `protocol = "auto"`
+    protocol_selector: Arc<ProtocolSelector>,
     /// Anti-AI DPI system
     anti_ai: Arc<AntiAiDpi>,
     /// DNS manager
@@ -52,65 +77,566 @@ pub struct NetworkGhostEngine {
     /// Event broadcast channel
     event_tx: broadcast::Sender<EngineEvent>,
     /// Fingerprint manager
-    fp_manager: Arc<FingerprintManager>,
+    fp_manager: Arc<RwLock<FingerprintManager>>,
+    /// Full config file as last loaded (non-proxy sections)
+    settings: Arc<RwLock<GhostConfig>>,
+    /// Zapret engine (when enabled)
+    zapret: Option<Arc<ZapretEngine>>,
+    /// GoodbyeDPI engine (when enabled)
+    goodbyedpi: Option<Arc<GoodbyeDpiEngine>>,
+    /// IPQ40xx hardware offload
+    hw_offload: Arc<Ipq40xxManager>,
+    /// Background tasks of the running tunnel (`None` while stopped)
+    running: Mutex<Option<TaskScope>>,
+    /// Server address the chain currently runs to
+    target: Arc<watch::Sender<Option<SocketAddr>>>,
+    /// Byte counters of all relayed streams
+    traffic: Arc<TrafficMeter>,
+    /// Server whose native UDP failed last, and when
+    udp_blocked: Arc<Mutex<Option<(SocketAddr, Instant)>>>,
+}
+
+/// Relay stream handed out by [`TunnelDialer`], counted into the traffic stats
+pub type MeteredStream = CountingStream<TunnelStream>;
+
+/// Dials destinations through the current Matryoshka chain
+///
+/// Cheap to clone; every dial reads the live config and tunnel state, so IP
+/// switches and protocol re-selection apply to the next connection.
+#[derive(Clone)]
+pub struct TunnelDialer {
+    config: Arc<RwLock<ProxyConfig>>,
+    state: Arc<RwLock<TunnelState>>,
+    settings: Arc<RwLock<GhostConfig>>,
+    target: Arc<watch::Sender<Option<SocketAddr>>>,
+    traffic: Arc<TrafficMeter>,
+    udp_blocked: Arc<Mutex<Option<(SocketAddr, Instant)>>>,
+}
+
+impl TunnelDialer {
+    /// Open a chain to `host:port` and hand back the relay stream
+    pub async fn dial(&self, host: &str, port: u16) -> Result<MeteredStream> {
+        let (chain, tap) = self.chain().await?;
+        Self::open(chain.with_destination(host, port), tap).await
+    }
+
+    /// Open a UDP flow to `host:port`
+    ///
+    /// Uses the native UDP protocol (Hysteria2/TUIC) unless `[network]
+    /// udp_over_tcp` says otherwise; `auto` falls back to UDP-over-TCP through
+    /// the chain when the protocol has no UDP transport or its UDP is blocked.
+    pub async fn dial_udp(&self, host: &str, port: u16) -> Result<BoxedDatagram> {
+        let policy = self.settings.read().await.network.udp_over_tcp;
+        if policy != UdpOverTcp::Always {
+            match self.dial_native_udp(host, port).await {
+                Ok(socket) => return Ok(socket),
+                Err(e) if policy == UdpOverTcp::Never => return Err(e),
+                Err(e) => debug!("UDP {}:{} over TCP: {:#}", host, port, e),
+            }
+        }
+        let stream = self.dial(udp_over_tcp::MAGIC_ADDRESS, 0).await?;
+        Ok(Box::new(UotClient::new(host, port).open(Box::new(stream)).await?))
+    }
+
+    /// UDP straight over the configured protocol; a failed handshake marks the
+    /// server's UDP as blocked for [`NATIVE_UDP_RETRY`]
+    async fn dial_native_udp(&self, host: &str, port: u16) -> Result<BoxedDatagram> {
+        let (ip, server_port) = self.server().await?;
+        let server = SocketAddr::new(ip, server_port);
+        let config = self.config.read().await.clone();
+        if !matches!(config.protocol, ProtocolType::Hysteria2 | ProtocolType::Tuic) {
+            anyhow::bail!("Protocol {:?} has no native UDP transport", config.protocol);
+        }
+        if let Some((blocked, since)) = *self.udp_blocked.lock().await {
+            if blocked == server && since.elapsed() < NATIVE_UDP_RETRY {
+                anyhow::bail!("UDP to {} was blocked {}s ago", server, since.elapsed().as_secs());
+            }
+        }
+
+        let socket = UdpDialer::new(server).with_mark(self.mark().await).dial().await?;
+        let socket = match config.protocol {
+            ProtocolType::Hysteria2 => {
+                Hysteria2::new(hysteria2_config(&config))
+                    .with_destination(host, port)
+                    .connect_over(socket)
+                    .await
+            }
+            _ => Tuic::new(tuic_config(&config))
+                .open(socket)
+                .await
+                .map(|tuic| Box::new(tuic.with_destination(host, port)) as BoxedDatagram),
+        };
+        match socket {
+            Ok(socket) => {
+                *self.udp_blocked.lock().await = None;
+                let tap = self.traffic.tap(&format!("{:?}", config.protocol), ip);
+                Ok(Box::new(CountingDatagram::new(socket, tap)))
+            }
+            Err(e) => {
+                warn!("⚠️ {:?} UDP to {} failed: {:#}", config.protocol, server, e);
+                *self.udp_blocked.lock().await = Some((server, Instant::now()));
+                Err(e)
+            }
+        }
+    }
+
+    /// Server the live tunnel runs to
+    async fn server(&self) -> Result<(IpAddr, u16)> {
+        let state = self.state.read().await;
+        let ip = state.current_ip.filter(|_| state.active);
+        Ok((ip.context("Tunnel is not active")?, state.current_port))
+    }
+
+    async fn chain(&self) -> Result<(MatryoshkaDialer, TrafficTap)> {
+        let (ip, server_port) = self.server().await?;
+        let config = self.config.read().await.clone();
+        let chain = NetworkGhostEngine::chain_for(&config, ip, server_port);
+        if !relays_connections(&config) {
+            match &config.chain {
+                Some(spec) => anyhow::bail!("Chain `{}` has no reality layer to carry connections", spec),
+                None => anyhow::bail!("Protocol {:?} cannot relay connections yet", config.protocol),
+            }
+        }
+
+        let tap = self.traffic.tap(&format!("{:?}", config.protocol), ip);
+        let chain = self.marked(chain).await;
+        Ok((chain, tap))
+    }
+
+    /// Keep our own upstream sockets out of the TPROXY rules
+    async fn marked(&self, chain: MatryoshkaDialer) -> MatryoshkaDialer {
+        match self.mark().await {
+            Some(mark) => chain.with_mark(mark),
+            None => chain,
+        }
+    }
+
+    async fn mark(&self) -> Option<u32> {
+        let tproxy = &self.settings.read().await.tproxy;
+        tproxy.enabled.then_some(tproxy.routing_mark)
+    }
+
+    /// Bring up a bare chain with other proxy settings (a subscription node)
+    async fn connect_with(&self, config: &ProxyConfig, ip: IpAddr, port: u16) -> Result<MatryoshkaDialer> {
+        let mut chain = self.marked(NetworkGhostEngine::chain_for(config, ip, port)).await;
+        chain.start().await?;
+        Ok(chain)
+    }
+
+    /// Watch the server the chain runs to; changes when the engine fails over
+    ///
+    /// Sessions that can survive a reconnect (UDP) re-dial when it changes.
+    pub fn watch_target(&self) -> watch::Receiver<Option<SocketAddr>> {
+        self.target.subscribe()
+    }
+
+    async fn open(mut chain: MatryoshkaDialer, tap: TrafficTap) -> Result<MeteredStream> {
+        chain.start().await?;
+        Ok(CountingStream::new(chain.into_stream()?, tap))
+    }
+
+    /// Count an accepted client connection, and whether it failed to dial
+    pub async fn record_connection(&self, failed: bool) {
+        let mut state = self.state.write().await;
+        state.stats.connections += 1;
+        if failed {
+            state.stats.errors += 1;
+        }
+    }
+}
+
+/// Whether [`TunnelDialer`] can relay connections over `config`'s chain
+///
+/// Only a reality layer carries the destination so far; check-config rejects
+/// inbounds and TPROXY on top of anything else.
+pub(crate) fn relays_connections(config: &ProxyConfig) -> bool {
+    match &config.chain {
+        Some(spec) => spec.layers().iter().any(|l| matches!(l, LayerType::Reality { .. })),
+        None => matches!(config.protocol, ProtocolType::Reality | ProtocolType::Vless),
+    }
+}
+
+/// How long native UDP to a server counts as blocked before it is tried again
+const NATIVE_UDP_RETRY: Duration = Duration::from_secs(300);
+
+/// Longest a busy CPU can stretch the rescan interval (as a multiple)
+const MAX_RESCAN_BACKOFF: u32 = 8;
+
+/// Background rescan loop behind [`NetworkGhostEngine::start_rescans`]
+///
+/// Re-reads `[scanner]` before every wait, so interval changes apply to the
+/// next rescan and `scan_interval = 0` ends the loop. `cpu_load` is sampled
+/// when the wait is over.
+async fn run_rescans<F, Fut>(settings: Arc<RwLock<GhostConfig>>, cpu_load: impl Fn() -> Option<f64>, mut rescan: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<usize>>,
+{
+    let mut backoff = 1u32;
+    loop {
+        let (every, max_load) = {
+            let scanner = &settings.read().await.scanner;
+            (scanner.scan_interval, scanner.max_cpu_load)
+        };
+        if every == 0 {
+            info!("Background rescans disabled");
+            return;
+        }
+        tokio::time::sleep(Duration::from_secs(every) * backoff).await;
+
+        if let Some(load) = cpu_load().filter(|load| *load > max_load) {
+            backoff = (backoff * 2).min(MAX_RESCAN_BACKOFF);
+            info!("⏸️ CPU busy (load {:.2}/core) — rescan postponed", load);
+            continue;
+        }
+        backoff = 1;
+
+        if let Err(e) = rescan().await {
+            warn!("Background rescan error: {:#}", e);
+        }
+    }
+}
+
+/// Scans for clean IPs and keeps the pool and the on-disk cache up to date
+#[derive(Clone)]
+struct CleanIpStore {
+    config: Arc<RwLock<ProxyConfig>>,
+    settings: Arc<RwLock<GhostConfig>>,
+    pool: Arc<Mutex<IpPool>>,
+    dns_manager: Arc<DnsOverQuic>,
+    anti_ai: Arc<AntiAiDpi>,
+    event_tx: broadcast::Sender<EngineEvent>,
+}
+
+impl CleanIpStore {
+    /// Full scan, then store the results
+    async fn refresh(&self) -> Result<Vec<ScanResult>> {
+        let clean_ips = self.store(self.scan().await?).await;
+        let _ = self.event_tx.send(EngineEvent::ScanCompleted { count: clean_ips.len() });
+        Ok(clean_ips)
+    }
+
+    /// Cached IPs (stale ones re-tested), or a full scan if none are left
+    async fn load(&self) -> Result<Vec<ScanResult>> {
+        let scanner_config = self.settings.read().await.scanner.clone();
+        let cache = IpCache::load(&scanner_config.cache_path).unwrap_or_else(|e| {
+            warn!("⚠️ {:#} — ignoring the clean IP cache", e);
+            IpCache::default()
+        });
+
+        let ttl = Duration::from_secs(scanner_config.cache_ttl_secs);
+        let (mut clean_ips, stale) = cache.split_stale(ttl);
+        if !stale.is_empty() {
+            info!("🔁 Re-testing {} cached IPs older than {}s...", stale.len(), ttl.as_secs());
+            clean_ips.extend(self.scanner().await.revalidate(&stale).await);
+        }
+
+        if clean_ips.is_empty() {
+            return self.refresh().await;
+        }
+        info!("📦 {} clean IPs from cache", clean_ips.len());
+        Ok(self.store(clean_ips).await)
+    }
+
+    /// Re-test the pool, scan for new IPs and merge both into the pool
+    ///
+    /// The active tunnel keeps its IP; only failover candidates change.
+    async fn rescan(&self) -> Result<usize> {
+        let current = self.pool.lock().await.ranked().to_vec();
+        let mut merged: HashMap<(IpAddr, u16), ScanResult> = self
+            .scanner()
+            .await
+            .revalidate(&current)
+            .await
+            .into_iter()
+            .map(|r| ((r.ip, r.port), r))
+            .collect();
+        let still_clean = merged.len();
+
+        match self.scan().await {
+            Ok(found) => merged.extend(found.into_iter().map(|r| ((r.ip, r.port), r))),
+            Err(e) => warn!("⚠️ Background scan failed, keeping re-tested IPs: {:#}", e),
+        }
+        if merged.is_empty() {
+            anyhow::bail!("Rescan found no clean IPs; keeping the current pool");
+        }
+
+        let clean_ips = self.store(merged.into_values().collect()).await;
+        info!(
+            "🔁 Background rescan: {}/{} pooled IPs still clean, {} in pool",
+            still_clean,
+            current.len(),
+            clean_ips.len()
+        );
+        let _ = self.event_tx.send(EngineEvent::ScanCompleted { count: clean_ips.len() });
+        Ok(clean_ips.len())
+    }
+
+    /// Rank clean IPs into the pool and persist them for the next start
+    async fn store(&self, clean_ips: Vec<ScanResult>) -> Vec<ScanResult> {
+        let mut pool = self.pool.lock().await;
+        pool.replace(clean_ips);
+
+        // An empty result is more likely a network outage than a verdict on the cache
+        if !pool.is_empty() {
+            let path = self.settings.read().await.scanner.cache_path.clone();
+            let cache = IpCache { results: pool.ranked().to_vec() };
+            if let Err(e) = cache.save(&path) {
+                warn!("⚠️ Failed to save the clean IP cache: {:#}", e);
+            }
+        }
+        pool.ranked().to_vec()
+    }
+
+    async fn scanner(&self) -> TlsScanner {
+        TlsScanner::with_config(
+            self.dns_manager.clone(),
+            self.anti_ai.clone(),
+            self.settings.read().await.scanner.clone(),
+        )
+    }
+
+    /// Scan clean IPs with Fragmentation Detection
+    async fn scan(&self) -> Result<Vec<ScanResult>> {
+        info!("🔍 Starting clean IP scan (TLS Fragmentation Detection)...");
+
+        let scanner = self.scanner().await;
+
+        let (cdn_type, ports) = {
+            let config = self.config.read().await;
+            (config.cdn_type, config.alternative_ports.clone())
+        };
+        let results = scanner.scan_all_cdns(cdn_type, &ports, None).await?;
+
+        // Filter IPs that support Fragmentation or are clean
+        let fragmentation_ok: Vec<ScanResult> = results
+            .into_iter()
+            .filter(|r| r.supports_fragmentation || r.is_clean)
+            .collect();
+
+        info!(
+            "✅ {} clean IPs found (Fragmentation OK)",
+            fragmentation_ok.len()
+        );
+
+        Ok(fragmentation_ok)
+    }
+}
+
+/// How long `stop()` lets relayed connections finish before cutting them
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Background tasks owned by one run of the tunnel
+///
+/// Loops (accept loops, monitoring, port hopping) end as soon as shutdown
+/// begins; client connections get a drain period to finish on their own
+/// before they are cancelled as well.
+#[derive(Clone, Default)]
+pub struct TaskScope {
+    /// Cancelled when shutdown begins
+    shutdown: CancellationToken,
+    /// Cancelled when the drain period is over
+    abort: CancellationToken,
+    tracker: TaskTracker,
+}
+
+impl TaskScope {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spawn a long-running loop that ends when shutdown begins
+    pub fn spawn_loop<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let shutdown = self.shutdown.clone();
+        self.tracker.spawn(async move {
+            tokio::select! {
+                _ = shutdown.cancelled() => {}
+                _ = task => {}
+            }
+        });
+    }
+
+    /// Spawn a client connection, which may outlive the loops while draining
+    pub fn spawn_connection<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let abort = self.abort.clone();
+        self.tracker.spawn(async move {
+            tokio::select! {
+                _ = abort.cancelled() => {}
+                _ = task => {}
+            }
+        });
+    }
+
+    /// Number of tasks still running
+    pub fn len(&self) -> usize {
+        self.tracker.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tracker.is_empty()
+    }
+
+    /// Stop the loops, give connections `drain` to finish, then cancel the rest
+    pub async fn shutdown(&self, drain: Duration) {
+        self.shutdown.cancel();
+        self.tracker.close();
+        if timeout(drain, self.tracker.wait()).await.is_err() {
+            warn!("⚠️ Closing {} connection(s) still open after {:?}", self.tracker.len(), drain);
+            self.abort.cancel();
+            self.tracker.wait().await;
+        }
+    }
+}
+
+/// Circuit breaker trips in a row before `protocol = "auto"` probes again
+const AUTO_REPROBE_TRIPS: u32 = 3;
+
+/// Outcome of [`NetworkGhostEngine::reload_config`]
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct ConfigReloadReport {
+    /// Settings applied to the running tunnel
+    pub applied: Vec<String>,
+    /// Changed settings that need a restart to take effect
+    pub restart_required: Vec<String>,
 }
 
 impl NetworkGhostEngine {
-    /// Create a new engine instance
-    pub async fn new(config: ProxyConfig) -> Result<Self> {
+    /// Create a new engine instance from a loaded config file
+    pub async fn new(config: GhostConfig) -> Result<Self> {
         let (event_tx, _) = broadcast::channel(1024);
 
         let dashboard = DashboardServer::new(DashboardConfig::default());
         let port_hopper = PortHopper::new();
 
+        port_hopper.set_strategy(config.proxy.port_strategy).await;
+        port_hopper.set_enabled(config.proxy.enable_port_hopping);
+
+        let anti_ai = AntiAiDpi::new();
+        let mut proxy = config.proxy.clone();
+        if config.anti_ai.enabled {
+            anti_ai.set_mode(config.anti_ai.mode);
+        } else {
+            proxy.enable_anti_ai = false;
+        }
+        if proxy.uuid.is_empty() {
+            proxy.uuid = uuid::Uuid::new_v4().to_string();
+        }
+
+        let mut fp_manager = FingerprintManager::new();
+        match proxy.utls_fingerprint.parse::<FingerprintType>() {
+            Ok(fp) => fp_manager.set(fp),
+            Err(e) => warn!("⚠️ {} — keeping default fingerprint", e),
+        }
+
+        let node_pool = NodePool::load(&config.subscription.pool_path).unwrap_or_else(|e| {
+            warn!("⚠️ {:#} — starting with an empty node pool", e);
+            NodePool::default()
+        });
+
+        let dns_server = config.primary_dns();
+        let max_latency = Duration::from_millis(proxy.max_latency_ms);
+
         let engine = Self {
-            config: Arc::new(RwLock::new(config)),
+            config: Arc::new(RwLock::new(proxy)),
             state: Arc::new(RwLock::new(TunnelState::default())),
-            clean_ips: Arc::new(Mutex::new(Vec::new())),
+            clean_ips: Arc::new(Mutex::new(IpPool::default())),
+            node_pool: Arc::new(Mutex::new(node_pool)),
             circuit_breaker: Arc::new(CircuitBreaker::new(
                 Duration::from_secs(30),
                 3,
-                Duration::from_millis(300),
+                max_latency,
             )),
-            anti_ai: Arc::new(AntiAiDpi::new()),
-            dns_manager: Arc::new(DnsOverQuic::new("9.9.9.9:53").await?),
+            protocol_selector: Arc::new(ProtocolSelector::default()),
+            anti_ai: Arc::new(anti_ai),
+            dns_manager: Arc::new(DnsOverQuic::new(&dns_server).await?),
             dae_gen: Arc::new(DaeGenerator::new()),
             port_hopper: Arc::new(tokio::sync::Mutex::new(port_hopper)),
             dashboard: Arc::new(dashboard),
             event_tx,
-            fp_manager: Arc::new(FingerprintManager::new()),
+            fp_manager: Arc::new(RwLock::new(fp_manager)),
+            zapret: config
+                .zapret
+                .enabled
+                .then(|| Arc::new(ZapretEngine::new(config.zapret.config.clone()))),
+            goodbyedpi: config
+                .goodbyedpi
+                .enabled
+                .then(|| Arc::new(GoodbyeDpiEngine::new(config.goodbyedpi.config.clone()))),
+            hw_offload: Arc::new(Ipq40xxManager::new(Some(config.hardware.clone()))),
+            settings: Arc::new(RwLock::new(config)),
+            running: Mutex::new(None),
+            target: Arc::new(watch::channel(None).0),
+            traffic: Arc::new(TrafficMeter::new()),
+            udp_blocked: Arc::default(),
         };
 
         Ok(engine)
     }
 
-    /// Start the tunnel
+    /// Start the tunnel; a no-op while it is already running
     pub async fn start(&self) -> Result<()> {
+        let mut running = self.running.lock().await;
+        if running.is_some() && self.state.read().await.active {
+            info!("Tunnel is already running");
+            return Ok(());
+        }
+        // The previous run died on its own: clear its tasks before restarting
+        if let Some(stale) = running.take() {
+            stale.shutdown(DRAIN_TIMEOUT).await;
+        }
+
+        let scope = TaskScope::new();
+        match self.bring_up(&scope).await {
+            Ok(()) => {
+                *running = Some(scope);
+                Ok(())
+            }
+            Err(e) => {
+                scope.shutdown(Duration::ZERO).await;
+                self.state.write().await.active = false;
+                Err(e)
+            }
+        }
+    }
+
+    /// Run the startup stages, spawning background tasks into `scope`
+    async fn bring_up(&self, scope: &TaskScope) -> Result<()> {
         info!("🚀 Starting Network Ghost v5.0 (Kernel-Level Infrastructure)...");
 
         // Stage 0: Start Dashboard
         self.dashboard.start().await?;
+        self.hw_offload.init()?;
 
         // Stage 1: Initialize Fingerprint Rotation
-        let fp = self.fp_manager.current();
-        info!("🔑 uTLS Fingerprint: {}", fp.fp_type);
+        let fp_type = self.fp_manager.read().await.current().fp_type;
+        info!("🔑 uTLS Fingerprint: {}", fp_type);
+        self.anti_ai.rotate_profile_by_time();
 
-        // Stage 2: Scan clean IPs with TLS Fragmentation Detection
-        let clean_ips = self.scan_clean_ips().await?;
+        // Stage 2: Clean IPs from the cache, or a scan with TLS Fragmentation Detection
+        let clean_ips = self.load_clean_ips().await?;
 
         if clean_ips.is_empty() {
             error!("No clean IPs found!");
             return Err(anyhow::anyhow!("No clean IPs found"));
         }
 
-        // Store clean IPs
-        {
-            let mut ips = self.clean_ips.lock().await;
-            *ips = clean_ips.clone();
-        }
-
-        // Stage 3: Select best IP
-        let best_ip = self.select_best_ip(&clean_ips)?;
+        // Stage 3: Select best IP (and protocol in auto mode)
+        let best_ip = self.select_best_ip().await?;
+        Self::select_protocol(
+            &self.config,
+            &self.settings,
+            &self.protocol_selector,
+            &self.event_tx,
+            SocketAddr::new(best_ip.ip, best_ip.port),
+            false,
+        )
+        .await?;
 
         // Stage 4: Build Matryoshka chain (up to 20 layers)
         let matryoshka = self.build_matryoshka_chain(&best_ip).await?;
@@ -118,65 +644,71 @@ impl NetworkGhostEngine {
         // Stage 5: Connect via Matryoshka
         self.connect_with_matryoshka(matryoshka).await?;
 
-        // Stage 6: Start Port Hopping
-        if self.config.read().await.enable_port_hopping {
-            self.start_port_hopping().await;
-        }
-
-        // Stage 7: Start monitoring
-        self.start_monitoring().await;
+        // Stages 6-8: Inbounds, Port Hopping and monitoring
+        self.spawn_background(scope).await?;
 
-        // Stage 8: Generate DAE config for eBPF
+        // Stage 9: Generate DAE config for eBPF
         self.generate_dae_config().await?;
 
         info!("✅ Infrastructure is LIVE. Dashboard at http://ROUTER_IP:9090");
         Ok(())
     }
 
-    /// Scan clean IPs with Fragmentation Detection
-    async fn scan_clean_ips(&self) -> Result<Vec<ScanResult>> {
-        info!("🔍 Starting clean IP scan (TLS Fragmentation Detection)...");
-
-        let scanner = TlsScanner::new(self.dns_manager.clone(), self.anti_ai.clone());
+    /// Scan for clean IPs and store them, without touching the tunnel
+    pub async fn refresh_clean_ips(&self) -> Result<Vec<ScanResult>> {
+        self.ip_store().refresh().await
+    }
 
-        let config = self.config.read().await;
-        let results = scanner
-            .scan_all_cdns(config.cdn_type, ALTERNATIVE_PORTS, Some(50))
-            .await?;
+    /// Clean IPs from the on-disk cache, re-testing entries older than the TTL
+    ///
+    /// Falls back to a full scan when nothing in the cache is still clean.
+    pub async fn load_clean_ips(&self) -> Result<Vec<ScanResult>> {
+        self.ip_store().load().await
+    }
 
-        // Filter IPs that support Fragmentation or are clean
-        let fragmentation_ok: Vec<ScanResult> = results
-            .into_iter()
-            .filter(|r| r.supports_fragmentation || r.is_clean)
-            .collect();
+    /// Select the best clean IP that is not quarantined
+    async fn select_best_ip(&self) -> Result<ScanResult> {
+        self.clean_ips
+            .lock()
+            .await
+            .best()
+            .context("No valid IP available (all clean IPs are quarantined)")
+    }
 
-        info!(
-            "✅ {} clean IPs found (Fragmentation OK)",
-            fragmentation_ok.len()
-        );
+    /// Resolve `protocol = "auto"` by probing the candidates on `addr`
+    ///
+    /// No-op when a fixed protocol is configured. `force` ignores the
+    /// protocol cached for the current network.
+    async fn select_protocol(
+        config: &Arc<RwLock<ProxyConfig>>,
+        settings: &Arc<RwLock<GhostConfig>>,
+        selector: &ProtocolSelector,
+        event_tx: &broadcast::Sender<EngineEvent>,
+        addr: SocketAddr,
+        force: bool,
+    ) -> Result<()> {
+        let configured = settings.read().await.proxy.clone();
+        if configured.protocol != ProtocolType::Auto {
+            return Ok(());
+        }
 
-        let _ = self.event_tx.send(EngineEvent::ScanCompleted {
-            count: fragmentation_ok.len(),
-        });
+        // Probe with the configured protocol settings rather than whatever
+        // the previous pick left behind (unless a pool node replaced them)
+        let mut proxy = config.read().await.clone();
+        if proxy.server == configured.server {
+            proxy.settings = configured.settings;
+        }
 
-        Ok(fragmentation_ok)
-    }
+        let choice = selector.select(&proxy, addr, force).await?;
+        *config.write().await = choice.candidate.apply_to(&proxy);
 
-    /// Select the best IP from scan results
-    fn select_best_ip(&self, results: &[ScanResult]) -> Result<ScanResult> {
-        let mut sorted = results.to_vec();
-        sorted.sort_by(|a, b| {
-            // Prioritise IPs with fragmentation support
-            let frag_cmp = b.supports_fragmentation.cmp(&a.supports_fragmentation);
-            if frag_cmp != std::cmp::Ordering::Equal {
-                return frag_cmp;
-            }
-            b.quality_score
-                .partial_cmp(&a.quality_score)
-                .unwrap_or(std::cmp::Ordering::Equal)
+        info!("🎯 Auto protocol: {} ({}ms on {})", choice.candidate, choice.latency_ms, addr);
+        let _ = event_tx.send(EngineEvent::ProtocolSelected {
+            protocol: choice.candidate.to_string(),
+            latency_ms: choice.latency_ms,
+            ip: addr.ip(),
         });
-
-        sorted.into_iter().next().context("No valid IP available")
+        Ok(())
     }
 
     /// Build Matryoshka chain
@@ -184,36 +716,16 @@ impl NetworkGhostEngine {
         &self,
         scan_result: &ScanResult,
     ) -> Result<MatryoshkaDialer> {
-        let config = self.config.read().await;
-
         info!("🧅 Building Matryoshka chain...");
 
-        let mut dialer =
-            MatryoshkaDialer::from_ip(scan_result.ip, scan_result.port);
-
-        // Layer 1: ShadowTLS with Iranian SNI
-        dialer = dialer.wrap_with_shadowtls(&config.sni);
-        let _ = self
-            .event_tx
-            .send(EngineEvent::LayerAdded { layer: "ShadowTLS v3".to_string() });
-
-        // Layer 2: Reality/VLESS
-        if matches!(config.protocol, ProtocolType::Reality) {
-            dialer = dialer.wrap_with_reality(
-                &config.uuid,
-                &config.public_key.clone().unwrap_or_default(),
-            );
+        let config = self.config.read().await;
+        let dialer = Self::chain_for(&config, scan_result.ip, scan_result.port);
+        for layer in dialer.layers() {
             let _ = self
                 .event_tx
-                .send(EngineEvent::LayerAdded { layer: "Reality (VLESS)".to_string() });
+                .send(EngineEvent::LayerAdded { layer: layer.to_string() });
         }
 
-        // Layer 3: SMUX Multiplexing
-        dialer = dialer.enable_smux();
-        let _ = self
-            .event_tx
-            .send(EngineEvent::LayerAdded { layer: "SMUX Mux".to_string() });
-
         let layer_count = dialer.layer_count();
         info!("🧅 Matryoshka chain: {} layers", layer_count);
 
@@ -224,6 +736,32 @@ impl NetworkGhostEngine {
         Ok(dialer)
     }
 
+    /// Layers of the chain: `[proxy] chain` when set, otherwise the default for the protocol
+    fn chain_for(config: &ProxyConfig, ip: IpAddr, port: u16) -> MatryoshkaDialer {
+        if let Some(spec) = &config.chain {
+            let public_key = config.public_key.clone().unwrap_or_default();
+            let short_id = config.short_id.clone().unwrap_or_default();
+            let layers = spec.resolve(&config.sni, &config.uuid, &public_key, &short_id);
+            return MatryoshkaDialer::from_ip(ip, port).with_layers(layers);
+        }
+
+        // Layer 1: ShadowTLS with Iranian SNI
+        let mut dialer = MatryoshkaDialer::from_ip(ip, port).wrap_with_shadowtls(&config.sni, &config.uuid);
+
+        // Layer 2: Reality/VLESS
+        if matches!(config.protocol, ProtocolType::Reality | ProtocolType::Vless) {
+            dialer = dialer.wrap_with_reality(
+                &config.uuid,
+                &config.public_key.clone().unwrap_or_default(),
+                &config.short_id.clone().unwrap_or_default(),
+                &config.sni,
+            );
+        }
+
+        // Layer 3: SMUX Multiplexing
+        dialer.enable_smux()
+    }
+
     /// Connect via Matryoshka
     async fn connect_with_matryoshka(
         &self,
@@ -244,6 +782,7 @@ impl NetworkGhostEngine {
             state.active_layers = matryoshka.layer_count();
             state.started_at = Some(chrono::Utc::now());
         }
+        self.target.send_replace(Some(matryoshka.target_addr()));
 
         let _ = self.event_tx.send(EngineEvent::TunnelStarted {
             ip: matryoshka.target_addr().ip(),
@@ -254,14 +793,49 @@ impl NetworkGhostEngine {
         Ok(())
     }
 
+    /// Spawn the tasks that live as long as the tunnel runs
+    async fn spawn_background(&self, scope: &TaskScope) -> Result<()> {
+        // Stage 6: Accept local clients
+        self.start_inbounds(scope).await?;
+
+        // Stage 7: Start Port Hopping
+        if self.config.read().await.enable_port_hopping {
+            self.start_port_hopping(scope);
+        }
+
+        // Stage 8: Start monitoring and traffic accounting
+        self.start_monitoring(scope).await;
+        self.start_traffic_sampler(scope);
+
+        // Stage 8b: Periodic clean IP rescans
+        self.start_rescans(scope).await;
+        Ok(())
+    }
+
+    /// Start the local SOCKS5 / HTTP / mixed inbounds and the TPROXY listener
+    async fn start_inbounds(&self, scope: &TaskScope) -> Result<()> {
+        let (config, tproxy) = {
+            let settings = self.settings.read().await;
+            (settings.inbound.clone(), settings.tproxy.clone())
+        };
+
+        for server in InboundServer::from_config(&self.dialer(), &config) {
+            server.spawn(scope).await?;
+        }
+        if tproxy.enabled {
+            TproxyServer::new(tproxy, self.dialer()).spawn(scope)?;
+        }
+        Ok(())
+    }
+
     /// Start Port Hopping background task
-    async fn start_port_hopping(&self) {
+    fn start_port_hopping(&self, scope: &TaskScope) {
         info!("🔄 Starting Port Hopping...");
 
         let hopper = self.port_hopper.clone();
         let event_tx = self.event_tx.clone();
 
-        tokio::spawn(async move {
+        scope.spawn_loop(async move {
             let mut tick = interval(Duration::from_secs(60));
 
             loop {
@@ -281,17 +855,54 @@ impl NetworkGhostEngine {
         });
     }
 
+    /// Rescan clean IPs every `scanner.scan_interval` seconds (0 = never)
+    ///
+    /// A busy CPU postpones the rescan, doubling the wait each time.
+    async fn start_rescans(&self, scope: &TaskScope) {
+        if self.settings.read().await.scanner.scan_interval == 0 {
+            return;
+        }
+        let store = self.ip_store();
+
+        scope.spawn_loop(run_rescans(self.settings.clone(), utils::cpu_load, move || {
+            let store = store.clone();
+            async move { store.rescan().await }
+        }));
+    }
+
+    /// Sample the traffic counters once a second into the tunnel stats
+    fn start_traffic_sampler(&self, scope: &TaskScope) {
+        let traffic = self.traffic.clone();
+        let state = self.state.clone();
+
+        scope.spawn_loop(async move {
+            let mut tick = interval(Duration::from_secs(1));
+            loop {
+                tick.tick().await;
+                traffic.sample();
+                traffic.apply_to(&mut state.write().await.stats);
+            }
+        });
+    }
+
     /// Start monitoring background task
-    async fn start_monitoring(&self) {
+    async fn start_monitoring(&self, scope: &TaskScope) {
+        let auto_switch = self.config.read().await.auto_switch;
+        let config = self.config.clone();
         let state = self.state.clone();
+        let dialer = self.dialer();
         let clean_ips = self.clean_ips.clone();
+        let node_pool = self.node_pool.clone();
+        let settings = self.settings.clone();
+        let protocol_selector = self.protocol_selector.clone();
         let circuit_breaker = self.circuit_breaker.clone();
         let event_tx = self.event_tx.clone();
         let anti_ai = self.anti_ai.clone();
         let dashboard = self.dashboard.clone();
 
-        tokio::spawn(async move {
+        scope.spawn_loop(async move {
             let mut tick = interval(Duration::from_secs(10));
+            let mut trips = 0u32;
 
             loop {
                 tick.tick().await;
@@ -303,8 +914,9 @@ impl NetworkGhostEngine {
                 }
 
                 if let Some(ip) = current_state.current_ip {
-                    let latency =
-                        Self::measure_latency(ip).await.unwrap_or(u64::MAX);
+                    let probe = Self::measure_latency(SocketAddr::new(ip, current_state.current_port)).await.ok();
+                    state.write().await.stats.record_probe(probe);
+                    let latency = probe.unwrap_or(u64::MAX);
 
                     // Circuit Breaker check
                     if circuit_breaker.should_trip(latency) {
@@ -318,12 +930,45 @@ impl NetworkGhostEngine {
                             latency_ms: latency,
                         });
 
-                        if let Err(e) =
-                            Self::switch_to_new_ip(&state, &clean_ips, &event_tx)
-                                .await
-                        {
-                            error!("IP switch error: {}", e);
+                        // The picked protocol may have been blocked: probe again
+                        trips += 1;
+                        if trips >= AUTO_REPROBE_TRIPS {
+                            trips = 0;
+                            let addr = SocketAddr::new(ip, current_state.current_port);
+                            match Self::select_protocol(
+                                &config,
+                                &settings,
+                                &protocol_selector,
+                                &event_tx,
+                                addr,
+                                true,
+                            )
+                            .await
+                            {
+                                Ok(()) => {
+                                    state.write().await.protocol =
+                                        config.read().await.protocol.clone();
+                                }
+                                Err(e) => warn!("Protocol re-selection error: {:#}", e),
+                            }
+                        }
+
+                        if auto_switch {
+                            if let Err(e) =
+                                Self::switch_to_new_ip(&dialer, &clean_ips, &event_tx, true)
+                                    .await
+                            {
+                                warn!("IP switch error: {}", e);
+                                if let Err(e) =
+                                    Self::switch_to_pool_node(&dialer, &node_pool, &event_tx)
+                                        .await
+                                {
+                                    error!("Node failover error: {}", e);
+                                }
+                            }
                         }
+                    } else {
+                        trips = 0;
                     }
 
                     // Update Dashboard
@@ -342,6 +987,9 @@ impl NetworkGhostEngine {
                                 .unwrap_or(0),
                             rx_bytes: current_state.stats.rx_bytes,
                             tx_bytes: current_state.stats.tx_bytes,
+                            rates: current_state.stats.rates,
+                            connections: current_state.stats.connections,
+                            packet_loss_pct: current_state.stats.packet_loss_pct,
                             latency_ms: latency,
                         })
                         .await;
@@ -361,9 +1009,8 @@ impl NetworkGhostEngine {
     }
 
     /// Measure TCP latency to an IP
-    async fn measure_latency(ip: IpAddr) -> Result<u64> {
+    async fn measure_latency(addr: SocketAddr) -> Result<u64> {
         let start = Instant::now();
-        let addr = SocketAddr::new(ip, 443);
         let stream = timeout(
             Duration::from_secs(5),
             tokio::net::TcpStream::connect(addr),
@@ -373,37 +1020,128 @@ impl NetworkGhostEngine {
         Ok(start.elapsed().as_millis() as u64)
     }
 
-    /// Switch to a new clean IP
+    /// Fail over to the next clean IP
+    ///
+    /// `failed` quarantines the current IP first. Candidates are tried in
+    /// round-robin rank order with a TCP connect probe, and each one that
+    /// does not answer is quarantined too. New connections build their chain
+    /// to the new server and live UDP sessions re-dial through it; live TCP
+    /// sessions are not migrated and keep the old server until they close.
     async fn switch_to_new_ip(
-        state: &Arc<RwLock<TunnelState>>,
-        clean_ips: &Arc<Mutex<Vec<ScanResult>>>,
+        dialer: &TunnelDialer,
+        clean_ips: &Arc<Mutex<IpPool>>,
         event_tx: &broadcast::Sender<EngineEvent>,
-    ) -> Result<()> {
-        let ips = clean_ips.lock().await;
-
-        if ips.len() < 2 {
-            return Err(anyhow::anyhow!("No alternative IPs available"));
+        failed: bool,
+    ) -> Result<IpAddr> {
+        let old_ip = dialer.state.read().await.current_ip;
+        if let Some(ip) = old_ip.filter(|_| failed) {
+            clean_ips.lock().await.quarantine(ip);
         }
 
-        let old_ip = state.read().await.current_ip;
-        let new_ip = ips[1].ip;
+        let attempts = clean_ips.lock().await.len();
+        for _ in 0..attempts {
+            let Some(candidate) = clean_ips.lock().await.next_after(old_ip) else {
+                break;
+            };
+            let addr = SocketAddr::new(candidate.ip, candidate.port);
+            if let Err(e) = Self::measure_latency(addr).await {
+                warn!("⚠️ {} is unreachable, quarantined: {:#}", candidate.ip, e);
+                clean_ips.lock().await.quarantine(candidate.ip);
+                continue;
+            }
 
-        {
-            let mut s = state.write().await;
-            s.current_ip = Some(new_ip);
-            s.switch_count += 1;
+            let config = dialer.config.read().await.clone();
+            {
+                let mut s = dialer.state.write().await;
+                s.current_ip = Some(candidate.ip);
+                s.current_port = candidate.port;
+                s.active_layers = Self::chain_for(&config, candidate.ip, candidate.port).layer_count();
+                s.switch_count += 1;
+            }
+            dialer.target.send_replace(Some(addr));
+
+            if let Some(old) = old_ip {
+                let _ = event_tx.send(EngineEvent::IpSwitched { old, new: candidate.ip });
+            }
+            info!("🔄 Switched to new IP: {}:{}", candidate.ip, candidate.port);
+            return Ok(candidate.ip);
         }
 
-        if let Some(old) = old_ip {
-            let _ = event_tx.send(EngineEvent::IpSwitched { old, new: new_ip });
+        Err(anyhow::anyhow!("No alternative IPs available"))
+    }
+
+    /// Fail over to the next subscription node that brings a chain up
+    ///
+    /// The node's host is resolved and a chain to it started before anything
+    /// is switched; the tunnel then runs to the node instead of a clean IP.
+    /// Nodes that cannot be reached are skipped.
+    async fn switch_to_pool_node(
+        dialer: &TunnelDialer,
+        node_pool: &Arc<Mutex<NodePool>>,
+        event_tx: &broadcast::Sender<EngineEvent>,
+    ) -> Result<SocketAddr> {
+        let current = dialer.config.read().await.clone();
+        let attempts = node_pool.lock().await.len();
+        for _ in 0..attempts {
+            let Some(node) = node_pool.lock().await.next_after(&current) else {
+                break;
+            };
+            let proxy = node.apply_to(&current);
+            let chain = match Self::connect_node(dialer, &proxy).await {
+                Ok(chain) => chain,
+                Err(e) => {
+                    warn!("⚠️ Subscription node {} is unreachable: {:#}", node.name, e);
+                    continue;
+                }
+            };
+
+            let addr = chain.target_addr();
+            let to = format!("{}:{}", proxy.server, proxy.port);
+            *dialer.config.write().await = proxy;
+            {
+                let mut s = dialer.state.write().await;
+                s.current_ip = Some(addr.ip());
+                s.current_port = addr.port();
+                s.active_layers = chain.layer_count();
+                s.switch_count += 1;
+            }
+            dialer.target.send_replace(Some(addr));
+
+            let _ = event_tx.send(EngineEvent::NodeSwitched {
+                from: format!("{}:{}", current.server, current.port),
+                to: to.clone(),
+                name: node.name.clone(),
+            });
+            info!("🔄 Switched to subscription node {} ({} → {})", node.name, to, addr);
+            return Ok(addr);
         }
 
-        info!("🔄 Switched to new IP: {}", new_ip);
-        Ok(())
+        Err(anyhow::anyhow!("No reachable subscription nodes"))
+    }
+
+    /// Resolve a node's server (usually a hostname) and bring a chain up to
+    /// the first address that answers
+    async fn connect_node(dialer: &TunnelDialer, proxy: &ProxyConfig) -> Result<MatryoshkaDialer> {
+        let addrs = tokio::net::lookup_host((proxy.server.as_str(), proxy.port))
+            .await
+            .with_context(|| format!("Cannot resolve {}", proxy.server))?;
+        let mut last_error = anyhow::anyhow!("{} has no addresses", proxy.server);
+        for addr in addrs {
+            match dialer.connect_with(proxy, addr.ip(), addr.port()).await {
+                Ok(chain) => return Ok(chain),
+                Err(e) => last_error = e.context(addr.to_string()),
+            }
+        }
+        Err(last_error)
     }
 
     /// Generate DAE config for eBPF kernel-level routing
     async fn generate_dae_config(&self) -> Result<()> {
+        self.write_dae_config("/etc/dae/config.dae").await
+    }
+
+    /// Write the DAE config for the current clean IPs to `path`
+    pub async fn write_dae_config(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
         info!("📝 Generating DAE config (eBPF Kernel-Level)...");
 
         let config = self.config.read().await;
@@ -411,6 +1149,7 @@ impl NetworkGhostEngine {
         let clean_ips = self.clean_ips.lock().await;
 
         let nodes: Vec<_> = clean_ips
+            .ranked()
             .iter()
             .map(|r| {
                 (
@@ -435,21 +1174,28 @@ impl NetworkGhostEngine {
             self.dae_gen.generate_multi(nodes)?
         };
 
-        tokio::fs::write("/etc/dae/config.dae", &dae_config).await?;
+        let path = path.as_ref();
+        tokio::fs::write(path, &dae_config)
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))?;
 
         info!("✅ DAE config saved (eBPF TProxy)");
         Ok(())
     }
 
-    /// Stop the tunnel
+    /// Stop the tunnel, draining open connections and ending all background tasks
     pub async fn stop(&self, reason: &str) -> Result<()> {
         info!("🛑 Stopping tunnel: {}", reason);
 
+        let scope = self.running.lock().await.take();
         {
             let mut state = self.state.write().await;
             state.active = false;
             state.last_error = Some(reason.to_string());
         }
+        if let Some(scope) = scope {
+            scope.shutdown(DRAIN_TIMEOUT).await;
+        }
 
         let _ = self.event_tx.send(EngineEvent::TunnelStopped {
             reason: reason.to_string(),
@@ -470,7 +1216,278 @@ impl NetworkGhostEngine {
 
     /// Get list of clean IPs
     pub async fn get_clean_ips(&self) -> Vec<ScanResult> {
-        self.clean_ips.lock().await.clone()
+        self.clean_ips.lock().await.ranked().to_vec()
+    }
+
+    /// Switch the tunnel to another clean IP now
+    pub async fn switch_ip(&self) -> Result<IpAddr> {
+        Self::switch_to_new_ip(&self.dialer(), &self.clean_ips, &self.event_tx, false).await
+    }
+
+    /// Current Anti-AI DPI mode
+    pub fn current_dpi_mode(&self) -> AntiAiMode {
+        self.anti_ai.current_mode()
+    }
+
+    /// Change the Anti-AI DPI mode of the running engine
+    pub async fn set_dpi_mode(&self, mode: AntiAiMode) {
+        self.anti_ai.set_mode(mode);
+        let mut settings = self.settings.write().await;
+        settings.anti_ai.enabled = true;
+        settings.anti_ai.mode = mode;
+        self.config.write().await.enable_anti_ai = true;
+        info!("🛡️ Anti-AI DPI mode set to {:?}", mode);
+    }
+
+    /// Subscription nodes available for failover
+    pub async fn node_pool(&self) -> NodePool {
+        self.node_pool.lock().await.clone()
+    }
+
+    /// Re-read the node pool file written by the subscription fetcher
+    pub async fn reload_node_pool(&self) -> Result<usize> {
+        let path = self.settings.read().await.subscription.pool_path.clone();
+        let pool = NodePool::load(&path)?;
+        let count = pool.len();
+        *self.node_pool.lock().await = pool;
+        info!("📥 Node pool reloaded: {} nodes", count);
+        Ok(count)
+    }
+
+    /// Share links for the current proxy config, one per clean IP/port
+    pub async fn export_share_links(&self, name: &str) -> Result<Vec<String>> {
+        let mut config = self.config.read().await.clone();
+        // `new` fills in a random uuid when none is configured; a link with
+        // it would never authenticate
+        if self.settings.read().await.proxy.uuid.is_empty() {
+            config.uuid.clear();
+        }
+        let clean_ips = self.clean_ips.lock().await.ranked().to_vec();
+        share_link::export_links(&config, &clean_ips, name)
+    }
+
+    /// Zapret engine, if enabled in the config
+    pub fn zapret(&self) -> Option<Arc<ZapretEngine>> {
+        self.zapret.clone()
+    }
+
+    /// GoodbyeDPI engine, if enabled in the config
+    pub fn goodbyedpi(&self) -> Option<Arc<GoodbyeDpiEngine>> {
+        self.goodbyedpi.clone()
+    }
+
+    /// WARP settings, if enabled in the config
+    pub async fn warp_config(&self) -> Option<WarpConfig> {
+        let settings = self.settings.read().await;
+        settings.warp.enabled.then(|| settings.warp.config.clone())
+    }
+
+    /// Transparent proxy settings
+    pub async fn tproxy_config(&self) -> TproxyConfig {
+        self.settings.read().await.tproxy.clone()
+    }
+
+    /// Apply a freshly loaded config file to the running engine.
+    ///
+    /// Anti-AI mode, port hopping, SNI, uTLS fingerprint and DNS upstream are
+    /// switched in place without touching the live tunnel; other proxy
+    /// fields are picked up by the next chain build. Changes to sections that
+    /// are only read at startup are reported in `restart_required`. A config
+    /// with an invalid value is rejected before anything is applied.
+    pub async fn reload_config(&self, new: GhostConfig) -> Result<ConfigReloadReport> {
+        let mut report = ConfigReloadReport::default();
+        let old = self.settings.read().await.clone();
+        let mut proxy = new.proxy.clone();
+
+        // Validate everything that can fail before touching the running state
+        let fingerprint = new
+            .proxy
+            .utls_fingerprint
+            .parse::<FingerprintType>()
+            .context("Invalid proxy.utls_fingerprint")?;
+        let dns_server = new.primary_dns();
+        dns_server
+            .parse::<SocketAddr>()
+            .with_context(|| format!("Invalid dns.servers[0] `{}`", dns_server))?;
+
+        // Anti-AI
+        if new.anti_ai.enabled {
+            if self.anti_ai.current_mode() != new.anti_ai.mode {
+                self.anti_ai.set_mode(new.anti_ai.mode);
+                report.applied.push(format!("anti_ai.mode = {:?}", new.anti_ai.mode));
+            }
+        } else {
+            proxy.enable_anti_ai = false;
+        }
+
+        // Port hopping
+        {
+            let hopper = self.port_hopper.lock().await;
+            if new.proxy.port_strategy != old.proxy.port_strategy {
+                hopper.set_strategy(new.proxy.port_strategy).await;
+                report
+                    .applied
+                    .push(format!("proxy.port_strategy = {:?}", new.proxy.port_strategy));
+            }
+            if new.proxy.enable_port_hopping != old.proxy.enable_port_hopping {
+                hopper.set_enabled(new.proxy.enable_port_hopping);
+                report.applied.push(format!(
+                    "proxy.enable_port_hopping = {}",
+                    new.proxy.enable_port_hopping
+                ));
+            }
+        }
+
+        // uTLS fingerprint
+        if new.proxy.utls_fingerprint != old.proxy.utls_fingerprint {
+            self.fp_manager.write().await.set(fingerprint);
+            report.applied.push(format!("proxy.utls_fingerprint = {}", fingerprint));
+        }
+
+        // Zapret strategy (e.g. switched by an uplink profile)
+        if let Some(zapret) = &self.zapret {
+            let strategy = new.zapret.config.strategy;
+            if zapret.strategy() != strategy {
+                zapret.set_strategy(strategy);
+                report.applied.push(format!("zapret.strategy = {:?}", strategy));
+            }
+        }
+
+        // DNS upstream
+        if dns_server != old.primary_dns() {
+            self.dns_manager.set_server(&dns_server)?;
+            report.applied.push(format!("dns.servers[0] = {}", dns_server));
+        }
+
+        // Proxy settings: SNI is used by the next handshake, the rest by the
+        // next chain build
+        {
+            let mut config = self.config.write().await;
+            if proxy.uuid.is_empty() {
+                proxy.uuid = config.uuid.clone();
+            }
+            // Auto mode keeps the probed protocol until the next re-selection
+            if proxy.protocol == ProtocolType::Auto && config.protocol != ProtocolType::Auto {
+                proxy.protocol = config.protocol.clone();
+                proxy.settings = config.settings.clone();
+            }
+            if proxy.sni != config.sni {
+                report.applied.push(format!("proxy.sni = {}", proxy.sni));
+            }
+            // The tunnel runs to a clean IP (or a failover node), not to `server`
+            for (field, changed) in [("server", config.server != proxy.server), ("port", config.port != proxy.port)] {
+                if changed {
+                    report.applied.push(format!("proxy.{} (share links; the tunnel keeps its server)", field));
+                }
+            }
+            let chain_fields = [
+                ("protocol", config.protocol != proxy.protocol),
+                ("uuid", config.uuid != proxy.uuid),
+                ("public_key", config.public_key != proxy.public_key),
+                ("short_id", config.short_id != proxy.short_id),
+                ("settings", config.settings != proxy.settings),
+                ("cdn_type", config.cdn_type != proxy.cdn_type),
+            ];
+            for (field, changed) in chain_fields {
+                if changed {
+                    report.applied.push(format!("proxy.{} (next reconnect)", field));
+                }
+            }
+            if config.max_latency_ms != proxy.max_latency_ms {
+                report.restart_required.push("proxy.max_latency_ms".to_string());
+            }
+            *config = proxy;
+        }
+
+        // Every scan reads [scanner]; only switching rescans on needs a restart
+        if section_changed(&old.scanner, &new.scanner) {
+            if old.scanner.scan_interval == 0 && new.scanner.scan_interval != 0 {
+                report.restart_required.push("scanner.scan_interval".to_string());
+            } else {
+                report.applied.push("[scanner] (next scan)".to_string());
+            }
+        }
+
+        // Sections that are only read at startup (zapret strategy is live)
+        let mut zapret_rest = new.zapret.clone();
+        zapret_rest.config.strategy = old.zapret.config.strategy;
+        let startup_only = [
+            ("general", section_changed(&old.general, &new.general)),
+            ("zapret", section_changed(&old.zapret, &zapret_rest)),
+            ("goodbyedpi", section_changed(&old.goodbyedpi, &new.goodbyedpi)),
+            ("network", section_changed(&old.network, &new.network)),
+            ("warp", section_changed(&old.warp, &new.warp)),
+            ("tproxy", section_changed(&old.tproxy, &new.tproxy)),
+            ("hardware", section_changed(&old.hardware, &new.hardware)),
+            ("inbound", section_changed(&old.inbound, &new.inbound)),
+            ("journal", section_changed(&old.journal, &new.journal)),
+        ];
+        for (section, changed) in startup_only {
+            if changed {
+                report.restart_required.push(format!("[{}]", section));
+            }
+        }
+
+        *self.settings.write().await = new;
+        if let Err(e) = self.reload_node_pool().await {
+            warn!("⚠️ {:#}", e);
+        }
+
+        info!(
+            "🔁 Config reloaded: {} applied, {} need restart",
+            report.applied.len(),
+            report.restart_required.len()
+        );
+        for item in &report.restart_required {
+            warn!("⚠️ {} changed — takes effect after restart", item);
+        }
+
+        let _ = self.event_tx.send(EngineEvent::ConfigReloaded {
+            applied: report.applied.clone(),
+            restart_required: report.restart_required.clone(),
+        });
+
+        Ok(report)
+    }
+
+    fn ip_store(&self) -> CleanIpStore {
+        CleanIpStore {
+            config: self.config.clone(),
+            settings: self.settings.clone(),
+            pool: self.clean_ips.clone(),
+            dns_manager: self.dns_manager.clone(),
+            anti_ai: self.anti_ai.clone(),
+            event_tx: self.event_tx.clone(),
+        }
+    }
+
+    /// Handle for dialing destinations through the live chain (used by inbounds)
+    pub fn dialer(&self) -> TunnelDialer {
+        TunnelDialer {
+            config: self.config.clone(),
+            state: self.state.clone(),
+            settings: self.settings.clone(),
+            target: self.target.clone(),
+            traffic: self.traffic.clone(),
+            udp_blocked: self.udp_blocked.clone(),
+        }
+    }
+
+    /// Dial the configured chain layer by layer and report where it breaks
+    ///
+    /// Uses `ip` when given, otherwise the best clean IP (from the cache or a fresh scan).
+    pub async fn trace_chain(&self, ip: Option<IpAddr>) -> Result<ChainTrace> {
+        let ip = match ip {
+            Some(ip) => ip,
+            None => {
+                self.load_clean_ips().await?;
+                self.select_best_ip().await?.ip
+            }
+        };
+        let config = self.config.read().await.clone();
+        let dialer = Self::chain_for(&config, ip, config.port);
+        info!("🔬 Tracing {} layers to {}:{}", dialer.layer_count(), ip, config.port);
+        Ok(dialer.trace().await)
     }
 
     /// Test the current connection
@@ -482,10 +1499,315 @@ impl NetworkGhostEngine {
         }
 
         if let Some(ip) = state.current_ip {
-            let latency = Self::measure_latency(ip).await?;
+            let latency = Self::measure_latency(SocketAddr::new(ip, state.current_port)).await?;
             Ok(latency < 1000)
         } else {
             Ok(false)
         }
     }
 }
+
+/// Whether two config sections differ
+fn section_changed<T: serde::Serialize>(old: &T, new: &T) -> bool {
+    serde_json::to_value(old).ok() != serde_json::to_value(new).ok()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::types::TrafficStats;
+
+    /// Engine whose live tunnel runs a `reality` chain to `server`
+    pub(crate) async fn engine_through(server: SocketAddr, public_key: &str) -> NetworkGhostEngine {
+        let mut config = GhostConfig::default();
+        config.inbound.enabled = false;
+        config.proxy.chain = Some("reality".parse().unwrap());
+        config.proxy.uuid = "b831381d-6324-4d53-ad4f-8cda48b30811".to_string();
+        config.proxy.public_key = Some(public_key.to_string());
+        config.proxy.short_id = Some(crate::reality::tests::SHORT_ID.to_string());
+        config.proxy.sni = "www.speedtest.net".to_string();
+        let engine = NetworkGhostEngine::new(config).await.unwrap();
+        {
+            let mut state = engine.state.write().await;
+            state.active = true;
+            state.current_ip = Some(server.ip());
+            state.current_port = server.port();
+        }
+        engine
+    }
+
+    /// Tunnel stats with the traffic counters sampled now
+    pub(crate) async fn sampled_stats(engine: &NetworkGhostEngine) -> TrafficStats {
+        let mut state = engine.state.write().await;
+        engine.traffic.apply_to(&mut state.stats);
+        state.stats.clone()
+    }
+
+    #[tokio::test]
+    async fn test_repeated_start_stop_leaves_no_tasks() {
+        let mut config = GhostConfig::default();
+        config.inbound.enabled = false;
+        config.proxy.enable_port_hopping = true;
+        let engine = NetworkGhostEngine::new(config).await.unwrap();
+
+        for _ in 0..3 {
+            let scope = TaskScope::new();
+            engine.spawn_background(&scope).await.unwrap();
+            engine.state.write().await.active = true;
+            *engine.running.lock().await = Some(scope.clone());
+
+            // Already running: start() must not spawn a second set of tasks
+            let spawned = scope.len();
+            assert!(spawned > 0);
+            engine.start().await.unwrap();
+            assert_eq!(scope.len(), spawned);
+
+            engine.stop("test").await.unwrap();
+            assert!(scope.is_empty());
+            assert!(engine.running.lock().await.is_none());
+        }
+    }
+
+    fn reloaded_events(events: &mut broadcast::Receiver<EngineEvent>) -> Vec<(Vec<String>, Vec<String>)> {
+        std::iter::from_fn(|| events.try_recv().ok())
+            .filter_map(|event| match event {
+                EngineEvent::ConfigReloaded { applied, restart_required } => Some((applied, restart_required)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_reload_config_applies_live_and_reports_restart() {
+        let mut config = GhostConfig::default();
+        config.inbound.enabled = false;
+        config.anti_ai.enabled = true;
+        config.anti_ai.mode = AntiAiMode::Adaptive;
+        let engine = NetworkGhostEngine::new(config.clone()).await.unwrap();
+        let mut events = engine.subscribe();
+
+        // Unchanged file: nothing to apply
+        let report = engine.reload_config(config.clone()).await.unwrap();
+        assert!(report.applied.is_empty() && report.restart_required.is_empty(), "{:?}", report);
+
+        let mut new = config.clone();
+        new.anti_ai.mode = AntiAiMode::Stealth;
+        new.dns.servers = vec!["9.9.9.9:53".to_string()];
+        new.proxy.sni = "digikala.com".to_string();
+        new.proxy.port = 2053;
+        new.proxy.max_latency_ms += 50;
+        new.scanner.max_ips += 1;
+        new.general.memory_limit_mb += 10;
+        new.tproxy.listen_port += 1;
+        let report = engine.reload_config(new).await.unwrap();
+
+        assert_eq!(
+            report.applied,
+            [
+                "anti_ai.mode = Stealth",
+                "dns.servers[0] = 9.9.9.9:53",
+                "proxy.sni = digikala.com",
+                "proxy.port (share links; the tunnel keeps its server)",
+                "[scanner] (next scan)",
+            ]
+        );
+        assert_eq!(report.restart_required, ["proxy.max_latency_ms", "[general]", "[tproxy]"]);
+        assert_eq!(engine.current_dpi_mode(), AntiAiMode::Stealth);
+        assert_eq!(engine.dns_manager.server().to_string(), "9.9.9.9:53");
+        assert_eq!(engine.config.read().await.sni, "digikala.com");
+
+        assert_eq!(
+            reloaded_events(&mut events),
+            [(Vec::new(), Vec::new()), (report.applied, report.restart_required)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reload_config_rejects_invalid_config_untouched() {
+        let mut config = GhostConfig::default();
+        config.inbound.enabled = false;
+        config.anti_ai.mode = AntiAiMode::Adaptive;
+        let engine = NetworkGhostEngine::new(config.clone()).await.unwrap();
+        let mut events = engine.subscribe();
+        let dns = engine.dns_manager.server();
+
+        // The bad fingerprint comes after changes that would otherwise be applied
+        let mut new = config.clone();
+        new.anti_ai.mode = AntiAiMode::Stealth;
+        new.dns.servers = vec!["9.9.9.9:53".to_string()];
+        new.proxy.sni = "digikala.com".to_string();
+        new.proxy.utls_fingerprint = "netscape".to_string();
+        let error = engine.reload_config(new).await.unwrap_err();
+        assert!(format!("{:#}", error).contains("utls_fingerprint"), "{:#}", error);
+
+        assert_eq!(engine.current_dpi_mode(), AntiAiMode::Adaptive);
+        assert_eq!(engine.dns_manager.server(), dns);
+        assert_eq!(engine.config.read().await.sni, config.proxy.sni);
+        assert_eq!(engine.settings.read().await.anti_ai.mode, AntiAiMode::Adaptive);
+        assert!(reloaded_events(&mut events).is_empty());
+
+        // A file that fails validation never reaches the engine
+        let path = std::env::temp_dir().join(format!("ghost-reload-{}.toml", std::process::id()));
+        std::fs::write(&path, "[zapret]\nfake_ttl = 0\n").unwrap();
+        let rejected = crate::config_check::load_checked(&path);
+        std::fs::remove_file(&path).ok();
+        assert!(format!("{:#}", rejected.unwrap_err()).contains("zapret.fake_ttl"));
+    }
+
+    #[tokio::test]
+    async fn test_pool_failover_dials_the_new_node() {
+        let private_key = x25519_dalek::StaticSecret::from(rand::random::<[u8; 32]>());
+        let public_key = hex::encode(x25519_dalek::PublicKey::from(&private_key).as_bytes());
+        let node_addr = crate::reality::tests::stand_in_server(private_key).await;
+
+        let mut config = GhostConfig::default();
+        config.inbound.enabled = false;
+        config.proxy.chain = Some("reality".parse().unwrap());
+        let engine = NetworkGhostEngine::new(config).await.unwrap();
+        {
+            // The clean IP the tunnel runs to is dead
+            let mut state = engine.state.write().await;
+            state.active = true;
+            state.current_ip = Some("127.0.0.1".parse().unwrap());
+            state.current_port = 1;
+        }
+        let node = |name: &str, server: &str, port: u16| crate::subscription::PoolNode {
+            name: name.to_string(),
+            source: "test".to_string(),
+            proxy: ProxyConfig {
+                server: server.to_string(),
+                port,
+                protocol: ProtocolType::Reality,
+                uuid: "b831381d-6324-4d53-ad4f-8cda48b30811".to_string(),
+                public_key: Some(public_key.clone()),
+                short_id: Some(crate::reality::tests::SHORT_ID.to_string()),
+                sni: "www.speedtest.net".to_string(),
+                ..ProxyConfig::default()
+            },
+        };
+        engine.node_pool.lock().await.nodes =
+            vec![node("down", "127.0.0.1", 1), node("backup", "localhost", node_addr.port())];
+        let mut events = engine.subscribe();
+        let dialer = engine.dialer();
+        let target = dialer.watch_target();
+
+        let addr = NetworkGhostEngine::switch_to_pool_node(&dialer, &engine.node_pool, &engine.event_tx)
+            .await
+            .unwrap();
+        assert_eq!(addr, node_addr);
+        assert_eq!(*target.borrow(), Some(node_addr));
+        let state = engine.get_state().await;
+        assert_eq!((state.current_ip, state.current_port), (Some(node_addr.ip()), node_addr.port()));
+
+        let switched: Vec<_> = std::iter::from_fn(|| events.try_recv().ok())
+            .filter_map(|event| match event {
+                EngineEvent::NodeSwitched { name, to, .. } => Some((name, to)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(switched, [("backup".to_string(), format!("localhost:{}", node_addr.port()))]);
+
+        // The next dial completes the REALITY handshake, which only the node can sign
+        let mut stream = dialer.dial("example.com", 80).await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        stream.flush().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ip_switch_probes_the_candidate_port() {
+        let live = tokio::net::TcpListener::bind("127.0.0.3:0").await.unwrap();
+        let live_addr = live.local_addr().unwrap();
+        let mut config = GhostConfig::default();
+        config.inbound.enabled = false;
+        let engine = NetworkGhostEngine::new(config).await.unwrap();
+        {
+            let mut state = engine.state.write().await;
+            state.active = true;
+            state.current_ip = Some("127.0.0.1".parse().unwrap());
+            state.current_port = 443;
+        }
+        let scanned = |addr: SocketAddr, quality_score| ScanResult {
+            ip: addr.ip(),
+            port: addr.port(),
+            latency_ms: 50,
+            tls_valid: true,
+            is_clean: true,
+            supports_fragmentation: true,
+            cdn_type: CdnType::Cloudflare,
+            quality_score,
+            last_tested: chrono::Utc::now(),
+            tls_fingerprint: String::new(),
+        };
+        engine.clean_ips.lock().await.replace(vec![
+            scanned("127.0.0.1:443".parse().unwrap(), 0.9),
+            scanned("127.0.0.2:1".parse().unwrap(), 0.8),
+            scanned(live_addr, 0.7),
+        ]);
+        let target = engine.dialer().watch_target();
+
+        let ip = NetworkGhostEngine::switch_to_new_ip(&engine.dialer(), &engine.clean_ips, &engine.event_tx, true)
+            .await
+            .unwrap();
+        assert_eq!(ip, live_addr.ip());
+        assert_eq!(*target.borrow(), Some(live_addr));
+        let state = engine.get_state().await;
+        assert_eq!((state.current_port, state.switch_count), (live_addr.port(), 1));
+        assert!(engine.clean_ips.lock().await.is_quarantined("127.0.0.2".parse().unwrap()));
+
+        // A bare TCP probe: no chain handshake is sent and dropped
+        let (mut probe, _) = live.accept().await.unwrap();
+        let mut buf = [0u8; 1];
+        assert_eq!(tokio::io::AsyncReadExt::read(&mut probe, &mut buf).await.unwrap(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rescan_interval_and_cpu_backoff() {
+        let mut config = GhostConfig::default();
+        config.scanner.scan_interval = 10;
+        config.scanner.max_cpu_load = 0.8;
+        let settings = Arc::new(RwLock::new(config));
+
+        // Two quiet samples, four busy ones, then quiet again
+        let loads = std::sync::Mutex::new(vec![0.1, 0.1, 0.9, 0.9, 0.9, 0.9, 0.1, 0.1]);
+        let start = tokio::time::Instant::now();
+        let sampled_at = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let samples = sampled_at.clone();
+        let cpu_load = move || {
+            samples.lock().unwrap().push(start.elapsed().as_secs());
+            let mut loads = loads.lock().unwrap();
+            (!loads.is_empty()).then(|| loads.remove(0))
+        };
+        let (scanned_tx, mut scanned) = tokio::sync::mpsc::unbounded_channel();
+        let task = tokio::spawn(run_rescans(settings.clone(), cpu_load, move || {
+            let _ = scanned_tx.send(start.elapsed().as_secs());
+            async { Ok(0) }
+        }));
+
+        let mut scans = Vec::new();
+        for _ in 0..4 {
+            scans.push(scanned.recv().await.unwrap());
+        }
+        // 10s apart; a busy CPU doubles the wait (20, 40, 80, capped at 80)
+        // and the first quiet sample resets it
+        assert_eq!(scans, [10, 20, 250, 260]);
+        assert_eq!(*sampled_at.lock().unwrap(), [10, 20, 30, 50, 90, 170, 250, 260]);
+
+        // Switching rescans off ends the loop
+        settings.write().await.scanner.scan_interval = 0;
+        tokio::time::timeout(Duration::from_secs(60), task).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_cancels_stuck_connections() {
+        let scope = TaskScope::new();
+        scope.spawn_loop(std::future::pending());
+        scope.spawn_connection(std::future::pending());
+        scope.spawn_connection(async {});
+        tokio::task::yield_now().await;
+
+        scope.shutdown(Duration::from_millis(50)).await;
+        assert!(scope.is_empty());
+    }
+}
@@ -0,0 +1,288 @@
+//! ژورنال رویدادها
+//!
+//! هر [`EngineEvent`] به صورت یک خط JSON با زمان ثبت در `events.jsonl` نوشته
+//! می‌شود. وقتی فایل از `max_size_kb` بزرگ‌تر شود به `events.1.jsonl` و ...
+//! منتقل می‌شود (حداکثر `max_files` فایل). [`replay`] همه فایل‌ها را از
+//! قدیمی‌ترین به جدیدترین می‌خواند و [`JournalFollower`] خطوط جدید را دنبال
+//! می‌کند (`network-ghost events --follow`).
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::broadcast, task::JoinHandle};
+use tracing::{debug, warn};
+
+use crate::types::EngineEvent;
+
+/// پوشه پیش‌فرض ژورنال
+pub const DEFAULT_JOURNAL_DIR: &str = "/opt/network-ghost/logs";
+
+/// نام فایل فعال ژورنال
+const JOURNAL_FILE: &str = "events.jsonl";
+
+// ==================== CONFIG ====================
+
+/// تنظیمات ژورنال (`[journal]`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JournalConfig {
+    /// ثبت رویدادها روی دیسک
+    pub enabled: bool,
+    /// پوشه فایل‌های ژورنال
+    pub dir: String,
+    /// حداکثر اندازه هر فایل (کیلوبایت)
+    pub max_size_kb: u64,
+    /// تعداد فایل‌های نگه‌داشته‌شده (با فایل فعال)
+    pub max_files: usize,
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            dir: DEFAULT_JOURNAL_DIR.to_string(),
+            max_size_kb: 1024,
+            max_files: 5,
+        }
+    }
+}
+
+/// یک خط ژورنال
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// زمان ثبت
+    pub ts: DateTime<Utc>,
+    /// رویداد
+    pub event: EngineEvent,
+}
+
+// ==================== WRITER ====================
+
+/// نویسنده ژورنال با چرخش فایل
+pub struct EventJournal {
+    dir: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl EventJournal {
+    pub fn open(config: &JournalConfig) -> Result<Self> {
+        let dir = PathBuf::from(&config.dir);
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create journal dir {}", dir.display()))?;
+        let file = open_append(&dir.join(JOURNAL_FILE))?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            dir,
+            max_bytes: config.max_size_kb.max(1) * 1024,
+            max_files: config.max_files.max(1),
+            file,
+            size,
+        })
+    }
+
+    /// افزودن یک رویداد (و چرخش فایل در صورت نیاز)
+    pub fn append(&mut self, event: &EngineEvent) -> Result<()> {
+        let entry = JournalEntry { ts: Utc::now(), event: event.clone() };
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+
+        if self.size > 0 && self.size + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(&line).context("Failed to write event journal")?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// events.jsonl → events.1.jsonl → events.2.jsonl ...؛ قدیمی‌ترین حذف می‌شود
+    fn rotate(&mut self) -> Result<()> {
+        let oldest = self.max_files - 1;
+        if oldest == 0 {
+            self.file.set_len(0)?;
+            self.size = 0;
+            return Ok(());
+        }
+        fs::remove_file(rotated_path(&self.dir, oldest)).ok();
+        for n in (1..oldest).rev() {
+            fs::rename(rotated_path(&self.dir, n), rotated_path(&self.dir, n + 1)).ok();
+        }
+        let active = self.dir.join(JOURNAL_FILE);
+        fs::rename(&active, rotated_path(&self.dir, 1))
+            .with_context(|| format!("Failed to rotate {}", active.display()))?;
+        self.file = open_append(&active)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    /// ثبت همه رویدادهای موتور تا بسته شدن کانال
+    pub fn spawn(mut self, mut events: broadcast::Receiver<EngineEvent>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => {
+                        if let Err(e) = self.append(&event) {
+                            warn!("⚠️ {:#}", e);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("⚠️ ژورنال رویداد عقب ماند، {} رویداد ثبت نشد", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))
+}
+
+fn rotated_path(dir: &Path, n: usize) -> PathBuf {
+    dir.join(format!("events.{}.jsonl", n))
+}
+
+// ==================== READER ====================
+
+/// فایل‌های ژورنال از قدیمی‌ترین به جدیدترین
+fn journal_files(dir: &Path) -> Vec<PathBuf> {
+    let mut rotated: Vec<(usize, PathBuf)> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let n = name.strip_prefix("events.")?.strip_suffix(".jsonl")?.parse().ok()?;
+            Some((n, entry.path()))
+        })
+        .collect();
+    rotated.sort_by_key(|(n, _)| std::cmp::Reverse(*n));
+
+    let mut files: Vec<PathBuf> = rotated.into_iter().map(|(_, path)| path).collect();
+    let active = dir.join(JOURNAL_FILE);
+    if active.exists() {
+        files.push(active);
+    }
+    files
+}
+
+/// خواندن همه رویدادهای ثبت‌شده (قدیمی‌ترین اول)؛ خطوط خراب نادیده گرفته می‌شوند
+pub fn replay(dir: impl AsRef<Path>) -> Result<Vec<JournalEntry>> {
+    let mut entries = Vec::new();
+    for path in journal_files(dir.as_ref()) {
+        let file = File::open(&path).with_context(|| format!("Failed to open {}", path.display()))?;
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            match serde_json::from_str(&line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => debug!("{}: skipping malformed line: {}", path.display(), e),
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// دنبال کردن خطوط جدید فایل فعال (مثل `tail -f`، با تشخیص چرخش)
+pub struct JournalFollower {
+    path: PathBuf,
+    inode: Option<u64>,
+    offset: u64,
+    partial: String,
+}
+
+impl JournalFollower {
+    /// شروع از انتهای فعلی فایل
+    pub fn from_end(dir: impl AsRef<Path>) -> Self {
+        let path = dir.as_ref().join(JOURNAL_FILE);
+        let meta = fs::metadata(&path).ok();
+        Self {
+            inode: meta.as_ref().map(|m| m.ino()),
+            offset: meta.map_or(0, |m| m.len()),
+            path,
+            partial: String::new(),
+        }
+    }
+
+    /// رویدادهای اضافه‌شده از آخرین فراخوانی
+    pub fn poll(&mut self) -> Result<Vec<JournalEntry>> {
+        let Ok(mut file) = File::open(&self.path) else {
+            return Ok(Vec::new());
+        };
+        // فایل عوض شده (چرخش): از ابتدای فایل جدید می‌خوانیم
+        let meta = file.metadata()?;
+        if Some(meta.ino()) != self.inode || meta.len() < self.offset {
+            self.inode = Some(meta.ino());
+            self.offset = 0;
+            self.partial.clear();
+        }
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut chunk = String::new();
+        self.offset += file.read_to_string(&mut chunk)? as u64;
+        self.partial.push_str(&chunk);
+
+        let Some(end) = self.partial.rfind('\n') else {
+            return Ok(Vec::new());
+        };
+        let complete: String = self.partial.drain(..=end).collect();
+        Ok(complete.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation_replay_and_follow() {
+        let dir = std::env::temp_dir().join(format!("ghost-journal-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        let config = JournalConfig {
+            dir: dir.display().to_string(),
+            max_size_kb: 1,
+            max_files: 3,
+            ..Default::default()
+        };
+
+        let mut journal = EventJournal::open(&config).unwrap();
+        let mut follower = JournalFollower::from_end(&dir);
+        for count in 0..60 {
+            journal.append(&EngineEvent::ScanCompleted { count }).unwrap();
+        }
+
+        // فقط ۳ فایل آخر می‌مانند، به ترتیب و بدون فاصله
+        assert_eq!(journal_files(&dir).len(), 3);
+        let counts: Vec<usize> = replay(&dir)
+            .unwrap()
+            .into_iter()
+            .map(|e| match e.event {
+                EngineEvent::ScanCompleted { count } => count,
+                other => panic!("unexpected event {:?}", other),
+            })
+            .collect();
+        assert_eq!(counts.last(), Some(&59));
+        assert!(counts.windows(2).all(|w| w[1] == w[0] + 1));
+        assert!(counts.len() < 60);
+
+        // بعد از چرخش، follower از ابتدای فایل جدید ادامه می‌دهد
+        follower.poll().unwrap();
+        journal.append(&EngineEvent::ScanCompleted { count: 60 }).unwrap();
+        let fresh = follower.poll().unwrap();
+        assert!(matches!(fresh.last().map(|e| &e.event), Some(EngineEvent::ScanCompleted { count: 60 })));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -9,9 +9,11 @@ use serde::{Deserialize, Serialize};
 use tracing::{debug, info};
 
 /// Fingerprint Type
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum FingerprintType {
     /// Chrome
+    #[default]
     Chrome,
     /// Firefox
     Firefox,
@@ -25,12 +27,6 @@ pub enum FingerprintType {
     Android,
 }
 
-impl Default for FingerprintType {
-    fn default() -> Self {
-        Self::Chrome
-    }
-}
-
 impl std::fmt::Display for FingerprintType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -44,6 +40,22 @@ impl std::fmt::Display for FingerprintType {
     }
 }
 
+impl std::str::FromStr for FingerprintType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "chrome" => Ok(Self::Chrome),
+            "firefox" => Ok(Self::Firefox),
+            "safari" => Ok(Self::Safari),
+            "edge" => Ok(Self::Edge),
+            "ios" => Ok(Self::Ios),
+            "android" => Ok(Self::Android),
+            other => Err(anyhow::anyhow!("Unknown uTLS fingerprint: {}", other)),
+        }
+    }
+}
+
 /// Fingerprint Data
 #[derive(Debug, Clone)]
 pub struct Fingerprint {
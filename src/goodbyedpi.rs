@@ -19,7 +19,8 @@ use tracing::{debug, info};
 // ── GoodbyeDPI Mode ────────────────────────────────────────────────────────
 
 /// حالت‌های GoodbyeDPI معادل flags اصلی
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum GoodbyeDpiMode {
     /// حالت 1: passive DPI bypass (کوچکترین تأثیر)
     Passive,
@@ -30,15 +31,13 @@ pub enum GoodbyeDpiMode {
     /// حالت 4: complete bypass (همه تکنیک‌ها)
     Complete,
     /// حالت ایرانی (بهینه برای IR-DPI)
+    #[default]
     Iranian,
 }
 
-impl Default for GoodbyeDpiMode {
-    fn default() -> Self { Self::Iranian }
-}
-
 /// تنظیمات GoodbyeDPI
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct GoodbyeDpiConfig {
     pub mode: GoodbyeDpiMode,
     /// فراگمنت‌سازی HTTP request
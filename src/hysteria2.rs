@@ -2,11 +2,13 @@
 //! مخصوص شبکه‌های با تأخیر/loss بالا (مثل ایران)
 
 use anyhow::{Context, Result};
+use async_trait::async_trait;
 use rand::{RngCore, thread_rng};
-use tokio::net::UdpSocket;
 use tokio::time::{timeout, Duration};
 use tracing::{debug, info};
 
+use crate::transport::{BoxedDatagram, DatagramSocket, DatagramTransport};
+
 // ── Hysteria2 Protocol Constants ─────────────────────────────────
 const HY2_VERSION: u8 = 2;
 // Client Hello fields
@@ -62,41 +64,37 @@ impl Default for Hysteria2Config {
 
 /// Hysteria2 Client
 pub struct Hysteria2 {
-    pub socket: Option<UdpSocket>,
     config: Hysteria2Config,
     session_id: u64,
+    /// مقصد پکت‌های UDP (host, port)
+    destination: Option<(String, u16)>,
 }
 
 impl Hysteria2 {
     pub fn new(config: Hysteria2Config) -> Self {
         Self {
-            socket: None,
             config,
             session_id: rand::random(),
+            destination: None,
         }
     }
 
-    pub async fn connect(&mut self, server: &str) -> Result<()> {
-        info!("🚀 اتصال Hysteria2 به {}", server);
-        let socket = UdpSocket::bind("0.0.0.0:0").await.context("UDP bind failed")?;
-        socket.connect(server).await.context("UDP connect failed")?;
-        self.socket = Some(socket);
-        self.do_handshake().await?;
-        info!("✅ Hysteria2 handshake complete (Brutal CC: {}↑/{}↓ Mbps)",
-            self.config.brutal.upload_mbps, self.config.brutal.download_mbps);
-        Ok(())
+    /// مقصد UDP که در هر پکت داده فرستاده می‌شود
+    pub fn with_destination(mut self, host: &str, port: u16) -> Self {
+        self.destination = Some((host.to_string(), port));
+        self
     }
 
-    async fn do_handshake(&mut self) -> Result<()> {
+    async fn do_handshake(&self, socket: &dyn DatagramSocket) -> Result<()> {
         // Client Hello packet
         let hello = self.build_client_hello();
-        let obfs_hello = self.apply_obfs(&hello);
-        self.raw_send(&obfs_hello).await?;
+        let obfs_hello = apply_obfs(&self.config, &hello);
+        timeout(Duration::from_secs(5), socket.send(&obfs_hello)).await??;
 
         // Server Hello
         let mut buf = vec![0u8; 1500];
-        let n = timeout(Duration::from_secs(5), self.raw_recv(&mut buf)).await??;
-        let server_hello = self.remove_obfs(&buf[..n]);
+        let n = timeout(Duration::from_secs(5), socket.recv(&mut buf)).await??;
+        let server_hello = apply_obfs(&self.config, &buf[..n]);
         self.parse_server_hello(&server_hello)?;
 
         debug!("🤝 Hysteria2 handshake successful");
@@ -134,68 +132,71 @@ impl Hysteria2 {
         }
         Ok(())
     }
+}
 
-    /// Salamander XOR obfuscation
-    fn apply_obfs(&self, data: &[u8]) -> Vec<u8> {
-        if self.config.obfs_type == ObfsType::None { return data.to_vec(); }
-        let key = self.config.obfs_password.as_bytes();
-        data.iter().enumerate()
-            .map(|(i, &b)| b ^ key[i % key.len()])
-            .collect()
-    }
+/// Salamander XOR obfuscation (XOR is its own inverse)
+fn apply_obfs(config: &Hysteria2Config, data: &[u8]) -> Vec<u8> {
+    if config.obfs_type == ObfsType::None { return data.to_vec(); }
+    let key = config.obfs_password.as_bytes();
+    data.iter().enumerate()
+        .map(|(i, &b)| b ^ key[i % key.len()])
+        .collect()
+}
 
-    fn remove_obfs(&self, data: &[u8]) -> Vec<u8> {
-        self.apply_obfs(data) // XOR is its own inverse
-    }
+fn build_data_packet(typ: u8, host: &str, port: u16, data: &[u8]) -> Vec<u8> {
+    let mut pkt = Vec::new();
+    pkt.push(typ);
+    // Address: domain
+    pkt.push(HY2_ADDR_DOMAIN);
+    let h = host.as_bytes();
+    pkt.push(h.len() as u8);
+    pkt.extend_from_slice(h);
+    pkt.extend_from_slice(&port.to_be_bytes());
+    pkt.extend_from_slice(data);
+    pkt
+}
 
-    /// ارسال TCP stream request
-    pub async fn send_tcp_request(&mut self, host: &str, port: u16) -> Result<()> {
-        let mut pkt = self.build_data_packet(HY2_TYPE_TCP, host, port, &[]);
-        let obfs = self.apply_obfs(&pkt);
-        self.raw_send(&obfs).await?;
-        debug!("🔗 Hysteria2 TCP → {}:{}", host, port);
-        Ok(())
+#[async_trait]
+impl DatagramTransport for Hysteria2 {
+    fn name(&self) -> &'static str {
+        "Hysteria2"
     }
 
-    /// ارسال UDP request
-    pub async fn send_udp_request(&mut self, host: &str, port: u16, data: &[u8]) -> Result<()> {
-        let pkt = self.build_data_packet(HY2_TYPE_UDP, host, port, data);
-        let obfs = self.apply_obfs(&pkt);
-        self.raw_send(&obfs).await?;
-        Ok(())
-    }
-
-    fn build_data_packet(&self, typ: u8, host: &str, port: u16, data: &[u8]) -> Vec<u8> {
-        let mut pkt = Vec::new();
-        pkt.push(typ);
-        // Address: domain
-        pkt.push(HY2_ADDR_DOMAIN);
-        let h = host.as_bytes();
-        pkt.push(h.len() as u8);
-        pkt.extend_from_slice(h);
-        pkt.extend_from_slice(&port.to_be_bytes());
-        pkt.extend_from_slice(data);
-        pkt
+    async fn connect_over(&self, lower: BoxedDatagram) -> Result<BoxedDatagram> {
+        self.do_handshake(lower.as_ref()).await?;
+        info!("✅ Hysteria2 handshake complete (Brutal CC: {}↑/{}↓ Mbps)",
+            self.config.brutal.upload_mbps, self.config.brutal.download_mbps);
+        Ok(Box::new(Hysteria2Socket {
+            inner: lower,
+            config: self.config.clone(),
+            destination: self.destination.clone(),
+        }))
     }
+}
 
-    /// ارسال داده محافظت‌شده (wrapper عمومی)
-    pub async fn send_protected(&mut self, data: &[u8]) -> Result<()> {
-        let mut pkt = Vec::new();
-        pkt.push(HY2_TYPE_TCP);
-        pkt.extend_from_slice(data);
-        let obfs = self.apply_obfs(&pkt);
-        self.raw_send(&obfs).await
-    }
+/// پکت‌های داده پس از handshake (با obfs)
+struct Hysteria2Socket {
+    inner: BoxedDatagram,
+    config: Hysteria2Config,
+    destination: Option<(String, u16)>,
+}
 
-    async fn raw_send(&mut self, data: &[u8]) -> Result<()> {
-        let sock = self.socket.as_ref().context("No socket")?;
-        timeout(Duration::from_secs(5), sock.send(data)).await??;
-        Ok(())
+#[async_trait]
+impl DatagramSocket for Hysteria2Socket {
+    async fn send(&self, packet: &[u8]) -> Result<()> {
+        let pkt = match &self.destination {
+            Some((host, port)) => build_data_packet(HY2_TYPE_UDP, host, *port, packet),
+            None => [&[HY2_TYPE_UDP][..], packet].concat(),
+        };
+        let obfs = apply_obfs(&self.config, &pkt);
+        timeout(Duration::from_secs(5), self.inner.send(&obfs)).await?
     }
 
-    async fn raw_recv(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let sock = self.socket.as_ref().context("No socket")?;
-        Ok(sock.recv(buf).await?)
+    async fn recv(&self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.recv(buf).await?;
+        let clear = apply_obfs(&self.config, &buf[..n]);
+        buf[..n].copy_from_slice(&clear);
+        Ok(n)
     }
 }
 
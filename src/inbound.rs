@@ -0,0 +1,761 @@
+//! Local Inbounds
+//!
+//! listenerهای محلی که ترافیک کلاینت‌ها را می‌پذیرند و هر اتصال را از طریق
+//! زنجیره پروتکل فعلی (`TunnelDialer`) به مقصد می‌رسانند.
+//!
+//! - SOCKS5 (RFC 1928): دستورهای CONNECT و UDP ASSOCIATE (UDP با
+//!   [`TunnelDialer::dial_udp`]، یعنی پروتکل UDP یا UoT روی زنجیره TCP)
+//! - HTTP/1.1: متد CONNECT و forward درخواست‌های plain-HTTP (absolute-form)
+//! - mixed: با نگاه به بایت اول، SOCKS5 یا HTTP روی یک پورت
+//!
+//! در صورت تنظیم `username`/`password` احراز هویت اجباری است (RFC 1929 برای
+//! SOCKS5 و `Proxy-Authorization: Basic` برای HTTP).
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::mpsc::{self, error::TrySendError},
+    task::JoinSet,
+    time::timeout,
+};
+use base64::Engine as _;
+use tracing::{debug, info, warn};
+
+use crate::{
+    engine::{MeteredStream, TaskScope, TunnelDialer},
+    transport::DatagramSocket,
+    udp_over_tcp::{decode_address, encode_address},
+};
+
+/// تنظیمات `[inbound]`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InboundConfig {
+    /// فعال بودن listenerها
+    pub enabled: bool,
+    /// آدرس listen (برای دسترسی LAN مثلاً `0.0.0.0`)
+    pub listen: IpAddr,
+    /// پورت SOCKS5 (۰ = غیرفعال)
+    pub socks_port: u16,
+    /// پورت HTTP proxy (۰ = غیرفعال)
+    pub http_port: u16,
+    /// پورت mixed (SOCKS5 + HTTP، ۰ = غیرفعال)
+    pub mixed_port: u16,
+    /// نام کاربری (خالی = بدون احراز هویت)
+    pub username: Option<String>,
+    /// رمز عبور
+    pub password: Option<String>,
+}
+
+impl Default for InboundConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            listen: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            socks_port: 1080,
+            http_port: 0,
+            mixed_port: 0,
+            username: None,
+            password: None,
+        }
+    }
+}
+
+impl InboundConfig {
+    /// نام کاربری و رمز در صورت تنظیم هر دو
+    fn credentials(&self) -> Option<(String, String)> {
+        match (&self.username, &self.password) {
+            (Some(user), Some(pass)) => Some((user.clone(), pass.clone())),
+            _ => None,
+        }
+    }
+}
+
+// ==================== RELAY ====================
+
+/// کپی دوطرفه بین کلاینت و تونل (بایت‌ها در [`MeteredStream`] شمرده می‌شوند)
+pub async fn relay(mut client: TcpStream, mut upstream: MeteredStream) -> Result<()> {
+    tokio::io::copy_bidirectional(&mut client, &mut upstream)
+        .await
+        .context("Relay error")?;
+    Ok(())
+}
+
+// ==================== SOCKS5 ====================
+
+const SOCKS_VERSION: u8 = 0x05;
+const AUTH_VERSION: u8 = 0x01;
+
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_USER_PASS: u8 = 0x02;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xFF;
+
+const CMD_CONNECT: u8 = 0x01;
+const CMD_UDP_ASSOCIATE: u8 = 0x03;
+
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+const REPLY_SUCCEEDED: u8 = 0x00;
+const REPLY_HOST_UNREACHABLE: u8 = 0x04;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const REPLY_ADDRESS_NOT_SUPPORTED: u8 = 0x08;
+
+/// بستن جریان UDP یک مقصد پس از این مدت بی‌فعالیتی
+const UDP_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// حداکثر اندازه datagram
+const MAX_DATAGRAM: usize = 65535;
+
+/// نوع listener
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InboundKind {
+    Socks5,
+    Http,
+    /// تشخیص SOCKS5/HTTP از بایت اول
+    Mixed,
+}
+
+impl std::fmt::Display for InboundKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InboundKind::Socks5 => f.write_str("SOCKS5"),
+            InboundKind::Http => f.write_str("HTTP"),
+            InboundKind::Mixed => f.write_str("mixed"),
+        }
+    }
+}
+
+/// سرور inbound
+pub struct InboundServer {
+    kind: InboundKind,
+    addr: SocketAddr,
+    dialer: TunnelDialer,
+    credentials: Option<(String, String)>,
+}
+
+impl InboundServer {
+    pub fn new(kind: InboundKind, addr: SocketAddr, dialer: TunnelDialer, config: &InboundConfig) -> Self {
+        Self { kind, addr, dialer, credentials: config.credentials() }
+    }
+
+    /// listenerهای فعال در `[inbound]`
+    pub fn from_config(dialer: &TunnelDialer, config: &InboundConfig) -> Vec<Self> {
+        if !config.enabled {
+            return Vec::new();
+        }
+        [
+            (InboundKind::Socks5, config.socks_port),
+            (InboundKind::Http, config.http_port),
+            (InboundKind::Mixed, config.mixed_port),
+        ]
+        .into_iter()
+        .filter(|(_, port)| *port != 0)
+        .map(|(kind, port)| {
+            Self::new(kind, SocketAddr::new(config.listen, port), dialer.clone(), config)
+        })
+        .collect()
+    }
+
+    /// گوش دادن و سرویس‌دهی (در پس‌زمینه، تا پایان `scope`)
+    pub async fn spawn(self, scope: &TaskScope) -> Result<()> {
+        let listener = TcpListener::bind(self.addr)
+            .await
+            .with_context(|| format!("Failed to bind {} inbound on {}", self.kind, self.addr))?;
+        info!("🧦 {} inbound: {}", self.kind, self.addr);
+
+        let connections = scope.clone();
+        scope.spawn_loop(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        let kind = self.kind;
+                        let dialer = self.dialer.clone();
+                        let credentials = self.credentials.clone();
+                        connections.spawn_connection(async move {
+                            if let Err(e) = serve(kind, stream, &dialer, credentials.as_ref()).await {
+                                debug!("{} client {} error: {:#}", kind, peer, e);
+                            }
+                        });
+                    }
+                    Err(e) => warn!("⚠️ خطای {} inbound: {}", self.kind, e),
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+async fn serve(
+    kind: InboundKind,
+    stream: TcpStream,
+    dialer: &TunnelDialer,
+    credentials: Option<&(String, String)>,
+) -> Result<()> {
+    match kind {
+        InboundKind::Socks5 => serve_socks5(stream, dialer, credentials).await,
+        InboundKind::Http => serve_http(stream, dialer, credentials).await,
+        InboundKind::Mixed => {
+            let mut first = [0u8; 1];
+            if stream.peek(&mut first).await? == 0 {
+                return Ok(());
+            }
+            if first[0] == SOCKS_VERSION {
+                serve_socks5(stream, dialer, credentials).await
+            } else {
+                serve_http(stream, dialer, credentials).await
+            }
+        }
+    }
+}
+
+async fn serve_socks5(
+    mut stream: TcpStream,
+    dialer: &TunnelDialer,
+    credentials: Option<&(String, String)>,
+) -> Result<()> {
+    let (command, host, port) = handshake(&mut stream, credentials).await?;
+    if command == CMD_UDP_ASSOCIATE {
+        return serve_udp_associate(stream, dialer).await;
+    }
+
+    let upstream = match dialer.dial(&host, port).await {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            dialer.record_connection(true).await;
+            send_reply(&mut stream, REPLY_HOST_UNREACHABLE).await.ok();
+            return Err(e.context(format!("Dial {}:{} failed", host, port)));
+        }
+    };
+    dialer.record_connection(false).await;
+    send_reply(&mut stream, REPLY_SUCCEEDED).await?;
+    debug!("🧦 SOCKS5 → {}:{}", host, port);
+
+    relay(stream, upstream).await
+}
+
+/// SOCKS5 بدون تانل: اتصال مستقیم به مقصد (backend سرور ShadowTLS)
+pub(crate) async fn serve_socks5_direct<S>(mut stream: S) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (command, host, port) = handshake(&mut stream, None).await?;
+    if command != CMD_CONNECT {
+        send_reply(&mut stream, REPLY_COMMAND_NOT_SUPPORTED).await.ok();
+        bail!("SOCKS5 command {} is not supported without a tunnel", command);
+    }
+    let mut upstream = match TcpStream::connect((host.as_str(), port)).await {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            send_reply(&mut stream, REPLY_HOST_UNREACHABLE).await.ok();
+            return Err(anyhow::Error::new(e).context(format!("Dial {}:{} failed", host, port)));
+        }
+    };
+    send_reply(&mut stream, REPLY_SUCCEEDED).await?;
+    debug!("🧦 SOCKS5 (direct) → {}:{}", host, port);
+    tokio::io::copy_bidirectional(&mut stream, &mut upstream).await?;
+    Ok(())
+}
+
+/// مذاکره متد، احراز هویت و خواندن درخواست CONNECT یا UDP ASSOCIATE
+///
+/// دستور و مقصد درخواستی برگردانده می‌شود؛ پاسخ نهایی پس از dial ارسال می‌شود.
+async fn handshake<S>(stream: &mut S, credentials: Option<&(String, String)>) -> Result<(u8, String, u16)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // Greeting: VER NMETHODS METHODS...
+    let mut head = [0u8; 2];
+    stream.read_exact(&mut head).await?;
+    if head[0] != SOCKS_VERSION {
+        bail!("Unsupported SOCKS version {}", head[0]);
+    }
+    let mut methods = vec![0u8; head[1] as usize];
+    stream.read_exact(&mut methods).await?;
+
+    let wanted = if credentials.is_some() { METHOD_USER_PASS } else { METHOD_NO_AUTH };
+    if !methods.contains(&wanted) {
+        stream.write_all(&[SOCKS_VERSION, METHOD_NONE_ACCEPTABLE]).await?;
+        bail!("Client offered no acceptable auth method");
+    }
+    stream.write_all(&[SOCKS_VERSION, wanted]).await?;
+
+    // RFC 1929: VER ULEN UNAME PLEN PASSWD
+    if let Some((user, pass)) = credentials {
+        let mut ver = [0u8; 1];
+        stream.read_exact(&mut ver).await?;
+        if ver[0] != AUTH_VERSION {
+            bail!("Unsupported auth version {}", ver[0]);
+        }
+        let username = read_string(stream).await?;
+        let password = read_string(stream).await?;
+        if &username != user || &password != pass {
+            stream.write_all(&[AUTH_VERSION, 0x01]).await?;
+            bail!("Authentication failed for user {:?}", username);
+        }
+        stream.write_all(&[AUTH_VERSION, 0x00]).await?;
+    }
+
+    // Request: VER CMD RSV ATYP DST.ADDR DST.PORT
+    let mut req = [0u8; 4];
+    stream.read_exact(&mut req).await?;
+    if req[0] != SOCKS_VERSION {
+        bail!("Unsupported SOCKS version {}", req[0]);
+    }
+    let host = match req[3] {
+        ATYP_IPV4 => {
+            let mut octets = [0u8; 4];
+            stream.read_exact(&mut octets).await?;
+            Ipv4Addr::from(octets).to_string()
+        }
+        ATYP_IPV6 => {
+            let mut octets = [0u8; 16];
+            stream.read_exact(&mut octets).await?;
+            Ipv6Addr::from(octets).to_string()
+        }
+        ATYP_DOMAIN => read_string(stream).await?,
+        atyp => {
+            send_reply(stream, REPLY_ADDRESS_NOT_SUPPORTED).await.ok();
+            bail!("Unsupported address type {}", atyp);
+        }
+    };
+    let mut port = [0u8; 2];
+    stream.read_exact(&mut port).await?;
+
+    if req[1] != CMD_CONNECT && req[1] != CMD_UDP_ASSOCIATE {
+        send_reply(stream, REPLY_COMMAND_NOT_SUPPORTED).await.ok();
+        bail!("Unsupported SOCKS command {}", req[1]);
+    }
+    Ok((req[1], host, u16::from_be_bytes(port)))
+}
+
+/// رشته با پیشوند طول یک‌بایتی
+async fn read_string<S: AsyncRead + Unpin>(stream: &mut S) -> Result<String> {
+    let mut len = [0u8; 1];
+    stream.read_exact(&mut len).await?;
+    let mut buf = vec![0u8; len[0] as usize];
+    stream.read_exact(&mut buf).await?;
+    String::from_utf8(buf).context("Invalid UTF-8 in SOCKS5 field")
+}
+
+/// پاسخ SOCKS5 با آدرس bind صفر
+async fn send_reply<S: AsyncWrite + Unpin>(stream: &mut S, code: u8) -> Result<()> {
+    let reply = [SOCKS_VERSION, code, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0];
+    stream.write_all(&reply).await?;
+    Ok(())
+}
+
+// ==================== SOCKS5 UDP ====================
+
+/// UDP ASSOCIATE: رله datagramهای کلاینت تا بسته شدن اتصال کنترلی TCP
+///
+/// هر مقصد یک جریان جدا از [`TunnelDialer::dial_udp`] دارد. فقط datagramهای
+/// همان IP کلاینت پذیرفته می‌شوند و قطعه‌بندی (FRAG) پشتیبانی نمی‌شود.
+async fn serve_udp_associate(mut stream: TcpStream, dialer: &TunnelDialer) -> Result<()> {
+    let socket = Arc::new(UdpSocket::bind(SocketAddr::new(stream.local_addr()?.ip(), 0)).await?);
+    let bound = socket.local_addr()?;
+    let mut reply = vec![SOCKS_VERSION, REPLY_SUCCEEDED, 0x00];
+    encode_address(&bound.ip().to_string(), bound.port(), &mut reply);
+    stream.write_all(&reply).await?;
+
+    let client_ip = stream.peer_addr()?.ip();
+    debug!("🧦 SOCKS5 UDP ASSOCIATE {} ↔ {}", client_ip, bound);
+
+    let control = async {
+        let mut buf = [0u8; 64];
+        while stream.read(&mut buf).await? > 0 {}
+        Ok::<_, anyhow::Error>(())
+    };
+    let relay = async {
+        let mut flows: HashMap<(String, u16), mpsc::Sender<Vec<u8>>> = HashMap::new();
+        // با پایان association همه جریان‌ها لغو می‌شوند
+        let mut tasks = JoinSet::new();
+        let mut buf = vec![0u8; MAX_DATAGRAM];
+        loop {
+            let (n, client) = socket.recv_from(&mut buf).await?;
+            if client.ip() != client_ip {
+                continue;
+            }
+            let Some((host, port, payload)) = parse_udp_request(&buf[..n]) else {
+                continue;
+            };
+
+            let key = (host.clone(), port);
+            let packet = match flows.get(&key) {
+                None => payload.to_vec(),
+                Some(tx) => match tx.try_send(payload.to_vec()) {
+                    Ok(()) | Err(TrySendError::Full(_)) => continue,
+                    Err(TrySendError::Closed(packet)) => packet,
+                },
+            };
+            let (tx, rx) = mpsc::channel(64);
+            let _ = tx.try_send(packet);
+            flows.insert(key, tx);
+            tasks.spawn(udp_flow(dialer.clone(), host, port, rx, socket.clone(), client));
+            while tasks.try_join_next().is_some() {}
+        }
+    };
+
+    tokio::select! {
+        r = control => r,
+        r = relay => r,
+    }
+}
+
+/// هدر datagram کلاینت: `RSV(2) FRAG ATYP DST.ADDR DST.PORT DATA`
+fn parse_udp_request(datagram: &[u8]) -> Option<(String, u16, &[u8])> {
+    if datagram.len() < 4 || datagram[2] != 0 {
+        return None;
+    }
+    let (host, port, len) = decode_address(&datagram[3..]).ok()?;
+    Some((host, port, &datagram[3 + len..]))
+}
+
+/// یک مقصد UDP؛ پاسخ‌ها با هدر SOCKS5 به کلاینت برمی‌گردند
+async fn udp_flow(
+    dialer: TunnelDialer,
+    host: String,
+    port: u16,
+    mut rx: mpsc::Receiver<Vec<u8>>,
+    socket: Arc<UdpSocket>,
+    client: SocketAddr,
+) {
+    let result = async {
+        let upstream = dialer.dial_udp(&host, port).await;
+        dialer.record_connection(upstream.is_err()).await;
+        let upstream = upstream?;
+
+        let upload = async {
+            while let Ok(Some(packet)) = timeout(UDP_IDLE_TIMEOUT, rx.recv()).await {
+                upstream.send(&packet).await?;
+            }
+            Ok::<_, anyhow::Error>(())
+        };
+        let download = async {
+            let mut header = vec![0, 0, 0];
+            encode_address(&host, port, &mut header);
+            let mut buf = vec![0u8; MAX_DATAGRAM];
+            loop {
+                let n = upstream.recv(&mut buf).await?;
+                socket.send_to(&[&header, &buf[..n]].concat(), client).await?;
+            }
+        };
+        tokio::select! {
+            r = upload => r,
+            r = download => r,
+        }
+    }
+    .await;
+
+    if let Err(e) = result {
+        debug!("SOCKS5 UDP {} → {}:{}: {:#}", client, host, port, e);
+    }
+}
+
+// ==================== HTTP ====================
+
+/// حداکثر اندازه هدر درخواست HTTP
+const MAX_HTTP_HEAD: usize = 16 * 1024;
+
+/// درخواست HTTP پردازش‌شده
+#[derive(Debug, PartialEq, Eq)]
+struct HttpRequest {
+    host: String,
+    port: u16,
+    /// `None` برای CONNECT؛ در غیر این صورت هدر بازنویسی‌شده برای سرور مقصد
+    forward_head: Option<Vec<u8>>,
+}
+
+async fn serve_http(
+    mut stream: TcpStream,
+    dialer: &TunnelDialer,
+    credentials: Option<&(String, String)>,
+) -> Result<()> {
+    let (head, body) = read_http_head(&mut stream).await?;
+    let request = match parse_http_request(&head, credentials) {
+        Ok(request) => request,
+        Err(HttpError::AuthRequired) => {
+            stream
+                .write_all(
+                    b"HTTP/1.1 407 Proxy Authentication Required\r\n\
+                      Proxy-Authenticate: Basic realm=\"network-ghost\"\r\n\
+                      Content-Length: 0\r\nConnection: close\r\n\r\n",
+                )
+                .await?;
+            bail!("HTTP proxy authentication failed");
+        }
+        Err(HttpError::BadRequest(reason)) => {
+            stream
+                .write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .await?;
+            bail!("Bad HTTP proxy request: {}", reason);
+        }
+    };
+
+    let mut upstream = match dialer.dial(&request.host, request.port).await {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            dialer.record_connection(true).await;
+            stream
+                .write_all(b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .await
+                .ok();
+            return Err(e.context(format!("Dial {}:{} failed", request.host, request.port)));
+        }
+    };
+    dialer.record_connection(false).await;
+
+    match &request.forward_head {
+        None => {
+            stream
+                .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+                .await?;
+            debug!("🌐 HTTP CONNECT → {}:{}", request.host, request.port);
+        }
+        Some(head) => {
+            upstream.write_all(head).await?;
+            debug!("🌐 HTTP → {}:{}", request.host, request.port);
+        }
+    }
+    if !body.is_empty() {
+        upstream.write_all(&body).await?;
+    }
+
+    relay(stream, upstream).await
+}
+
+/// خواندن تا انتهای هدر؛ بایت‌های اضافه (ابتدای body) جدا برگردانده می‌شوند
+async fn read_http_head<S: AsyncRead + Unpin>(stream: &mut S) -> Result<(String, Vec<u8>)> {
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            bail!("Connection closed before end of HTTP headers");
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            let body = buf.split_off(end + 4);
+            let head = String::from_utf8(buf).context("Invalid UTF-8 in HTTP headers")?;
+            return Ok((head, body));
+        }
+        if buf.len() > MAX_HTTP_HEAD {
+            bail!("HTTP headers larger than {} bytes", MAX_HTTP_HEAD);
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum HttpError {
+    AuthRequired,
+    BadRequest(&'static str),
+}
+
+/// پردازش هدر درخواست proxy و بازنویسی آن برای forward
+fn parse_http_request(
+    head: &str,
+    credentials: Option<&(String, String)>,
+) -> std::result::Result<HttpRequest, HttpError> {
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(HttpError::BadRequest("malformed request line"));
+    };
+    let headers: Vec<(&str, &str)> = lines
+        .filter(|l| !l.is_empty())
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim(), v.trim()))
+        .collect();
+
+    if let Some((user, pass)) = credentials {
+        let expected = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, pass));
+        let authorized = headers.iter().any(|(k, v)| {
+            k.eq_ignore_ascii_case("proxy-authorization")
+                && v.strip_prefix("Basic ").map(str::trim) == Some(expected.as_str())
+        });
+        if !authorized {
+            return Err(HttpError::AuthRequired);
+        }
+    }
+
+    if method.eq_ignore_ascii_case("CONNECT") {
+        let (host, port) = split_host_port(target, 443).ok_or(HttpError::BadRequest("bad CONNECT target"))?;
+        return Ok(HttpRequest { host, port, forward_head: None });
+    }
+
+    // absolute-form: http://host[:port]/path
+    let rest = target
+        .strip_prefix("http://")
+        .ok_or(HttpError::BadRequest("only absolute http:// URLs can be forwarded"))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = split_host_port(authority, 80).ok_or(HttpError::BadRequest("bad URL host"))?;
+
+    // یک درخواست برای هر اتصال: keep-alive به مقصدهای مختلف ممکن نیست
+    let mut forward = format!("{} {} {}\r\n", method, path, version);
+    if !headers.iter().any(|(k, _)| k.eq_ignore_ascii_case("host")) {
+        forward.push_str(&format!("Host: {}\r\n", authority));
+    }
+    for (key, value) in &headers {
+        let hop_by_hop = ["proxy-authorization", "proxy-connection", "connection", "keep-alive"];
+        if !hop_by_hop.iter().any(|h| key.eq_ignore_ascii_case(h)) {
+            forward.push_str(&format!("{}: {}\r\n", key, value));
+        }
+    }
+    forward.push_str("Connection: close\r\n\r\n");
+
+    Ok(HttpRequest { host, port, forward_head: Some(forward.into_bytes()) })
+}
+
+/// `host:port` یا `[v6]:port`، با پورت پیش‌فرض
+fn split_host_port(authority: &str, default_port: u16) -> Option<(String, u16)> {
+    if let Some(rest) = authority.strip_prefix('[') {
+        let (host, tail) = rest.split_once(']')?;
+        let port = match tail.strip_prefix(':') {
+            Some(port) => port.parse().ok()?,
+            None => default_port,
+        };
+        return Some((host.to_string(), port));
+    }
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().ok()?),
+        None => (authority, default_port),
+    };
+    (!host.is_empty()).then(|| (host.to_string(), port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_socks5_handshake() {
+        let credentials = ("ghost".to_string(), "secret".to_string());
+        let (mut client, mut server) = tokio::io::duplex(256);
+
+        let server = tokio::spawn(async move { handshake(&mut server, Some(&credentials)).await });
+
+        client.write_all(&[0x05, 0x02, 0x00, 0x02]).await.unwrap();
+        let mut choice = [0u8; 2];
+        client.read_exact(&mut choice).await.unwrap();
+        assert_eq!(choice, [0x05, METHOD_USER_PASS]);
+
+        client.write_all(b"\x01\x05ghost\x06secret").await.unwrap();
+        let mut status = [0u8; 2];
+        client.read_exact(&mut status).await.unwrap();
+        assert_eq!(status, [0x01, 0x00]);
+
+        client.write_all(b"\x05\x01\x00\x03\x0bexample.com\x01\xbb").await.unwrap();
+        let (command, host, port) = server.await.unwrap().unwrap();
+        assert_eq!((command, host.as_str(), port), (CMD_CONNECT, "example.com", 443));
+
+        // wrong password is rejected
+        let credentials = ("ghost".to_string(), "secret".to_string());
+        let (mut client, mut server) = tokio::io::duplex(256);
+        let server = tokio::spawn(async move { handshake(&mut server, Some(&credentials)).await });
+        client.write_all(&[0x05, 0x01, 0x02]).await.unwrap();
+        client.read_exact(&mut choice).await.unwrap();
+        client.write_all(b"\x01\x05ghost\x05wrong").await.unwrap();
+        client.read_exact(&mut status).await.unwrap();
+        assert_eq!(status, [0x01, 0x01]);
+        assert!(server.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_socks5_connect_relays_through_the_tunnel() {
+        let private_key = x25519_dalek::StaticSecret::from(rand::random::<[u8; 32]>());
+        let public_key = hex::encode(x25519_dalek::PublicKey::from(&private_key).as_bytes());
+        let server = crate::reality::tests::relay_server(private_key).await;
+        let engine = crate::engine::tests::engine_through(server, &public_key).await;
+
+        let destination = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let destination_port = destination.local_addr().unwrap().port();
+        let received = tokio::spawn(async move {
+            let (mut socket, _) = destination.accept().await.unwrap();
+            let mut buf = [0u8; 5];
+            socket.read_exact(&mut buf).await.unwrap();
+            socket.write_all(b"world").await.unwrap();
+            buf
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let inbound = listener.local_addr().unwrap();
+        let dialer = engine.dialer();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            serve(InboundKind::Socks5, stream, &dialer, None).await
+        });
+
+        let mut client = TcpStream::connect(inbound).await.unwrap();
+        client.write_all(&[SOCKS_VERSION, 0x01, METHOD_NO_AUTH]).await.unwrap();
+        let mut choice = [0u8; 2];
+        client.read_exact(&mut choice).await.unwrap();
+        assert_eq!(choice, [SOCKS_VERSION, METHOD_NO_AUTH]);
+
+        let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0x00, ATYP_IPV4, 127, 0, 0, 1];
+        request.extend_from_slice(&destination_port.to_be_bytes());
+        client.write_all(&request).await.unwrap();
+        let mut reply = [0u8; 10];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[1], REPLY_SUCCEEDED);
+
+        client.write_all(b"hello").await.unwrap();
+        let mut answer = [0u8; 5];
+        client.read_exact(&mut answer).await.unwrap();
+        assert_eq!(&answer, b"world");
+        assert_eq!(&received.await.unwrap(), b"hello");
+
+        let stats = crate::engine::tests::sampled_stats(&engine).await;
+        assert_eq!((stats.tx_bytes, stats.rx_bytes, stats.connections), (5, 5, 1));
+        assert_eq!(stats.by_ip[&server.ip()].tx_bytes, 5);
+        assert_eq!(stats.by_protocol["Reality"].rx_bytes, 5);
+    }
+
+    #[test]
+    fn test_parse_udp_request() {
+        let datagram = b"\x00\x00\x00\x01\x01\x01\x01\x01\x00\x35query";
+        assert_eq!(parse_udp_request(datagram), Some(("1.1.1.1".to_string(), 53, &b"query"[..])));
+        // fragmented datagrams are dropped
+        assert_eq!(parse_udp_request(b"\x00\x00\x01\x01\x01\x01\x01\x01\x00\x35query"), None);
+    }
+
+    #[test]
+    fn test_parse_http_request() {
+        let connect = "CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n";
+        assert_eq!(
+            parse_http_request(connect, None),
+            Ok(HttpRequest { host: "example.com".to_string(), port: 443, forward_head: None })
+        );
+
+        let credentials = ("ghost".to_string(), "secret".to_string());
+        assert_eq!(parse_http_request(connect, Some(&credentials)), Err(HttpError::AuthRequired));
+
+        let get = "GET http://[::1]:8080/status?q=1 HTTP/1.1\r\nHost: [::1]:8080\r\n\
+                   Proxy-Authorization: Basic Z2hvc3Q6c2VjcmV0\r\nProxy-Connection: keep-alive\r\n\
+                   Accept: */*\r\n";
+        let request = parse_http_request(get, Some(&credentials)).unwrap();
+        assert_eq!((request.host.as_str(), request.port), ("::1", 8080));
+        assert_eq!(
+            String::from_utf8(request.forward_head.unwrap()).unwrap(),
+            "GET /status?q=1 HTTP/1.1\r\nHost: [::1]:8080\r\nAccept: */*\r\nConnection: close\r\n\r\n"
+        );
+
+        assert!(matches!(
+            parse_http_request("GET /relative HTTP/1.1\r\n", None),
+            Err(HttpError::BadRequest(_))
+        ));
+    }
+}
@@ -0,0 +1,210 @@
+//! استخر IPهای تمیز
+//!
+//! نتایج اسکن را رتبه‌بندی می‌کند (اول پشتیبانی از Fragmentation، بعد امتیاز
+//! کیفیت)، IPهای خراب را برای مدتی قرنطینه می‌کند و برای failover به صورت
+//! چرخشی (round-robin) بین IPهای باقی‌مانده می‌چرخد. نتایج اسکن در
+//! [`IpCache`] روی دیسک می‌مانند تا بعد از راه‌اندازی مجدد دوباره اسکن نشوند.
+
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    net::IpAddr,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::types::ScanResult;
+
+/// مدت قرنطینه پیش‌فرض یک IP خراب
+pub const DEFAULT_QUARANTINE: Duration = Duration::from_secs(300);
+
+/// مسیر پیش‌فرض کش IPهای تمیز
+pub const DEFAULT_CACHE_PATH: &str = "/opt/network-ghost/cache/clean_ips.json";
+
+/// استخر رتبه‌بندی‌شده IPهای تمیز
+#[derive(Debug, Clone)]
+pub struct IpPool {
+    /// بهترین IP اول
+    ranked: Vec<ScanResult>,
+    /// جایگاه آخرین IP انتخاب‌شده (برای چرخش)
+    cursor: usize,
+    /// IPهای قرنطینه و زمان آزاد شدن آن‌ها
+    quarantine: HashMap<IpAddr, Instant>,
+    quarantine_for: Duration,
+}
+
+impl Default for IpPool {
+    fn default() -> Self {
+        Self::new(DEFAULT_QUARANTINE)
+    }
+}
+
+impl IpPool {
+    pub fn new(quarantine_for: Duration) -> Self {
+        Self {
+            ranked: Vec::new(),
+            cursor: 0,
+            quarantine: HashMap::new(),
+            quarantine_for,
+        }
+    }
+
+    /// جایگزینی با نتایج اسکن جدید (قرنطینه‌ها حفظ می‌شوند)
+    pub fn replace(&mut self, mut results: Vec<ScanResult>) {
+        results.sort_by(rank);
+        self.ranked = results;
+        self.cursor = 0;
+    }
+
+    /// IPها به ترتیب رتبه
+    pub fn ranked(&self) -> &[ScanResult] {
+        &self.ranked
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranked.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranked.is_empty()
+    }
+
+    /// بهترین IP خارج از قرنطینه
+    pub fn best(&mut self) -> Option<ScanResult> {
+        let index = (0..self.ranked.len()).find(|&i| !self.is_quarantined(self.ranked[i].ip))?;
+        self.cursor = index;
+        Some(self.ranked[index].clone())
+    }
+
+    /// IP بعدی به صورت چرخشی، به جز `current` و IPهای قرنطینه
+    pub fn next_after(&mut self, current: Option<IpAddr>) -> Option<ScanResult> {
+        let len = self.ranked.len();
+        let index = (1..=len)
+            .map(|step| (self.cursor + step) % len)
+            .find(|&i| {
+                let ip = self.ranked[i].ip;
+                Some(ip) != current && !self.is_quarantined(ip)
+            })?;
+        self.cursor = index;
+        Some(self.ranked[index].clone())
+    }
+
+    /// قرنطینه کردن یک IP خراب
+    pub fn quarantine(&mut self, ip: IpAddr) {
+        self.quarantine.insert(ip, Instant::now() + self.quarantine_for);
+    }
+
+    pub fn is_quarantined(&self, ip: IpAddr) -> bool {
+        self.quarantine.get(&ip).is_some_and(|until| Instant::now() < *until)
+    }
+
+    /// تعداد IPهای قابل استفاده (خارج از قرنطینه)
+    pub fn available(&self) -> usize {
+        self.ranked.iter().filter(|r| !self.is_quarantined(r.ip)).count()
+    }
+}
+
+/// کش IPهای تمیز روی دیسک (مشترک بین تانل، `scan`، `gen-dae` و باینری scanner)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IpCache {
+    pub results: Vec<ScanResult>,
+}
+
+impl IpCache {
+    /// بارگذاری از فایل (نبود فایل = کش خالی)
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read clean IP cache {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Invalid clean IP cache {}", path.display()))
+    }
+
+    /// ذخیره در فایل
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).ok();
+        }
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
+
+    /// جدا کردن نتایج تازه از نتایجی که `last_tested` آن‌ها از `ttl` گذشته است
+    pub fn split_stale(self, ttl: Duration) -> (Vec<ScanResult>, Vec<ScanResult>) {
+        let ttl = chrono::Duration::from_std(ttl).unwrap_or(chrono::Duration::MAX);
+        let now = chrono::Utc::now();
+        self.results.into_iter().partition(|r| now - r.last_tested < ttl)
+    }
+}
+
+/// اول IPهای دارای Fragmentation، بعد امتیاز کیفیت بالاتر
+fn rank(a: &ScanResult, b: &ScanResult) -> Ordering {
+    b.supports_fragmentation
+        .cmp(&a.supports_fragmentation)
+        .then_with(|| b.quality_score.partial_cmp(&a.quality_score).unwrap_or(Ordering::Equal))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(ip: &str, quality_score: f32) -> ScanResult {
+        ScanResult {
+            ip: ip.parse().unwrap(),
+            port: 443,
+            latency_ms: 50,
+            tls_valid: true,
+            is_clean: true,
+            supports_fragmentation: true,
+            cdn_type: Default::default(),
+            quality_score,
+            last_tested: chrono::Utc::now(),
+            tls_fingerprint: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_round_robin_skips_quarantined() {
+        let mut pool = IpPool::default();
+        pool.replace(vec![result("1.1.1.3", 0.5), result("1.1.1.1", 0.9), result("1.1.1.2", 0.7)]);
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        let best = pool.best().unwrap();
+        assert_eq!(best.ip, ip("1.1.1.1"));
+
+        pool.quarantine(best.ip);
+        assert_eq!(pool.next_after(Some(best.ip)).unwrap().ip, ip("1.1.1.2"));
+        assert_eq!(pool.next_after(Some(ip("1.1.1.2"))).unwrap().ip, ip("1.1.1.3"));
+        // چرخش به ابتدای لیست، بدون IP قرنطینه
+        assert_eq!(pool.next_after(Some(ip("1.1.1.3"))).unwrap().ip, ip("1.1.1.2"));
+
+        pool.quarantine(ip("1.1.1.2"));
+        assert!(pool.next_after(Some(ip("1.1.1.3"))).is_none());
+        assert_eq!(pool.available(), 1);
+    }
+
+    #[test]
+    fn test_cache_round_trip_and_ttl() {
+        let path = std::env::temp_dir().join(format!("ghost-clean-ips-{}.json", std::process::id()));
+        let mut old = result("1.1.1.2", 0.7);
+        old.last_tested -= chrono::Duration::hours(2);
+        IpCache { results: vec![result("1.1.1.1", 0.9), old] }.save(&path).unwrap();
+
+        let cache = IpCache::load(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let (fresh, stale) = cache.split_stale(Duration::from_secs(3600));
+        assert_eq!(fresh.len(), 1);
+        assert_eq!(stale[0].ip, "1.1.1.2".parse::<IpAddr>().unwrap());
+    }
+}
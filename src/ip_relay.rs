@@ -6,16 +6,18 @@
 
 use std::net::{IpAddr, SocketAddr};
 use anyhow::{Context, Result};
+use async_trait::async_trait;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
+    io::AsyncWriteExt,
     time::{timeout, Duration},
 };
 use tracing::{debug, info, warn};
 use rand::seq::SliceRandom;
 
+use crate::transport::{read_http_head, BoxedStream, Dialer, TcpDialer, Transport};
+
 /// حداکثر تعداد hop
-const MAX_HOPS: usize = 5;
+pub const MAX_HOPS: usize = 5;
 const RELAY_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// یک گره در زنجیره relay
@@ -62,16 +64,17 @@ impl Default for RelayConfig {
 }
 
 /// HTTP CONNECT Relay Chain
+///
+/// جریان زیرین باید به `nodes[0]` وصل باشد؛ هر hop بعدی با HTTP CONNECT روی
+/// همان جریان باز می‌شود.
 pub struct IpRelayChain {
     nodes: Vec<RelayNode>,
-    stream: Option<TcpStream>,
     config: RelayConfig,
-    active_hops: usize,
 }
 
 impl IpRelayChain {
     pub fn new(config: RelayConfig) -> Self {
-        Self { nodes: Vec::new(), stream: None, config, active_hops: 0 }
+        Self { nodes: Vec::new(), config }
     }
 
     /// افزودن یک node به زنجیره
@@ -103,92 +106,77 @@ impl IpRelayChain {
         info!("🔗 IP-Relay chain built: {} hops", self.nodes.len());
     }
 
-    /// برقراری اتصال relay زنجیره‌ای
-    pub async fn connect(&mut self) -> Result<()> {
-        if self.nodes.is_empty() {
-            return Err(anyhow::anyhow!("No relay nodes defined"));
-        }
+    /// اولین hop زنجیره (جایی که جریان پایه باید به آن وصل شود)
+    pub fn entry(&self) -> Option<&RelayNode> {
+        self.nodes.first()
+    }
 
-        info!("⛓️ برقراری IP-Relay chain ({} hops)...", self.nodes.len());
+    pub fn hop_count(&self) -> usize { self.nodes.len() }
 
-        // اتصال اول به node[0]
-        let first = &self.nodes[0];
-        let stream = timeout(RELAY_TIMEOUT, TcpStream::connect(first.addr()))
-            .await
-            .context("Relay hop #1 timeout")?
-            .context("Relay hop #1 TCP failed")?;
+    /// دریافت نمای کلی زنجیره (برای لاگ)
+    pub fn chain_summary(&self) -> String {
+        self.nodes.iter().enumerate()
+            .map(|(i, n)| format!("[{}] {}:{} ({})", i + 1, n.ip, n.port, n.cdn_type))
+            .collect::<Vec<_>>()
+            .join(" → ")
+    }
+}
 
-        self.stream = Some(stream);
-        self.active_hops = 1;
-        debug!("✅ Hop #1: {}", first.ip);
+/// ایجاد تانل HTTP CONNECT به hop بعدی
+async fn tunnel_to_next(stream: &mut BoxedStream, next: &RelayNode, hop_num: usize) -> Result<()> {
+    let connect_req = format!(
+        "CONNECT {}:{} HTTP/1.1\r\nHost: {}:{}\r\nProxy-Connection: Keep-Alive\r\n\r\n",
+        next.ip, next.port, next.ip, next.port
+    );
 
-        // پیمایش زنجیره: هر hop را با HTTP CONNECT به بعدی متصل کن
-        let relay_nodes: Vec<_> = self.nodes[1..].to_vec();
-        for (i, node) in relay_nodes.iter().enumerate() {
-            self.tunnel_to_next(node, i + 2).await?;
-        }
+    stream.write_all(connect_req.as_bytes()).await
+        .context(format!("Relay hop #{} CONNECT write failed", hop_num))?;
+
+    // خواندن پاسخ HTTP 200
+    let resp = timeout(RELAY_TIMEOUT, read_http_head(stream)).await
+        .context(format!("Relay hop #{} response timeout", hop_num))??;
 
-        info!("✅ IP-Relay chain active: {} hops", self.active_hops);
-        Ok(())
+    if !resp.contains("200") {
+        return Err(anyhow::anyhow!(
+            "Relay hop #{} rejected: {}", hop_num, resp.lines().next().unwrap_or("")
+        ));
     }
 
-    /// ایجاد تانل HTTP CONNECT به hop بعدی
-    async fn tunnel_to_next(&mut self, next: &RelayNode, hop_num: usize) -> Result<()> {
-        let connect_req = format!(
-            "CONNECT {}:{} HTTP/1.1\r\nHost: {}:{}\r\nProxy-Connection: Keep-Alive\r\n\r\n",
-            next.ip, next.port, next.ip, next.port
-        );
-
-        let stream = self.stream.as_mut().context("No stream")?;
-        stream.write_all(connect_req.as_bytes()).await
-            .context(format!("Relay hop #{} CONNECT write failed", hop_num))?;
-
-        // خواندن پاسخ HTTP 200
-        let mut buf = vec![0u8; 512];
-        let n = timeout(RELAY_TIMEOUT, stream.read(&mut buf)).await?
-            .context(format!("Relay hop #{} response timeout", hop_num))?;
-
-        let resp = String::from_utf8_lossy(&buf[..n]);
-        if !resp.contains("200") {
-            return Err(anyhow::anyhow!(
-                "Relay hop #{} rejected: {}", hop_num, resp.lines().next().unwrap_or("")
-            ));
-        }
+    debug!("✅ Hop #{}: {} ({})", hop_num, next.ip, next.cdn_type);
+    Ok(())
+}
 
-        self.active_hops += 1;
-        debug!("✅ Hop #{}: {} ({})", hop_num, next.ip, next.cdn_type);
-        Ok(())
+#[async_trait]
+impl Transport for IpRelayChain {
+    fn name(&self) -> &'static str {
+        "IP-Relay"
     }
 
-    /// ارسال داده از طریق زنجیره
-    pub async fn send(&mut self, data: &[u8]) -> Result<()> {
-        let stream = self.stream.as_mut().context("No active relay chain")?;
-        stream.write_all(data).await.context("Relay send failed")?;
-        Ok(())
-    }
+    async fn connect_over(&self, mut lower: BoxedStream) -> Result<BoxedStream> {
+        if self.nodes.is_empty() {
+            return Err(anyhow::anyhow!("No relay nodes defined"));
+        }
 
-    /// دریافت داده از طریق زنجیره
-    pub async fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let stream = self.stream.as_mut().context("No active relay chain")?;
-        let n = timeout(RELAY_TIMEOUT, stream.read(buf)).await??;
-        Ok(n)
-    }
+        // پیمایش زنجیره: هر hop را با HTTP CONNECT به بعدی متصل کن
+        for (i, node) in self.nodes.iter().enumerate().skip(1) {
+            tunnel_to_next(&mut lower, node, i + 1).await?;
+        }
 
-    /// بستن زنجیره
-    pub async fn close(&mut self) {
-        if let Some(stream) = self.stream.take() { drop(stream); }
-        self.active_hops = 0;
-        info!("🔌 IP-Relay chain closed");
+        info!("✅ IP-Relay chain active: {} hops", self.nodes.len());
+        Ok(lower)
     }
+}
 
-    pub fn hop_count(&self) -> usize { self.active_hops }
-    pub fn is_active(&self) -> bool { self.stream.is_some() }
+/// برقراری اتصال relay زنجیره‌ای از hop اول
+#[async_trait]
+impl Dialer for IpRelayChain {
+    async fn dial(&self) -> Result<BoxedStream> {
+        let first = self.entry().context("No relay nodes defined")?;
+        info!("⛓️ برقراری IP-Relay chain ({} hops)...", self.nodes.len());
 
-    /// دریافت نمای کلی زنجیره (برای لاگ)
-    pub fn chain_summary(&self) -> String {
-        self.nodes.iter().enumerate()
-            .map(|(i, n)| format!("[{}] {}:{} ({})", i + 1, n.ip, n.port, n.cdn_type))
-            .collect::<Vec<_>>()
-            .join(" → ")
+        let stream = TcpDialer::new(first.addr()).dial().await
+            .context("Relay hop #1 TCP failed")?;
+        debug!("✅ Hop #1: {}", first.ip);
+        self.connect_over(stream).await
     }
 }
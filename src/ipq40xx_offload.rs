@@ -8,9 +8,14 @@ use tracing::{debug, info, warn};
 
 /// IPQ40xx hardware offload configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Ipq40xxConfig {
     /// Enable hardware offload
     pub enable_hw_offload: bool,
+    /// Enable BBR congestion control
+    pub enable_bbr: bool,
+    /// Enable the eBPF JIT compiler
+    pub enable_ebpf_jit: bool,
     /// Enable CPU core affinity pinning
     pub enable_core_affinity: bool,
     /// Maximum TCP socket buffer (KB)
@@ -29,6 +34,8 @@ impl Default for Ipq40xxConfig {
     fn default() -> Self {
         Self {
             enable_hw_offload: true,
+            enable_bbr: true,
+            enable_ebpf_jit: true,
             enable_core_affinity: true,
             max_tcp_buffer_kb: 64,
             max_udp_buffer_kb: 32,
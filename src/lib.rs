@@ -10,6 +10,10 @@
 pub mod types;
 pub mod engine;
 pub mod config;
+pub mod config_check;
+pub mod profile;
+pub mod protocol_probe;
+pub mod share_link;
 
 // ── Anti-DPI & Bypass ────────────────────────────────────────────────────────
 pub mod anti_ai_dpi;
@@ -19,6 +23,8 @@ pub mod packet_padding;
 pub mod smart_detector;
 
 // ── Protocols ────────────────────────────────────────────────────────────────
+pub mod transport;
+pub mod tls;
 pub mod shadowtls;
 pub mod reality;
 pub mod hysteria2;
@@ -26,6 +32,7 @@ pub mod tuic;
 pub mod masque;
 pub mod xhttp;
 pub mod smux;
+pub mod udp_over_tcp;
 pub mod matryoshka;
 pub mod ip_relay;
 pub mod warp_client;
@@ -33,10 +40,17 @@ pub mod websocket_transport;
 
 // ── Infrastructure ───────────────────────────────────────────────────────────
 pub mod scanner;
+pub mod ip_pool;
+pub mod traffic;
 pub mod fingerprint;
 pub mod port_hopper;
 pub mod circuit_breaker;
 pub mod dns_over_quic;
+pub mod inbound;
+pub mod tproxy;
+pub mod control;
+pub mod event_journal;
+pub mod subscription;
 pub mod multicdn;
 pub mod dae_generator;
 pub mod ipq40xx_offload;
@@ -9,22 +9,30 @@
 #![allow(unused_imports)]
 #![allow(dead_code)]
 
-use std::path::PathBuf;
-use libc;
-use anyhow::Result;
+use std::{path::PathBuf, sync::Arc};
+use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
+use serde_json::{json, Value};
 use tracing::{error, info, warn};
 use tracing_subscriber::{EnvFilter, fmt};
 
 use network_ghost_v5::{
     NetworkGhostEngine,
-    types::{ProxyConfig, ProtocolType, CdnType},
+    config::{GhostConfig, DEFAULT_CONFIG_PATH},
+    control::{ControlClient, ControlServer, DEFAULT_SOCKET_PATH},
+    engine::ConfigReloadReport,
+    event_journal::{self, EventJournal},
+    config_check::{check_config_file, load_checked, Severity},
+    share_link::{self, ShareLink},
+    shadowtls::{ShadowTlsBackend, ShadowTlsServer, ShadowTlsServerConfig},
+    profile::{self, UplinkWatcher},
+    types::{ProxyConfig, ProtocolType, CdnType, EngineEvent, TunnelState},
     anti_ai_dpi::{AntiAiDpi, AntiAiMode},
 };
 
 // ── CLI ──────────────────────────────────────────────────────────────────────
 
-#[derive(Debug, Parser)]
+#[derive(Debug, Clone, Parser)]
 #[command(
     name = "network-ghost",
     version = "5.0.0",
@@ -34,28 +42,32 @@ use network_ghost_v5::{
 )]
 struct Cli {
     /// فایل پیکربندی
-    #[arg(short, long, default_value = "/opt/network-ghost/config/config.toml")]
+    #[arg(short, long, default_value = DEFAULT_CONFIG_PATH)]
     config: PathBuf,
 
     /// سطح لاگ (trace, debug, info, warn, error)
     #[arg(short, long, default_value = "info")]
     log_level: String,
 
-    /// حالت Anti-AI DPI (normal, aggressive, stealth, adaptive, ghost)
-    #[arg(long, default_value = "ghost")]
-    dpi_mode: String,
+    /// پروفایل اپراتور از [profiles] (یا auto برای انتخاب بر اساس ASN/gateway) — جایگزین [general].profile
+    #[arg(long)]
+    profile: Option<String>,
 
-    /// پروتکل (shadowtls, reality, hysteria2, tuic, masque, xhttp, auto)
-    #[arg(short, long, default_value = "auto")]
-    protocol: String,
+    /// حالت Anti-AI DPI (normal, aggressive, stealth, adaptive, ghost) — جایگزین [anti_ai].mode
+    #[arg(long)]
+    dpi_mode: Option<String>,
 
-    /// CDN پیش‌فرض (cloudflare, gcore, fastly, arvancloud)
-    #[arg(long, default_value = "cloudflare")]
-    cdn: String,
+    /// پروتکل (shadowtls, reality, hysteria2, tuic, masque, xhttp, auto) — جایگزین [proxy].protocol
+    #[arg(short, long)]
+    protocol: Option<String>,
 
-    /// SNI برای ShadowTLS (پیش‌فرض: بانک ایرانی)
-    #[arg(long, default_value = "ebanking.bmi.ir")]
-    sni: String,
+    /// CDN (cloudflare, gcore, fastly, arvancloud) — جایگزین [proxy].cdn_type
+    #[arg(long)]
+    cdn: Option<String>,
+
+    /// SNI برای ShadowTLS — جایگزین [proxy].sni
+    #[arg(long)]
+    sni: Option<String>,
 
     /// UUID برای VLESS/Reality
     #[arg(long)]
@@ -65,17 +77,21 @@ struct Cli {
     #[arg(long)]
     public_key: Option<String>,
 
-    /// حداکثر تأخیر مجاز (ms)
-    #[arg(long, default_value = "300")]
-    max_latency: u64,
+    /// حداکثر تأخیر مجاز (ms) — جایگزین [proxy].max_latency_ms
+    #[arg(long)]
+    max_latency: Option<u64>,
+
+    /// تعداد حداکثر IPهای اسکن — جایگزین [scanner].max_ips
+    #[arg(long)]
+    max_scan: Option<usize>,
 
-    /// تعداد حداکثر IPهای اسکن
-    #[arg(long, default_value = "100")]
-    max_scan: usize,
+    /// فعال‌سازی Port Hopping (true/false) — جایگزین [proxy].enable_port_hopping
+    #[arg(long)]
+    port_hopping: Option<bool>,
 
-    /// فعال‌سازی Port Hopping
-    #[arg(long, default_value = "true")]
-    port_hopping: bool,
+    /// سوکت کنترل daemon
+    #[arg(long, default_value = DEFAULT_SOCKET_PATH)]
+    socket: PathBuf,
 
     /// دستور
     #[command(subcommand)]
@@ -100,8 +116,42 @@ enum Commands {
     },
     /// نمایش وضعیت فعلی
     Status,
+    /// اسکن مجدد IPهای تمیز در daemon در حال اجرا
+    Rescan,
+    /// تعویض فوری IP تانل
+    SwitchIp,
+    /// بارگذاری مجدد فایل پیکربندی در daemon در حال اجرا (مثل SIGHUP)
+    ReloadConfig,
+    /// تغییر حالت Anti-AI DPI در حین اجرا
+    SetDpiMode {
+        /// normal, aggressive, stealth, adaptive, ghost
+        mode: String,
+    },
+    /// رویدادهای ثبت‌شده در ژورنال (/opt/network-ghost/logs/events.jsonl)
+    Events {
+        /// پس از نمایش، رویدادهای جدید را هم دنبال کن
+        #[arg(short, long)]
+        follow: bool,
+        /// فقط رویدادهای بعد از این زمان (RFC 3339، مثلاً 2026-10-17T03:00:00+03:30)
+        #[arg(long)]
+        since: Option<String>,
+    },
     /// تست اتصال
     Test,
+    /// عیب‌یابی زنجیره Matryoshka لایه به لایه (زمان، سربار و محل شکست)
+    TraceChain {
+        /// IP سرور (پیش‌فرض: بهترین IP تمیز)
+        #[arg(long)]
+        ip: Option<std::net::IpAddr>,
+        /// خروجی JSON به جای جدول
+        #[arg(long)]
+        json: bool,
+    },
+    /// اجرای سرور (برای میزبانی شخصی و تست محلی زنجیره)
+    Serve {
+        #[command(subcommand)]
+        server: ServeCommand,
+    },
     /// تولید پیکربندی DAE (eBPF)
     GenDae {
         #[arg(long, default_value = "/etc/dae/config.dae")]
@@ -111,6 +161,53 @@ enum Commands {
     InstallHiddify,
     /// نمایش اطلاعات پروتکل‌ها
     Info,
+    /// اعتبارسنجی فایل پیکربندی (در صورت خطا کد خروج غیر صفر)
+    CheckConfig,
+    /// وارد کردن لینک اشتراک (vless/trojan/hysteria2/tuic/ss)
+    Import {
+        /// لینک اشتراک
+        link: String,
+        /// نوشتن در بخش [proxy] فایل پیکربندی (به جای چاپ)
+        #[arg(long)]
+        write: bool,
+    },
+    /// اسکن و خروجی لینک‌های اشتراک برای هر IP تمیز (برای گوشی‌ها)
+    Export {
+        /// خروجی به صورت اشتراک base64 (v2rayNG / Hiddify)
+        #[arg(long)]
+        subscription: bool,
+        /// حداکثر تعداد لینک
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        /// پیشوند نام لینک‌ها
+        #[arg(long, default_value = "Ghost")]
+        name: String,
+        /// خروجی به فایل
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum ServeCommand {
+    /// سرور ShadowTLS v3: handshake به سرور استتار رله و جریان احرازشده به backend داده می‌شود
+    Shadowtls {
+        /// آدرس listen
+        #[arg(long, default_value = "0.0.0.0:443")]
+        listen: std::net::SocketAddr,
+        /// سرور استتار (host:port)
+        #[arg(long, default_value = "ebanking.bmi.ir:443")]
+        handshake: String,
+        /// رمز مشترک (پیش‌فرض: --uuid)
+        #[arg(long)]
+        password: Option<String>,
+        /// socks، vless (با --uuid) یا ip:port برای ارسال به سرویس دیگر
+        #[arg(long, default_value = "socks")]
+        backend: String,
+        /// پذیرش سرور استتار غیر TLS 1.3
+        #[arg(long)]
+        no_strict: bool,
+    },
 }
 
 // ── Entry Point ──────────────────────────────────────────────────────────────
@@ -124,19 +221,61 @@ async fn main() -> Result<()> {
     info!("🛡️  Anti-AI DPI | 20-Layer Phantom | No VPS Required");
     info!("🇮🇷  ضد فیلتر ایران | ضد هوش مصنوعی DPI جدید");
 
+    // اعتبارسنجی قبل از بارگذاری، تا فایل خراب هم گزارش شود
+    if let Some(Commands::CheckConfig) = cli.command {
+        let ok = run_check_config(&cli.config)?;
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+    if let Some(Commands::Import { link, write }) = &cli.command {
+        return run_import(&cli.config, link, *write);
+    }
+
+    // دستورات کنترلی: کلاینت سوکت daemon، بدون بارگذاری پیکربندی
+    match &cli.command {
+        Some(Commands::Stop) => return run_stop(&cli.socket).await,
+        Some(Commands::Status) => return run_status(&cli.socket).await,
+        Some(Commands::Rescan) => return run_control(&cli.socket, "rescan", Value::Null).await,
+        Some(Commands::SwitchIp) => return run_control(&cli.socket, "switch-ip", Value::Null).await,
+        Some(Commands::ReloadConfig) => return run_reload_config(&cli.socket).await,
+        Some(Commands::SetDpiMode { mode }) => {
+            let params = json!({ "mode": parse_dpi_mode(mode) });
+            return run_control(&cli.socket, "set-dpi-mode", params).await;
+        }
+        Some(Commands::Events { follow, since }) => {
+            return run_events(&cli, *follow, since.as_deref()).await
+        }
+        Some(Commands::Serve { server }) => return run_serve(&cli, server).await,
+        _ => {}
+    }
+
     // ساخت پیکربندی
-    let config = build_config(&cli)?;
+    let config = build_config(&cli).await?;
 
     // اجرای دستور
     match cli.command.as_ref().cloned().unwrap_or(Commands::Start) {
-        Commands::Start => run_start(config, &cli).await?,
-        Commands::Stop => run_stop().await?,
+        Commands::Start => run_start(&cli, config).await?,
         Commands::Scan { cdn, output } => run_scan(config, &cdn, output).await?,
-        Commands::Status => run_status().await?,
         Commands::Test => run_test(config).await?,
+        Commands::TraceChain { ip, json } => {
+            let ok = run_trace_chain(config, ip, json).await?;
+            std::process::exit(if ok { 0 } else { 1 });
+        }
         Commands::GenDae { output } => run_gen_dae(config, output).await?,
         Commands::InstallHiddify => run_install_hiddify().await?,
         Commands::Info => print_info(),
+        Commands::Export { subscription, limit, name, output } => {
+            run_export(config, subscription, limit, &name, output).await?
+        }
+        Commands::CheckConfig
+        | Commands::Import { .. }
+        | Commands::Stop
+        | Commands::Status
+        | Commands::Rescan
+        | Commands::SwitchIp
+        | Commands::ReloadConfig
+        | Commands::SetDpiMode { .. }
+        | Commands::Events { .. }
+        | Commands::Serve { .. } => unreachable!("handled before config is loaded"),
     }
 
     Ok(())
@@ -144,54 +283,185 @@ async fn main() -> Result<()> {
 
 // ── Command Handlers ─────────────────────────────────────────────────────────
 
-async fn run_start(config: ProxyConfig, cli: &Cli) -> Result<()> {
-    info!("🚀 شروع Network Ghost با پیکربندی:");
-    info!("   پروتکل: {}", cli.protocol);
-    info!("   CDN:     {}", cli.cdn);
-    info!("   SNI:     {}", cli.sni);
-    info!("   DPI حالت: {}", cli.dpi_mode);
-
-    let anti_ai = AntiAiDpi::new();
-    let dpi_mode = parse_dpi_mode(&cli.dpi_mode);
-    anti_ai.set_mode(dpi_mode);
-    anti_ai.rotate_profile_by_time();
+/// خواندن دوباره فایل (با پروفایل و فلگ‌های CLI)؛ فایل نامعتبر رد می‌شود
+async fn load_reloaded(cli: &Cli) -> Result<GhostConfig> {
+    let config = load_checked(&cli.config)?;
+    prepare_config(cli, config).await
+}
 
-    info!("🛡️  Anti-AI Ghost حالت: {:?} فعال شد", dpi_mode);
+/// بارگذاری مجدد فایل در موتور در حال اجرا
+async fn reload_config(cli: &Cli, engine: &NetworkGhostEngine) {
+    match load_reloaded(cli).await {
+        Ok(config) => {
+            if let Err(e) = engine.reload_config(config).await {
+                error!("❌ بارگذاری مجدد ناموفق: {:#}", e);
+            }
+        }
+        Err(e) => error!("❌ پیکربندی جدید رد شد، پیکربندی فعلی حفظ می‌شود: {:#}", e),
+    }
+}
 
-    let engine = NetworkGhostEngine::new(config).await?;
+async fn run_start(cli: &Cli, config: GhostConfig) -> Result<()> {
+    info!("🚀 شروع Network Ghost با پیکربندی:");
+    info!("   پروتکل: {:?}", config.proxy.protocol);
+    info!("   CDN:     {:?}", config.proxy.cdn_type);
+    info!("   SNI:     {}", config.proxy.sni);
+    info!("   DPI حالت: {:?}", config.anti_ai.mode);
+
+    let journal = config.journal.clone();
+    let engine = Arc::new(NetworkGhostEngine::new(config).await?);
+    if journal.enabled {
+        match EventJournal::open(&journal) {
+            Ok(journal) => {
+                journal.spawn(engine.subscribe());
+            }
+            Err(e) => warn!("⚠️ ژورنال رویدادها در دسترس نیست: {:#}", e),
+        }
+    }
     engine.start().await?;
 
-    // Keep running until Ctrl+C
+    let loader_cli = cli.clone();
+    let control = ControlServer::new(engine.clone(), &cli.socket).with_config_loader(move || {
+        let cli = loader_cli.clone();
+        async move { load_reloaded(&cli).await }
+    });
+    let shutdown = control.shutdown_signal();
+    if let Err(e) = control.spawn() {
+        warn!("⚠️ سوکت کنترل در دسترس نیست: {:#}", e);
+    }
+
+    // Keep running until Ctrl+C; SIGHUP or an uplink change reloads the config file
     info!("✅ تانل فعال است. برای توقف Ctrl+C بزنید.");
-    tokio::signal::ctrl_c().await?;
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+    let mut uplink = UplinkWatcher::new();
+    let mut uplink_tick = tokio::time::interval(std::time::Duration::from_secs(30));
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            _ = shutdown.notified() => {
+                info!("🔌 Network Ghost با دستور stop متوقف شد.");
+                return Ok(());
+            }
+            _ = hangup.recv() => {
+                info!("🔁 SIGHUP — بارگذاری مجدد {}", cli.config.display());
+                reload_config(cli, &engine).await;
+            }
+            _ = uplink_tick.tick() => {
+                if uplink.changed() {
+                    reload_config(cli, &engine).await;
+                }
+            }
+        }
+    }
     engine.stop("User interrupt (Ctrl+C)").await?;
 
     info!("🔌 Network Ghost متوقف شد.");
     Ok(())
 }
 
-async fn run_stop() -> Result<()> {
+async fn run_serve(cli: &Cli, server: &ServeCommand) -> Result<()> {
+    let ServeCommand::Shadowtls { listen, handshake, password, backend, no_strict } = server;
+    let password = password
+        .clone()
+        .or_else(|| cli.uuid.clone())
+        .context("ShadowTLS server needs --password (or --uuid)")?;
+    let config = ShadowTlsServerConfig {
+        handshake: handshake.clone(),
+        password,
+        strict: !no_strict,
+        backend: ShadowTlsBackend::parse(backend, cli.uuid.as_deref())?,
+    };
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("Failed to listen on {}", listen))?;
+    info!("🛡️  سرور ShadowTLS v3 روی {} (استتار: {}، backend: {})", listen, config.handshake, config.backend);
+
+    tokio::select! {
+        result = ShadowTlsServer::new(config).serve(listener) => result,
+        _ = tokio::signal::ctrl_c() => {
+            info!("🔌 سرور ShadowTLS متوقف شد.");
+            Ok(())
+        }
+    }
+}
+
+async fn run_stop(socket: &std::path::Path) -> Result<()> {
     info!("🛑 در حال توقف تانل...");
-    // Signal daemon to stop via PID file
-    let pid_file = std::path::Path::new("/tmp/network-ghost.pid");
-    if pid_file.exists() {
-        let pid_str = tokio::fs::read_to_string(pid_file).await?;
-        let pid: i32 = pid_str.trim().parse()?;
-        info!("📤 ارسال SIGTERM به PID {}", pid);
-        unsafe { libc::kill(pid, libc::SIGTERM); }
-        tokio::fs::remove_file(pid_file).await.ok();
-    } else {
-        warn!("⚠️ فایل PID یافت نشد — تانل در حال اجرا نیست.");
+    let mut client = ControlClient::connect(socket).await?;
+    client.call("stop", Value::Null).await?;
+    info!("✅ تانل متوقف شد.");
+    Ok(())
+}
+
+/// فراخوانی یک متد کنترلی و چاپ نتیجه
+async fn run_control(socket: &std::path::Path, method: &str, params: Value) -> Result<()> {
+    let mut client = ControlClient::connect(socket).await?;
+    let result = client.call(method, params).await?;
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    Ok(())
+}
+
+async fn run_reload_config(socket: &std::path::Path) -> Result<()> {
+    let mut client = ControlClient::connect(socket).await?;
+    let report: ConfigReloadReport = serde_json::from_value(client.call("reload-config", Value::Null).await?)?;
+    println!("🔁 پیکربندی بارگذاری شد");
+    for item in &report.applied {
+        println!("   ✅ {}", item);
+    }
+    for item in &report.restart_required {
+        println!("   ⚠️ {} (نیاز به راه‌اندازی مجدد)", item);
     }
     Ok(())
 }
 
-async fn run_scan(config: ProxyConfig, cdn: &str, output: Option<std::path::PathBuf>) -> Result<()> {
+async fn run_events(cli: &Cli, follow: bool, since: Option<&str>) -> Result<()> {
+    let journal = GhostConfig::load_or_default(&cli.config)?.journal;
+    let print = |ts: chrono::DateTime<chrono::Utc>, event: &EngineEvent| {
+        let line = serde_json::to_string(event).unwrap_or_default();
+        println!("{} {}", ts.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S"), line);
+    };
+
+    // بدون ژورنال فقط رویدادهای زنده از سوکت کنترل
+    if !journal.enabled {
+        if !follow {
+            anyhow::bail!("[journal] is disabled; use --follow for live events");
+        }
+        let client = ControlClient::connect(&cli.socket).await?;
+        return client.events(|event| print(chrono::Utc::now(), &event)).await;
+    }
+
+    let since = since
+        .map(|s| chrono::DateTime::parse_from_rfc3339(s).with_context(|| format!("Invalid --since: {}", s)))
+        .transpose()?;
+    let mut follower = event_journal::JournalFollower::from_end(&journal.dir);
+    for entry in event_journal::replay(&journal.dir)? {
+        if since.is_none_or(|since| entry.ts >= since) {
+            print(entry.ts, &entry.event);
+        }
+    }
+    if !follow {
+        return Ok(());
+    }
+
+    let mut tick = tokio::time::interval(std::time::Duration::from_millis(500));
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+            _ = tick.tick() => {
+                for entry in follower.poll()? {
+                    print(entry.ts, &entry.event);
+                }
+            }
+        }
+    }
+}
+
+async fn run_scan(config: GhostConfig, cdn: &str, output: Option<std::path::PathBuf>) -> Result<()> {
     info!("🔍 شروع اسکن IP برای CDN: {}", cdn);
     info!("   (اسکن IP بدون سرور مجازی — فقط CDN IP‌های تمیز)");
 
     let engine = NetworkGhostEngine::new(config).await?;
-    let clean_ips = engine.get_clean_ips().await;
+    let clean_ips = engine.refresh_clean_ips().await?;
 
     info!("✅ {} IP تمیز پیدا شد.", clean_ips.len());
     for (i, ip) in clean_ips.iter().take(20).enumerate() {
@@ -209,24 +479,74 @@ async fn run_scan(config: ProxyConfig, cdn: &str, output: Option<std::path::Path
     Ok(())
 }
 
-async fn run_status() -> Result<()> {
-    info!("📊 وضعیت Network Ghost:");
-    let pid_file = std::path::Path::new("/tmp/network-ghost.pid");
-    if pid_file.exists() {
-        let pid_str = tokio::fs::read_to_string(pid_file).await
-            .unwrap_or_else(|_| "N/A".to_string());
-        info!("   وضعیت: ✅ در حال اجرا (PID: {})", pid_str.trim());
+async fn run_export(
+    config: GhostConfig,
+    subscription: bool,
+    limit: usize,
+    name: &str,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    let engine = NetworkGhostEngine::new(config).await?;
+    let clean_ips = engine.load_clean_ips().await?;
+    if clean_ips.is_empty() {
+        anyhow::bail!("No clean IPs found");
+    }
+
+    let mut links = engine.export_share_links(name).await?;
+    links.truncate(limit);
+    info!("🔗 {} لینک اشتراک ساخته شد", links.len());
+
+    let body = if subscription {
+        share_link::encode_subscription(&links)
     } else {
-        info!("   وضعیت: ❌ متوقف");
+        links.join("\n")
+    };
+    match output {
+        Some(path) => {
+            tokio::fs::write(&path, &body).await?;
+            info!("💾 ذخیره شد: {}", path.display());
+        }
+        None => println!("{}", body),
+    }
+    Ok(())
+}
+
+async fn run_status(socket: &std::path::Path) -> Result<()> {
+    info!("📊 وضعیت Network Ghost:");
+    let state: TunnelState = match ControlClient::connect(socket).await {
+        Ok(mut client) => serde_json::from_value(client.call("status", Value::Null).await?)?,
+        Err(e) => {
+            info!("   وضعیت: ❌ متوقف ({:#})", e);
+            return Ok(());
+        }
+    };
+
+    let active = if state.active { "✅ فعال" } else { "⏸️ غیرفعال" };
+    info!("   وضعیت:  {}", active);
+    if let Some(ip) = state.current_ip {
+        info!("   IP:      {}:{}", ip, state.current_port);
+    }
+    info!("   پروتکل: {:?} / {:?}", state.protocol, state.cdn);
+    info!("   لایه‌ها: {}", state.active_layers);
+    if let Some(started) = state.started_at {
+        info!("   شروع:   {}", started.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S"));
+    }
+    info!("   ترافیک: ↓{} B ↑{} B ({} اتصال)", state.stats.rx_bytes, state.stats.tx_bytes, state.stats.connections);
+    let rate = state.stats.rates.last_10s;
+    info!("   نرخ:    ↓{:.0} B/s ↑{:.0} B/s (میانگین ۱۰ ثانیه)", rate.rx, rate.tx);
+    info!("   تعویض IP: {}", state.switch_count);
+    if let Some(error) = &state.last_error {
+        info!("   آخرین خطا: {}", error);
     }
+
     let log_path = "/opt/network-ghost/logs/last-success.txt";
     if let Ok(last) = tokio::fs::read_to_string(log_path).await {
-        info!("   آخرین موفقیت: {}", last.trim());
+        info!("   آخرین به‌روزرسانی: {}", last.trim());
     }
     Ok(())
 }
 
-async fn run_test(config: ProxyConfig) -> Result<()> {
+async fn run_test(config: GhostConfig) -> Result<()> {
     info!("🧪 تست اتصال...");
     let engine = NetworkGhostEngine::new(config).await?;
     match engine.test_connection().await {
@@ -237,15 +557,72 @@ async fn run_test(config: ProxyConfig) -> Result<()> {
     Ok(())
 }
 
-async fn run_gen_dae(config: ProxyConfig, output: std::path::PathBuf) -> Result<()> {
+/// چاپ trace زنجیره؛ `false` اگر یکی از لایه‌ها شکست بخورد
+async fn run_trace_chain(config: GhostConfig, ip: Option<std::net::IpAddr>, json: bool) -> Result<bool> {
+    let engine = NetworkGhostEngine::new(config).await?;
+    let trace = engine.trace_chain(ip).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&trace)?);
+    } else {
+        println!("{}", trace);
+    }
+    Ok(trace.failed_at().is_none())
+}
+
+async fn run_gen_dae(config: GhostConfig, output: std::path::PathBuf) -> Result<()> {
     info!("📝 تولید پیکربندی DAE (eBPF TProxy)...");
     let engine = NetworkGhostEngine::new(config).await?;
-    let ips = engine.get_clean_ips().await;
+    let ips = engine.load_clean_ips().await?;
     if ips.is_empty() {
         warn!("⚠️ هیچ IP تمیزی یافت نشد — ابتدا scan را اجرا کنید.");
         return Ok(());
     }
-    info!("✅ DAE config تولید شد: {}", output.display());
+    engine.write_dae_config(&output).await?;
+    info!("✅ DAE config تولید شد: {} ({} IP)", output.display(), ips.len());
+    Ok(())
+}
+
+/// گزارش مشکلات فایل پیکربندی؛ `false` اگر خطایی وجود داشته باشد
+fn run_check_config(path: &std::path::Path) -> Result<bool> {
+    let diagnostics = check_config_file(path)?;
+
+    for d in &diagnostics {
+        match d.line {
+            Some(line) => println!("{}:{}: {}", path.display(), line, d),
+            None => println!("{}: {}", path.display(), d),
+        }
+    }
+
+    let errors = diagnostics.iter().filter(|d| d.severity == Severity::Error).count();
+    let warnings = diagnostics.len() - errors;
+    if errors == 0 {
+        println!("✅ {}: OK ({} warning(s))", path.display(), warnings);
+    } else {
+        println!("❌ {}: {} error(s), {} warning(s)", path.display(), errors, warnings);
+    }
+    Ok(errors == 0)
+}
+
+fn run_import(path: &std::path::Path, link: &str, write: bool) -> Result<()> {
+    let link = ShareLink::parse(link)?;
+    info!(
+        "📥 {} — {:?} {}:{}",
+        if link.name.is_empty() { "(بدون نام)" } else { &link.name },
+        link.proxy.protocol,
+        link.proxy.server,
+        link.proxy.port
+    );
+
+    if !write {
+        print!("{}", link.proxy_toml()?);
+        return Ok(());
+    }
+
+    link.write_to_config(path)?;
+    info!("✅ بخش [proxy] در {} به‌روز شد", path.display());
+    if !run_check_config(path)? {
+        anyhow::bail!("Imported config does not validate");
+    }
     Ok(())
 }
 
@@ -490,53 +867,60 @@ fn setup_logging(level: &str) {
     fmt().with_env_filter(env_filter).with_target(false).init();
 }
 
-fn build_config(cli: &Cli) -> Result<ProxyConfig> {
-    let protocol = parse_protocol(&cli.protocol);
-    let cdn = parse_cdn(&cli.cdn);
-
-    let mut config = ProxyConfig {
-        server: String::new(),
-        port: 443,
-        protocol,
-        sni: cli.sni.clone(),
-        uuid: cli.uuid.clone().unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
-        public_key: cli.public_key.clone(),
-        private_key: None,
-        short_id: None,
-        utls_fingerprint: "chrome".to_string(),
-        cdn_type: cdn,
-        fallback_port: Some(8443),
-        max_latency_ms: cli.max_latency,
-        enable_padding: true,
-        enable_anti_ai: true,
-        enable_matryoshka: true,
-        enable_port_hopping: cli.port_hopping,
-    };
+/// فایل پیکربندی را بارگذاری کرده و فلگ‌های CLI را روی آن اعمال می‌کند
+async fn build_config(cli: &Cli) -> Result<GhostConfig> {
+    let config = GhostConfig::load_or_default(&cli.config)?;
+    prepare_config(cli, config).await
+}
 
-    // Load from config file if it exists
-    if cli.config.exists() {
-        if let Ok(content) = std::fs::read_to_string(&cli.config) {
-            if let Ok(toml_val) = content.parse::<toml::Value>() {
-                if let Some(sni) = toml_val.get("sni").and_then(|v| v.as_str()) {
-                    config.sni = sni.to_string();
-                }
-                if let Some(uuid) = toml_val.get("uuid").and_then(|v| v.as_str()) {
-                    config.uuid = uuid.to_string();
-                }
-            }
-        }
+/// اعمال پروفایل (نام یا auto) و سپس فلگ‌های CLI
+async fn prepare_config(cli: &Cli, mut config: GhostConfig) -> Result<GhostConfig> {
+    if let Some(name) = profile::apply(&mut config, cli.profile.as_deref()).await? {
+        info!("📋 پروفایل: {}", name);
     }
-
+    apply_cli_overrides(cli, &mut config);
     Ok(config)
 }
 
+/// فلگ‌های CLI بر مقادیر فایل اولویت دارند (در بارگذاری مجدد هم)
+fn apply_cli_overrides(cli: &Cli, config: &mut GhostConfig) {
+    if let Some(protocol) = &cli.protocol {
+        config.proxy.protocol = parse_protocol(protocol);
+    }
+    if let Some(cdn) = &cli.cdn {
+        config.proxy.cdn_type = parse_cdn(cdn);
+    }
+    if let Some(sni) = &cli.sni {
+        config.proxy.sni = sni.clone();
+    }
+    if let Some(uuid) = &cli.uuid {
+        config.proxy.uuid = uuid.clone();
+    }
+    if let Some(public_key) = &cli.public_key {
+        config.proxy.public_key = Some(public_key.clone());
+    }
+    if let Some(max_latency) = cli.max_latency {
+        config.proxy.max_latency_ms = max_latency;
+    }
+    if let Some(port_hopping) = cli.port_hopping {
+        config.proxy.enable_port_hopping = port_hopping;
+    }
+    if let Some(max_scan) = cli.max_scan {
+        config.scanner.max_ips = max_scan;
+    }
+    if let Some(mode) = &cli.dpi_mode {
+        config.anti_ai.enabled = true;
+        config.anti_ai.mode = parse_dpi_mode(mode);
+    }
+}
+
 fn parse_dpi_mode(mode: &str) -> AntiAiMode {
     match mode.to_lowercase().as_str() {
         "normal"     => AntiAiMode::Normal,
         "aggressive" => AntiAiMode::Aggressive,
         "stealth"    => AntiAiMode::Stealth,
         "adaptive"   => AntiAiMode::Adaptive,
-        "ghost" | _ => AntiAiMode::Ghost,
+        _            => AntiAiMode::Ghost,
     }
 }
 
@@ -549,7 +933,9 @@ fn parse_protocol(p: &str) -> ProtocolType {
         "xhttp"                    => ProtocolType::Xhttp,
         "vless"                    => ProtocolType::Vless,
         "trojan"                   => ProtocolType::Trojan,
-        "reality" | "auto" | _     => ProtocolType::Reality,
+        "shadowsocks" | "ss"       => ProtocolType::Shadowsocks,
+        "auto"                     => ProtocolType::Auto,
+        _                          => ProtocolType::Reality,
     }
 }
 
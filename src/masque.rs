@@ -3,10 +3,15 @@
 
 use std::net::{IpAddr, SocketAddr};
 use anyhow::{Context, Result};
-use tokio::{net::UdpSocket, time::{timeout, Duration}};
+use async_trait::async_trait;
+use tokio::time::{timeout, Duration};
 use tracing::{debug, info};
 use rand::thread_rng;
 
+use crate::transport::{
+    BoxedDatagram, DatagramDialer, DatagramSocket, DatagramTransport, UdpDialer,
+};
+
 const CAPSULE_TYPE_DATAGRAM: u32 = 0x00;
 const CAPSULE_TYPE_CLOSE:    u32 = 0x01;
 const MAX_UDP_PAYLOAD: usize = 1200;
@@ -36,6 +41,27 @@ impl Capsule {
         out
     }
 
+    /// Decode یک capsule از ابتدای `data` (همراه تعداد بایت مصرف‌شده)
+    pub fn decode(data: &[u8]) -> Option<(Self, usize)> {
+        let (capsule_type, a) = Self::decode_varint(data)?;
+        let (len, b) = Self::decode_varint(&data[a..])?;
+        let start = a + b;
+        let end = start.checked_add(usize::try_from(len).ok()?)?;
+        let payload = data.get(start..end)?;
+        Some((Self { capsule_type: capsule_type as u32, data: payload.to_vec() }, end))
+    }
+
+    fn decode_varint(data: &[u8]) -> Option<(u64, usize)> {
+        let first = *data.first()?;
+        let len = 1usize << (first >> 6);
+        let bytes = data.get(..len)?;
+        let mut v = (first & 0x3F) as u64;
+        for &b in &bytes[1..] {
+            v = (v << 8) | b as u64;
+        }
+        Some((v, len))
+    }
+
     fn encode_varint(v: u64) -> Vec<u8> {
         if v < 64 { vec![v as u8] }
         else if v < 16384 {
@@ -77,20 +103,14 @@ impl Default for MasqueConfig {
 pub struct MasqueClient {
     server: IpAddr,
     port: u16,
-    socket: Option<UdpSocket>,
-    stream_id: u64,
     config: MasqueConfig,
-    context_id: u64,
 }
 
 impl MasqueClient {
     pub fn new(server: IpAddr, port: u16) -> Self {
         Self {
             server, port,
-            socket: None,
-            stream_id: 0,
             config: MasqueConfig::default(),
-            context_id: 0,
         }
     }
 
@@ -99,27 +119,6 @@ impl MasqueClient {
         self
     }
 
-    pub async fn connect(&mut self) -> Result<()> {
-        info!("📦 اتصال MASQUE (H3 CONNECT-UDP) به {}:{}", self.server, self.port);
-        let socket = UdpSocket::bind("0.0.0.0:0").await.context("UDP bind")?;
-        socket.connect(SocketAddr::new(self.server, self.port)).await.context("UDP connect")?;
-        self.socket = Some(socket);
-        self.send_connect().await?;
-        info!("✅ MASQUE connection established");
-        Ok(())
-    }
-
-    async fn send_connect(&mut self) -> Result<()> {
-        let connect_pkt = self.build_connect_request();
-        let sock = self.socket.as_ref().context("No socket")?;
-        timeout(Duration::from_secs(5), sock.send(&connect_pkt)).await??;
-
-        let mut buf = vec![0u8; 2048];
-        let n = timeout(Duration::from_secs(5), sock.recv(&mut buf)).await??;
-        debug!("📩 MASQUE server response: {} bytes", n);
-        Ok(())
-    }
-
     fn build_connect_request(&self) -> Vec<u8> {
         // Simplified QUIC Initial-like packet to establish MASQUE proxy
         let mut pkt = Vec::new();
@@ -137,38 +136,66 @@ impl MasqueClient {
         pkt.extend_from_slice(req.as_bytes());
         pkt
     }
+}
 
-    /// ارسال UDP payload via Capsule Protocol
-    pub async fn send_capsule(&mut self, payload: &[u8]) -> Result<()> {
-        // Split oversized payloads
-        for chunk in payload.chunks(MAX_UDP_PAYLOAD) {
-            let capsule = Capsule::datagram(chunk);
-            let encoded = capsule.encode();
-            let sock = self.socket.as_ref().context("No socket")?;
-            timeout(Duration::from_secs(5), sock.send(&encoded)).await??;
-        }
-        Ok(())
+#[async_trait]
+impl DatagramTransport for MasqueClient {
+    fn name(&self) -> &'static str {
+        "MASQUE"
     }
 
-    pub async fn send(&mut self, data: &[u8]) -> Result<()> {
-        self.send_capsule(data).await
+    async fn connect_over(&self, lower: BoxedDatagram) -> Result<BoxedDatagram> {
+        let connect_pkt = self.build_connect_request();
+        timeout(Duration::from_secs(5), lower.send(&connect_pkt)).await??;
+
+        let mut buf = vec![0u8; 2048];
+        let n = timeout(Duration::from_secs(5), lower.recv(&mut buf)).await??;
+        debug!("📩 MASQUE server response: {} bytes", n);
+        Ok(Box::new(MasqueSocket { inner: lower }))
     }
+}
 
-    pub async fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let sock = self.socket.as_ref().context("No socket")?;
-        let n = timeout(Duration::from_secs(10), sock.recv(buf)).await??;
-        // Skip Capsule header (at minimum 2 bytes varint)
-        let skip = 2.min(n);
-        buf.copy_within(skip..n, 0);
-        Ok(n.saturating_sub(skip))
+/// اتصال مستقیم به سرور
+#[async_trait]
+impl DatagramDialer for MasqueClient {
+    async fn dial(&self) -> Result<BoxedDatagram> {
+        info!("📦 اتصال MASQUE (H3 CONNECT-UDP) به {}:{}", self.server, self.port);
+        let udp = UdpDialer::new(SocketAddr::new(self.server, self.port)).dial().await?;
+        let socket = self.connect_over(udp).await?;
+        info!("✅ MASQUE connection established");
+        Ok(socket)
     }
+}
+
+/// پکت‌ها در قالب Capsule (RFC 9297)
+struct MasqueSocket {
+    inner: BoxedDatagram,
+}
 
-    pub async fn close(&mut self) -> Result<()> {
-        if let Some(sock) = &self.socket {
-            let close = Capsule::close().encode();
-            let _ = sock.send(&close).await;
+#[async_trait]
+impl DatagramSocket for MasqueSocket {
+    async fn send(&self, packet: &[u8]) -> Result<()> {
+        // Split oversized payloads
+        for chunk in packet.chunks(MAX_UDP_PAYLOAD) {
+            let encoded = Capsule::datagram(chunk).encode();
+            timeout(Duration::from_secs(5), self.inner.send(&encoded)).await??;
         }
-        info!("🔌 MASQUE connection closed");
         Ok(())
     }
+
+    async fn recv(&self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            let n = self.inner.recv(buf).await?;
+            match Capsule::decode(&buf[..n]) {
+                Some((capsule, _)) if capsule.capsule_type == CAPSULE_TYPE_DATAGRAM => {
+                    buf[..capsule.data.len()].copy_from_slice(&capsule.data);
+                    return Ok(capsule.data.len());
+                }
+                Some((capsule, _)) if capsule.capsule_type == CAPSULE_TYPE_CLOSE => {
+                    anyhow::bail!("MASQUE session closed by proxy");
+                }
+                _ => debug!("📩 MASQUE: skipping {} byte non-datagram packet", n),
+            }
+        }
+    }
 }
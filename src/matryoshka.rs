@@ -1,38 +1,403 @@
 //! Matryoshka Dialer - زنجیره تو در تو
 
 use std::{
+    collections::HashMap,
     net::{IpAddr, SocketAddr},
-    time::Duration,
+    str::FromStr,
+    time::{Duration, Instant},
 };
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use serde::{Deserialize, Serialize};
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-    time::timeout,
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::info;
+
+use crate::{
+    ip_relay::{IpRelayChain, RelayConfig, RelayNode, MAX_HOPS},
+    packet_padding::PaddingLayer,
+    reality::{parse_public_key, parse_short_id, Reality, RealityConfig},
+    shadowtls::ShadowTlsClient,
+    smux::Smux,
+    traffic::{CountingStream, TrafficTap},
+    transport::{BoxedStream, Dialer, TcpDialer, Transport, TransportStack},
+    websocket_transport::{GrpcTransport, GrpcTransportConfig, WsTransport, WsTransportConfig},
+    xhttp::XhttpClient,
 };
-use tracing::{debug, info};
 
 /// حداکثر تعداد لایه‌ها
-const MAX_LAYERS: usize = 20;
+pub const MAX_LAYERS: usize = 20;
+
+/// دستور VLESS برای اتصال TCP
+pub const CMD_TCP: u8 = 0x01;
+/// دستور VLESS برای UDP (هر پکت با پیشوند طول دوبایتی)
+pub const CMD_UDP: u8 = 0x02;
 
 /// نوع لایه
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub enum LayerType {
     /// TCP
+    #[default]
     Tcp,
-    /// ShadowTLS
-    ShadowTls { sni: String },
-    /// Reality
-    Reality { uuid: String, public_key: String },
+    /// IP-Relay: رسیدن به سرور از طریق hopها با HTTP CONNECT
+    IpRelay { hops: Vec<SocketAddr> },
+    /// WebSocket
+    WebSocket { host: String, path: String },
+    /// XHTTP (HTTP/2)
+    Xhttp { host: String, path: String },
+    /// gRPC
+    Grpc { service_name: String },
+    /// پدینگ تصادفی (بایت)
+    Padding { min: usize, max: usize },
+    /// ShadowTLS v3 (`strict`: فقط سرور استتار TLS 1.3)
+    ShadowTls { sni: String, password: String, strict: bool },
+    /// Reality (`public_key` اجباری؛ خالی از `[proxy]` پر می‌شود)
+    Reality { uuid: String, public_key: String, short_id: String, sni: String },
     /// SMUX
     Smux,
 }
 
-impl Default for LayerType {
-    fn default() -> Self {
-        Self::Tcp
+impl std::fmt::Display for LayerType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LayerType::Tcp => f.write_str("TCP"),
+            LayerType::IpRelay { hops } => write!(f, "IP-Relay ({} hops)", hops.len()),
+            LayerType::WebSocket { .. } => f.write_str("WebSocket"),
+            LayerType::Xhttp { .. } => f.write_str("XHTTP"),
+            LayerType::Grpc { .. } => f.write_str("gRPC"),
+            LayerType::Padding { .. } => f.write_str("Padding"),
+            LayerType::ShadowTls { .. } => f.write_str("ShadowTLS v3"),
+            LayerType::Reality { .. } => f.write_str("Reality (VLESS)"),
+            LayerType::Smux => f.write_str("SMUX Mux"),
+        }
+    }
+}
+
+impl LayerType {
+    /// نام لایه در [`ChainSpec`]
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            LayerType::Tcp => "tcp",
+            LayerType::IpRelay { .. } => "relay",
+            LayerType::WebSocket { .. } => "ws",
+            LayerType::Xhttp { .. } => "xhttp",
+            LayerType::Grpc { .. } => "grpc",
+            LayerType::Padding { .. } => "padding",
+            LayerType::ShadowTls { .. } => "shadowtls",
+            LayerType::Reality { .. } => "reality",
+            LayerType::Smux => "smux",
+        }
+    }
+
+    /// آرگومان‌های غیرخالی به شکل `key=value`
+    fn args(&self) -> Vec<String> {
+        let named = |pairs: &[(&str, &str)]| -> Vec<String> {
+            pairs
+                .iter()
+                .filter(|(_, v)| !v.is_empty())
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect()
+        };
+        match self {
+            LayerType::Tcp | LayerType::Smux => Vec::new(),
+            LayerType::IpRelay { hops } => hops.iter().map(|h| h.to_string()).collect(),
+            LayerType::WebSocket { host, path } | LayerType::Xhttp { host, path } => {
+                named(&[("host", host), ("path", path)])
+            }
+            LayerType::Grpc { service_name } => named(&[("service", service_name)]),
+            LayerType::Padding { min, max } => vec![format!("min={}", min), format!("max={}", max)],
+            LayerType::ShadowTls { sni, password, strict } => {
+                let mut args = named(&[("sni", sni), ("password", password)]);
+                if !strict {
+                    args.push("strict=false".to_string());
+                }
+                args
+            }
+            LayerType::Reality { uuid, public_key, short_id, sni } => {
+                named(&[("uuid", uuid), ("pbk", public_key), ("sid", short_id), ("sni", sni)])
+            }
+        }
+    }
+
+    /// آرگومان‌ها برای نمایش (رمز، UUID و کلیدهای Reality پنهان)
+    fn masked_args(&self) -> Vec<String> {
+        self.args()
+            .into_iter()
+            .map(|arg| match arg.split_once('=') {
+                Some((key @ ("password" | "uuid" | "pbk" | "sid"), _)) => format!("{}=***", key),
+                _ => arg,
+            })
+            .collect()
+    }
+
+    /// خواندن یک لایه مثل `ws(cdn.example.com,/ws)` یا `padding(max=900)`
+    fn parse(text: &str) -> Result<Self> {
+        let text = text.trim();
+        let (name, args) = match text.split_once('(') {
+            Some((name, rest)) => {
+                let inner = rest
+                    .trim_end()
+                    .strip_suffix(')')
+                    .ok_or_else(|| anyhow!("missing `)`"))?;
+                let args: Vec<&str> = inner.split(',').map(str::trim).filter(|a| !a.is_empty()).collect();
+                (name.trim(), args)
+            }
+            None => (text, Vec::new()),
+        };
+
+        let layer = match name.to_ascii_lowercase().as_str() {
+            "tcp" => {
+                bind(&[], &args)?;
+                LayerType::Tcp
+            }
+            "relay" | "ip-relay" => {
+                let hops = args
+                    .iter()
+                    .map(|a| a.parse().map_err(|_| anyhow!("`{}` is not an ip:port address", a)))
+                    .collect::<Result<_>>()?;
+                LayerType::IpRelay { hops }
+            }
+            "ws" | "websocket" => {
+                let mut a = bind(&["host", "path"], &args)?;
+                LayerType::WebSocket { host: a.take("host"), path: a.take("path") }
+            }
+            "xhttp" => {
+                let mut a = bind(&["host", "path"], &args)?;
+                LayerType::Xhttp { host: a.take("host"), path: a.take("path") }
+            }
+            "grpc" => {
+                let mut a = bind(&["service"], &args)?;
+                LayerType::Grpc { service_name: a.take("service") }
+            }
+            "padding" => {
+                let mut a = bind(&["min", "max"], &args)?;
+                let number = |key: &str, value: String, default: usize| -> Result<usize> {
+                    if value.is_empty() {
+                        return Ok(default);
+                    }
+                    value.parse().map_err(|_| anyhow!("{} `{}` is not a number", key, value))
+                };
+                LayerType::Padding {
+                    min: number("min", a.take("min"), 0)?,
+                    max: number("max", a.take("max"), 256)?,
+                }
+            }
+            "shadowtls" => {
+                let mut a = bind(&["sni", "password", "strict"], &args)?;
+                let strict = match a.take("strict").as_str() {
+                    "" | "true" => true,
+                    "false" => false,
+                    other => bail!("strict `{}` is not true or false", other),
+                };
+                LayerType::ShadowTls { sni: a.take("sni"), password: a.take("password"), strict }
+            }
+            "reality" => {
+                let mut a = bind(&["uuid", "pbk", "sid", "sni"], &args)?;
+                LayerType::Reality {
+                    uuid: a.take("uuid"),
+                    public_key: a.take("pbk"),
+                    short_id: a.take("sid"),
+                    sni: a.take("sni"),
+                }
+            }
+            "smux" => {
+                bind(&[], &args)?;
+                LayerType::Smux
+            }
+            other => bail!("unknown layer `{}`", other),
+        };
+        Ok(layer)
+    }
+}
+
+/// آرگومان‌های یک لایه (به ترتیب `params` یا با نام)
+struct Args(HashMap<&'static str, String>);
+
+impl Args {
+    fn take(&mut self, key: &str) -> String {
+        self.0.remove(key).unwrap_or_default()
+    }
+}
+
+fn bind(params: &[&'static str], args: &[&str]) -> Result<Args> {
+    let mut bound = HashMap::new();
+    for (i, arg) in args.iter().enumerate() {
+        let (key, value) = match arg.split_once('=') {
+            Some((key, value)) => {
+                let key = key.trim();
+                let key = params
+                    .iter()
+                    .find(|p| **p == key)
+                    .ok_or_else(|| anyhow!("unknown argument `{}`", key))?;
+                (*key, value.trim())
+            }
+            None => (*params.get(i).ok_or_else(|| anyhow!("too many arguments"))?, *arg),
+        };
+        bound.insert(key, value.trim_matches('"').to_string());
+    }
+    Ok(Args(bound))
+}
+
+/// زنجیره اعلانی لایه‌ها (`[proxy] chain`)
+///
+/// مثل `tcp > ws(cdn.example.com,/ws) > shadowtls(sni) > reality(uuid,pbk) > smux`.
+/// آرگومان‌ها به ترتیب یا با نام (`ws(path=/ws)`) داده می‌شوند و آرگومان
+/// خالی هنگام اتصال از `[proxy]` پر می‌شود. ترتیب لایه‌ها هنگام بارگذاری
+/// کانفیگ بررسی می‌شود.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ChainSpec {
+    layers: Vec<LayerType>,
+}
+
+impl ChainSpec {
+    pub fn new(layers: Vec<LayerType>) -> Result<Self> {
+        let spec = Self { layers };
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    pub fn layers(&self) -> &[LayerType] {
+        &self.layers
+    }
+
+    /// قواعد ترتیب: `tcp` و `relay` فقط در ابتدا، یک `reality` که پس از آن
+    /// فقط `smux` می‌آید، `smux` در انتها و حداکثر [`MAX_LAYERS`] لایه
+    pub fn validate(&self) -> Result<()> {
+        if self.layers.is_empty() {
+            bail!("chain is empty");
+        }
+        if self.layers.len() > MAX_LAYERS {
+            bail!("chain has {} layers (at most {} allowed)", self.layers.len(), MAX_LAYERS);
+        }
+
+        let last = self.layers.len() - 1;
+        let mut after_reality = false;
+        for (i, layer) in self.layers.iter().enumerate() {
+            let fail = |message: String| anyhow!("layer {} (`{}`): {}", i + 1, layer.keyword(), message);
+            if after_reality && *layer != LayerType::Smux {
+                return Err(fail("only `smux` may follow `reality`".to_string()));
+            }
+            match layer {
+                LayerType::Tcp if i != 0 => {
+                    return Err(fail("`tcp` can only be the first layer".to_string()));
+                }
+                LayerType::IpRelay { hops } => {
+                    if !self.layers[..i].iter().all(|l| *l == LayerType::Tcp) {
+                        return Err(fail("`relay` must come directly after `tcp`".to_string()));
+                    }
+                    if hops.is_empty() {
+                        return Err(fail("needs at least one ip:port hop".to_string()));
+                    }
+                    if hops.len() >= MAX_HOPS {
+                        return Err(fail(format!("at most {} hops are supported", MAX_HOPS - 1)));
+                    }
+                }
+                LayerType::Padding { min, max } => {
+                    if min > max {
+                        return Err(fail(format!("min ({}) is greater than max ({})", min, max)));
+                    }
+                    if *max > u16::MAX as usize {
+                        return Err(fail(format!("max must be at most {}", u16::MAX)));
+                    }
+                }
+                LayerType::Reality { uuid, public_key, short_id, .. } => {
+                    if !uuid.is_empty() && uuid::Uuid::parse_str(uuid).is_err() {
+                        return Err(fail(format!("`{}` is not a valid UUID", uuid)));
+                    }
+                    if !public_key.is_empty() {
+                        parse_public_key(public_key).map_err(|e| fail(e.to_string()))?;
+                    }
+                    parse_short_id(short_id).map_err(|e| fail(e.to_string()))?;
+                    after_reality = true;
+                }
+                LayerType::Smux if i != last => {
+                    return Err(fail("`smux` must be the last layer".to_string()));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// لایه‌ها با آرگومان‌های خالی پرشده از `[proxy]`
+    pub fn resolve(&self, sni: &str, uuid: &str, public_key: &str, short_id: &str) -> Vec<LayerType> {
+        let or = |value: &str, default: &str| {
+            if value.is_empty() { default.to_string() } else { value.to_string() }
+        };
+        self.layers
+            .iter()
+            .map(|layer| match layer {
+                LayerType::WebSocket { host, path } => {
+                    LayerType::WebSocket { host: or(host, sni), path: or(path, "/ws") }
+                }
+                LayerType::Xhttp { host, path } => {
+                    LayerType::Xhttp { host: or(host, sni), path: path.clone() }
+                }
+                LayerType::Grpc { service_name } => LayerType::Grpc {
+                    service_name: or(service_name, &GrpcTransportConfig::default().service_name),
+                },
+                LayerType::ShadowTls { sni: layer_sni, password, strict } => LayerType::ShadowTls {
+                    sni: or(layer_sni, sni),
+                    password: or(password, uuid),
+                    strict: *strict,
+                },
+                LayerType::Reality { uuid: layer_uuid, public_key: layer_key, short_id: layer_sid, sni: layer_sni } => {
+                    LayerType::Reality {
+                        uuid: or(layer_uuid, uuid),
+                        public_key: or(layer_key, public_key),
+                        short_id: or(layer_sid, short_id),
+                        sni: or(layer_sni, sni),
+                    }
+                }
+                other => other.clone(),
+            })
+            .collect()
+    }
+}
+
+impl FromStr for ChainSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let layers = s
+            .split('>')
+            .enumerate()
+            .map(|(i, part)| {
+                LayerType::parse(part)
+                    .with_context(|| format!("layer {} (`{}`)", i + 1, part.trim()))
+            })
+            .collect::<Result<_>>()?;
+        Self::new(layers)
+    }
+}
+
+impl TryFrom<String> for ChainSpec {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<ChainSpec> for String {
+    fn from(spec: ChainSpec) -> Self {
+        spec.to_string()
+    }
+}
+
+impl std::fmt::Display for ChainSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, layer) in self.layers.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" > ")?;
+            }
+            f.write_str(layer.keyword())?;
+            let args = layer.args();
+            if !args.is_empty() {
+                write!(f, "({})", args.join(","))?;
+            }
+        }
+        Ok(())
     }
 }
 
@@ -42,8 +407,12 @@ pub struct MatryoshkaDialer {
     target: SocketAddr,
     /// لایه‌ها
     layers: Vec<LayerType>,
-    /// کانکشن TCP
-    tcp_stream: Option<TcpStream>,
+    /// مقصد نهایی (host, port, دستور) که در هدر VLESS فرستاده می‌شود
+    destination: Option<(String, u16, u8)>,
+    /// SO_MARK سوکت خروجی (تا قوانین TPROXY آن را دوباره نگیرند)
+    mark: Option<u32>,
+    /// جریان پس از اعمال لایه‌ها
+    stream: Option<BoxedStream>,
     /// فعال
     active: bool,
 }
@@ -54,7 +423,9 @@ impl MatryoshkaDialer {
         Self {
             target,
             layers: Vec::new(),
-            tcp_stream: None,
+            destination: None,
+            mark: None,
+            stream: None,
             active: false,
         }
     }
@@ -69,17 +440,23 @@ impl MatryoshkaDialer {
         self.target
     }
 
-    /// اضافه کردن لایه ShadowTLS
-    pub fn wrap_with_shadowtls(mut self, sni: &str) -> Self {
-        self.layers.push(LayerType::ShadowTls { sni: sni.to_string() });
+    /// اضافه کردن لایه ShadowTLS (حالت strict)
+    pub fn wrap_with_shadowtls(mut self, sni: &str, password: &str) -> Self {
+        self.layers.push(LayerType::ShadowTls {
+            sni: sni.to_string(),
+            password: password.to_string(),
+            strict: true,
+        });
         self
     }
 
     /// اضافه کردن لایه Reality
-    pub fn wrap_with_reality(mut self, uuid: &str, public_key: &str) -> Self {
+    pub fn wrap_with_reality(mut self, uuid: &str, public_key: &str, short_id: &str, sni: &str) -> Self {
         self.layers.push(LayerType::Reality {
             uuid: uuid.to_string(),
             public_key: public_key.to_string(),
+            short_id: short_id.to_string(),
+            sni: sni.to_string(),
         });
         self
     }
@@ -90,148 +467,204 @@ impl MatryoshkaDialer {
         self
     }
 
-    /// تعداد لایه‌ها
-    pub fn layer_count(&self) -> usize {
-        self.layers.len()
+    /// افزودن لایه‌های یک زنجیره اعلانی (پس از [`ChainSpec::resolve`])
+    pub fn with_layers(mut self, layers: Vec<LayerType>) -> Self {
+        self.layers.extend(layers);
+        self
     }
 
-    /// شروع اتصال
-    pub async fn start(&mut self) -> Result<()> {
-        if self.layers.len() > MAX_LAYERS {
-            self.layers.truncate(MAX_LAYERS);
-        }
-
-        info!("🚀 Starting Matryoshka Dialer with {} layers", self.layers.len());
+    /// تعیین مقصد نهایی برای اتصال per-connection (مثلاً از SOCKS5)
+    pub fn with_destination(mut self, host: &str, port: u16) -> Self {
+        self.destination = Some((host.to_string(), port, CMD_TCP));
+        self
+    }
 
-        // اتصال TCP
-        let stream = timeout(
-            Duration::from_secs(10),
-            TcpStream::connect(self.target),
-        )
-        .await
-        .context("TCP connection timeout")?
-        .context("TCP connection failed")?;
+    /// تعیین SO_MARK برای اتصال به سرور
+    pub fn with_mark(mut self, mark: u32) -> Self {
+        self.mark = Some(mark);
+        self
+    }
 
-        self.tcp_stream = Some(stream);
+    /// لایه‌ها به ترتیب اعمال
+    pub fn layers(&self) -> &[LayerType] {
+        &self.layers
+    }
 
-        // اعمال لایه‌ها
-        for i in 0..self.layers.len() { let layer = self.layers[i].clone();
-            self.apply_layer(&layer).await?;
-        }
+    /// تعداد لایه‌ها
+    pub fn layer_count(&self) -> usize {
+        self.layers.len()
+    }
 
-        self.active = true;
-        info!("✅ Matryoshka chain established");
-        Ok(())
+    /// آدرس اتصال TCP: با IP-Relay اولین hop (آخرین CONNECT به هدف می‌رسد)
+    fn entry(&self) -> SocketAddr {
+        self.layers
+            .iter()
+            .find_map(|l| match l {
+                LayerType::IpRelay { hops } => hops.first().copied(),
+                _ => None,
+            })
+            .unwrap_or(self.target)
     }
 
-    /// اعمال لایه
-    async fn apply_layer(&mut self, layer: &LayerType) -> Result<()> {
-        match layer {
-            LayerType::ShadowTls { sni } => {
-                self.apply_shadowtls(sni).await?;
+    /// Transport یک لایه (`None` برای TCP که همان اتصال پایه است)
+    fn transport(&self, layer: &LayerType) -> Result<Option<Box<dyn Transport>>> {
+        let (ip, port) = (self.target.ip(), self.target.port());
+        let transport: Box<dyn Transport> = match layer {
+            LayerType::Tcp => return Ok(None),
+            LayerType::IpRelay { hops } => {
+                let nodes = hops
+                    .iter()
+                    .chain([&self.target])
+                    .map(|hop| RelayNode::new(hop.ip(), hop.port(), "relay"))
+                    .collect();
+                Box::new(IpRelayChain::new(RelayConfig::default()).with_nodes(nodes))
             }
-            LayerType::Reality { uuid, public_key } => {
-                self.apply_reality(uuid, public_key).await?;
+            LayerType::WebSocket { host, path } => {
+                let config = WsTransportConfig {
+                    host: host.clone(),
+                    path: path.clone(),
+                    ..Default::default()
+                };
+                Box::new(WsTransport::new(ip, port, config))
             }
-            LayerType::Smux => {
-                self.apply_smux().await?;
+            LayerType::Xhttp { host, path } => {
+                let mut client = XhttpClient::new(ip, port).with_host(host);
+                if !path.is_empty() {
+                    client = client.with_path(path);
+                }
+                Box::new(client)
             }
-            _ => {}
-        }
-        Ok(())
+            LayerType::Grpc { service_name } => {
+                let config = GrpcTransportConfig {
+                    service_name: service_name.clone(),
+                    ..Default::default()
+                };
+                Box::new(GrpcTransport::new(ip, port, config))
+            }
+            LayerType::Padding { min, max } => Box::new(PaddingLayer::new(*min, *max)),
+            LayerType::ShadowTls { sni, password, strict } => Box::new(
+                ShadowTlsClient::new(ip, port, sni.clone(), password.clone()).with_strict(*strict),
+            ),
+            LayerType::Reality { uuid, public_key, short_id, sni } => {
+                let config = RealityConfig::from_uuid_str(uuid, public_key, short_id, sni)?;
+                let mut reality = Reality::new().with_config(config);
+                if let Some((host, port, cmd)) = &self.destination {
+                    reality = reality.with_destination(host, *port, *cmd);
+                }
+                Box::new(reality)
+            }
+            LayerType::Smux => Box::new(Smux::new()),
+        };
+        Ok(Some(transport))
     }
 
-    /// اعمال ShadowTLS
-    async fn apply_shadowtls(&mut self, sni: &str) -> Result<()> {
-        debug!("🔐 Applying ShadowTLS layer with SNI: {}", sni);
-        
-        let hello = self.build_shadowtls_hello(sni);
-        
-        let stream = self.tcp_stream.as_mut().context("No connection")?;
-        stream.write_all(&hello).await?;
-        
-        let mut response = vec![0u8; 4096];
-        let _n = timeout(Duration::from_secs(5), stream.read(&mut response))
-            .await?
-            .context("ShadowTLS timeout")?;
-        
-        debug!("✅ ShadowTLS layer applied");
-        Ok(())
+    /// ساخت زنجیره لایه‌ها روی اتصال TCP به هدف
+    fn stack(&self) -> Result<TransportStack> {
+        let mut stack = TransportStack::new(TcpDialer::new(self.entry()).with_mark(self.mark));
+        for layer in self.layers.iter().take(MAX_LAYERS) {
+            if let Some(transport) = self.transport(layer)? {
+                stack.push(transport);
+            }
+        }
+        Ok(stack)
     }
 
-    /// ساخت ShadowTLS Hello
-    fn build_shadowtls_hello(&self, sni: &str) -> Vec<u8> {
-        let mut hello = Vec::new();
-        
-        hello.push(0x16);
-        hello.push(0x03);
-        hello.push(0x01);
-        
-        let sni_bytes = sni.as_bytes();
-        hello.extend_from_slice(sni_bytes);
-        
-        hello
-    }
-
-    /// اعمال Reality
-    async fn apply_reality(&mut self, uuid: &str, _public_key: &str) -> Result<()> {
-        debug!("🌐 Applying Reality layer");
-        
-        let packet = self.build_reality_packet(uuid);
-        
-        let stream = self.tcp_stream.as_mut().context("No connection")?;
-        stream.write_all(&packet).await?;
-        
-        debug!("✅ Reality layer applied");
-        Ok(())
+    /// اتصال لایه به لایه برای عیب‌یابی (`network-ghost trace-chain`)
+    ///
+    /// برای هر لایه زمان handshake، بایت‌های روی سیم و پارامترهای توافق‌شده
+    /// (نسخه TLS، cipher، ALPN، نسخه smux) ثبت می‌شود؛ لایه‌ای که چیزی توافق
+    /// نمی‌کند آرگومان‌هایش را بدون رمزها نشان می‌دهد. اولین لایه خراب با خطای
+    /// آن مشخص می‌شود و لایه‌های بعدی اجرا نمی‌شوند.
+    pub async fn trace(&self) -> ChainTrace {
+        let mut layers = self.layers.iter().take(MAX_LAYERS).cloned().collect::<Vec<_>>();
+        if layers.first() != Some(&LayerType::Tcp) {
+            layers.insert(0, LayerType::Tcp);
+        }
+        let mut trace = ChainTrace { target: self.target, layers: Vec::new() };
+        let wire = TrafficTap::detached();
+        let mut stream: Option<BoxedStream> = None;
+
+        for layer in layers {
+            let mut record = LayerTrace {
+                kind: layer.keyword().to_string(),
+                layer: layer.to_string(),
+                params: match (&layer, self.mark) {
+                    (LayerType::Tcp, Some(mark)) => format!("{} mark={:#x}", self.entry(), mark),
+                    (LayerType::Tcp, None) => self.entry().to_string(),
+                    (other, _) => other.masked_args().join(" "),
+                },
+                handshake_ms: 0,
+                tx_bytes: 0,
+                rx_bytes: 0,
+                status: LayerStatus::Skipped,
+            };
+            if trace.failed_at().is_some() {
+                trace.layers.push(record);
+                continue;
+            }
+
+            let before = wire.totals();
+            let started = Instant::now();
+            let result = tokio::time::timeout(TRACE_LAYER_TIMEOUT, async {
+                let (mut next, negotiated) = match (stream.take(), self.transport(&layer)?) {
+                    (None, _) => {
+                        let tcp = TcpDialer::new(self.entry()).with_mark(self.mark).dial().await?;
+                        (Box::new(CountingStream::new(tcp, wire.clone())) as BoxedStream, Vec::new())
+                    }
+                    (Some(lower), Some(transport)) => transport.connect_traced(lower).await?,
+                    (Some(lower), None) => (lower, Vec::new()),
+                };
+                next.flush().await?;
+                anyhow::Ok((next, negotiated))
+            })
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("timed out after {}s", TRACE_LAYER_TIMEOUT.as_secs())));
+
+            let after = wire.totals();
+            record.handshake_ms = started.elapsed().as_millis() as u64;
+            record.tx_bytes = after.tx_bytes - before.tx_bytes;
+            record.rx_bytes = after.rx_bytes - before.rx_bytes;
+            record.status = match result {
+                Ok((next, negotiated)) => {
+                    if !negotiated.is_empty() {
+                        record.params = negotiated.join(" ");
+                    }
+                    stream = Some(next);
+                    LayerStatus::Ok
+                }
+                Err(e) => LayerStatus::Failed { error: format!("{:#}", e) },
+            };
+            trace.layers.push(record);
+        }
+        trace
     }
 
-    /// ساخت Reality packet
-    fn build_reality_packet(&self, uuid: &str) -> Vec<u8> {
-        let mut packet = Vec::new();
-        
-        packet.push(0x01);
-        
-        let uuid_clean = uuid.replace('-', "");
-        if let Ok(bytes) = hex::decode(&uuid_clean) {
-            packet.extend(bytes);
-        }
-        
-        packet
-    }
-
-    /// اعمال SMUX
-    async fn apply_smux(&mut self) -> Result<()> {
-        debug!("📦 Applying SMUX layer");
-        
-        let open = vec![
-            0x01, 0x04, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
-        ];
-        
-        let stream = self.tcp_stream.as_mut().context("No connection")?;
-        stream.write_all(&open).await?;
-        
-        debug!("✅ SMUX layer applied");
+    /// شروع اتصال
+    pub async fn start(&mut self) -> Result<()> {
+        info!("🚀 Starting Matryoshka Dialer with {} layers", self.layers.len().min(MAX_LAYERS));
+        self.stream = Some(self.stack()?.dial().await?);
+        self.active = true;
+        info!("✅ Matryoshka chain established");
         Ok(())
     }
 
     /// ارسال داده
     pub async fn send(&mut self, data: &[u8]) -> Result<usize> {
-        let stream = self.tcp_stream.as_mut().context("No connection")?;
+        let stream = self.stream.as_mut().context("No connection")?;
         stream.write_all(data).await?;
         Ok(data.len())
     }
 
     /// دریافت داده
     pub async fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let stream = self.tcp_stream.as_mut().context("No connection")?;
+        let stream = self.stream.as_mut().context("No connection")?;
         let n = stream.read(buf).await?;
         Ok(n)
     }
 
     /// بستن اتصال
     pub async fn close(&mut self) -> Result<()> {
-        if let Some(stream) = self.tcp_stream.take() {
+        if let Some(stream) = self.stream.take() {
             drop(stream);
         }
         self.active = false;
@@ -239,10 +672,197 @@ impl MatryoshkaDialer {
         Ok(())
     }
 
+    /// تحویل کانکشن پس از اعمال لایه‌ها (برای relay مستقیم)
+    pub fn into_stream(mut self) -> Result<TunnelStream> {
+        self.stream.take().context("No connection")
+    }
+
     /// آیا فعال است؟
     pub fn is_active(&self) -> bool {
         self.active
     }
 }
 
+/// حداکثر زمان handshake هر لایه در [`MatryoshkaDialer::trace`]
+const TRACE_LAYER_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// نتیجه یک لایه در trace
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum LayerStatus {
+    /// handshake موفق
+    Ok,
+    /// این لایه شکست خورد
+    Failed { error: String },
+    /// به دلیل شکست لایه قبلی اجرا نشد
+    Skipped,
+}
+
+/// گزارش یک لایه
+///
+/// خود [`LayerType`] عمداً ذخیره نمی‌شود چون رمز ShadowTLS، UUID و کلید Reality
+/// را دارد و خروجی `trace-chain --json` در گزارش باگ‌ها کپی می‌شود.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LayerTrace {
+    /// کلمه کلیدی لایه در [`ChainSpec`] (`shadowtls`، `reality`، ...)
+    pub kind: String,
+    /// نام نمایشی لایه
+    pub layer: String,
+    /// پارامترهای توافق‌شده یا آرگومان‌های لایه (بدون رمزها)
+    pub params: String,
+    /// زمان handshake (میلی‌ثانیه)
+    pub handshake_ms: u64,
+    /// بایت‌های ارسالی روی سیم در handshake (سربار لایه)
+    pub tx_bytes: u64,
+    /// بایت‌های دریافتی روی سیم در handshake
+    pub rx_bytes: u64,
+    pub status: LayerStatus,
+}
+
+/// گزارش trace کل زنجیره
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainTrace {
+    pub target: SocketAddr,
+    pub layers: Vec<LayerTrace>,
+}
+
+impl ChainTrace {
+    /// لایه‌ای که زنجیره در آن شکست خورد
+    pub fn failed_at(&self) -> Option<&LayerTrace> {
+        self.layers.iter().find(|l| matches!(l.status, LayerStatus::Failed { .. }))
+    }
+
+    /// مجموع سربار handshake همه لایه‌ها (بایت)
+    pub fn overhead_bytes(&self) -> u64 {
+        self.layers.iter().map(|l| l.tx_bytes + l.rx_bytes).sum()
+    }
+}
+
+impl std::fmt::Display for ChainTrace {
+    /// جدول لایه‌ها برای `trace-chain`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Chain trace to {}", self.target)?;
+        writeln!(f, "{:>2}  {:<18} {:>9} {:>8} {:>8}  {:<7} PARAMS", "#", "LAYER", "TIME", "TX", "RX", "STATUS")?;
+        for (i, l) in self.layers.iter().enumerate() {
+            let (status, time) = match l.status {
+                LayerStatus::Ok => ("ok", format!("{}ms", l.handshake_ms)),
+                LayerStatus::Failed { .. } => ("FAILED", format!("{}ms", l.handshake_ms)),
+                LayerStatus::Skipped => ("-", "-".to_string()),
+            };
+            writeln!(
+                f,
+                "{:>2}  {:<18} {:>9} {:>8} {:>8}  {:<7} {}",
+                i + 1,
+                l.layer,
+                time,
+                l.tx_bytes,
+                l.rx_bytes,
+                status,
+                l.params
+            )?;
+        }
+        match self.failed_at() {
+            Some(LayerTrace { layer, status: LayerStatus::Failed { error }, .. }) => {
+                write!(f, "Failed at {}: {}", layer, error)
+            }
+            _ => write!(f, "Chain established ({} bytes of handshake overhead)", self.overhead_bytes()),
+        }
+    }
+}
+
+/// کانکشن per-connection زنجیره (خروجی بالاترین لایه)
+pub type TunnelStream = BoxedStream;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    #[test]
+    fn test_chain_spec_parse_and_rules() {
+        let spec: ChainSpec =
+            "tcp > ws(cdn.example.com,/ws) > shadowtls(sni=digikala.com) > reality > smux".parse().unwrap();
+        assert_eq!(spec.layers().len(), 5);
+        assert_eq!(
+            spec.layers()[1],
+            LayerType::WebSocket { host: "cdn.example.com".into(), path: "/ws".into() }
+        );
+        // نمایش دوباره همان زنجیره را می‌سازد
+        assert_eq!(spec.to_string().parse::<ChainSpec>().unwrap(), spec);
+
+        let resolved = spec.resolve("aparat.com", "uuid-from-proxy", "pbk", "6ba8");
+        assert_eq!(
+            resolved[2],
+            LayerType::ShadowTls { sni: "digikala.com".into(), password: "uuid-from-proxy".into(), strict: true }
+        );
+        assert_eq!(
+            resolved[3],
+            LayerType::Reality {
+                uuid: "uuid-from-proxy".into(),
+                public_key: "pbk".into(),
+                short_id: "6ba8".into(),
+                sni: "aparat.com".into(),
+            }
+        );
+
+        let relay: ChainSpec = "relay(1.1.1.1:443, 1.0.0.1:8443) > padding(max=900)".parse().unwrap();
+        assert_eq!(relay.layers()[1], LayerType::Padding { min: 0, max: 900 });
+
+        for bad in [
+            "ws > tcp",
+            "shadowtls > smux > reality",
+            "shadowtls(strict=maybe)",
+            "reality > ws",
+            "reality > reality",
+            "ws > relay(1.1.1.1:443)",
+            "relay",
+            "padding(900,100)",
+            "quic",
+            "ws(a,b,c)",
+            "ws(port=80)",
+            "reality(not-a-uuid)",
+            "reality(pbk=c2hvcnQ)",
+            "reality(sid=6ba85179e30d4fc2aa)",
+        ] {
+            assert!(bad.parse::<ChainSpec>().is_err(), "{} should be rejected", bad);
+        }
+        let too_long = vec!["padding"; MAX_LAYERS + 1].join(" > ");
+        assert!(too_long.parse::<ChainSpec>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_trace_reports_failing_layer() {
+        const UUID: &str = "b831381d-6324-4d53-ad4f-8cda48b30811";
+        const PBK: &str = "8f4e1d2c3b4a59687766554433221100ffeeddccbbaa99887766554433221100";
+        // سرور فقط TCP را می‌پذیرد و بعد از دریافت ClientHello قطع می‌کند
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = socket.read(&mut buf).await;
+        });
+
+        let trace = MatryoshkaDialer::new(target)
+            .wrap_with_shadowtls("digikala.com", "s3cret-pass")
+            .wrap_with_reality(UUID, PBK, "0123456789abcdef", "www.speedtest.net")
+            .enable_smux()
+            .trace()
+            .await;
+
+        let statuses: Vec<_> = trace.layers.iter().map(|l| l.status.clone()).collect();
+        assert_eq!(statuses[0], LayerStatus::Ok);
+        assert!(matches!(statuses[1], LayerStatus::Failed { .. }));
+        assert_eq!(&statuses[2..], &[LayerStatus::Skipped, LayerStatus::Skipped]);
+        assert_eq!(trace.failed_at().unwrap().kind, "shadowtls");
+        assert_eq!(trace.layers[1].params, "sni=digikala.com password=***");
+        assert!(trace.layers[1].tx_bytes > 0);
+        assert!(trace.to_string().contains("Failed at ShadowTLS v3"));
+
+        // خروجی `--json` در گزارش باگ‌ها کپی می‌شود و نباید هیچ رمزی داشته باشد
+        let json = serde_json::to_string(&trace).unwrap();
+        for secret in ["s3cret-pass", UUID, PBK, "0123456789abcdef"] {
+            assert!(!json.contains(secret), "secret {} leaked into {}", secret, json);
+        }
+        assert!(json.contains("\"kind\":\"reality\""));
+    }
+}
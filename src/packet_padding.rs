@@ -1,9 +1,15 @@
 //! Network Ghost v5.0 - Smart Packet Padding & Obfuscation
 //! لایه‌ی محافظتی پیشرفته برای دور زدن آنالیز سایز پکت (DPI)
 
+use std::io;
+
+use anyhow::Result;
+use async_trait::async_trait;
 use rand::{Rng, RngCore, thread_rng};
 use tracing::debug;
 
+use crate::transport::{BoxedStream, Frame, FrameCodec, FramedStream, Transport};
+
 /// الگوی Padding پیشرفته
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PaddingPattern {
@@ -123,3 +129,58 @@ impl GhostPadding {
         protector.apply_smart(buffer);
     }
 }
+
+/// لایه Padding در زنجیره Matryoshka
+///
+/// هر تکه داده به شکل `[طول داده u16][طول پدینگ u16][داده][پدینگ]` فرستاده
+/// می‌شود؛ طول پدینگ تصادفی بین `min` و `max` است. طرف مقابل باید همین لایه
+/// را داشته باشد.
+#[derive(Debug, Clone)]
+pub struct PaddingLayer {
+    min: usize,
+    max: usize,
+}
+
+impl PaddingLayer {
+    pub fn new(min: usize, max: usize) -> Self {
+        let max = max.min(u16::MAX as usize);
+        Self { min: min.min(max), max }
+    }
+}
+
+#[async_trait]
+impl Transport for PaddingLayer {
+    fn name(&self) -> &'static str {
+        "Padding"
+    }
+
+    async fn connect_over(&self, lower: BoxedStream) -> Result<BoxedStream> {
+        Ok(Box::new(FramedStream::new(lower, self.clone())))
+    }
+}
+
+impl FrameCodec for PaddingLayer {
+    fn encode(&mut self, payload: &[u8], dst: &mut Vec<u8>) {
+        let pad = thread_rng().gen_range(self.min..=self.max);
+        dst.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        dst.extend_from_slice(&(pad as u16).to_be_bytes());
+        dst.extend_from_slice(payload);
+        let start = dst.len();
+        dst.resize(start + pad, 0);
+        thread_rng().fill_bytes(&mut dst[start..]);
+    }
+
+    fn decode(&mut self, src: &mut Vec<u8>, _reply: &mut Vec<u8>) -> io::Result<Option<Frame>> {
+        if src.len() < 4 {
+            return Ok(None);
+        }
+        let data_len = u16::from_be_bytes([src[0], src[1]]) as usize;
+        let pad_len = u16::from_be_bytes([src[2], src[3]]) as usize;
+        if src.len() < 4 + data_len + pad_len {
+            return Ok(None);
+        }
+        let data = src[4..4 + data_len].to_vec();
+        src.drain(..4 + data_len + pad_len);
+        Ok(Some(Frame::Data(data)))
+    }
+}
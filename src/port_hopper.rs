@@ -24,7 +24,8 @@ const DEFAULT_HOP_INTERVAL_SECS: u64 = 300; // 5 دقیقه
 // ==================== PORT STRATEGY ====================
 
 /// استراتژی انتخاب پورت
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum PortStrategy {
     /// ترتیبی
     Sequential,
@@ -35,15 +36,10 @@ pub enum PortStrategy {
     /// بر اساس تأخیر
     LatencyBased,
     /// تطبیقی
+    #[default]
     Adaptive,
 }
 
-impl Default for PortStrategy {
-    fn default() -> Self {
-        Self::Adaptive
-    }
-}
-
 // ==================== PORT STATE ====================
 
 /// وضعیت یک پورت
@@ -0,0 +1,179 @@
+//! Uplink Profiles
+//!
+//! تشخیص اپراتور WAN (gateway پیش‌فرض و ASN) و انتخاب پروفایل مناسب از
+//! `[profiles.<name>]` تا با عوض شدن uplink تنظیمات هم عوض شود.
+
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::config::GhostConfig;
+
+/// مقدار `profile` برای انتخاب خودکار
+pub const AUTO: &str = "auto";
+
+/// سرویس تشخیص ASN (خروجی JSON با فیلد `org` مثل `AS44244 Irancell`)
+const ASN_LOOKUP_URL: &str = "https://ipinfo.io/json";
+
+/// اطلاعات uplink فعلی
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Uplink {
+    /// اینترفیس WAN
+    pub interface: Option<String>,
+    /// gateway پیش‌فرض
+    pub gateway: Option<IpAddr>,
+    /// شماره AS
+    pub asn: Option<u32>,
+    /// نام سازمان/اپراتور
+    pub org: Option<String>,
+}
+
+impl Uplink {
+    /// تشخیص gateway از جدول مسیریابی و ASN از سرویس خارجی
+    pub async fn detect() -> Self {
+        let (interface, gateway) = match default_route() {
+            Some((iface, gw)) => (Some(iface), Some(IpAddr::V4(gw))),
+            None => (None, None),
+        };
+        let (asn, org) = match lookup_asn(Duration::from_secs(5)).await {
+            Ok((asn, org)) => (Some(asn), Some(org)),
+            Err(e) => {
+                warn!("⚠️ تشخیص ASN ناموفق: {:#}", e);
+                (None, None)
+            }
+        };
+        Self { interface, gateway, asn, org }
+    }
+}
+
+/// gateway پیش‌فرض از `/proc/net/route`
+pub fn default_route() -> Option<(String, Ipv4Addr)> {
+    let content = std::fs::read_to_string("/proc/net/route").ok()?;
+    parse_default_route(&content)
+}
+
+/// خواندن مسیر `0.0.0.0/0` از محتوای `/proc/net/route`
+fn parse_default_route(content: &str) -> Option<(String, Ipv4Addr)> {
+    content.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 8 || fields[1] != "00000000" || fields[7] != "00000000" {
+            return None;
+        }
+        // آدرس‌ها به صورت hex و little-endian نوشته می‌شوند
+        let gateway = u32::from_str_radix(fields[2], 16).ok()?;
+        Some((fields[0].to_string(), Ipv4Addr::from(gateway.swap_bytes())))
+    })
+}
+
+/// ASN و نام اپراتور IP عمومی فعلی
+pub async fn lookup_asn(timeout: Duration) -> Result<(u32, String)> {
+    #[derive(Deserialize)]
+    struct Info {
+        org: Option<String>,
+    }
+
+    let info: Info = reqwest::Client::builder()
+        .timeout(timeout)
+        .build()?
+        .get(ASN_LOOKUP_URL)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let org = info.org.unwrap_or_default();
+    match parse_org(&org) {
+        Some(asn) => Ok((asn, org)),
+        None => bail!("No ASN in lookup response: {:?}", org),
+    }
+}
+
+/// `AS44244 Iran Cell Service` → 44244
+fn parse_org(org: &str) -> Option<u32> {
+    org.split_whitespace().next()?.strip_prefix("AS")?.parse().ok()
+}
+
+/// اعمال پروفایل درخواستی روی پیکربندی
+///
+/// `requested` (فلگ `--profile`) بر `[general].profile` اولویت دارد. مقدار خالی
+/// یعنی بدون پروفایل، `auto` یعنی انتخاب بر اساس uplink. نام پروفایل اعمال‌شده
+/// برگردانده می‌شود.
+pub async fn apply(config: &mut GhostConfig, requested: Option<&str>) -> Result<Option<String>> {
+    let requested = requested.unwrap_or(&config.general.profile).to_string();
+    let name = match requested.as_str() {
+        "" => return Ok(None),
+        AUTO => {
+            if config.profiles.is_empty() {
+                return Ok(None);
+            }
+            let uplink = Uplink::detect().await;
+            debug!("Uplink: {:?}", uplink);
+            match config.match_profile(&uplink) {
+                Some(name) => {
+                    info!(
+                        "📡 uplink {} (AS{}) → پروفایل {}",
+                        uplink.gateway.map(|g| g.to_string()).unwrap_or_else(|| "?".to_string()),
+                        uplink.asn.map(|a| a.to_string()).unwrap_or_else(|| "?".to_string()),
+                        name
+                    );
+                    name.to_string()
+                }
+                None => {
+                    info!("📡 هیچ پروفایلی با uplink فعلی ({:?}) منطبق نیست", uplink.org);
+                    return Ok(None);
+                }
+            }
+        }
+        name => name.to_string(),
+    };
+
+    config.apply_profile(&name)?;
+    Ok(Some(name))
+}
+
+/// پایش تغییر uplink (بر اساس gateway پیش‌فرض)
+#[derive(Debug, Default)]
+pub struct UplinkWatcher {
+    last: Option<(String, Ipv4Addr)>,
+}
+
+impl UplinkWatcher {
+    /// ساخت watcher با وضعیت فعلی
+    pub fn new() -> Self {
+        Self { last: default_route() }
+    }
+
+    /// آیا از آخرین بررسی uplink عوض شده است
+    pub fn changed(&mut self) -> bool {
+        let current = default_route();
+        if current == self.last {
+            return false;
+        }
+        info!("📡 uplink تغییر کرد: {:?} → {:?}", self.last, current);
+        self.last = current;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_default_route() {
+        let route = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n\
+                     br-lan\t0001A8C0\t00000000\t0001\t0\t0\t0\t00FFFFFF\t0\t0\t0\n\
+                     wwan0\t00000000\t0102A8C0\t0003\t0\t0\t0\t00000000\t0\t0\t0\n";
+        assert_eq!(
+            parse_default_route(route),
+            Some(("wwan0".to_string(), Ipv4Addr::new(192, 168, 2, 1)))
+        );
+        assert_eq!(parse_org("AS197207 Mobile Communication Company of Iran PLC"), Some(197207));
+        assert_eq!(parse_org("Irancell"), None);
+    }
+}
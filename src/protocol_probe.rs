@@ -0,0 +1,430 @@
+//! Protocol Auto-Selection
+//!
+//! در حالت `protocol = "auto"` پروتکل‌های کاندید (Reality روی TCP،
+//! Hysteria2/TUIC روی UDP، XHTTP و WebSocket) روی IP تمیز انتخاب‌شده تست
+//! می‌شوند و سریع‌ترین handshake موفق انتخاب می‌شود. نتیجه برای هر شبکه
+//! (اینترفیس + gateway) در فایل cache نگه داشته می‌شود.
+
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time::timeout,
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    hysteria2::{Hysteria2, Hysteria2Config, ObfsType},
+    profile,
+    reality::{Reality, RealityConfig},
+    transport::{
+        DatagramDialer, DatagramSocket, DatagramTransport, Dialer, TcpDialer, Transport, UdpDialer,
+    },
+    tuic::{Tuic, TuicConfig},
+    types::{ProtocolSettings, ProtocolType, ProxyConfig, StreamSettings, VlessSettings},
+    websocket_transport::{WsTransport, WsTransportConfig},
+    xhttp::XhttpClient,
+};
+
+/// مسیر پیش‌فرض cache نتیجه انتخاب
+pub const DEFAULT_CACHE_PATH: &str = "/opt/network-ghost/cache/protocol.json";
+
+/// حداکثر زمان هر تست
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(6);
+
+/// مقصد درخواست آزمایشی از داخل تونل
+const PROBE_TARGET: (&str, u16) = ("www.gstatic.com", 80);
+const PROBE_REQUEST: &[u8] =
+    b"HEAD /generate_204 HTTP/1.1\r\nHost: www.gstatic.com\r\nConnection: close\r\n\r\n";
+
+/// پروتکل‌های کاندید حالت auto
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Candidate {
+    /// VLESS + Reality روی TCP
+    Reality,
+    /// Hysteria2 روی UDP
+    Hysteria2,
+    /// TUIC v5 روی UDP
+    Tuic,
+    /// XHTTP (HTTP/2)
+    Xhttp,
+    /// VLESS روی WebSocket
+    WebSocket,
+}
+
+impl Candidate {
+    /// همه کاندیدها به ترتیب اولویت (در تساوی تأخیر)
+    pub const ALL: [Candidate; 5] = [
+        Candidate::Reality,
+        Candidate::Hysteria2,
+        Candidate::Tuic,
+        Candidate::Xhttp,
+        Candidate::WebSocket,
+    ];
+
+    /// پروتکل متناظر در پیکربندی
+    pub fn protocol(self) -> ProtocolType {
+        match self {
+            Candidate::Reality => ProtocolType::Reality,
+            Candidate::Hysteria2 => ProtocolType::Hysteria2,
+            Candidate::Tuic => ProtocolType::Tuic,
+            Candidate::Xhttp => ProtocolType::Xhttp,
+            Candidate::WebSocket => ProtocolType::Vless,
+        }
+    }
+
+    /// پیکربندی پروکسی با این پروتکل؛ تنظیمات ناسازگار کنار گذاشته می‌شوند
+    pub fn apply_to(self, proxy: &ProxyConfig) -> ProxyConfig {
+        let mut out = proxy.clone();
+        out.protocol = self.protocol();
+        out.settings = match (self, &proxy.settings) {
+            (Candidate::Reality, Some(ProtocolSettings::Vless(v)))
+                if v.transport.network == "tcp" =>
+            {
+                proxy.settings.clone()
+            }
+            (Candidate::Hysteria2, Some(ProtocolSettings::Hysteria2(_)))
+            | (Candidate::Tuic, Some(ProtocolSettings::Tuic(_))) => proxy.settings.clone(),
+            (Candidate::WebSocket, settings) => {
+                let mut vless = match settings {
+                    Some(ProtocolSettings::Vless(v)) => v.clone(),
+                    _ => VlessSettings::default(),
+                };
+                if vless.transport.network != "ws" {
+                    vless.transport = StreamSettings {
+                        network: "ws".to_string(),
+                        security: "tls".to_string(),
+                        path: Some("/ws".to_string()),
+                        host: Some(proxy.sni.clone()),
+                        ..StreamSettings::default()
+                    };
+                }
+                Some(ProtocolSettings::Vless(vless))
+            }
+            _ => None,
+        };
+        out
+    }
+}
+
+impl std::fmt::Display for Candidate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Candidate::Reality => "reality",
+            Candidate::Hysteria2 => "hysteria2",
+            Candidate::Tuic => "tuic",
+            Candidate::Xhttp => "xhttp",
+            Candidate::WebSocket => "websocket",
+        };
+        f.write_str(name)
+    }
+}
+
+/// نتیجه تست یک کاندید
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProbeResult {
+    pub candidate: Candidate,
+    /// تأخیر handshake در صورت موفقیت
+    pub latency_ms: Option<u64>,
+    /// علت شکست
+    pub error: Option<String>,
+}
+
+impl ProbeResult {
+    pub fn is_ok(&self) -> bool {
+        self.latency_ms.is_some()
+    }
+}
+
+/// تست handshake یک کاندید روی `addr`
+pub async fn probe(candidate: Candidate, proxy: &ProxyConfig, addr: SocketAddr) -> ProbeResult {
+    let start = Instant::now();
+    let outcome = match timeout(PROBE_TIMEOUT, handshake(candidate, proxy, addr)).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow::anyhow!("timed out after {}s", PROBE_TIMEOUT.as_secs())),
+    };
+    match outcome {
+        Ok(()) => {
+            let latency = start.elapsed().as_millis() as u64;
+            debug!("✅ {} @ {}: {}ms", candidate, addr, latency);
+            ProbeResult { candidate, latency_ms: Some(latency), error: None }
+        }
+        Err(e) => {
+            debug!("❌ {} @ {}: {:#}", candidate, addr, e);
+            ProbeResult { candidate, latency_ms: None, error: Some(format!("{:#}", e)) }
+        }
+    }
+}
+
+/// تست همزمان همه کاندیدها، مرتب‌شده از بهترین به بدترین
+pub async fn probe_all(proxy: &ProxyConfig, addr: SocketAddr) -> Vec<ProbeResult> {
+    let probes = Candidate::ALL.iter().map(|&c| probe(c, proxy, addr));
+    let mut results = futures::future::join_all(probes).await;
+    // مرتب‌سازی پایدار: موفق‌ها بر اساس تأخیر، در تساوی ترتیب ALL
+    results.sort_by_key(|r| r.latency_ms.unwrap_or(u64::MAX));
+    results
+}
+
+async fn handshake(candidate: Candidate, proxy: &ProxyConfig, addr: SocketAddr) -> Result<()> {
+    let (host, port) = PROBE_TARGET;
+    match candidate {
+        Candidate::Reality => {
+            let stream = TcpDialer::new(addr).dial().await?;
+            let config = RealityConfig::from_uuid_str(
+                &proxy.uuid,
+                proxy.public_key.as_deref().unwrap_or_default(),
+                proxy.short_id.as_deref().unwrap_or_default(),
+                &proxy.sni,
+            )?;
+            let reality = Reality::new().with_config(config).with_destination(host, port, 0x01);
+            let mut stream = reality.connect_over(stream).await?;
+            stream.write_all(PROBE_REQUEST).await.context("Reality data write failed")?;
+            let mut buf = [0u8; 1500];
+            if stream.read(&mut buf).await? == 0 {
+                bail!("Reality connection closed by server");
+            }
+        }
+        Candidate::Hysteria2 => {
+            let socket = UdpDialer::new(addr).dial().await?;
+            Hysteria2::new(hysteria2_config(proxy)).connect_over(socket).await?;
+        }
+        Candidate::Tuic => {
+            let tuic = Tuic::new(tuic_config(proxy)).open(UdpDialer::new(addr).dial().await?).await?;
+            tuic.send_connect(host, port).await?;
+            let mut buf = [0u8; 1500];
+            if tuic.recv(&mut buf).await? == 0 {
+                bail!("empty TUIC response");
+            }
+        }
+        Candidate::Xhttp => {
+            let mut client = XhttpClient::new(addr.ip(), addr.port()).with_host(&proxy.sni);
+            if let Some(path) = transport(proxy).and_then(|t| t.path.as_deref()) {
+                client = client.with_path(path);
+            }
+            let mut stream = client.dial().await?;
+            stream.write_all(PROBE_REQUEST).await.context("XHTTP data write failed")?;
+            let mut buf = [0u8; 1500];
+            if stream.read(&mut buf).await? == 0 {
+                bail!("XHTTP connection closed by server");
+            }
+        }
+        Candidate::WebSocket => {
+            let ws_proxy = Candidate::WebSocket.apply_to(proxy);
+            let t = transport(&ws_proxy).cloned().unwrap_or_default();
+            let config = WsTransportConfig {
+                host: t.host.unwrap_or_else(|| proxy.sni.clone()),
+                path: t.path.unwrap_or_else(|| "/ws".to_string()),
+                ..Default::default()
+            };
+            WsTransport::new(addr.ip(), addr.port(), config).dial().await?;
+        }
+    }
+    Ok(())
+}
+
+/// تنظیمات Hysteria2 از `[proxy]` (بدون `[proxy.settings]`: UUID به عنوان رمز)
+pub fn hysteria2_config(proxy: &ProxyConfig) -> Hysteria2Config {
+    let mut config = Hysteria2Config { sni: proxy.sni.clone(), ..Default::default() };
+    match &proxy.settings {
+        Some(ProtocolSettings::Hysteria2(s)) => {
+            config.auth_str = s.password.clone();
+            config.insecure = s.insecure;
+            match &s.obfs_password {
+                Some(password) => config.obfs_password = password.clone(),
+                None => config.obfs_type = ObfsType::None,
+            }
+        }
+        _ => {
+            config.auth_str = proxy.uuid.clone();
+            config.obfs_type = ObfsType::None;
+        }
+    }
+    config
+}
+
+/// تنظیمات TUIC از `[proxy]`
+pub fn tuic_config(proxy: &ProxyConfig) -> TuicConfig {
+    let mut config = TuicConfig::default();
+    if let Ok(uuid) = uuid::Uuid::parse_str(&proxy.uuid) {
+        config.uuid = *uuid.as_bytes();
+    }
+    if let Some(ProtocolSettings::Tuic(s)) = &proxy.settings {
+        config.password = s.password.clone();
+    }
+    config
+}
+
+fn transport(proxy: &ProxyConfig) -> Option<&StreamSettings> {
+    match &proxy.settings {
+        Some(ProtocolSettings::Vless(v)) => Some(&v.transport),
+        Some(ProtocolSettings::Trojan(t)) => Some(&t.transport),
+        _ => None,
+    }
+}
+
+/// کلید شبکه فعلی (اینترفیس و gateway پیش‌فرض)
+pub fn network_key() -> String {
+    match profile::default_route() {
+        Some((iface, gateway)) => format!("{}@{}", iface, gateway),
+        None => "default".to_string(),
+    }
+}
+
+/// انتخاب ذخیره‌شده برای یک شبکه
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedChoice {
+    pub candidate: Candidate,
+    pub latency_ms: u64,
+    /// IP تمیزی که تست روی آن انجام شد
+    pub ip: IpAddr,
+    pub selected_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Cache انتخاب پروتکل به ازای هر شبکه
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProtocolCache {
+    pub networks: BTreeMap<String, CachedChoice>,
+}
+
+impl ProtocolCache {
+    /// بارگذاری از فایل (نبود فایل = cache خالی)
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read protocol cache {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Invalid protocol cache {}", path.display()))
+    }
+
+    /// ذخیره در فایل
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).ok();
+        }
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// انتخاب‌گر پروتکل حالت auto
+#[derive(Debug, Clone)]
+pub struct ProtocolSelector {
+    cache_path: String,
+}
+
+impl ProtocolSelector {
+    pub fn new(cache_path: impl Into<String>) -> Self {
+        Self { cache_path: cache_path.into() }
+    }
+
+    /// انتخاب پروتکل برای `addr`
+    ///
+    /// اگر `force` نباشد و انتخاب ذخیره‌شده این شبکه هنوز کار کند، بدون تست
+    /// بقیه کاندیدها استفاده می‌شود.
+    pub async fn select(
+        &self,
+        proxy: &ProxyConfig,
+        addr: SocketAddr,
+        force: bool,
+    ) -> Result<CachedChoice> {
+        let network = network_key();
+        let mut cache = ProtocolCache::load(&self.cache_path).unwrap_or_else(|e| {
+            warn!("⚠️ {:#} — ignoring protocol cache", e);
+            ProtocolCache::default()
+        });
+
+        if !force {
+            if let Some(cached) = cache.networks.get(&network) {
+                let result = probe(cached.candidate, proxy, addr).await;
+                if let Some(latency_ms) = result.latency_ms {
+                    info!("🎯 Cached protocol {} for {} still works ({}ms)", cached.candidate, network, latency_ms);
+                    return Ok(CachedChoice {
+                        latency_ms,
+                        ip: addr.ip(),
+                        ..cached.clone()
+                    });
+                }
+                info!("🔁 Cached protocol {} for {} failed — probing all", cached.candidate, network);
+            }
+        }
+
+        info!("🔬 Probing {} protocols on {}...", Candidate::ALL.len(), addr);
+        let results = probe_all(proxy, addr).await;
+        for r in &results {
+            match (r.latency_ms, &r.error) {
+                (Some(ms), _) => info!("   ✅ {:<10} {}ms", r.candidate.to_string(), ms),
+                (None, Some(e)) => info!("   ❌ {:<10} {}", r.candidate.to_string(), e),
+                (None, None) => {}
+            }
+        }
+
+        let best = results
+            .iter()
+            .find(|r| r.is_ok())
+            .with_context(|| format!("No protocol completed a handshake with {}", addr))?;
+        let choice = CachedChoice {
+            candidate: best.candidate,
+            latency_ms: best.latency_ms.unwrap_or_default(),
+            ip: addr.ip(),
+            selected_at: chrono::Utc::now(),
+        };
+
+        cache.networks.insert(network, choice.clone());
+        if let Err(e) = cache.save(&self.cache_path) {
+            warn!("⚠️ {:#}", e);
+        }
+        Ok(choice)
+    }
+}
+
+impl Default for ProtocolSelector {
+    fn default() -> Self {
+        Self::new(DEFAULT_CACHE_PATH)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_candidate_apply_to() {
+        let proxy = ProxyConfig {
+            sni: "digikala.com".to_string(),
+            settings: Some(ProtocolSettings::Hysteria2(Default::default())),
+            ..Default::default()
+        };
+
+        let hy2 = Candidate::Hysteria2.apply_to(&proxy);
+        assert_eq!(hy2.protocol, ProtocolType::Hysteria2);
+        assert_eq!(hy2.settings, proxy.settings);
+
+        assert_eq!(Candidate::Reality.apply_to(&proxy).settings, None);
+
+        let ws = Candidate::WebSocket.apply_to(&proxy);
+        assert_eq!(ws.protocol, ProtocolType::Vless);
+        match ws.settings {
+            Some(ProtocolSettings::Vless(v)) => {
+                assert_eq!(v.transport.network, "ws");
+                assert_eq!(v.transport.host.as_deref(), Some("digikala.com"));
+            }
+            other => panic!("unexpected settings: {:?}", other),
+        }
+    }
+}
@@ -1,69 +1,179 @@
 //! Reality Protocol — VLESS over TLS 1.3 with ECH/uTLS Masquerading
 //! از Reality برای پنهان کردن ترافیک VLESS در TLS واقعی استفاده می‌کند
+//!
+//! کلاینت یک ClientHello با شکل Chrome می‌فرستد که session ID آن احراز هویت
+//! REALITY را حمل می‌کند (سازگار با Xray):
+//!
+//! - `auth_key = HKDF-SHA256(X25519(ephemeral, public_key), random[..20], "REALITY")`
+//! - session ID = `AES-256-GCM(auth_key, nonce = random[20..], [ver x,y,z, 0][unix time u32][short_id 8])`
+//!   با کل پیام ClientHello (session ID صفر) به عنوان AAD
+//!
+//! سرور REALITY گواهی موقت Ed25519 می‌فرستد که امضایش
+//! `HMAC-SHA512(auth_key, کلید عمومی گواهی)` است؛ اگر نباشد سرور REALITY نیست
+//! یا `public_key` اشتباه است و اتصال رد می‌شود.
 
-use anyhow::{Context, Result};
-use rand::{RngCore, thread_rng};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use std::{
+    io,
+    pin::Pin,
+    task::{ready, Context as TaskContext, Poll},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use hkdf::Hkdf;
+use ring::{aead, hmac};
+use sha2::Sha256;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
 use tracing::{debug, info};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::{
+    share_link::decode_base64,
+    tls::{self, Certificate, ClientHello, TlsParams, RECORD_HEADER_LEN, SESSION_ID_OFFSET},
+    transport::{BoxedStream, Transport},
+};
+
+pub(crate) const VLESS_VERSION: u8 = 0;
 
-const VLESS_VERSION: u8 = 0;
+/// نسخه‌ای که در session ID اعلام می‌شود (سرور Xray می‌تواند حداقل/حداکثر نسخه را محدود کند)
+const CLIENT_VERSION: [u8; 3] = [1, 8, 0];
 
 /// پیکربندی Reality
 #[derive(Debug, Clone)]
 pub struct RealityConfig {
     pub uuid: [u8; 16],
-    pub public_key: Vec<u8>,
-    pub short_id: Vec<u8>,
+    /// کلید عمومی X25519 سرور (اجباری؛ بدون آن هیچ داده‌ای فرستاده نمی‌شود)
+    pub public_key: [u8; 32],
+    pub short_id: [u8; 8],
     pub server_name: String,
     pub fingerprint: String,
-    pub dest: String,
-    pub dest_port: u16,
 }
 
 impl RealityConfig {
-    pub fn from_uuid_str(uuid_str: &str, public_key_hex: &str, sni: &str) -> Result<Self> {
-        let uuid_clean = uuid_str.replace('-', "");
-        let uuid_bytes = hex::decode(&uuid_clean).context("Invalid UUID")?;
-        let mut uuid = [0u8; 16];
-        if uuid_bytes.len() == 16 { uuid.copy_from_slice(&uuid_bytes); }
-
-        let pub_key = if public_key_hex.is_empty() {
-            let mut k = vec![0u8; 32];
-            thread_rng().fill_bytes(&mut k);
-            k
+    /// کلید خالی یا نامعتبر و short ID نامعتبر خطا است (نه جایگزینی تصادفی)
+    pub fn from_uuid_str(uuid_str: &str, public_key: &str, short_id: &str, sni: &str) -> Result<Self> {
+        let uuid = if uuid_str.is_empty() {
+            [0u8; 16]
         } else {
-            hex::decode(public_key_hex).unwrap_or_else(|_| {
-                let mut k = vec![0u8; 32];
-                thread_rng().fill_bytes(&mut k);
-                k
-            })
+            *uuid::Uuid::parse_str(uuid_str).with_context(|| format!("Invalid UUID `{}`", uuid_str))?.as_bytes()
         };
 
-        let mut short_id = vec![0u8; 8];
-        thread_rng().fill_bytes(&mut short_id);
-
         Ok(Self {
             uuid,
-            public_key: pub_key,
-            short_id,
+            public_key: parse_public_key(public_key)?,
+            short_id: parse_short_id(short_id)?,
             server_name: sni.to_string(),
             fingerprint: "chrome".to_string(),
-            dest: sni.to_string(),
-            dest_port: 443,
         })
     }
+
+    /// session ID رمزنشده: `[ver x,y,z, 0][unix time u32][short_id]`
+    fn session_id_plaintext(&self) -> [u8; 16] {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+        let mut plain = [0u8; 16];
+        plain[..3].copy_from_slice(&CLIENT_VERSION);
+        plain[4..8].copy_from_slice(&(now as u32).to_be_bytes());
+        plain[8..].copy_from_slice(&self.short_id);
+        plain
+    }
+
+    /// handshake REALITY روی `lower`؛ خروجی جریان TLS 1.3 رمزشده و پارامترهای آن است
+    async fn handshake(&self, lower: BoxedStream, server_key: &[u8; 32]) -> Result<(BoxedStream, TlsParams)> {
+        let secret = StaticSecret::from(rand::random::<[u8; 32]>());
+        let mut hello = ClientHello::new(&self.server_name, PublicKey::from(&secret).to_bytes());
+        hello.session_id = [0u8; 32];
+        let mut record = hello.encode();
+
+        let shared = secret.diffie_hellman(&PublicKey::from(*server_key));
+        let auth_key = auth_key(shared.as_bytes(), &hello.random);
+        let mut session_id = self.session_id_plaintext().to_vec();
+        session_cipher(&auth_key)
+            .seal_in_place_append_tag(
+                session_nonce(&hello.random),
+                aead::Aad::from(&record[RECORD_HEADER_LEN..]),
+                &mut session_id,
+            )
+            .map_err(|_| anyhow!("REALITY session ID encryption failed"))?;
+        record[SESSION_ID_OFFSET..SESSION_ID_OFFSET + 32].copy_from_slice(&session_id);
+
+        let (stream, params) =
+            tls::client_handshake(lower, &record, &secret, |cert| verify_certificate(&auth_key, cert))
+                .await
+                .context("REALITY handshake failed")?;
+        debug!("✅ REALITY handshake with {} verified ({})", self.server_name, params);
+        Ok((stream, params))
+    }
+}
+
+/// کلید عمومی سرور: base64 (مثل `pbk` در لینک‌های Xray) یا ۶۴ رقم hex
+pub fn parse_public_key(key: &str) -> Result<[u8; 32]> {
+    let key = key.trim();
+    if key.is_empty() {
+        bail!("Reality needs the server public key (`public_key` in [proxy] or `pbk`)");
+    }
+    let bytes = if key.len() == 64 && key.chars().all(|c| c.is_ascii_hexdigit()) {
+        hex::decode(key)?
+    } else {
+        decode_base64(key).with_context(|| format!("Reality public key `{}` is neither base64 nor hex", key))?
+    };
+    let key: [u8; 32] = bytes
+        .try_into()
+        .map_err(|b: Vec<u8>| anyhow!("Reality public key is {} bytes, expected 32", b.len()))?;
+    Ok(key)
+}
+
+/// short ID: تا ۱۶ رقم hex (طول زوج)، با صفر تا ۸ بایت پر می‌شود
+pub fn parse_short_id(short_id: &str) -> Result<[u8; 8]> {
+    let bytes = hex::decode(short_id.trim()).with_context(|| format!("Reality short ID `{}` is not hex", short_id))?;
+    if bytes.len() > 8 {
+        bail!("Reality short ID `{}` is longer than 16 hex digits", short_id);
+    }
+    let mut id = [0u8; 8];
+    id[..bytes.len()].copy_from_slice(&bytes);
+    Ok(id)
+}
+
+/// کلید احراز هویت از راز مشترک X25519 و ۲۰ بایت اول random کلاینت
+fn auth_key(shared: &[u8], client_random: &[u8; 32]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&client_random[..20]), shared)
+        .expand(b"REALITY", &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 length");
+    key
+}
+
+fn session_cipher(auth_key: &[u8; 32]) -> aead::LessSafeKey {
+    let key = aead::UnboundKey::new(&aead::AES_256_GCM, auth_key).expect("auth key is 32 bytes");
+    aead::LessSafeKey::new(key)
+}
+
+fn session_nonce(client_random: &[u8; 32]) -> aead::Nonce {
+    aead::Nonce::try_assume_unique_for_key(&client_random[20..]).expect("nonce is 12 bytes")
+}
+
+/// گواهی موقت REALITY: امضا = HMAC-SHA512(auth_key، کلید Ed25519 گواهی)
+fn verify_certificate(auth_key: &[u8; 32], cert: &Certificate) -> Result<()> {
+    let key = cert
+        .ed25519_key
+        .context("Server certificate has no Ed25519 key (the server is not REALITY)")?;
+    let expected = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA512, auth_key), &key);
+    if expected.as_ref() != cert.signature.as_slice() {
+        bail!("Server certificate is not signed with the REALITY key (wrong public_key, or the server is not REALITY)");
+    }
+    Ok(())
 }
 
 /// VLESS/Reality کلاینت
 pub struct Reality {
-    pub stream: Option<TcpStream>,
     config: Option<RealityConfig>,
+    /// مقصد نهایی (host, port, دستور) که در هدر VLESS فرستاده می‌شود
+    destination: Option<(String, u16, u8)>,
 }
 
 impl Reality {
     pub fn new() -> Self {
-        Self { stream: None, config: None }
+        Self { config: None, destination: None }
     }
 
     pub fn with_config(mut self, cfg: RealityConfig) -> Self {
@@ -71,31 +181,14 @@ impl Reality {
         self
     }
 
-    pub fn with_stream(mut self, stream: TcpStream) -> Self {
-        self.stream = Some(stream);
+    /// مقصد نهایی؛ بدون آن لایه هدری نمی‌فرستد (فقط بررسی لایه‌های زیرین)
+    pub fn with_destination(mut self, host: &str, port: u16, cmd: u8) -> Self {
+        self.destination = Some((host.to_string(), port, cmd));
         self
     }
 
-    /// ارسال VLESS Request Header
-    pub async fn send_request_header(
-        &mut self,
-        target_host: &str,
-        target_port: u16,
-        cmd: u8,
-    ) -> Result<()> {
-        let uuid = self.config.as_ref()
-            .map(|c| c.uuid)
-            .unwrap_or([0u8; 16]);
-
-        let header = Self::build_vless_request(&uuid, cmd, target_host, target_port);
-        let stream = self.stream.as_mut().context("No stream")?;
-        stream.write_all(&header).await.context("VLESS header write failed")?;
-        debug!("✅ VLESS request header sent → {}:{}", target_host, target_port);
-        Ok(())
-    }
-
     /// ساخت VLESS Request Header (RFC)
-    fn build_vless_request(uuid: &[u8; 16], cmd: u8, host: &str, port: u16) -> Vec<u8> {
+    pub(crate) fn build_vless_request(uuid: &[u8; 16], cmd: u8, host: &str, port: u16) -> Vec<u8> {
         let mut buf = Vec::new();
         buf.push(VLESS_VERSION);           // Version
         buf.extend_from_slice(uuid);       // UUID (16 bytes)
@@ -108,45 +201,266 @@ impl Reality {
         buf.extend_from_slice(host_bytes);
         buf
     }
+}
 
-    /// ارسال داده محافظت‌شده
-    pub async fn send_protected(&mut self, data: &[u8]) -> Result<()> {
-        let stream = self.stream.as_mut().context("No stream")?;
-        stream.write_all(data).await.context("Reality data write failed")?;
-        Ok(())
+/// خواندن هدر درخواست VLESS در سمت سرور: (UUID، دستور، host، port)
+pub(crate) async fn read_vless_request<S: AsyncRead + Unpin>(
+    stream: &mut S,
+) -> Result<([u8; 16], u8, String, u16)> {
+    let mut head = [0u8; 18];
+    stream.read_exact(&mut head).await.context("Truncated VLESS request")?;
+    if head[0] != VLESS_VERSION {
+        bail!("Unsupported VLESS version {}", head[0]);
     }
+    let mut uuid = [0u8; 16];
+    uuid.copy_from_slice(&head[1..17]);
+    let mut addons = vec![0u8; head[17] as usize];
+    stream.read_exact(&mut addons).await?;
 
-    /// دریافت پاسخ VLESS
-    pub async fn read_response(&mut self) -> Result<Vec<u8>> {
-        let stream = self.stream.as_mut().context("No stream")?;
-        let mut version = [0u8; 1];
-        stream.read_exact(&mut version).await.context("VLESS response version")?;
-        let mut addons_len = [0u8; 1];
-        stream.read_exact(&mut addons_len).await?;
-        if addons_len[0] > 0 {
-            let mut addons = vec![0u8; addons_len[0] as usize];
-            stream.read_exact(&mut addons).await?;
+    // Command, port, address type
+    let mut req = [0u8; 4];
+    stream.read_exact(&mut req).await?;
+    let host = match req[3] {
+        0x01 => {
+            let mut octets = [0u8; 4];
+            stream.read_exact(&mut octets).await?;
+            std::net::Ipv4Addr::from(octets).to_string()
+        }
+        0x02 => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).await?;
+            let mut name = vec![0u8; len[0] as usize];
+            stream.read_exact(&mut name).await?;
+            String::from_utf8(name).context("Invalid UTF-8 in VLESS domain")?
         }
-        let mut data = Vec::new();
-        let mut buf = [0u8; 4096];
-        if let Ok(n) = stream.read(&mut buf).await {
-            data.extend_from_slice(&buf[..n]);
+        0x03 => {
+            let mut octets = [0u8; 16];
+            stream.read_exact(&mut octets).await?;
+            std::net::Ipv6Addr::from(octets).to_string()
         }
-        Ok(data)
+        atyp => bail!("Unsupported VLESS address type {}", atyp),
+    };
+    Ok((uuid, req[0], host, u16::from_be_bytes([req[1], req[2]])))
+}
+
+#[async_trait]
+impl Transport for Reality {
+    fn name(&self) -> &'static str {
+        "Reality"
     }
 
-    /// ارسال پکت UDP (VLESS UDP)
-    pub async fn send_udp_packet(&mut self, data: &[u8]) -> Result<()> {
-        let mut packet = Vec::new();
-        let len = data.len() as u16;
-        packet.extend_from_slice(&len.to_be_bytes());
-        packet.extend_from_slice(data);
-        let stream = self.stream.as_mut().context("No stream")?;
-        stream.write_all(&packet).await?;
-        Ok(())
+    async fn connect_over(&self, lower: BoxedStream) -> Result<BoxedStream> {
+        Ok(self.connect_traced(lower).await?.0)
+    }
+
+    async fn connect_traced(&self, lower: BoxedStream) -> Result<(BoxedStream, Vec<String>)> {
+        // بدون handshake REALITY هدر VLESS (UUID و مقصد) هرگز به صورت متن ساده فرستاده نمی‌شود
+        let config = self.config.as_ref().context("Reality needs a config with the server public key")?;
+        let (mut stream, params) = config.handshake(lower, &config.public_key).await?;
+        let params = vec![params.to_string()];
+        let Some((host, port, cmd)) = &self.destination else {
+            return Ok((stream, params));
+        };
+        let header = Self::build_vless_request(&config.uuid, *cmd, host, *port);
+        stream.write_all(&header).await.context("VLESS header write failed")?;
+        debug!("✅ VLESS request header sent → {}:{}", host, port);
+        Ok((Box::new(VlessStream { inner: stream, header_pending: true, pending: Vec::new() }), params))
     }
 }
 
 impl Default for Reality {
     fn default() -> Self { Self::new() }
 }
+
+/// جریان VLESS پس از ارسال هدر درخواست
+///
+/// هدر پاسخ VLESS (نسخه + addons) که سرور پیش از اولین داده می‌فرستد در
+/// خواندن حذف می‌شود تا لایه بالا فقط داده مقصد را ببیند.
+struct VlessStream {
+    inner: BoxedStream,
+    header_pending: bool,
+    /// بایت‌های خوانده‌شده تا تکمیل هدر (و داده پس از آن)
+    pending: Vec<u8>,
+}
+
+impl AsyncRead for VlessStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while this.header_pending {
+            let mut chunk = [0u8; 1024];
+            let mut chunk_buf = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf))?;
+            if chunk_buf.filled().is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            this.pending.extend_from_slice(chunk_buf.filled());
+            // Version (1) + addons length (1) + addons
+            if this.pending.len() >= 2 && this.pending.len() >= 2 + this.pending[1] as usize {
+                let header_len = 2 + this.pending[1] as usize;
+                this.pending.drain(..header_len);
+                this.header_pending = false;
+            }
+        }
+        if !this.pending.is_empty() {
+            let n = this.pending.len().min(buf.remaining());
+            buf.put_slice(&this.pending[..n]);
+            this.pending.drain(..n);
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for VlessStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::{
+        tls::{serve_test_handshake, Record},
+        transport::FramedStream,
+    };
+
+    pub(crate) const SHORT_ID: &str = "6ba85179e30d4fc2";
+
+    /// سرور REALITY: session ID را باز می‌کند، گواهی موقت می‌فرستد و داده را برمی‌گرداند.
+    /// با احراز هویت ناموفق هم handshake را (مثل یک سایت معمولی) ادامه می‌دهد.
+    pub(crate) async fn stand_in_server(private_key: StaticSecret) -> SocketAddr {
+        spawn_server(private_key, false).await
+    }
+
+    /// مثل [`stand_in_server`] ولی به مقصد هدر VLESS وصل می‌شود و داده را رله می‌کند
+    pub(crate) async fn relay_server(private_key: StaticSecret) -> SocketAddr {
+        spawn_server(private_key, true).await
+    }
+
+    async fn spawn_server(private_key: StaticSecret, relay: bool) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let private_key = private_key.clone();
+                tokio::spawn(async move {
+                    let _ = serve(socket, &private_key, relay).await;
+                });
+            }
+        });
+        addr
+    }
+
+    async fn serve(mut socket: TcpStream, private_key: &StaticSecret, relay: bool) -> Result<()> {
+        let hello_record = Record::read(&mut socket).await?;
+        let hello = ClientHello::parse(&hello_record.payload)?;
+        let client_key = PublicKey::from(hello.key_share);
+        let mut auth_key = auth_key(private_key.diffie_hellman(&client_key).as_bytes(), &hello.random);
+
+        let mut aad = hello_record.payload.clone();
+        aad[SESSION_ID_OFFSET - RECORD_HEADER_LEN..][..32].fill(0);
+        let mut session_id = hello.session_id.to_vec();
+        match session_cipher(&auth_key).open_in_place(session_nonce(&hello.random), aead::Aad::from(&aad), &mut session_id) {
+            Ok(plain) => {
+                assert_eq!(&plain[..3], &CLIENT_VERSION);
+                assert_eq!(hex::encode(&plain[8..16]), SHORT_ID);
+            }
+            // گواهی یک سایت معمولی: امضا با کلید احراز هویت نیست
+            Err(_) => auth_key = rand::random(),
+        }
+
+        let codec = serve_test_handshake(&mut socket, &hello_record, |public_key| {
+            hmac::sign(&hmac::Key::new(hmac::HMAC_SHA512, &auth_key), public_key).as_ref().to_vec()
+        })
+        .await?;
+        let mut stream = FramedStream::new(Box::new(socket), codec);
+        if relay {
+            let (_, _, host, port) = read_vless_request(&mut stream).await?;
+            let mut destination = TcpStream::connect((host.as_str(), port)).await?;
+            stream.write_all(&[VLESS_VERSION, 0]).await?;
+            tokio::io::copy_bidirectional(&mut stream, &mut destination).await?;
+            return Ok(());
+        }
+        let mut buf = [0u8; 1024];
+        let n = stream.read(&mut buf).await?;
+        stream.write_all(&buf[..n]).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+
+    async fn connect(addr: SocketAddr, public_key: &str) -> Result<BoxedStream> {
+        let config = RealityConfig::from_uuid_str("", public_key, SHORT_ID, "www.speedtest.net")?;
+        let lower: BoxedStream = Box::new(TcpStream::connect(addr).await?);
+        Reality::new().with_config(config).connect_over(lower).await
+    }
+
+    #[tokio::test]
+    async fn test_handshake_with_stand_in_server() {
+        let private_key = StaticSecret::from(rand::random::<[u8; 32]>());
+        let public_key = PublicKey::from(&private_key);
+        let addr = stand_in_server(private_key).await;
+
+        let mut stream = connect(addr, &hex::encode(public_key.as_bytes())).await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        stream.flush().await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        let other = PublicKey::from(&StaticSecret::from(rand::random::<[u8; 32]>()));
+        let error = connect(addr, &hex::encode(other.as_bytes())).await.err().unwrap();
+        assert!(format!("{:#}", error).contains("not signed with the REALITY key"), "{:#}", error);
+    }
+
+    #[tokio::test]
+    async fn test_trace_reports_negotiated_parameters() {
+        let private_key = StaticSecret::from(rand::random::<[u8; 32]>());
+        let public_key = hex::encode(PublicKey::from(&private_key).as_bytes());
+        let addr = stand_in_server(private_key).await;
+
+        let trace = crate::matryoshka::MatryoshkaDialer::new(addr)
+            .wrap_with_reality("", &public_key, SHORT_ID, "www.speedtest.net")
+            .enable_smux()
+            .trace()
+            .await;
+        assert!(trace.failed_at().is_none(), "{}", trace);
+        assert_eq!(trace.layers[1].params, "tls=1.3 cipher=TLS_AES_128_GCM_SHA256 alpn=h2");
+        assert_eq!(trace.layers[2].params, "version=2 stream=1");
+    }
+
+    #[test]
+    fn test_key_and_short_id_parsing() {
+        let key = parse_public_key("SbVKOEMjK0sIlbwg4akyBg5mL5KZwwB-ed4eEE7YnRc").unwrap();
+        assert_eq!(parse_public_key(&hex::encode(key)).unwrap(), key);
+        assert!(parse_public_key("").is_err());
+        assert!(RealityConfig::from_uuid_str("", "", SHORT_ID, "a.ir").is_err());
+        assert!(parse_public_key("c2hvcnQ").is_err());
+        assert!(parse_public_key("not a key!").is_err());
+
+        assert_eq!(parse_short_id("6ba8").unwrap(), [0x6b, 0xa8, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(parse_short_id("").unwrap(), [0u8; 8]);
+        assert!(parse_short_id("6ba").is_err());
+        assert!(parse_short_id("6ba85179e30d4fc2aa").is_err());
+    }
+}
@@ -61,7 +61,10 @@ impl Default for RouterProfile {
 
 /// تنظیمات TPROXY
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct TproxyConfig {
+    /// listener داخلی TPROXY (TCP/UDP روی `listen_port`)
+    pub enabled: bool,
     pub listen_port: u16,
     pub dns_port: u16,
     pub mark: u32,
@@ -70,11 +73,16 @@ pub struct TproxyConfig {
     pub enable_ipv6: bool,
     pub bypass_private: bool,
     pub bypass_iran_geoip: bool,
+    /// SO_MARK اتصال‌های خروجی خود Ghost (از قوانین TPROXY مستثنا می‌شوند)
+    pub routing_mark: u32,
+    /// مقصدهای اضافه‌ای که مستقیم می‌روند (CIDR، مثل `10.8.0.0/16` یا `2001:db8::/32`)
+    pub bypass_cidrs: Vec<String>,
 }
 
 impl Default for TproxyConfig {
     fn default() -> Self {
         Self {
+            enabled: false,
             listen_port: 7892,
             dns_port: 7874,
             mark: 1,
@@ -83,6 +91,8 @@ impl Default for TproxyConfig {
             enable_ipv6: true,
             bypass_private: true,
             bypass_iran_geoip: true,
+            routing_mark: 255,
+            bypass_cidrs: Vec::new(),
         }
     }
 }
@@ -104,8 +114,24 @@ impl TproxyManager {
         let dns_port = self.config.dns_port;
         let mark = self.config.mark;
         let table = self.config.table_id;
+        let routing_mark = self.config.routing_mark;
         let wan = &self.profile.wan_interface;
 
+        // رنج‌های نامعتبر را check-config گزارش می‌کند؛ اینجا فقط کنار گذاشته می‌شوند
+        let (mut bypass, mut bypass_local, mut bypass6) = (String::new(), String::new(), String::new());
+        for range in &self.config.bypass_cidrs {
+            match crate::utils::parse_cidr(range) {
+                Ok((ip, prefix)) if ip.is_ipv4() => {
+                    bypass.push_str(&format!("iptables -t mangle -A GHOST_TP -d {}/{} -j RETURN\n", ip, prefix));
+                    bypass_local.push_str(&format!("iptables -t mangle -A GHOST_LOCAL -d {}/{} -j RETURN\n", ip, prefix));
+                }
+                Ok((ip, prefix)) => {
+                    bypass6.push_str(&format!("    ip6tables -t mangle -A GHOST_TP6 -d {}/{} -j RETURN\n", ip, prefix));
+                }
+                Err(e) => warn!("⚠️ tproxy.bypass_cidrs: `{}` نادیده گرفته شد: {:#}", range, e),
+            }
+        }
+
         format!(
             r#"#!/bin/sh
 # ══════════════════════════════════════════════════════════════════════
@@ -117,6 +143,7 @@ set -e
 PROXY_PORT={port}
 DNS_PORT={dns_port}
 MARK={mark}
+ROUTING_MARK={routing_mark}
 TABLE={table}
 WAN={wan}
 
@@ -144,7 +171,7 @@ iptables -t mangle -A GHOST_TP -d 192.168.0.0/16 -j RETURN
 iptables -t mangle -A GHOST_TP -d 10.0.0.0/8 -j RETURN
 iptables -t mangle -A GHOST_TP -d 224.0.0.0/4 -j RETURN
 iptables -t mangle -A GHOST_TP -d 240.0.0.0/4 -j RETURN
-
+{bypass}
 # Bypass: ترافیک از پروسه Ghost خودش (جلوگیری از loop)
 iptables -t mangle -A GHOST_TP -m mark --mark $MARK -j RETURN
 
@@ -167,11 +194,12 @@ iptables -t nat -A PREROUTING -j GHOST_NAT
 
 # ── ترافیک LOCAL (خود روتر) ──────────────────────────────────────
 iptables -t mangle -N GHOST_LOCAL
+iptables -t mangle -A GHOST_LOCAL -m mark --mark $ROUTING_MARK -j RETURN
 iptables -t mangle -A GHOST_LOCAL -d 127.0.0.0/8 -j RETURN
 iptables -t mangle -A GHOST_LOCAL -d 10.0.0.0/8 -j RETURN
 iptables -t mangle -A GHOST_LOCAL -d 172.16.0.0/12 -j RETURN
 iptables -t mangle -A GHOST_LOCAL -d 192.168.0.0/16 -j RETURN
-iptables -t mangle -A GHOST_LOCAL -p tcp -j MARK --set-mark $MARK
+{bypass_local}iptables -t mangle -A GHOST_LOCAL -p tcp -j MARK --set-mark $MARK
 iptables -t mangle -A GHOST_LOCAL -p udp -j MARK --set-mark $MARK
 iptables -t mangle -A OUTPUT -j GHOST_LOCAL
 
@@ -180,7 +208,7 @@ if [ -n "$(which ip6tables)" ]; then
     ip6tables -t mangle -N GHOST_TP6 2>/dev/null || true
     ip6tables -t mangle -A GHOST_TP6 -d ::1/128 -j RETURN
     ip6tables -t mangle -A GHOST_TP6 -d fc00::/7 -j RETURN
-    ip6tables -t mangle -A GHOST_TP6 -p tcp -j TPROXY \
+{bypass6}    ip6tables -t mangle -A GHOST_TP6 -p tcp -j TPROXY \
       --tproxy-mark $MARK --on-port $PROXY_PORT
     ip6tables -t mangle -A GHOST_TP6 -p udp -j TPROXY \
       --tproxy-mark $MARK --on-port $PROXY_PORT
@@ -205,6 +233,9 @@ echo "✅ TPROXY راه‌اندازی شد (پورت: $PROXY_PORT, DNS: $DNS_PO
             mark = mark,
             table = table,
             wan = wan,
+            bypass = bypass,
+            bypass_local = bypass_local,
+            bypass6 = bypass6,
         )
     }
 
@@ -406,13 +437,13 @@ start() {
 stop() {
     echo "🛑 توقف Network Ghost..."
     /opt/network-ghost/scripts/tproxy-cleanup.sh
-    [ -f $PID_FILE ] && kill $(cat $PID_FILE) 2>/dev/null
+    $PROG stop 2>/dev/null || { [ -f $PID_FILE ] && kill $(cat $PID_FILE) 2>/dev/null; }
     rm -f $PID_FILE
 }
 
 restart() { stop; sleep 1; start; }
 status() {
-    [ -f $PID_FILE ] && echo "✅ در حال اجرا (PID: $(cat $PID_FILE))" || echo "❌ متوقف"
+    $PROG status
 }
 INIT
 chmod +x /etc/init.d/network-ghost
@@ -15,24 +15,38 @@ use tokio::{
     sync::Mutex,
     time::timeout,
 };
+use futures::StreamExt;
 use tracing::{debug, info, warn};
 
 use super::{
-    anti_ai_dpi::AntiAiDpi, dns_over_quic::DnsOverQuic, CdnType, ScanResult,
-    ALTERNATIVE_PORTS,
+    anti_ai_dpi::AntiAiDpi, dns_over_quic::DnsOverQuic, ip_pool::DEFAULT_CACHE_PATH, CdnType,
+    ScanResult, ALTERNATIVE_PORTS,
 };
 
 /// تنظیمات اسکنر
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ScannerConfig {
     /// حداکثر IP برای تست
     pub max_ips: usize,
     /// تایم‌اوت اتصال (میلی‌ثانیه)
     pub connect_timeout_ms: u64,
+    /// تایم‌اوت TLS handshake (میلی‌ثانیه)
+    pub tls_timeout_ms: u64,
     /// حداکثر تأخیر مجاز
     pub max_latency_ms: u64,
     /// تعداد threadها
     pub concurrency: usize,
+    /// استفاده از Anti-AI هنگام اسکن
+    pub enable_anti_ai: bool,
+    /// فاصله اسکن مجدد در پس‌زمینه (ثانیه، ۰ = غیرفعال)
+    pub scan_interval: u64,
+    /// اگر load هر هسته CPU بیشتر از این باشد اسکن پس‌زمینه عقب می‌افتد
+    pub max_cpu_load: f64,
+    /// فایل کش IPهای تمیز
+    pub cache_path: String,
+    /// اعتبار نتایج کش (ثانیه)؛ قدیمی‌ترها هنگام شروع دوباره تست می‌شوند
+    pub cache_ttl_secs: u64,
 }
 
 impl Default for ScannerConfig {
@@ -40,8 +54,14 @@ impl Default for ScannerConfig {
         Self {
             max_ips: 100,
             connect_timeout_ms: 3000,
+            tls_timeout_ms: 4000,
             max_latency_ms: 300,
             concurrency: 10,
+            enable_anti_ai: true,
+            scan_interval: 300,
+            max_cpu_load: 0.8,
+            cache_path: DEFAULT_CACHE_PATH.to_string(),
+            cache_ttl_secs: 3600,
         }
     }
 }
@@ -69,6 +89,20 @@ impl TlsScanner {
         }
     }
 
+    /// ایجاد اسکنر با تنظیمات دلخواه
+    pub fn with_config(
+        dns: Arc<DnsOverQuic>,
+        anti_ai: Arc<AntiAiDpi>,
+        config: ScannerConfig,
+    ) -> Self {
+        Self {
+            dns,
+            anti_ai,
+            config,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
     /// اسکن همه CDNها
     pub async fn scan_all_cdns(
         &self,
@@ -78,14 +112,14 @@ impl TlsScanner {
     ) -> Result<Vec<ScanResult>> {
         info!("🔍 شروع اسکن Multi-CDN...");
 
-        let _max = max_ips.unwrap_or(self.config.max_ips);
+        let max = max_ips.unwrap_or(self.config.max_ips);
         let mut results = Vec::new();
 
         // resolve IPها
         let ips = self.resolve_cdn_ips(preferred_cdn).await?;
 
         // تست IPها
-        for ip in ips.iter().take(max_ips.unwrap_or(10)) {
+        for ip in ips.iter().take(max) {
             for port in ports {
                 if let Ok(Some(result)) = self.test_single_ip(*ip, *port, preferred_cdn).await {
                     if result.is_clean {
@@ -103,6 +137,23 @@ impl TlsScanner {
         Ok(results)
     }
 
+    /// تست مجدد نتایج قبلی (مثلاً از کش)؛ فقط IPهایی که هنوز تمیزند برمی‌گردند
+    pub async fn revalidate(&self, results: &[ScanResult]) -> Vec<ScanResult> {
+        let mut tests = Vec::with_capacity(results.len());
+        for r in results {
+            tests.push(self.test_single_ip(r.ip, r.port, r.cdn_type));
+        }
+        let tested: Vec<_> = futures::stream::iter(tests)
+            .buffer_unordered(self.config.concurrency.max(1))
+            .collect()
+            .await;
+        tested
+            .into_iter()
+            .filter_map(|r| r.ok().flatten())
+            .filter(|r| r.is_clean)
+            .collect()
+    }
+
     /// resolve IPهای CDN
     async fn resolve_cdn_ips(&self, cdn: CdnType) -> Result<Vec<IpAddr>> {
         let domains = match cdn {
@@ -174,9 +225,8 @@ impl TlsScanner {
     }
 
     /// تست TLS
-    async fn test_tls(&self, stream: &TcpStream) -> Result<bool> {
+    async fn test_tls(&self, _stream: &TcpStream) -> Result<bool> {
         // در پیاده‌سازی واقعی باید TLS handshake انجام شود
-        drop(stream);
         Ok(true)
     }
 }
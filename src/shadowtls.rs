@@ -1,16 +1,167 @@
-//! ShadowTLS v3 Client
+//! ShadowTLS v3 Client و Server
+//!
+//! مراحل پروتکل (سازگار با shadow-tls نسخه ۳):
+//!
+//! 1. ClientHello واقعی TLS 1.3 که ۴ بایت آخر session ID آن
+//!    HMAC-SHA1(password) روی کل پیام handshake است (با همان ۴ بایت صفر)
+//! 2. سرور handshake را با سرور استتار (مثلاً یک بانک ایرانی) رله می‌کند و
+//!    رکوردهای ApplicationData آن را با `SHA256(password || ServerRandom)`
+//!    XOR و با تگ `HMAC(password, ServerRandom)` امضا می‌کند (این HMAC فقط با
+//!    داده رکوردها پیش می‌رود)؛ اولین رکورد تگ‌دار یعنی سرور رمز را می‌داند
+//! 3. کلاینت پرواز رمزشده را باز می‌کند و با ChangeCipherSpec و Finished
+//!    واقعی handshake سرور استتار را کامل می‌کند؛ از آن به بعد هر رکورد داده
+//!    `[تگ ۴ بایتی][داده]` است: کلاینت با `ServerRandom + "C"` و سرور با
+//!    `ServerRandom + "S"`. هر تگ پس از محاسبه به HMAC اضافه می‌شود تا
+//!    رکوردها قابل جابه‌جایی یا تکرار نباشند.
+//!
+//! در حالت `strict` (پیش‌فرض) ServerHello باید TLS 1.3 با key_share
+//! X25519 باشد و session ID کلاینت را برگرداند؛ در غیر این صورت اتصال
+//! قطع می‌شود چون یا سرور استتار مناسب نیست یا کسی وسط راه است.
+//!
+//! [`ShadowTlsServer`] همین پروتکل را در سمت سرور پیاده می‌کند
+//! (`network-ghost serve shadowtls`) تا کل زنجیره روی یک ماشین اجرا شود.
 
-use std::net::{IpAddr, SocketAddr};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
 
-use anyhow::{Context, Result};
-use serde::{Deserialize, Serialize};
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use ring::hmac;
+use sha2::{Digest, Sha256};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+    sync::oneshot,
     time::{timeout, Duration},
 };
 use tracing::{debug, info};
 
+use crate::{
+    inbound::serve_socks5_direct,
+    matryoshka::CMD_TCP,
+    reality::{read_vless_request, VLESS_VERSION},
+    tls::{
+        encode_record, ClientHello, Record, ServerHello, Tls13Client, TlsParams, CONTENT_ALERT, CONTENT_APPLICATION_DATA,
+        CONTENT_CHANGE_CIPHER_SPEC, CONTENT_HANDSHAKE, GROUP_X25519, HANDSHAKE_CLIENT_HELLO,
+        RECORD_HEADER_LEN, SESSION_ID_OFFSET, TLS10, TLS12, TLS13,
+    },
+    transport::{BoxedStream, Dialer, Frame, FrameCodec, FramedStream, TcpDialer, Transport},
+};
+
+/// طول تگ HMAC در session ID و رکوردها
+pub const HMAC_LEN: usize = 4;
+
+/// حداکثر زمان handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// رکورد ChangeCipherSpec کلاینت (پایان handshake از دید ناظر)
+const CHANGE_CIPHER_SPEC: [u8; 6] = [CONTENT_CHANGE_CIPHER_SPEC, 0x03, 0x03, 0x00, 0x01, 0x01];
+
+// ==================== HMAC ====================
+
+/// HMAC-SHA1 پیوسته با کلید password
+#[derive(Clone)]
+pub(crate) struct RunningHmac(hmac::Context);
+
+impl RunningHmac {
+    fn new(password: &str, seed: &[&[u8]]) -> Self {
+        let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, password.as_bytes());
+        let mut context = hmac::Context::with_key(&key);
+        for part in seed {
+            context.update(part);
+        }
+        Self(context)
+    }
+
+    /// رکوردهای رله‌شده سرور استتار در handshake
+    pub(crate) fn server_random(password: &str, server_random: &[u8; 32]) -> Self {
+        Self::new(password, &[server_random])
+    }
+
+    /// داده کلاینت → سرور
+    pub(crate) fn client_data(password: &str, server_random: &[u8; 32]) -> Self {
+        Self::new(password, &[server_random, b"C"])
+    }
+
+    /// داده سرور → کلاینت
+    pub(crate) fn server_data(password: &str, server_random: &[u8; 32]) -> Self {
+        Self::new(password, &[server_random, b"S"])
+    }
+
+    /// مرحله داده: تگ `data` که خودش هم به HMAC اضافه می‌شود
+    pub(crate) fn tag(&mut self, data: &[u8]) -> [u8; HMAC_LEN] {
+        let tag = self.update(data);
+        self.0.update(&tag);
+        tag
+    }
+
+    /// مرحله handshake: فقط `data` به HMAC اضافه می‌شود (تگ زنجیر نمی‌شود)
+    pub(crate) fn update(&mut self, data: &[u8]) -> [u8; HMAC_LEN] {
+        self.0.update(data);
+        truncate(self.0.clone().sign())
+    }
+
+    /// تگ handshake برای `data` بدون تغییر وضعیت
+    pub(crate) fn peek(&self, data: &[u8]) -> [u8; HMAC_LEN] {
+        self.clone().update(data)
+    }
+
+    /// بررسی تگ مرحله داده؛ وضعیت فقط در صورت تطابق پیش می‌رود
+    pub(crate) fn verify(&mut self, tag: &[u8], data: &[u8]) -> bool {
+        let mut next = self.clone();
+        if next.tag(data)[..] != *tag {
+            return false;
+        }
+        *self = next;
+        true
+    }
+
+    /// بررسی تگ رکورد handshake؛ وضعیت فقط در صورت تطابق پیش می‌رود
+    pub(crate) fn verify_update(&mut self, tag: &[u8], data: &[u8]) -> bool {
+        if self.peek(data)[..] != *tag {
+            return false;
+        }
+        self.0.update(data);
+        true
+    }
+}
+
+fn truncate(tag: hmac::Tag) -> [u8; HMAC_LEN] {
+    let mut out = [0u8; HMAC_LEN];
+    out.copy_from_slice(&tag.as_ref()[..HMAC_LEN]);
+    out
+}
+
+/// تگ session ID در رکورد ClientHello (با ۴ بایت آخر session ID صفر)
+pub(crate) fn client_hello_tag(password: &str, record: &[u8]) -> [u8; HMAC_LEN] {
+    let tag_at = SESSION_ID_OFFSET + 32 - HMAC_LEN;
+    let mut message = record[RECORD_HEADER_LEN..].to_vec();
+    message[tag_at - RECORD_HEADER_LEN..tag_at - RECORD_HEADER_LEN + HMAC_LEN].fill(0);
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, password.as_bytes());
+    truncate(hmac::sign(&key, &message))
+}
+
+/// کلید XOR رکوردهای رله‌شده: `SHA256(password || ServerRandom)`
+pub(crate) fn xor_key(password: &str, server_random: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(password.as_bytes());
+    hasher.update(server_random);
+    hasher.finalize().into()
+}
+
+/// رکورد ApplicationData با تگ: `[header][tag][data]`
+pub(crate) fn encode_tagged(tag: [u8; HMAC_LEN], data: &[u8], dst: &mut Vec<u8>) {
+    dst.push(CONTENT_APPLICATION_DATA);
+    dst.extend_from_slice(&TLS12.to_be_bytes());
+    dst.extend_from_slice(&((data.len() + HMAC_LEN) as u16).to_be_bytes());
+    dst.extend_from_slice(&tag);
+    dst.extend_from_slice(data);
+}
+
+// ==================== CLIENT ====================
 
 /// کلاینت ShadowTLS v3
 pub struct ShadowTlsClient {
@@ -18,104 +169,614 @@ pub struct ShadowTlsClient {
     server: IpAddr,
     /// پورت
     port: u16,
-    /// SNI
+    /// SNI سرور استتار
     sni: String,
-    /// کانکشن TCP
-    stream: Option<TcpStream>,
+    /// رمز مشترک با سرور
+    password: String,
+    /// بررسی‌های سخت‌گیرانه ServerHello
+    strict: bool,
 }
 
 impl ShadowTlsClient {
-    /// ایجاد کلاینت جدید
-    pub fn new(server: IpAddr, port: u16, sni: String) -> Self {
-        Self {
-            server,
-            port,
-            sni,
-            stream: None,
-        }
+    /// ایجاد کلاینت جدید (حالت strict فعال)
+    pub fn new(server: IpAddr, port: u16, sni: String, password: String) -> Self {
+        Self { server, port, sni, password, strict: true }
     }
 
-    /// اتصال
-    pub async fn connect(&mut self) -> Result<()> {
-        info!("🔐 اتصال ShadowTLS v3 به {}:{}", self.server, self.port);
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
 
-        let addr = SocketAddr::new(self.server, self.port);
+    /// ClientHello با session ID امضاشده و کلید X25519 آن
+    fn client_hello(&self) -> (ClientHello, Vec<u8>, x25519_dalek::StaticSecret) {
+        let secret = x25519_dalek::StaticSecret::from(rand::random::<[u8; 32]>());
+        let mut hello = ClientHello::new(&self.sni, x25519_dalek::PublicKey::from(&secret).to_bytes());
+        hello.session_id[32 - HMAC_LEN..].fill(0);
+        let mut record = hello.encode();
 
-        let stream = timeout(Duration::from_secs(10), TcpStream::connect(addr))
-            .await
-            .context("Connection timeout")?
-            .context("TCP connection failed")?;
+        let tag = client_hello_tag(&self.password, &record);
+        let tag_at = SESSION_ID_OFFSET + 32 - HMAC_LEN;
+        record[tag_at..tag_at + HMAC_LEN].copy_from_slice(&tag);
+        hello.session_id[32 - HMAC_LEN..].copy_from_slice(&tag);
+        (hello, record, secret)
+    }
 
-        self.stream = Some(stream);
+    /// انجام Handshake؛ HMAC رکوردهای رله‌شده، ServerRandom و پارامترهای TLS سرور استتار
+    async fn do_handshake(&self, stream: &mut BoxedStream) -> Result<(RunningHmac, [u8; 32], TlsParams)> {
+        let (hello, record, secret) = self.client_hello();
+        stream.write_all(&record).await?;
+        stream.flush().await?;
 
-        // Handshake
-        self.do_handshake().await?;
+        // دریافت Server Hello
+        let first = Record::read(stream).await?;
+        if first.content_type == CONTENT_ALERT {
+            bail!("Server rejected the ClientHello with a TLS alert");
+        }
+        if first.content_type != CONTENT_HANDSHAKE {
+            bail!("Expected a handshake record, got content type {}", first.content_type);
+        }
+        let server_hello = ServerHello::parse(&first.payload)?;
+        self.check_server_hello(&hello, &server_hello)?;
 
-        info!("✅ ShadowTLS handshake موفق");
+        // TLS 1.3: پرواز سرور استتار باز و handshake با Finished واقعی کامل می‌شود
+        // تا سرور استتار (و هر ناظر فعال) یک نشست کامل ببیند
+        let mut tls = match Tls13Client::new(&record, &first.payload, &secret) {
+            Ok(tls) => Some(tls.without_signature_check()),
+            Err(e) if self.strict => return Err(e.context("Camouflage server handshake cannot be completed")),
+            Err(e) => {
+                debug!("Camouflage handshake cannot be completed ({:#}), sending ChangeCipherSpec only", e);
+                None
+            }
+        };
+
+        // همه ApplicationDataهای handshake باید با ServerRandom امضا شده باشند
+        let key = xor_key(&self.password, &server_hello.random);
+        let mut hmac_sr = RunningHmac::server_random(&self.password, &server_hello.random);
+        loop {
+            let record = Record::read(stream).await?;
+            match record.content_type {
+                CONTENT_APPLICATION_DATA => {
+                    let (tag, data) = record.payload.split_at(HMAC_LEN.min(record.payload.len()));
+                    if !hmac_sr.verify_update(tag, data) {
+                        bail!("Server is not a ShadowTLS v3 server for this password (untagged handshake data)");
+                    }
+                    let Some(tls) = tls.as_mut() else {
+                        break;
+                    };
+                    let data: Vec<u8> = data.iter().enumerate().map(|(i, b)| b ^ key[i % key.len()]).collect();
+                    if tls.read_record(&data).context("Camouflage server handshake failed")? {
+                        break;
+                    }
+                }
+                CONTENT_ALERT => bail!("Server sent a TLS alert during the handshake"),
+                _ => continue,
+            }
+        }
+
+        let params = match tls {
+            Some(tls) => {
+                let params = tls.params().clone();
+                stream.write_all(&tls.finish()?.0).await?;
+                params
+            }
+            None => {
+                stream.write_all(&CHANGE_CIPHER_SPEC).await?;
+                TlsParams::from_server_hello(&server_hello)
+            }
+        };
+        Ok((hmac_sr, server_hello.random, params))
+    }
+
+    /// بررسی‌های حالت strict روی ServerHello
+    fn check_server_hello(&self, hello: &ClientHello, server_hello: &ServerHello) -> Result<()> {
+        if !self.strict {
+            return Ok(());
+        }
+        if server_hello.version != TLS13 {
+            bail!(
+                "{} negotiated TLS version {:#06x} instead of TLS 1.3 (strict mode)",
+                self.sni,
+                server_hello.version
+            );
+        }
+        if server_hello.session_id != hello.session_id {
+            bail!("ServerHello did not echo the session ID (strict mode)");
+        }
+        if !matches!(&server_hello.key_share, Some((GROUP_X25519, key)) if key.len() == 32) {
+            bail!("ServerHello has no X25519 key share (strict mode)");
+        }
         Ok(())
     }
+}
 
-    /// انجام Handshake
-    async fn do_handshake(&mut self) -> Result<()> {
-        // ساخت Client Hello
-        let hello = self.build_client_hello();
-        
-        let stream = self.stream.as_mut().context("No connection")?;
-        stream.write_all(&hello).await?;
+#[async_trait]
+impl Transport for ShadowTlsClient {
+    fn name(&self) -> &'static str {
+        "ShadowTLS"
+    }
 
-        // دریافت Server Hello
-        let mut response = vec![0u8; 4096];
-        let _n = timeout(Duration::from_secs(10), stream.read(&mut response))
+    async fn connect_over(&self, lower: BoxedStream) -> Result<BoxedStream> {
+        Ok(self.connect_traced(lower).await?.0)
+    }
+
+    async fn connect_traced(&self, mut lower: BoxedStream) -> Result<(BoxedStream, Vec<String>)> {
+        let (hmac_sr, server_random, params) = timeout(HANDSHAKE_TIMEOUT, self.do_handshake(&mut lower))
             .await
             .context("Handshake timeout")??;
+        debug!("✅ ShadowTLS handshake successful ({})", params);
 
-        debug!("✅ ShadowTLS handshake successful");
-        Ok(())
+        let codec = ShadowTlsCodec {
+            send: RunningHmac::client_data(&self.password, &server_random),
+            recv: RunningHmac::server_data(&self.password, &server_random),
+            discard: Some(hmac_sr),
+            pending: None,
+        };
+        Ok((Box::new(FramedStream::new(lower, codec)), vec![format!("sni={}", self.sni), params.to_string()]))
     }
+}
 
-    /// ساخت Client Hello
-    fn build_client_hello(&self) -> Vec<u8> {
-        let mut hello = Vec::new();
+/// اتصال مستقیم به سرور
+#[async_trait]
+impl Dialer for ShadowTlsClient {
+    async fn dial(&self) -> Result<BoxedStream> {
+        info!("🔐 اتصال ShadowTLS v3 به {}:{}", self.server, self.port);
+        let tcp = TcpDialer::new(SocketAddr::new(self.server, self.port)).dial().await?;
+        let stream = self.connect_over(tcp).await?;
+        info!("✅ ShadowTLS handshake موفق");
+        Ok(stream)
+    }
+}
 
-        // TLS Record Layer
-        hello.push(0x16);
-        hello.push(0x03);
-        hello.push(0x01);
+// ==================== FRAMING ====================
 
-        // Length placeholder
-        hello.extend_from_slice(&[0x00, 0x00]);
+/// رکوردهای داده پس از handshake (هر طرف با HMAC خودش)
+struct ShadowTlsCodec {
+    /// تگ رکوردهای ارسالی
+    send: RunningHmac,
+    /// تگ رکوردهای دریافتی
+    recv: RunningHmac,
+    /// رکوردهای باقی‌مانده سرور استتار (مثل NewSessionTicket) که دور ریخته می‌شوند
+    discard: Option<RunningHmac>,
+    /// داده‌ای که پیش از ساخت codec خوانده شده است
+    pending: Option<Vec<u8>>,
+}
 
-        // Handshake Type
-        hello.push(0x01);
+impl FrameCodec for ShadowTlsCodec {
+    const MAX_PAYLOAD: usize = 16384 - HMAC_LEN;
 
-        // Version
-        hello.push(0x03);
-        hello.push(0x03);
+    fn encode(&mut self, payload: &[u8], dst: &mut Vec<u8>) {
+        encode_tagged(self.send.tag(payload), payload, dst);
+    }
 
-        // Random
-        let random: [u8; 32] = rand::random();
-        hello.extend_from_slice(&random);
+    fn decode(&mut self, src: &mut Vec<u8>, _reply: &mut Vec<u8>) -> io::Result<Option<Frame>> {
+        if let Some(data) = self.pending.take() {
+            return Ok(Some(Frame::Data(data)));
+        }
+        let Some(record) = Record::take(src)? else {
+            return Ok(None);
+        };
+        match record.content_type {
+            CONTENT_APPLICATION_DATA if record.payload.len() >= HMAC_LEN => {
+                let (tag, data) = record.payload.split_at(HMAC_LEN);
+                if self.recv.verify(tag, data) {
+                    Ok(Some(Frame::Data(data.to_vec())))
+                } else if self.discard.as_mut().is_some_and(|hmac| hmac.verify_update(tag, data)) {
+                    Ok(Some(Frame::Control))
+                } else {
+                    Err(io::Error::new(io::ErrorKind::InvalidData, "ShadowTLS record failed HMAC verification"))
+                }
+            }
+            CONTENT_APPLICATION_DATA => {
+                Err(io::Error::new(io::ErrorKind::InvalidData, "ShadowTLS record too short"))
+            }
+            CONTENT_ALERT => Ok(Some(Frame::Close)),
+            _ => Ok(Some(Frame::Control)),
+        }
+    }
+}
 
-        // SNI
-        let sni_bytes = self.sni.as_bytes();
-        hello.extend_from_slice(sni_bytes);
+// ==================== SERVER ====================
 
-        hello
+/// مقصد جریان‌های احرازشده در سرور
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShadowTlsBackend {
+    /// سرور SOCKS5 داخلی (اتصال مستقیم به مقصد)
+    Socks,
+    /// سرور VLESS داخلی با یک UUID
+    Vless { uuid: [u8; 16] },
+    /// ارسال جریان به یک سرویس دیگر (مثلاً inbound VLESS در sing-box)
+    Forward(SocketAddr),
+}
+
+impl ShadowTlsBackend {
+    /// `socks`، `vless` (با `uuid`) یا آدرس `ip:port`
+    pub fn parse(spec: &str, uuid: Option<&str>) -> Result<Self> {
+        match spec {
+            "socks" | "socks5" => Ok(Self::Socks),
+            "vless" => {
+                let uuid = uuid.context("The vless backend needs a UUID (--uuid)")?;
+                let uuid = uuid::Uuid::parse_str(uuid).with_context(|| format!("Invalid UUID `{}`", uuid))?;
+                Ok(Self::Vless { uuid: *uuid.as_bytes() })
+            }
+            addr => addr
+                .parse()
+                .map(Self::Forward)
+                .map_err(|_| anyhow!("Backend `{}` is not socks, vless or an ip:port address", addr)),
+        }
     }
+}
 
-    /// ارسال داده
-    pub async fn send(&mut self, data: &[u8]) -> Result<()> {
-        let stream = self.stream.as_mut().context("No connection")?;
-        stream.write_all(data).await?;
-        Ok(())
+impl std::fmt::Display for ShadowTlsBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Socks => f.write_str("socks"),
+            Self::Vless { uuid } => write!(f, "vless ({})", uuid::Uuid::from_bytes(*uuid)),
+            Self::Forward(addr) => write!(f, "forward to {}", addr),
+        }
+    }
+}
+
+/// تنظیمات سرور ShadowTLS v3
+#[derive(Debug, Clone)]
+pub struct ShadowTlsServerConfig {
+    /// سرور استتار (`host:port`) که handshake به آن رله می‌شود
+    pub handshake: String,
+    /// رمز مشترک با کلاینت‌ها
+    pub password: String,
+    /// فقط سرور استتار TLS 1.3 (در غیر این صورت رله خالص)
+    pub strict: bool,
+    pub backend: ShadowTlsBackend,
+}
+
+/// سرور ShadowTLS v3
+///
+/// ClientHello بدون تگ معتبر (مثلاً probe سانسورچی) بدون تغییر به سرور
+/// استتار رله می‌شود، پس پاسخ همان سایت واقعی است. برای کلاینت احرازشده
+/// handshake تا اولین رکورد امضاشده کلاینت رله و سپس جریان به backend
+/// تحویل داده می‌شود.
+pub struct ShadowTlsServer {
+    config: Arc<ShadowTlsServerConfig>,
+}
+
+impl ShadowTlsServer {
+    pub fn new(config: ShadowTlsServerConfig) -> Self {
+        Self { config: Arc::new(config) }
     }
 
-    /// بستن اتصال
-    pub async fn close(&mut self) -> Result<()> {
-        if let Some(stream) = self.stream.take() {
-            drop(stream);
+    /// پذیرش اتصال‌ها تا خطای listener
+    pub async fn serve(self, listener: TcpListener) -> Result<()> {
+        loop {
+            let (client, peer) = listener.accept().await.context("ShadowTLS accept failed")?;
+            let config = self.config.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(client, &config).await {
+                    debug!("ShadowTLS {}: {:#}", peer, e);
+                }
+            });
         }
-        info!("🔌 ShadowTLS connection closed");
-        Ok(())
+    }
+}
+
+async fn handle_connection(mut client: TcpStream, config: &ShadowTlsServerConfig) -> Result<()> {
+    let hello = timeout(HANDSHAKE_TIMEOUT, Record::read(&mut client))
+        .await
+        .context("ClientHello timeout")??;
+    let mut raw = Vec::with_capacity(hello.payload.len() + RECORD_HEADER_LEN);
+    encode_record(hello.content_type, TLS10, &hello.payload, &mut raw);
+
+    let mut upstream = TcpStream::connect(&config.handshake)
+        .await
+        .with_context(|| format!("Failed to reach handshake server {}", config.handshake))?;
+    upstream.write_all(&raw).await?;
+    if !is_tagged_client_hello(&config.password, &raw) {
+        debug!("ناشناس: رله خالص به {}", config.handshake);
+        tokio::io::copy_bidirectional(&mut client, &mut upstream).await.ok();
+        return Ok(());
+    }
+
+    let first = timeout(HANDSHAKE_TIMEOUT, Record::read(&mut upstream))
+        .await
+        .context("ServerHello timeout")??;
+    let mut out = Vec::with_capacity(first.payload.len() + RECORD_HEADER_LEN);
+    encode_record(first.content_type, TLS12, &first.payload, &mut out);
+    client.write_all(&out).await?;
+    let server_hello = match ServerHello::parse(&first.payload) {
+        Ok(hello) if !config.strict || hello.version == TLS13 => hello,
+        _ => {
+            debug!("{} did not negotiate TLS 1.3, relaying only", config.handshake);
+            tokio::io::copy_bidirectional(&mut client, &mut upstream).await.ok();
+            return Ok(());
+        }
+    };
+
+    let stream = timeout(HANDSHAKE_TIMEOUT, switch_to_data(client, upstream, config, server_hello.random))
+        .await
+        .context("ShadowTLS handshake timeout")??;
+    serve_backend(stream, &config.backend).await
+}
+
+/// ClientHello با session ID ۳۲ بایتی که ۴ بایت آخرش تگ رمز است
+fn is_tagged_client_hello(password: &str, record: &[u8]) -> bool {
+    let tag_at = SESSION_ID_OFFSET + 32 - HMAC_LEN;
+    record.len() > SESSION_ID_OFFSET + 32
+        && record[0] == CONTENT_HANDSHAKE
+        && record[RECORD_HEADER_LEN] == HANDSHAKE_CLIENT_HELLO
+        && record[SESSION_ID_OFFSET - 1] == 32
+        && client_hello_tag(password, record)[..] == record[tag_at..tag_at + HMAC_LEN]
+}
+
+/// رله handshake تا اولین رکورد امضاشده کلاینت و ساخت جریان داده
+async fn switch_to_data(
+    client: TcpStream,
+    upstream: TcpStream,
+    config: &ShadowTlsServerConfig,
+    server_random: [u8; 32],
+) -> Result<BoxedStream> {
+    let (mut upstream_read, mut upstream_write) = upstream.into_split();
+    let (mut client_read, mut client_write) = client.into_split();
+    let (stop, mut stopped) = oneshot::channel::<()>();
+
+    // سرور استتار → کلاینت: ApplicationData با XOR و تگ ServerRandom
+    let password = config.password.clone();
+    let relay = tokio::spawn(async move {
+        let key = xor_key(&password, &server_random);
+        let mut hmac_sr = RunningHmac::server_random(&password, &server_random);
+        loop {
+            let mut record = tokio::select! {
+                record = Record::read(&mut upstream_read) => match record {
+                    Ok(record) => record,
+                    Err(_) => {
+                        (&mut stopped).await.ok();
+                        break;
+                    }
+                },
+                _ = &mut stopped => break,
+            };
+            let mut out = Vec::with_capacity(record.payload.len() + RECORD_HEADER_LEN + HMAC_LEN);
+            if record.content_type == CONTENT_APPLICATION_DATA {
+                for (i, byte) in record.payload.iter_mut().enumerate() {
+                    *byte ^= key[i % key.len()];
+                }
+                encode_tagged(hmac_sr.update(&record.payload), &record.payload, &mut out);
+            } else {
+                encode_record(record.content_type, TLS12, &record.payload, &mut out);
+            }
+            client_write.write_all(&out).await?;
+        }
+        anyhow::Ok(client_write)
+    });
+
+    // کلاینت → سرور استتار، تا اولین رکورد با تگ داده کلاینت
+    let mut hmac_c = RunningHmac::client_data(&config.password, &server_random);
+    let first = loop {
+        let record = Record::read(&mut client_read).await?;
+        if record.content_type == CONTENT_APPLICATION_DATA && record.payload.len() >= HMAC_LEN {
+            let (tag, data) = record.payload.split_at(HMAC_LEN);
+            if hmac_c.verify(tag, data) {
+                break data.to_vec();
+            }
+        }
+        let mut out = Vec::with_capacity(record.payload.len() + RECORD_HEADER_LEN);
+        encode_record(record.content_type, TLS12, &record.payload, &mut out);
+        upstream_write.write_all(&out).await?;
+    };
+    stop.send(()).ok();
+    let client_write = relay.await??;
+    let client = client_read.reunite(client_write)?;
+
+    let codec = ShadowTlsCodec {
+        send: RunningHmac::server_data(&config.password, &server_random),
+        recv: hmac_c,
+        discard: None,
+        pending: Some(first),
+    };
+    Ok(Box::new(FramedStream::new(Box::new(client), codec)))
+}
+
+/// تحویل جریان احرازشده به backend
+async fn serve_backend(mut stream: BoxedStream, backend: &ShadowTlsBackend) -> Result<()> {
+    match backend {
+        ShadowTlsBackend::Socks => serve_socks5_direct(stream).await,
+        ShadowTlsBackend::Vless { uuid } => {
+            let (user, command, host, port) = read_vless_request(&mut stream).await?;
+            if user != *uuid {
+                bail!("Unknown VLESS user {}", uuid::Uuid::from_bytes(user));
+            }
+            if command != CMD_TCP {
+                bail!("VLESS command {} is not supported", command);
+            }
+            let mut target = TcpStream::connect((host.as_str(), port))
+                .await
+                .with_context(|| format!("Dial {}:{} failed", host, port))?;
+            stream.write_all(&[VLESS_VERSION, 0]).await?;
+            debug!("VLESS → {}:{}", host, port);
+            tokio::io::copy_bidirectional(&mut stream, &mut target).await?;
+            Ok(())
+        }
+        ShadowTlsBackend::Forward(addr) => {
+            let mut target = TcpStream::connect(addr)
+                .await
+                .with_context(|| format!("Backend {} is unreachable", addr))?;
+            tokio::io::copy_bidirectional(&mut stream, &mut target).await?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::tls::{handshake_message, serve_test_handshake};
+
+    const PASSWORD: &str = "shadow-password";
+
+    #[test]
+    fn test_shadowtls_v3_vectors() {
+        // مقادیر با الگوریتم shadow-tls v3 (HMAC-SHA1 با کلید password) جداگانه محاسبه شده‌اند
+        let server_random: [u8; 32] = std::array::from_fn(|i| i as u8);
+
+        // handshake: تگ دوم روی ServerRandom || d1 || d2 (بدون زنجیر تگ اول)
+        let mut hmac_sr = RunningHmac::server_random(PASSWORD, &server_random);
+        assert_eq!(hmac_sr.peek(b"first record"), [0x74, 0x75, 0xe5, 0xba]);
+        assert!(hmac_sr.verify_update(&[0x74, 0x75, 0xe5, 0xba], b"first record"));
+        assert!(!hmac_sr.verify_update(&[0xd1, 0xa4, 0x6d, 0x1d], b"second record"));
+        assert!(hmac_sr.verify_update(&[0x86, 0xe1, 0x60, 0x23], b"second record"));
+
+        // داده: تگ اول پیش از رکورد دوم به HMAC اضافه می‌شود
+        let mut hmac_c = RunningHmac::client_data(PASSWORD, &server_random);
+        assert_eq!(hmac_c.tag(b"first record"), [0xa9, 0x07, 0xed, 0xbd]);
+        assert_eq!(hmac_c.tag(b"second record"), [0xc0, 0xfa, 0x36, 0x90]);
+
+        assert_eq!(
+            hex::encode(xor_key(PASSWORD, &server_random)),
+            "ab55f1734cbbb231f0169774a1ccd88522d42d9242b783f0b61e57ef24bbe6a6"
+        );
+        let record: Vec<u8> = (0..100).map(|i| (i * 7 % 256) as u8).collect();
+        assert_eq!(client_hello_tag(PASSWORD, &record), [0x8e, 0xea, 0x25, 0x0c]);
+    }
+
+    /// سرور استتار
+    ///
+    /// TLS 1.3: handshake واقعی با هر پیام در یک رکورد، بررسی Finished کلاینت و
+    /// یک NewSessionTicket؛ گیرنده پس از Finished معتبر کلاینت کامل می‌شود.
+    /// TLS 1.2: فقط ServerHello و دو رکورد ساختگی.
+    async fn camouflage(version: u16) -> (SocketAddr, oneshot::Receiver<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (done, finished) = oneshot::channel();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let hello = Record::read(&mut socket).await.unwrap();
+            if version == TLS13 {
+                let Ok(mut codec) = serve_test_handshake(&mut socket, &hello, |_| vec![0u8; 64]).await else {
+                    return;
+                };
+                done.send(()).ok();
+                let mut ticket = Vec::new();
+                codec.send.seal(CONTENT_HANDSHAKE, &handshake_message(0x04, &[0u8; 32]), &mut ticket);
+                socket.write_all(&ticket).await.ok();
+            } else {
+                let session_id = hello.payload[SESSION_ID_OFFSET - RECORD_HEADER_LEN..][..32].to_vec();
+                let mut flight = ServerHello {
+                    random: rand::random(),
+                    session_id,
+                    cipher_suite: 0x1301,
+                    version,
+                    key_share: Some((GROUP_X25519, vec![9u8; 32])),
+                }
+                .encode();
+                flight.extend_from_slice(&CHANGE_CIPHER_SPEC);
+                encode_record(CONTENT_APPLICATION_DATA, TLS12, &[0x42; 900], &mut flight);
+                encode_record(CONTENT_APPLICATION_DATA, TLS12, &[0x43; 200], &mut flight);
+                socket.write_all(&flight).await.unwrap();
+            }
+            let mut rest = Vec::new();
+            let _ = socket.read_to_end(&mut rest).await;
+        });
+        (addr, finished)
+    }
+
+    async fn echo_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut read, mut write) = socket.split();
+                    let _ = tokio::io::copy(&mut read, &mut write).await;
+                });
+            }
+        });
+        addr
+    }
+
+    async fn server(backend: ShadowTlsBackend, camouflage: SocketAddr, strict: bool) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = ShadowTlsServerConfig {
+            handshake: camouflage.to_string(),
+            password: PASSWORD.into(),
+            strict,
+            backend,
+        };
+        tokio::spawn(ShadowTlsServer::new(config).serve(listener));
+        addr
+    }
+
+    fn client(server: SocketAddr, password: &str) -> ShadowTlsClient {
+        ShadowTlsClient::new(server.ip(), server.port(), "ebanking.bmi.ir".into(), password.into())
+    }
+
+    #[tokio::test]
+    async fn test_rejects_wrong_password_and_tls12() {
+        let echo = echo_server().await;
+
+        // رمز اشتباه: فقط سرور استتار دیده می‌شود
+        let (camouflage_addr, _) = camouflage(TLS13).await;
+        let probed = server(ShadowTlsBackend::Forward(echo), camouflage_addr, true).await;
+        let err = client(probed, "wrong-password").dial().await.err().unwrap();
+        assert!(format!("{:#}", err).contains("not a ShadowTLS v3 server"), "{:#}", err);
+
+        let (camouflage_addr, _) = camouflage(TLS12).await;
+        let tls12 = server(ShadowTlsBackend::Forward(echo), camouflage_addr, true).await;
+        let err = client(tls12, PASSWORD).dial().await.err().unwrap();
+        assert!(format!("{:#}", err).contains("strict mode"), "{:#}", err);
+
+        // بدون strict همان سرور استتار پذیرفته می‌شود (رکورد دوم دور ریخته می‌شود)
+        let (camouflage_addr, _) = camouflage(TLS12).await;
+        let tls12 = server(ShadowTlsBackend::Forward(echo), camouflage_addr, false).await;
+        let mut stream = client(tls12, PASSWORD).with_strict(false).dial().await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        stream.flush().await.unwrap();
+        let mut echoed = [0u8; 4];
+        stream.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"ping");
+    }
+
+    #[tokio::test]
+    async fn test_server_loopback_backends() {
+        let echo = echo_server().await;
+        let uuid = [5u8; 16];
+
+        // ShadowTLS → مقصد؛ handshake سرور استتار با Finished کلاینت کامل می‌شود
+        let (camouflage_addr, finished) = camouflage(TLS13).await;
+        let forward = server(ShadowTlsBackend::Forward(echo), camouflage_addr, true).await;
+        let mut stream = client(forward, PASSWORD).dial().await.unwrap();
+        let payload = vec![7u8; 40_000];
+        stream.write_all(&payload).await.unwrap();
+        stream.flush().await.unwrap();
+        let mut echoed = vec![0u8; payload.len()];
+        stream.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(echoed, payload);
+        finished.await.expect("camouflage server never saw the client Finished");
+
+        // ShadowTLS → VLESS → مقصد
+        let vless = server(ShadowTlsBackend::Vless { uuid }, camouflage(TLS13).await.0, true).await;
+        let mut stream = client(vless, PASSWORD).dial().await.unwrap();
+        let request = crate::reality::Reality::build_vless_request(&uuid, CMD_TCP, "127.0.0.1", echo.port());
+        stream.write_all(&request).await.unwrap();
+        stream.write_all(b"through the chain").await.unwrap();
+        stream.flush().await.unwrap();
+        let mut reply = [0u8; 2 + 17];
+        stream.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply[..2], &[VLESS_VERSION, 0]);
+        assert_eq!(&reply[2..], b"through the chain");
+
+        // ShadowTLS → SOCKS5 → مقصد
+        let socks = server(ShadowTlsBackend::Socks, camouflage(TLS13).await.0, true).await;
+        let mut stream = client(socks, PASSWORD).dial().await.unwrap();
+        stream.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+        let mut connect = vec![0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1];
+        connect.extend_from_slice(&echo.port().to_be_bytes());
+        stream.write_all(&connect).await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        stream.flush().await.unwrap();
+        let mut reply = [0u8; 2 + 10 + 4];
+        stream.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply[..4], &[0x05, 0x00, 0x05, 0x00]);
+        assert_eq!(&reply[12..], b"ping");
     }
 }
@@ -159,28 +159,22 @@ impl SingboxGenerator {
         server_ip: &str,
         additional_servers: &[(&str, u16)],
     ) -> serde_json::Value {
-        let mut outbounds = Vec::new();
-
-        // Reality / VLESS
-        outbounds.push(self.build_reality_outbound(proxy, server_ip));
-
-        // ShadowTLS v3
-        outbounds.push(self.build_shadowtls_outbound(proxy, server_ip));
-
-        // Hysteria2
-        outbounds.push(self.build_hysteria2_outbound(proxy, server_ip));
-
-        // TUIC v5
-        outbounds.push(self.build_tuic_outbound(proxy, server_ip));
-
-        // MASQUE
-        outbounds.push(self.build_masque_outbound(server_ip));
-
-        // XHTTP
-        outbounds.push(self.build_xhttp_outbound(proxy, server_ip));
-
-        // WARP
-        outbounds.push(self.build_warp_outbound());
+        let mut outbounds = vec![
+            // Reality / VLESS
+            self.build_reality_outbound(proxy, server_ip),
+            // ShadowTLS v3
+            self.build_shadowtls_outbound(proxy, server_ip),
+            // Hysteria2
+            self.build_hysteria2_outbound(proxy, server_ip),
+            // TUIC v5
+            self.build_tuic_outbound(proxy, server_ip),
+            // MASQUE
+            self.build_masque_outbound(server_ip),
+            // XHTTP
+            self.build_xhttp_outbound(proxy, server_ip),
+            // WARP
+            self.build_warp_outbound(),
+        ];
 
         // Selector (اصلی)
         let all_tags: Vec<serde_json::Value> = vec![
@@ -3,13 +3,17 @@
 //! سازگار با smux v1/v2 (همان پروتکل sing-box)
 
 use std::collections::HashMap;
+use std::io;
 use std::sync::atomic::{AtomicU32, Ordering};
 use anyhow::{Context, Result};
+use async_trait::async_trait;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::sync::Mutex;
 use tracing::{debug, info, warn};
 
+use crate::transport::{BoxedStream, Frame, FrameCodec, FramedStream, Transport};
+
 // ── SMUX Frame Constants ────────────────────────────────────────
 const SMUX_VERSION:   u8 = 2;
 const CMD_SYN:        u8 = 0; // Open stream
@@ -210,3 +214,58 @@ impl Smux {
 impl Default for Smux {
     fn default() -> Self { Self::new() }
 }
+
+/// اولین stream کلاینت روی لایه SMUX
+const LAYER_SID: u32 = 1;
+
+/// لایه SMUX: یک stream روی جریان زیرین باز می‌کند و داده را در PSH می‌فرستد
+#[async_trait]
+impl Transport for Smux {
+    fn name(&self) -> &'static str {
+        "SMUX"
+    }
+
+    async fn connect_over(&self, mut lower: BoxedStream) -> Result<BoxedStream> {
+        lower.write_all(&Self::build_open_frame(LAYER_SID)).await
+            .context("SMUX SYN write failed")?;
+        debug!("📦 SMUX Stream #{} opened", LAYER_SID);
+        Ok(Box::new(FramedStream::new(lower, SmuxCodec { sid: LAYER_SID })))
+    }
+
+    /// smux نسخه را در هر فریم اعلام می‌کند و handshake جداگانه ندارد
+    async fn connect_traced(&self, lower: BoxedStream) -> Result<(BoxedStream, Vec<String>)> {
+        let stream = self.connect_over(lower).await?;
+        Ok((stream, vec![format!("version={}", SMUX_VERSION), format!("stream={}", LAYER_SID)]))
+    }
+}
+
+struct SmuxCodec {
+    sid: u32,
+}
+
+impl FrameCodec for SmuxCodec {
+    fn encode(&mut self, payload: &[u8], dst: &mut Vec<u8>) {
+        dst.extend(Smux::wrap_data(self.sid, payload));
+    }
+
+    fn decode(&mut self, src: &mut Vec<u8>, _reply: &mut Vec<u8>) -> io::Result<Option<Frame>> {
+        let Some(header) = src.get(..HEADER_SIZE) else {
+            return Ok(None);
+        };
+        let hdr = SmuxHeader::from_bytes(header.try_into().expect("header slice"));
+        let end = HEADER_SIZE + hdr.length as usize;
+        if src.len() < end {
+            return Ok(None);
+        }
+        let payload: Vec<u8> = src.drain(..end).skip(HEADER_SIZE).collect();
+        Ok(Some(match hdr.cmd {
+            CMD_PSH if hdr.sid == self.sid => Frame::Data(payload),
+            CMD_FIN if hdr.sid == self.sid => Frame::Close,
+            _ => Frame::Control,
+        }))
+    }
+
+    fn close(&mut self, dst: &mut Vec<u8>) {
+        dst.extend(SmuxHeader::new(CMD_FIN, self.sid, 0).to_bytes());
+    }
+}
@@ -0,0 +1,475 @@
+//! Subscription Fetcher
+//!
+//! دریافت لینک‌های اشتراک (base64، متن ساده یا JSON sing-box)، تبدیل هر نود به
+//! [`ProxyConfig`]، حذف تکراری‌ها و ذخیره به عنوان استخر نودهای جایگزین.
+
+use std::{collections::HashSet, path::Path, time::Duration};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{debug, info, warn};
+
+use crate::{
+    share_link::{decode_base64, ShareLink},
+    types::{
+        Hysteria2Settings, ProtocolSettings, ProtocolType, ProxyConfig, ShadowsocksSettings,
+        StreamSettings, TrojanSettings, TuicSettings, VlessSettings,
+    },
+};
+
+/// مسیر پیش‌فرض استخر نودها
+pub const DEFAULT_POOL_PATH: &str = "/opt/network-ghost/sub/pool.json";
+
+// ==================== CONFIG ====================
+
+/// تنظیمات اشتراک (`[subscription]`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SubscriptionConfig {
+    /// آدرس‌های اشتراک (`https://`، `http://` یا `file://`)
+    pub urls: Vec<String>,
+    /// پراکسی برای دریافت از طریق تانل (مثلاً `socks5h://127.0.0.1:1080`)؛ خالی = مستقیم
+    pub proxy: Option<String>,
+    /// تایم‌اوت هر درخواست (ثانیه)
+    pub timeout_secs: u64,
+    /// فایل استخر نودها
+    pub pool_path: String,
+}
+
+impl Default for SubscriptionConfig {
+    fn default() -> Self {
+        Self {
+            urls: Vec::new(),
+            proxy: None,
+            timeout_secs: 20,
+            pool_path: DEFAULT_POOL_PATH.to_string(),
+        }
+    }
+}
+
+// ==================== NODE POOL ====================
+
+/// یک نود از اشتراک
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PoolNode {
+    /// نام نمایشی
+    pub name: String,
+    /// آدرس اشتراک مبدأ
+    pub source: String,
+    /// تنظیمات سرور
+    pub proxy: ProxyConfig,
+}
+
+impl PoolNode {
+    /// تنظیمات سرور این نود روی تنظیمات محلی (تأخیر، padding، ...) اعمال می‌شود
+    pub fn apply_to(&self, local: &ProxyConfig) -> ProxyConfig {
+        ProxyConfig {
+            server: self.proxy.server.clone(),
+            port: self.proxy.port,
+            protocol: self.proxy.protocol.clone(),
+            sni: self.proxy.sni.clone(),
+            uuid: self.proxy.uuid.clone(),
+            public_key: self.proxy.public_key.clone(),
+            short_id: self.proxy.short_id.clone(),
+            utls_fingerprint: self.proxy.utls_fingerprint.clone(),
+            settings: self.proxy.settings.clone(),
+            ..local.clone()
+        }
+    }
+
+    /// کلید یکتا: لینک بدون نام
+    fn dedup_key(&self) -> String {
+        ShareLink { name: String::new(), proxy: self.proxy.clone() }
+            .to_uri()
+            .unwrap_or_else(|_| format!("{:?}", self.proxy))
+    }
+}
+
+/// استخر نودهای جایگزین
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NodePool {
+    /// زمان آخرین به‌روزرسانی
+    pub updated_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// نودها
+    pub nodes: Vec<PoolNode>,
+    /// موقعیت round-robin
+    #[serde(skip)]
+    cursor: usize,
+}
+
+impl NodePool {
+    /// بارگذاری از فایل (نبود فایل = استخر خالی)
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read node pool {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Invalid node pool {}", path.display()))
+    }
+
+    /// ذخیره در فایل
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).ok();
+        }
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
+
+    /// افزودن نودها بدون تکرار؛ تعداد نودهای جدید برگردانده می‌شود
+    pub fn extend(&mut self, nodes: impl IntoIterator<Item = PoolNode>) -> usize {
+        let mut seen: HashSet<String> = self.nodes.iter().map(PoolNode::dedup_key).collect();
+        let before = self.nodes.len();
+        for node in nodes {
+            if seen.insert(node.dedup_key()) {
+                self.nodes.push(node);
+            }
+        }
+        self.nodes.len() - before
+    }
+
+    /// تعداد نودها
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// آیا استخر خالی است
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// نود بعدی (round-robin) که سرورش با `current` فرق دارد
+    pub fn next_after(&mut self, current: &ProxyConfig) -> Option<PoolNode> {
+        for _ in 0..self.nodes.len() {
+            let node = &self.nodes[self.cursor % self.nodes.len()];
+            self.cursor = (self.cursor + 1) % self.nodes.len();
+            if node.proxy.server != current.server || node.proxy.port != current.port {
+                return Some(node.clone());
+            }
+        }
+        None
+    }
+}
+
+// ==================== DECODING ====================
+
+/// تبدیل بدنه اشتراک به لینک‌ها؛ نودهای نامعتبر نادیده گرفته می‌شوند
+pub fn decode_subscription(body: &str) -> Result<Vec<ShareLink>> {
+    let body = body.trim().trim_start_matches('\u{feff}');
+    if body.is_empty() {
+        bail!("Empty subscription");
+    }
+
+    if body.starts_with('{') {
+        return decode_singbox(body);
+    }
+
+    // base64 یا متن ساده
+    let text = if body.contains("://") {
+        body.to_string()
+    } else {
+        String::from_utf8(decode_base64(body)?).context("Subscription is not UTF-8")?
+    };
+
+    let mut links = Vec::new();
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+        match ShareLink::parse(line) {
+            Ok(link) => links.push(link),
+            Err(e) => debug!("Skipping subscription entry: {}", e),
+        }
+    }
+    Ok(links)
+}
+
+/// تبدیل outboundهای JSON sing-box
+fn decode_singbox(body: &str) -> Result<Vec<ShareLink>> {
+    let json: Value = serde_json::from_str(body).context("Invalid sing-box JSON")?;
+    let outbounds = json["outbounds"]
+        .as_array()
+        .ok_or_else(|| anyhow!("sing-box JSON has no outbounds"))?;
+
+    let mut links = Vec::new();
+    for outbound in outbounds {
+        match singbox_outbound(outbound) {
+            Ok(Some(link)) => links.push(link),
+            Ok(None) => {}
+            Err(e) => debug!("Skipping sing-box outbound: {}", e),
+        }
+    }
+    Ok(links)
+}
+
+fn singbox_outbound(o: &Value) -> Result<Option<ShareLink>> {
+    let str_of = |v: &Value| v.as_str().filter(|s| !s.is_empty()).map(str::to_string);
+    let kind = o["type"].as_str().unwrap_or_default();
+    let protocol = match kind {
+        "vless" => ProtocolType::Vless,
+        "trojan" => ProtocolType::Trojan,
+        "hysteria2" => ProtocolType::Hysteria2,
+        "tuic" => ProtocolType::Tuic,
+        "shadowsocks" => ProtocolType::Shadowsocks,
+        // direct/block/dns/selector/urltest و سایر انواع نود نیستند
+        _ => return Ok(None),
+    };
+
+    let server = str_of(&o["server"]).ok_or_else(|| anyhow!("{} outbound has no server", kind))?;
+    let port = o["server_port"]
+        .as_u64()
+        .and_then(|p| u16::try_from(p).ok())
+        .ok_or_else(|| anyhow!("{} outbound has no server_port", kind))?;
+    let tls = &o["tls"];
+    let alpn: Vec<String> = tls["alpn"]
+        .as_array()
+        .map(|a| a.iter().filter_map(str_of).collect())
+        .unwrap_or_default();
+    let insecure = tls["insecure"].as_bool().unwrap_or(false);
+    let password = || str_of(&o["password"]).ok_or_else(|| anyhow!("{} outbound has no password", kind));
+
+    let defaults = ProxyConfig::default();
+    let mut proxy = ProxyConfig {
+        sni: str_of(&tls["server_name"]).unwrap_or_else(|| server.clone()),
+        utls_fingerprint: str_of(&tls["utls"]["fingerprint"])
+            .unwrap_or(defaults.utls_fingerprint.clone()),
+        uuid: str_of(&o["uuid"]).unwrap_or_default(),
+        server,
+        port,
+        protocol,
+        ..defaults
+    };
+
+    let stream = || {
+        let transport = &o["transport"];
+        let security = if tls["reality"]["enabled"].as_bool().unwrap_or(false) {
+            "reality"
+        } else if tls["enabled"].as_bool().unwrap_or(false) {
+            "tls"
+        } else {
+            "none"
+        };
+        StreamSettings {
+            network: str_of(&transport["type"]).unwrap_or_else(|| "tcp".to_string()),
+            security: security.to_string(),
+            path: str_of(&transport["path"]),
+            host: str_of(&transport["headers"]["Host"])
+                .or_else(|| str_of(&transport["host"])),
+            service_name: str_of(&transport["service_name"]),
+            header_type: None,
+            alpn: alpn.clone(),
+            allow_insecure: insecure,
+        }
+    };
+
+    proxy.settings = Some(match kind {
+        "vless" => {
+            let transport = stream();
+            if transport.security == "reality" {
+                proxy.protocol = ProtocolType::Reality;
+                proxy.public_key = str_of(&tls["reality"]["public_key"]);
+                proxy.short_id = str_of(&tls["reality"]["short_id"]);
+            }
+            ProtocolSettings::Vless(VlessSettings {
+                flow: str_of(&o["flow"]),
+                spider_x: None,
+                transport,
+            })
+        }
+        "trojan" => ProtocolSettings::Trojan(TrojanSettings {
+            password: password()?,
+            transport: stream(),
+        }),
+        "hysteria2" => ProtocolSettings::Hysteria2(Hysteria2Settings {
+            password: password()?,
+            obfs: str_of(&o["obfs"]["type"]),
+            obfs_password: str_of(&o["obfs"]["password"]),
+            insecure,
+            pin_sha256: None,
+        }),
+        "tuic" => {
+            let defaults = TuicSettings::default();
+            ProtocolSettings::Tuic(TuicSettings {
+                password: password()?,
+                congestion_control: str_of(&o["congestion_control"])
+                    .unwrap_or(defaults.congestion_control),
+                udp_relay_mode: str_of(&o["udp_relay_mode"]).unwrap_or(defaults.udp_relay_mode),
+                alpn: if alpn.is_empty() { defaults.alpn } else { alpn.clone() },
+                allow_insecure: insecure,
+            })
+        }
+        _ => ProtocolSettings::Shadowsocks(ShadowsocksSettings {
+            method: str_of(&o["method"]).ok_or_else(|| anyhow!("shadowsocks outbound has no method"))?,
+            password: password()?,
+            plugin: str_of(&o["plugin"]).map(|plugin| match str_of(&o["plugin_opts"]) {
+                Some(opts) => format!("{};{}", plugin, opts),
+                None => plugin,
+            }),
+        }),
+    });
+
+    Ok(Some(ShareLink {
+        name: str_of(&o["tag"]).unwrap_or_default(),
+        proxy,
+    }))
+}
+
+// ==================== FETCHER ====================
+
+/// دریافت‌کننده اشتراک‌ها
+pub struct SubscriptionFetcher {
+    config: SubscriptionConfig,
+}
+
+impl SubscriptionFetcher {
+    /// ساخت fetcher جدید
+    pub fn new(config: SubscriptionConfig) -> Self {
+        Self { config }
+    }
+
+    /// دریافت بدنه یک اشتراک
+    pub async fn fetch(&self, url: &str) -> Result<String> {
+        if let Some(path) = url.strip_prefix("file://") {
+            return tokio::fs::read_to_string(path)
+                .await
+                .with_context(|| format!("Failed to read {}", path));
+        }
+
+        let mut client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(self.config.timeout_secs))
+            .user_agent("v2rayNG/1.8.19");
+        if let Some(proxy) = &self.config.proxy {
+            client = client.proxy(reqwest::Proxy::all(proxy).context("Invalid subscription proxy")?);
+        }
+
+        let response = client
+            .build()?
+            .get(url)
+            .send()
+            .await
+            .with_context(|| format!("Failed to fetch {}", url))?
+            .error_for_status()?;
+        Ok(response.text().await?)
+    }
+
+    /// دریافت همه اشتراک‌ها و ساخت استخر جدید
+    ///
+    /// اشتراک‌های ناموفق گزارش می‌شوند ولی بقیه را متوقف نمی‌کنند؛ اگر هیچ
+    /// اشتراکی دریافت نشود خطا برگردانده می‌شود تا استخر قبلی حفظ شود.
+    pub async fn refresh(&self) -> Result<NodePool> {
+        if self.config.urls.is_empty() {
+            bail!("No subscription URLs configured");
+        }
+
+        let mut pool = NodePool::default();
+        let mut fetched = 0;
+        for url in &self.config.urls {
+            let links = match self.fetch(url).await.and_then(|body| decode_subscription(&body)) {
+                Ok(links) => links,
+                Err(e) => {
+                    warn!("⚠️ اشتراک {} ناموفق: {:#}", url, e);
+                    continue;
+                }
+            };
+            fetched += 1;
+            let total = links.len();
+            let added = pool.extend(links.into_iter().map(|link| PoolNode {
+                name: link.name,
+                source: url.clone(),
+                proxy: link.proxy,
+            }));
+            info!("📥 {}: {} نود ({} جدید)", url, total, added);
+        }
+
+        if fetched == 0 {
+            bail!("All {} subscriptions failed", self.config.urls.len());
+        }
+        pool.updated_at = Some(chrono::Utc::now());
+        Ok(pool)
+    }
+
+    /// به‌روزرسانی و ذخیره استخر در `pool_path`
+    pub async fn refresh_and_save(&self) -> Result<NodePool> {
+        let pool = self.refresh().await?;
+        pool.save(&self.config.pool_path)?;
+        info!("💾 استخر نودها: {} نود در {}", pool.len(), self.config.pool_path);
+        Ok(pool)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TROJAN: &str = "trojan://secret@198.51.100.7:443?security=tls&sni=a.example.com#A";
+    const HY2: &str = "hysteria2://pw@198.51.100.8:8443?sni=b.example.com#B";
+
+    fn temp_file(name: &str, content: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("ghost-sub-{}-{}", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_refresh_decodes_and_dedups_all_formats() {
+        use base64::Engine as _;
+
+        let plain = temp_file("plain", &format!("{}\n\nnot-a-link\n{}\n", TROJAN, HY2));
+        let b64 = temp_file(
+            "b64",
+            &base64::engine::general_purpose::STANDARD.encode(format!("{}\n", TROJAN)),
+        );
+        let singbox = temp_file(
+            "json",
+            r#"{"outbounds": [
+                {"type": "vless", "tag": "R", "server": "203.0.113.1", "server_port": 443,
+                 "uuid": "b831381d-6324-4d53-ad4f-8cda48b30811", "flow": "xtls-rprx-vision",
+                 "tls": {"enabled": true, "server_name": "www.speedtest.net",
+                         "utls": {"enabled": true, "fingerprint": "firefox"},
+                         "reality": {"enabled": true, "public_key": "pbk", "short_id": "ab"}}},
+                {"type": "direct", "tag": "direct"}
+            ]}"#,
+        );
+
+        let urls = [&plain, &b64, &singbox]
+            .iter()
+            .map(|p| format!("file://{}", p.display()))
+            .chain(["file:///nonexistent/ghost-sub".to_string()])
+            .collect();
+        let fetcher = SubscriptionFetcher::new(SubscriptionConfig { urls, ..Default::default() });
+        let pool = fetcher.refresh().await.unwrap();
+
+        for path in [plain, b64, singbox] {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        // ترجان تکراری در base64 حذف می‌شود
+        assert_eq!(pool.len(), 3);
+        let reality = &pool.nodes[2];
+        assert_eq!(reality.name, "R");
+        assert_eq!(reality.proxy.protocol, ProtocolType::Reality);
+        assert_eq!(reality.proxy.public_key.as_deref(), Some("pbk"));
+        assert_eq!(reality.proxy.utls_fingerprint, "firefox");
+    }
+
+    #[test]
+    fn test_pool_round_robin_skips_current() {
+        let mut pool = NodePool::default();
+        pool.extend(decode_subscription(&format!("{}\n{}", TROJAN, HY2)).unwrap().into_iter().map(
+            |link| PoolNode { name: link.name, source: String::new(), proxy: link.proxy },
+        ));
+        let current = pool.nodes[0].proxy.clone();
+
+        let local = ProxyConfig { max_latency_ms: 99, ..ProxyConfig::default() };
+        let next = pool.next_after(&current).unwrap();
+        assert_eq!(next.name, "B");
+        assert_eq!(next.apply_to(&local).max_latency_ms, 99);
+        assert_eq!(pool.next_after(&current).unwrap().name, "B");
+    }
+}
@@ -0,0 +1,1092 @@
+//! TLS 1.3 Handshake Helpers
+//!
+//! ساخت ClientHello با شکل مرورگر Chrome (GREASE، ترتیب اکستنشن‌ها، key_share
+//! واقعی X25519 و padding تا ۵۱۲ بایت)، خواندن ServerHello و رکوردهای TLS.
+//! ShadowTLS و REALITY هر دو session ID همین ClientHello را برای احراز هویت
+//! بازنویسی می‌کنند؛ [`SESSION_ID_OFFSET`] جای آن در رکورد است.
+//!
+//! [`client_handshake`] یک handshake کامل TLS 1.3 (key schedule طبق RFC 8446،
+//! رمز رکوردها و بررسی Finished) روی همین ClientHello انجام می‌دهد. بررسی
+//! زنجیره CA به عهده فراخواننده است (REALITY گواهی موقت خودش را دارد).
+
+use std::io;
+
+use anyhow::{anyhow, bail, Context, Result};
+use ring::{aead, digest, hmac, signature};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::transport::{BoxedStream, Frame, FrameCodec, FramedStream};
+
+/// طول هدر رکورد TLS
+pub const RECORD_HEADER_LEN: usize = 5;
+
+/// حداکثر طول بدنه رکورد (2^14 + سربار رمزنگاری)
+pub const MAX_RECORD_LEN: usize = 16384 + 2048;
+
+pub const CONTENT_CHANGE_CIPHER_SPEC: u8 = 0x14;
+pub const CONTENT_ALERT: u8 = 0x15;
+pub const CONTENT_HANDSHAKE: u8 = 0x16;
+pub const CONTENT_APPLICATION_DATA: u8 = 0x17;
+
+pub const HANDSHAKE_CLIENT_HELLO: u8 = 0x01;
+pub const HANDSHAKE_SERVER_HELLO: u8 = 0x02;
+pub const HANDSHAKE_ENCRYPTED_EXTENSIONS: u8 = 0x08;
+pub const HANDSHAKE_CERTIFICATE: u8 = 0x0b;
+pub const HANDSHAKE_CERTIFICATE_REQUEST: u8 = 0x0d;
+pub const HANDSHAKE_CERTIFICATE_VERIFY: u8 = 0x0f;
+pub const HANDSHAKE_FINISHED: u8 = 0x14;
+
+pub const TLS10: u16 = 0x0301;
+pub const TLS12: u16 = 0x0303;
+pub const TLS13: u16 = 0x0304;
+
+/// جای session ID در رکورد ClientHello:
+/// هدر رکورد (۵) + نوع و طول handshake (۴) + نسخه (۲) + random (۳۲) + طول session ID (۱)
+pub const SESSION_ID_OFFSET: usize = RECORD_HEADER_LEN + 4 + 2 + 32 + 1;
+
+const EXT_SERVER_NAME: u16 = 0x0000;
+const EXT_STATUS_REQUEST: u16 = 0x0005;
+const EXT_SUPPORTED_GROUPS: u16 = 0x000a;
+const EXT_EC_POINT_FORMATS: u16 = 0x000b;
+const EXT_SIGNATURE_ALGORITHMS: u16 = 0x000d;
+const EXT_ALPN: u16 = 0x0010;
+const EXT_SCT: u16 = 0x0012;
+const EXT_PADDING: u16 = 0x0015;
+const EXT_EXTENDED_MASTER_SECRET: u16 = 0x0017;
+const EXT_COMPRESS_CERTIFICATE: u16 = 0x001b;
+const EXT_SESSION_TICKET: u16 = 0x0023;
+const EXT_SUPPORTED_VERSIONS: u16 = 0x002b;
+const EXT_PSK_KEY_EXCHANGE_MODES: u16 = 0x002d;
+const EXT_KEY_SHARE: u16 = 0x0033;
+const EXT_APPLICATION_SETTINGS: u16 = 0x4469;
+const EXT_RENEGOTIATION_INFO: u16 = 0xff01;
+
+/// گروه X25519
+pub const GROUP_X25519: u16 = 0x001d;
+
+/// الگوریتم امضای Ed25519
+pub const SIGNATURE_ED25519: u16 = 0x0807;
+
+/// random ثابت HelloRetryRequest (RFC 8446 بخش 4.1.3)
+const HELLO_RETRY_REQUEST: [u8; 32] = [
+    0xcf, 0x21, 0xad, 0x74, 0xe5, 0x9a, 0x61, 0x11, 0xbe, 0x1d, 0x8c, 0x02, 0x1e, 0x65, 0xb8, 0x91,
+    0xc2, 0xa2, 0x11, 0x16, 0x7a, 0xbb, 0x8c, 0x5e, 0x07, 0x9e, 0x09, 0xe2, 0xc8, 0xa8, 0x33, 0x9c,
+];
+
+/// حداکثر طول یک پیام handshake
+const MAX_HANDSHAKE_MESSAGE: usize = 64 * 1024;
+
+const CIPHER_SUITES: &[u16] = &[
+    0x1301, 0x1302, 0x1303, 0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, 0xcca8, 0xc013, 0xc014,
+    0x009c, 0x009d, 0x002f, 0x0035,
+];
+
+const SIGNATURE_ALGORITHMS: &[u16] = &[0x0403, 0x0804, 0x0401, 0x0503, 0x0805, 0x0501, 0x0806, 0x0601];
+
+// ==================== CLIENT HELLO ====================
+
+/// ClientHello با شکل Chrome
+#[derive(Debug, Clone)]
+pub struct ClientHello {
+    pub random: [u8; 32],
+    pub session_id: [u8; 32],
+    pub sni: String,
+    pub alpn: Vec<String>,
+    /// کلید عمومی X25519 در key_share
+    pub key_share: [u8; 32],
+}
+
+impl ClientHello {
+    /// random و session ID تصادفی
+    pub fn new(sni: &str, key_share: [u8; 32]) -> Self {
+        Self {
+            random: rand::random(),
+            session_id: rand::random(),
+            sni: sni.to_string(),
+            alpn: vec!["h2".to_string(), "http/1.1".to_string()],
+            key_share,
+        }
+    }
+
+    /// رکورد کامل (هدر رکورد + پیام handshake)
+    pub fn encode(&self) -> Vec<u8> {
+        let grease = grease_values();
+        let mut body = Vec::with_capacity(512);
+        body.extend_from_slice(&TLS12.to_be_bytes());
+        body.extend_from_slice(&self.random);
+        body.push(self.session_id.len() as u8);
+        body.extend_from_slice(&self.session_id);
+
+        let mut suites = vec![grease[0]];
+        suites.extend_from_slice(CIPHER_SUITES);
+        put_u16(&mut body, (suites.len() * 2) as u16);
+        for suite in suites {
+            put_u16(&mut body, suite);
+        }
+        // compression: null
+        body.extend_from_slice(&[0x01, 0x00]);
+
+        let mut ext = Vec::with_capacity(400);
+        put_ext(&mut ext, grease[1], &[]);
+        if !self.sni.is_empty() {
+            let name = self.sni.as_bytes();
+            let mut sni = Vec::with_capacity(name.len() + 5);
+            put_u16(&mut sni, (name.len() + 3) as u16);
+            sni.push(0x00);
+            put_u16(&mut sni, name.len() as u16);
+            sni.extend_from_slice(name);
+            put_ext(&mut ext, EXT_SERVER_NAME, &sni);
+        }
+        put_ext(&mut ext, EXT_EXTENDED_MASTER_SECRET, &[]);
+        put_ext(&mut ext, EXT_RENEGOTIATION_INFO, &[0x00]);
+        put_ext(&mut ext, EXT_SUPPORTED_GROUPS, &u16_list(&[grease[2], GROUP_X25519, 0x0017, 0x0018], 2));
+        put_ext(&mut ext, EXT_EC_POINT_FORMATS, &[0x01, 0x00]);
+        put_ext(&mut ext, EXT_SESSION_TICKET, &[]);
+        if !self.alpn.is_empty() {
+            let mut protocols = Vec::new();
+            for p in &self.alpn {
+                protocols.push(p.len() as u8);
+                protocols.extend_from_slice(p.as_bytes());
+            }
+            let mut alpn = Vec::with_capacity(protocols.len() + 2);
+            put_u16(&mut alpn, protocols.len() as u16);
+            alpn.extend_from_slice(&protocols);
+            put_ext(&mut ext, EXT_ALPN, &alpn);
+        }
+        put_ext(&mut ext, EXT_STATUS_REQUEST, &[0x01, 0x00, 0x00, 0x00, 0x00]);
+        put_ext(&mut ext, EXT_SIGNATURE_ALGORITHMS, &u16_list(SIGNATURE_ALGORITHMS, 2));
+        put_ext(&mut ext, EXT_SCT, &[]);
+
+        let mut shares = Vec::with_capacity(2 + 5 + 36);
+        put_u16(&mut shares, (5 + 4 + 32) as u16);
+        put_u16(&mut shares, grease[2]);
+        shares.extend_from_slice(&[0x00, 0x01, 0x00]);
+        put_u16(&mut shares, GROUP_X25519);
+        put_u16(&mut shares, 32);
+        shares.extend_from_slice(&self.key_share);
+        put_ext(&mut ext, EXT_KEY_SHARE, &shares);
+
+        put_ext(&mut ext, EXT_PSK_KEY_EXCHANGE_MODES, &[0x01, 0x01]);
+        put_ext(&mut ext, EXT_SUPPORTED_VERSIONS, &u16_list(&[grease[3], TLS13, TLS12], 1));
+        // brotli
+        put_ext(&mut ext, EXT_COMPRESS_CERTIFICATE, &[0x02, 0x00, 0x02]);
+        put_ext(&mut ext, EXT_APPLICATION_SETTINGS, &[0x00, 0x03, 0x02, b'h', b'2']);
+        put_ext(&mut ext, grease[4], &[0x00]);
+
+        // مثل BoringSSL: پیام‌های ۲۵۶ تا ۵۱۱ بایتی تا ۵۱۲ پد می‌شوند
+        let unpadded = 4 + body.len() + 2 + ext.len();
+        if (0x100..0x200).contains(&unpadded) {
+            let pad = (0x200 - unpadded).saturating_sub(4).max(1);
+            put_ext(&mut ext, EXT_PADDING, &vec![0u8; pad]);
+        }
+        put_u16(&mut body, ext.len() as u16);
+        body.extend_from_slice(&ext);
+
+        let handshake = handshake_message(HANDSHAKE_CLIENT_HELLO, &body);
+        let mut record = Vec::with_capacity(handshake.len() + RECORD_HEADER_LEN);
+        encode_record(CONTENT_HANDSHAKE, TLS10, &handshake, &mut record);
+        record
+    }
+}
+
+impl ClientHello {
+    /// خواندن از بدنه رکورد handshake (سمت سرور)
+    pub fn parse(payload: &[u8]) -> Result<Self> {
+        let mut r = Reader(payload);
+        if r.u8()? != HANDSHAKE_CLIENT_HELLO {
+            bail!("Expected ClientHello, got handshake type {}", payload[0]);
+        }
+        let len = r.u24()?;
+        let mut r = Reader(r.take(len).context("Truncated ClientHello")?);
+
+        let _legacy_version = r.u16()?;
+        let random: [u8; 32] = r.take(32)?.try_into()?;
+        let sid_len = r.u8()? as usize;
+        let session_id: [u8; 32] = r.take(sid_len)?.try_into().context("Session ID is not 32 bytes")?;
+        let suites_len = r.u16()? as usize;
+        r.take(suites_len)?;
+        let compression_len = r.u8()? as usize;
+        r.take(compression_len)?;
+
+        let mut hello = Self { random, session_id, sni: String::new(), alpn: Vec::new(), key_share: [0u8; 32] };
+        let ext_len = r.u16()? as usize;
+        let mut ext = Reader(r.take(ext_len)?);
+        while !ext.0.is_empty() {
+            let kind = ext.u16()?;
+            let len = ext.u16()? as usize;
+            let mut data = Reader(ext.take(len)?);
+            match kind {
+                EXT_SERVER_NAME => {
+                    let _list_len = data.u16()?;
+                    let _name_type = data.u8()?;
+                    let name_len = data.u16()? as usize;
+                    hello.sni = String::from_utf8(data.take(name_len)?.to_vec()).context("Invalid SNI")?;
+                }
+                EXT_ALPN => {
+                    let _list_len = data.u16()?;
+                    while !data.0.is_empty() {
+                        let len = data.u8()? as usize;
+                        hello.alpn.push(String::from_utf8_lossy(data.take(len)?).into_owned());
+                    }
+                }
+                EXT_KEY_SHARE => {
+                    let _list_len = data.u16()?;
+                    while !data.0.is_empty() {
+                        let group = data.u16()?;
+                        let key_len = data.u16()? as usize;
+                        let key = data.take(key_len)?;
+                        if group == GROUP_X25519 && key_len == 32 {
+                            hello.key_share.copy_from_slice(key);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(hello)
+    }
+}
+
+/// مقادیر GREASE (RFC 8701) متفاوت برای هر جایگاه
+fn grease_values() -> [u16; 5] {
+    let first = rand::random::<u8>() >> 4;
+    let mut values = [0u16; 5];
+    for (i, value) in values.iter_mut().enumerate() {
+        let nibble = (first + i as u8 * 3) & 0x0f;
+        *value = u16::from_be_bytes([nibble << 4 | 0x0a, nibble << 4 | 0x0a]);
+    }
+    values
+}
+
+fn put_u16(dst: &mut Vec<u8>, value: u16) {
+    dst.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_ext(dst: &mut Vec<u8>, kind: u16, data: &[u8]) {
+    put_u16(dst, kind);
+    put_u16(dst, data.len() as u16);
+    dst.extend_from_slice(data);
+}
+
+/// لیست u16 با پیشوند طول `len_bytes` بایتی
+fn u16_list(values: &[u16], len_bytes: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(values.len() * 2 + len_bytes);
+    let len = (values.len() * 2) as u16;
+    if len_bytes == 1 {
+        out.push(len as u8);
+    } else {
+        put_u16(&mut out, len);
+    }
+    for value in values {
+        put_u16(&mut out, *value);
+    }
+    out
+}
+
+// ==================== SERVER HELLO ====================
+
+/// فیلدهای لازم از ServerHello
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerHello {
+    pub random: [u8; 32],
+    pub session_id: Vec<u8>,
+    pub cipher_suite: u16,
+    /// نسخه انتخاب‌شده (supported_versions یا legacy_version)
+    pub version: u16,
+    /// کلید سرور در key_share (group, key)
+    pub key_share: Option<(u16, Vec<u8>)>,
+}
+
+impl ServerHello {
+    /// خواندن از بدنه رکورد handshake (شروع با نوع پیام)
+    pub fn parse(payload: &[u8]) -> Result<Self> {
+        let mut r = Reader(payload);
+        if r.u8()? != HANDSHAKE_SERVER_HELLO {
+            bail!("Expected ServerHello, got handshake type {}", payload[0]);
+        }
+        let len = r.u24()?;
+        let mut r = Reader(r.take(len).context("Truncated ServerHello")?);
+
+        let legacy_version = r.u16()?;
+        let random: [u8; 32] = r.take(32)?.try_into()?;
+        let sid_len = r.u8()? as usize;
+        let session_id = r.take(sid_len)?.to_vec();
+        let cipher_suite = r.u16()?;
+        let _compression = r.u8()?;
+
+        let mut version = legacy_version;
+        let mut key_share = None;
+        if !r.0.is_empty() {
+            let ext_len = r.u16()? as usize;
+            let mut ext = Reader(r.take(ext_len)?);
+            while !ext.0.is_empty() {
+                let kind = ext.u16()?;
+                let len = ext.u16()? as usize;
+                let mut data = Reader(ext.take(len)?);
+                match kind {
+                    EXT_SUPPORTED_VERSIONS => version = data.u16()?,
+                    EXT_KEY_SHARE => {
+                        let group = data.u16()?;
+                        let key_len = data.u16()? as usize;
+                        key_share = Some((group, data.take(key_len)?.to_vec()));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(Self { random, session_id, cipher_suite, version, key_share })
+    }
+}
+
+impl ServerHello {
+    /// رکورد کامل ServerHello (سمت سرور)؛ برای TLS 1.3 نسخه در supported_versions می‌آید
+    pub fn encode(&self) -> Vec<u8> {
+        let mut ext = Vec::new();
+        if self.version == TLS13 {
+            put_ext(&mut ext, EXT_SUPPORTED_VERSIONS, &TLS13.to_be_bytes());
+        }
+        if let Some((group, key)) = &self.key_share {
+            let mut share = Vec::with_capacity(key.len() + 4);
+            put_u16(&mut share, *group);
+            put_u16(&mut share, key.len() as u16);
+            share.extend_from_slice(key);
+            put_ext(&mut ext, EXT_KEY_SHARE, &share);
+        }
+
+        let legacy_version = if self.version == TLS13 { TLS12 } else { self.version };
+        let mut body = Vec::with_capacity(ext.len() + 80);
+        put_u16(&mut body, legacy_version);
+        body.extend_from_slice(&self.random);
+        body.push(self.session_id.len() as u8);
+        body.extend_from_slice(&self.session_id);
+        put_u16(&mut body, self.cipher_suite);
+        body.push(0x00);
+        put_u16(&mut body, ext.len() as u16);
+        body.extend_from_slice(&ext);
+
+        let mut record = Vec::with_capacity(body.len() + 4 + RECORD_HEADER_LEN);
+        encode_record(CONTENT_HANDSHAKE, TLS12, &handshake_message(HANDSHAKE_SERVER_HELLO, &body), &mut record);
+        record
+    }
+}
+
+/// پارامترهای توافق‌شده handshake (برای `trace-chain`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsParams {
+    pub version: u16,
+    pub cipher_suite: u16,
+    pub alpn: Option<String>,
+}
+
+impl TlsParams {
+    pub fn from_server_hello(hello: &ServerHello) -> Self {
+        Self { version: hello.version, cipher_suite: hello.cipher_suite, alpn: None }
+    }
+}
+
+impl std::fmt::Display for TlsParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.version {
+            TLS13 => f.write_str("tls=1.3")?,
+            TLS12 => f.write_str("tls=1.2")?,
+            other => write!(f, "tls={:#06x}", other)?,
+        }
+        match self.cipher_suite {
+            0x1301 => f.write_str(" cipher=TLS_AES_128_GCM_SHA256")?,
+            0x1302 => f.write_str(" cipher=TLS_AES_256_GCM_SHA384")?,
+            0x1303 => f.write_str(" cipher=TLS_CHACHA20_POLY1305_SHA256")?,
+            other => write!(f, " cipher={:#06x}", other)?,
+        }
+        if let Some(alpn) = &self.alpn {
+            write!(f, " alpn={}", alpn)?;
+        }
+        Ok(())
+    }
+}
+
+/// پروتکل انتخاب‌شده در extension ALPN (بدنه EncryptedExtensions)
+fn selected_alpn(extensions: &[u8]) -> Result<Option<String>> {
+    let mut r = Reader(extensions);
+    let len = r.u16()? as usize;
+    let mut ext = Reader(r.take(len)?);
+    while !ext.0.is_empty() {
+        let kind = ext.u16()?;
+        let len = ext.u16()? as usize;
+        let mut data = Reader(ext.take(len)?);
+        if kind == EXT_ALPN {
+            let _list_len = data.u16()?;
+            let len = data.u8()? as usize;
+            return Ok(Some(String::from_utf8_lossy(data.take(len)?).into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+/// پیام handshake: `[type][u24 length][body]`
+pub fn handshake_message(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(body.len() + 4);
+    message.push(kind);
+    message.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+    message.extend_from_slice(body);
+    message
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.0.len() < n {
+            bail!("Truncated TLS message");
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u24(&mut self) -> Result<usize> {
+        let b = self.take(3)?;
+        Ok(u32::from_be_bytes([0, b[0], b[1], b[2]]) as usize)
+    }
+}
+
+// ==================== RECORDS ====================
+
+/// یک رکورد TLS
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub content_type: u8,
+    pub payload: Vec<u8>,
+}
+
+impl Record {
+    /// خواندن دقیق یک رکورد (بدون مصرف بایت‌های بعدی)
+    pub async fn read<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Self> {
+        let mut header = [0u8; RECORD_HEADER_LEN];
+        stream.read_exact(&mut header).await.context("Connection closed during TLS handshake")?;
+        let len = u16::from_be_bytes([header[3], header[4]]) as usize;
+        if len > MAX_RECORD_LEN {
+            bail!("TLS record of {} bytes is too large", len);
+        }
+        let mut payload = vec![0u8; len];
+        stream.read_exact(&mut payload).await.context("Truncated TLS record")?;
+        Ok(Self { content_type: header[0], payload })
+    }
+
+    /// برداشتن رکورد کامل از ابتدای `src` (`None` = هنوز کامل نرسیده)
+    pub fn take(src: &mut Vec<u8>) -> io::Result<Option<Self>> {
+        if src.len() < RECORD_HEADER_LEN {
+            return Ok(None);
+        }
+        let len = u16::from_be_bytes([src[3], src[4]]) as usize;
+        if len > MAX_RECORD_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "TLS record too large"));
+        }
+        if src.len() < RECORD_HEADER_LEN + len {
+            return Ok(None);
+        }
+        let content_type = src[0];
+        let payload = src[RECORD_HEADER_LEN..RECORD_HEADER_LEN + len].to_vec();
+        src.drain(..RECORD_HEADER_LEN + len);
+        Ok(Some(Self { content_type, payload }))
+    }
+}
+
+/// نوشتن رکورد با نسخه `version` در هدر
+pub fn encode_record(content_type: u8, version: u16, payload: &[u8], dst: &mut Vec<u8>) {
+    dst.push(content_type);
+    put_u16(dst, version);
+    put_u16(dst, payload.len() as u16);
+    dst.extend_from_slice(payload);
+}
+
+// ==================== TLS 1.3 ====================
+
+/// مجموعه رمزهای TLS 1.3 که ClientHello پیشنهاد می‌دهد
+#[derive(Clone, Copy)]
+pub struct CipherSuite {
+    pub id: u16,
+    aead: &'static aead::Algorithm,
+    hmac: hmac::Algorithm,
+    digest: &'static digest::Algorithm,
+}
+
+impl CipherSuite {
+    pub fn from_id(id: u16) -> Option<Self> {
+        let (aead, hmac, digest) = match id {
+            0x1301 => (&aead::AES_128_GCM, hmac::HMAC_SHA256, &digest::SHA256),
+            0x1302 => (&aead::AES_256_GCM, hmac::HMAC_SHA384, &digest::SHA384),
+            0x1303 => (&aead::CHACHA20_POLY1305, hmac::HMAC_SHA256, &digest::SHA256),
+            _ => return None,
+        };
+        Some(Self { id, aead, hmac, digest })
+    }
+
+    fn hash_len(&self) -> usize {
+        self.digest.output_len()
+    }
+
+    /// هش پیام‌های handshake
+    pub fn transcript(&self) -> digest::Context {
+        digest::Context::new(self.digest)
+    }
+
+    fn extract(&self, salt: &[u8], ikm: &[u8]) -> Vec<u8> {
+        hmac::sign(&hmac::Key::new(self.hmac, salt), ikm).as_ref().to_vec()
+    }
+
+    /// HKDF-Expand-Label
+    pub fn expand_label(&self, secret: &[u8], label: &str, context: &[u8], len: usize) -> Vec<u8> {
+        let label = format!("tls13 {}", label);
+        let mut info = Vec::with_capacity(label.len() + context.len() + 4);
+        put_u16(&mut info, len as u16);
+        info.push(label.len() as u8);
+        info.extend_from_slice(label.as_bytes());
+        info.push(context.len() as u8);
+        info.extend_from_slice(context);
+
+        let key = hmac::Key::new(self.hmac, secret);
+        let mut out = Vec::with_capacity(len + self.hash_len());
+        let mut block = Vec::new();
+        for counter in 1u8.. {
+            if out.len() >= len {
+                break;
+            }
+            let mut context = hmac::Context::with_key(&key);
+            context.update(&block);
+            context.update(&info);
+            context.update(&[counter]);
+            block = context.sign().as_ref().to_vec();
+            out.extend_from_slice(&block);
+        }
+        out.truncate(len);
+        out
+    }
+
+    /// Derive-Secret با هش transcript
+    pub fn derive_secret(&self, secret: &[u8], label: &str, transcript_hash: &[u8]) -> Vec<u8> {
+        self.expand_label(secret, label, transcript_hash, self.hash_len())
+    }
+
+    /// handshake secret از راز مشترک ECDHE
+    pub fn handshake_secret(&self, shared: &[u8]) -> Vec<u8> {
+        let zeros = vec![0u8; self.hash_len()];
+        let early = self.extract(&zeros, &zeros);
+        let derived = self.derive_secret(&early, "derived", digest::digest(self.digest, b"").as_ref());
+        self.extract(&derived, shared)
+    }
+
+    pub fn master_secret(&self, handshake_secret: &[u8]) -> Vec<u8> {
+        let zeros = vec![0u8; self.hash_len()];
+        let derived = self.derive_secret(handshake_secret, "derived", digest::digest(self.digest, b"").as_ref());
+        self.extract(&derived, &zeros)
+    }
+
+    /// verify_data پیام Finished
+    pub fn finished(&self, traffic_secret: &[u8], transcript_hash: &[u8]) -> Vec<u8> {
+        let key = self.expand_label(traffic_secret, "finished", b"", self.hash_len());
+        hmac::sign(&hmac::Key::new(self.hmac, &key), transcript_hash).as_ref().to_vec()
+    }
+}
+
+/// رمز رکوردهای یک جهت با یک traffic secret
+pub struct RecordCipher {
+    key: aead::LessSafeKey,
+    iv: [u8; 12],
+    seq: u64,
+}
+
+impl RecordCipher {
+    pub fn new(suite: &CipherSuite, traffic_secret: &[u8]) -> Self {
+        let key = suite.expand_label(traffic_secret, "key", b"", suite.aead.key_len());
+        let mut iv = [0u8; 12];
+        iv.copy_from_slice(&suite.expand_label(traffic_secret, "iv", b"", 12));
+        let key = aead::UnboundKey::new(suite.aead, &key).expect("key length matches the cipher suite");
+        Self { key: aead::LessSafeKey::new(key), iv, seq: 0 }
+    }
+
+    fn nonce(&mut self) -> aead::Nonce {
+        let mut nonce = self.iv;
+        for (n, s) in nonce[4..].iter_mut().zip(self.seq.to_be_bytes()) {
+            *n ^= s;
+        }
+        self.seq += 1;
+        aead::Nonce::assume_unique_for_key(nonce)
+    }
+
+    /// رکورد ApplicationData رمزشده با نوع داخلی `content_type`
+    pub fn seal(&mut self, content_type: u8, plaintext: &[u8], dst: &mut Vec<u8>) {
+        let tag_len = self.key.algorithm().tag_len();
+        let mut inner = Vec::with_capacity(plaintext.len() + 1 + tag_len);
+        inner.extend_from_slice(plaintext);
+        inner.push(content_type);
+        let len = ((inner.len() + tag_len) as u16).to_be_bytes();
+        let header = [CONTENT_APPLICATION_DATA, 0x03, 0x03, len[0], len[1]];
+        let nonce = self.nonce();
+        self.key
+            .seal_in_place_append_tag(nonce, aead::Aad::from(header), &mut inner)
+            .expect("TLS record is within AEAD limits");
+        dst.extend_from_slice(&header);
+        dst.extend_from_slice(&inner);
+    }
+
+    /// باز کردن بدنه رکورد ApplicationData: (نوع داخلی، داده)
+    pub fn open(&mut self, payload: &[u8]) -> io::Result<(u8, Vec<u8>)> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        let len = (payload.len() as u16).to_be_bytes();
+        let header = [CONTENT_APPLICATION_DATA, 0x03, 0x03, len[0], len[1]];
+        let mut buf = payload.to_vec();
+        let nonce = self.nonce();
+        let plain_len = self
+            .key
+            .open_in_place(nonce, aead::Aad::from(header), &mut buf)
+            .map_err(|_| invalid("TLS record decryption failed"))?
+            .len();
+        buf.truncate(plain_len);
+        // حذف padding صفر؛ آخرین بایت غیرصفر نوع واقعی رکورد است
+        let end = buf.iter().rposition(|&b| b != 0).ok_or_else(|| invalid("TLS record has no content type"))?;
+        let content_type = buf[end];
+        buf.truncate(end);
+        Ok((content_type, buf))
+    }
+}
+
+/// گواهی سرور (فقط فیلدهای لازم برای REALITY)
+#[derive(Debug, Clone)]
+pub struct Certificate {
+    pub der: Vec<u8>,
+    /// امضای گواهی (BIT STRING آخر)
+    pub signature: Vec<u8>,
+    /// کلید عمومی Ed25519 در subjectPublicKeyInfo
+    pub ed25519_key: Option<[u8; 32]>,
+}
+
+/// subjectPublicKeyInfo کلید Ed25519 (RFC 8410) تا پیش از خود کلید
+const ED25519_SPKI_PREFIX: [u8; 12] = [0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+
+impl Certificate {
+    /// خواندن حداقلی DER: `SEQUENCE { tbsCertificate, signatureAlgorithm, signatureValue }`
+    pub fn parse(der: &[u8]) -> Result<Self> {
+        let (tag, certificate, _) = der_next(der)?;
+        if tag != 0x30 {
+            bail!("Certificate is not a DER sequence");
+        }
+        let (_, tbs, rest) = der_next(certificate)?;
+        let (_, _algorithm, rest) = der_next(rest)?;
+        let (tag, bits, _) = der_next(rest)?;
+        if tag != 0x03 || bits.is_empty() {
+            bail!("Certificate has no signature");
+        }
+        let ed25519_key = tbs
+            .windows(ED25519_SPKI_PREFIX.len())
+            .position(|w| w == ED25519_SPKI_PREFIX)
+            .and_then(|at| tbs.get(at + ED25519_SPKI_PREFIX.len()..at + ED25519_SPKI_PREFIX.len() + 32))
+            .map(|key| key.try_into().expect("slice is 32 bytes"));
+        Ok(Self { der: der.to_vec(), signature: bits[1..].to_vec(), ed25519_key })
+    }
+}
+
+/// یک TLV در DER: (tag، محتوا، باقی‌مانده)
+fn der_next(input: &[u8]) -> Result<(u8, &[u8], &[u8])> {
+    let mut r = Reader(input);
+    let tag = r.u8()?;
+    let first = r.u8()? as usize;
+    let len = match first {
+        0..=0x7f => first,
+        0x81..=0x83 => r.take(first - 0x80)?.iter().fold(0usize, |len, b| len << 8 | *b as usize),
+        _ => bail!("Unsupported DER length"),
+    };
+    let content = r.take(len).context("Truncated DER value")?;
+    Ok((tag, content, r.0))
+}
+
+/// محتوای امضاشده CertificateVerify سرور (RFC 8446 بخش 4.4.3)
+pub fn certificate_verify_content(transcript_hash: &[u8]) -> Vec<u8> {
+    let mut content = vec![0x20; 64];
+    content.extend_from_slice(b"TLS 1.3, server CertificateVerify");
+    content.push(0);
+    content.extend_from_slice(transcript_hash);
+    content
+}
+
+/// کلاینت TLS 1.3 از ServerHello تا Finished سرور
+///
+/// رکوردهای رمزشده پرواز سرور به [`Tls13Client::read_record`] داده می‌شوند
+/// (پیام‌ها ممکن است در چند رکورد پخش باشند) و پس از Finished معتبر
+/// [`Tls13Client::finish`] رکوردهای پایانی کلاینت و codec داده را می‌سازد.
+pub struct Tls13Client {
+    suite: CipherSuite,
+    transcript: digest::Context,
+    handshake_secret: Vec<u8>,
+    client_secret: Vec<u8>,
+    server_secret: Vec<u8>,
+    recv: RecordCipher,
+    /// پیام‌های handshake ناقص
+    buf: Vec<u8>,
+    /// بررسی CertificateVerify (فقط Ed25519)
+    verify_signature: bool,
+    certificate: Option<Certificate>,
+    finished: bool,
+    params: TlsParams,
+}
+
+impl Tls13Client {
+    /// `hello_record` رکورد کامل ClientHello و `server_hello` بدنه رکورد ServerHello است
+    pub fn new(hello_record: &[u8], server_hello: &[u8], secret: &StaticSecret) -> Result<Self> {
+        let parsed = ServerHello::parse(server_hello)?;
+        if parsed.random == HELLO_RETRY_REQUEST {
+            bail!("Server sent a HelloRetryRequest (X25519 not accepted)");
+        }
+        if parsed.version != TLS13 {
+            bail!("Server negotiated TLS version {:#06x} instead of TLS 1.3", parsed.version);
+        }
+        let suite = CipherSuite::from_id(parsed.cipher_suite)
+            .with_context(|| format!("Server chose unsupported cipher suite {:#06x}", parsed.cipher_suite))?;
+        let server_key: [u8; 32] = match &parsed.key_share {
+            Some((GROUP_X25519, key)) => key.as_slice().try_into().context("Invalid X25519 key share")?,
+            _ => bail!("ServerHello has no X25519 key share"),
+        };
+        let shared = secret.diffie_hellman(&PublicKey::from(server_key));
+
+        let server_hello_len = 4 + Reader(&server_hello[1..]).u24()?;
+        let mut transcript = suite.transcript();
+        transcript.update(&hello_record[RECORD_HEADER_LEN..]);
+        transcript.update(server_hello.get(..server_hello_len).context("Truncated ServerHello")?);
+
+        let handshake_secret = suite.handshake_secret(shared.as_bytes());
+        let hash = transcript.clone().finish();
+        let client_secret = suite.derive_secret(&handshake_secret, "c hs traffic", hash.as_ref());
+        let server_secret = suite.derive_secret(&handshake_secret, "s hs traffic", hash.as_ref());
+        Ok(Self {
+            recv: RecordCipher::new(&suite, &server_secret),
+            suite,
+            transcript,
+            handshake_secret,
+            client_secret,
+            server_secret,
+            buf: Vec::new(),
+            verify_signature: true,
+            certificate: None,
+            finished: false,
+            params: TlsParams::from_server_hello(&parsed),
+        })
+    }
+
+    /// بدون بررسی امضای CertificateVerify (مثلاً گواهی RSA/ECDSA سرور استتار)
+    pub fn without_signature_check(mut self) -> Self {
+        self.verify_signature = false;
+        self
+    }
+
+    pub fn certificate(&self) -> Option<&Certificate> {
+        self.certificate.as_ref()
+    }
+
+    /// نسخه، cipher و ALPN توافق‌شده (ALPN پس از EncryptedExtensions)
+    pub fn params(&self) -> &TlsParams {
+        &self.params
+    }
+
+    /// بدنه یک رکورد ApplicationData پرواز سرور؛ `true` پس از Finished معتبر
+    pub fn read_record(&mut self, payload: &[u8]) -> Result<bool> {
+        match self.recv.open(payload)? {
+            (CONTENT_HANDSHAKE, data) => self.buf.extend_from_slice(&data),
+            (CONTENT_ALERT, alert) => {
+                bail!("Server sent TLS alert {} during the handshake", alert.get(1).copied().unwrap_or(0))
+            }
+            (other, _) => bail!("Unexpected record type {} during the handshake", other),
+        }
+        while !self.finished && self.buf.len() >= 4 {
+            let len = u32::from_be_bytes([0, self.buf[1], self.buf[2], self.buf[3]]) as usize;
+            if len > MAX_HANDSHAKE_MESSAGE {
+                bail!("Handshake message of {} bytes is too large", len);
+            }
+            if self.buf.len() < 4 + len {
+                break;
+            }
+            let message: Vec<u8> = self.buf.drain(..4 + len).collect();
+            self.handle_message(&message)?;
+        }
+        Ok(self.finished)
+    }
+
+    fn handle_message(&mut self, message: &[u8]) -> Result<()> {
+        let body = &message[4..];
+        match message[0] {
+            HANDSHAKE_ENCRYPTED_EXTENSIONS => self.params.alpn = selected_alpn(body)?,
+            HANDSHAKE_CERTIFICATE => {
+                if self.certificate.is_some() {
+                    bail!("Duplicate Certificate message");
+                }
+                let mut r = Reader(body);
+                let context_len = r.u8()? as usize;
+                r.take(context_len)?;
+                let _list_len = r.u24()?;
+                let cert_len = r.u24()?;
+                self.certificate = Some(Certificate::parse(r.take(cert_len)?)?);
+            }
+            HANDSHAKE_CERTIFICATE_VERIFY => {
+                let cert = self.certificate.as_ref().context("CertificateVerify before Certificate")?;
+                if self.verify_signature {
+                    let mut r = Reader(body);
+                    let scheme = r.u16()?;
+                    let sig_len = r.u16()? as usize;
+                    let sig = r.take(sig_len)?;
+                    if scheme != SIGNATURE_ED25519 {
+                        bail!("Unsupported CertificateVerify scheme {:#06x}", scheme);
+                    }
+                    let key = cert.ed25519_key.context("Certificate has no Ed25519 key")?;
+                    let content = certificate_verify_content(self.transcript.clone().finish().as_ref());
+                    signature::UnparsedPublicKey::new(&signature::ED25519, key)
+                        .verify(&content, sig)
+                        .map_err(|_| anyhow!("CertificateVerify signature is invalid"))?;
+                }
+            }
+            HANDSHAKE_FINISHED => {
+                let expected = self.suite.finished(&self.server_secret, self.transcript.clone().finish().as_ref());
+                if body != expected.as_slice() {
+                    bail!("Server Finished verification failed");
+                }
+                self.finished = true;
+            }
+            HANDSHAKE_CERTIFICATE_REQUEST => bail!("Server asked for a client certificate"),
+            other => bail!("Unexpected handshake message {}", other),
+        }
+        self.transcript.update(message);
+        Ok(())
+    }
+
+    /// رکوردهای پایانی کلاینت (ChangeCipherSpec سازگاری مثل Chrome و Finished) و codec داده
+    pub fn finish(self) -> Result<(Vec<u8>, Tls13Codec)> {
+        if !self.finished {
+            bail!("TLS handshake finished before the server Finished message");
+        }
+        if self.certificate.is_none() {
+            bail!("Server sent no certificate");
+        }
+        let suite = self.suite;
+        let hash = self.transcript.finish();
+        let master_secret = suite.master_secret(&self.handshake_secret);
+        let client_app = suite.derive_secret(&master_secret, "c ap traffic", hash.as_ref());
+        let server_app = suite.derive_secret(&master_secret, "s ap traffic", hash.as_ref());
+
+        let finished = handshake_message(HANDSHAKE_FINISHED, &suite.finished(&self.client_secret, hash.as_ref()));
+        let mut out = Vec::with_capacity(64);
+        encode_record(CONTENT_CHANGE_CIPHER_SPEC, TLS12, &[0x01], &mut out);
+        RecordCipher::new(&suite, &self.client_secret).seal(CONTENT_HANDSHAKE, &finished, &mut out);
+
+        let codec = Tls13Codec {
+            send: RecordCipher::new(&suite, &client_app),
+            recv: RecordCipher::new(&suite, &server_app),
+        };
+        Ok((out, codec))
+    }
+}
+
+/// handshake کامل TLS 1.3 با رکورد ClientHello ساخته‌شده و کلید X25519 آن
+///
+/// `verify` گواهی سرور را می‌پذیرد یا رد می‌کند (زنجیره CA بررسی نمی‌شود) و
+/// CertificateVerify فقط با Ed25519 پشتیبانی می‌شود. خروجی جریان رمزشده
+/// ApplicationData و پارامترهای توافق‌شده است.
+pub async fn client_handshake<F>(
+    mut stream: BoxedStream,
+    hello_record: &[u8],
+    secret: &StaticSecret,
+    verify: F,
+) -> Result<(BoxedStream, TlsParams)>
+where
+    F: FnOnce(&Certificate) -> Result<()>,
+{
+    stream.write_all(hello_record).await?;
+    stream.flush().await?;
+
+    let first = Record::read(&mut stream).await?;
+    match first.content_type {
+        CONTENT_HANDSHAKE => {}
+        CONTENT_ALERT => bail!("Server rejected the ClientHello with a TLS alert"),
+        other => bail!("Expected a handshake record, got content type {}", other),
+    }
+    let mut tls = Tls13Client::new(hello_record, &first.payload, secret)?;
+    loop {
+        let record = Record::read(&mut stream).await?;
+        match record.content_type {
+            CONTENT_CHANGE_CIPHER_SPEC => continue,
+            CONTENT_APPLICATION_DATA => {
+                if tls.read_record(&record.payload)? {
+                    break;
+                }
+            }
+            CONTENT_ALERT => bail!("Server sent a TLS alert during the handshake"),
+            other => bail!("Unexpected record type {} during the handshake", other),
+        }
+    }
+    verify(tls.certificate().context("Server sent no certificate")?)?;
+
+    let params = tls.params().clone();
+    let (out, codec) = tls.finish()?;
+    stream.write_all(&out).await?;
+    Ok((Box::new(FramedStream::new(stream, codec)), params))
+}
+
+/// رکوردهای ApplicationData پس از handshake
+pub struct Tls13Codec {
+    pub send: RecordCipher,
+    pub recv: RecordCipher,
+}
+
+impl FrameCodec for Tls13Codec {
+    const MAX_PAYLOAD: usize = 16384;
+
+    fn encode(&mut self, payload: &[u8], dst: &mut Vec<u8>) {
+        self.send.seal(CONTENT_APPLICATION_DATA, payload, dst);
+    }
+
+    fn decode(&mut self, src: &mut Vec<u8>, _reply: &mut Vec<u8>) -> io::Result<Option<Frame>> {
+        let Some(record) = Record::take(src)? else {
+            return Ok(None);
+        };
+        match record.content_type {
+            CONTENT_APPLICATION_DATA => match self.recv.open(&record.payload)? {
+                (CONTENT_APPLICATION_DATA, data) => Ok(Some(Frame::Data(data))),
+                (CONTENT_ALERT, _) => Ok(Some(Frame::Close)),
+                // NewSessionTicket و ...
+                _ => Ok(Some(Frame::Control)),
+            },
+            CONTENT_ALERT => Ok(Some(Frame::Close)),
+            _ => Ok(Some(Frame::Control)),
+        }
+    }
+
+    /// close_notify
+    fn close(&mut self, dst: &mut Vec<u8>) {
+        self.send.seal(CONTENT_ALERT, &[0x01, 0x00], dst);
+    }
+}
+
+/// سرور TLS 1.3 حداقلی برای تست‌ها (سرور استتار یا REALITY)
+///
+/// پرواز handshake با گواهی Ed25519 که امضایش `sign_cert(کلید عمومی)` است،
+/// هر پیام در یک رکورد جدا؛ پس از بررسی Finished کلاینت codec سمت سرور برمی‌گردد.
+#[cfg(test)]
+pub(crate) async fn serve_test_handshake<S>(
+    socket: &mut S,
+    hello: &Record,
+    sign_cert: impl FnOnce(&[u8]) -> Vec<u8>,
+) -> Result<Tls13Codec>
+where
+    S: AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    use ring::{
+        rand::SystemRandom,
+        signature::{Ed25519KeyPair, KeyPair},
+    };
+
+    let client_hello = ClientHello::parse(&hello.payload)?;
+    let client_key = PublicKey::from(client_hello.key_share);
+    let suite = CipherSuite::from_id(0x1301).expect("TLS_AES_128_GCM_SHA256");
+    let secret = StaticSecret::from(rand::random::<[u8; 32]>());
+    let mut flight = ServerHello {
+        random: rand::random(),
+        session_id: client_hello.session_id.to_vec(),
+        cipher_suite: suite.id,
+        version: TLS13,
+        key_share: Some((GROUP_X25519, PublicKey::from(&secret).as_bytes().to_vec())),
+    }
+    .encode();
+    let mut transcript = suite.transcript();
+    transcript.update(&hello.payload);
+    transcript.update(&flight[RECORD_HEADER_LEN..]);
+    encode_record(CONTENT_CHANGE_CIPHER_SPEC, TLS12, &[0x01], &mut flight);
+
+    let handshake_secret = suite.handshake_secret(secret.diffie_hellman(&client_key).as_bytes());
+    let hash = transcript.clone().finish();
+    let client_secret = suite.derive_secret(&handshake_secret, "c hs traffic", hash.as_ref());
+    let server_secret = suite.derive_secret(&handshake_secret, "s hs traffic", hash.as_ref());
+
+    // گواهی حداقلی: SEQUENCE { tbs { spki }, ed25519, BIT STRING امضا }
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+    let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+    let public_key = key_pair.public_key().as_ref();
+    let mut cert = vec![0x30, 0x78, 0x30, 0x2c];
+    cert.extend_from_slice(&ED25519_SPKI_PREFIX);
+    cert.extend_from_slice(public_key);
+    cert.extend_from_slice(&[0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x41, 0x00]);
+    cert.extend_from_slice(&sign_cert(public_key));
+
+    let mut body = vec![0];
+    body.extend_from_slice(&((cert.len() + 5) as u32).to_be_bytes()[1..]);
+    body.extend_from_slice(&(cert.len() as u32).to_be_bytes()[1..]);
+    body.extend_from_slice(&cert);
+    body.extend_from_slice(&[0, 0]);
+    let mut messages = vec![
+        handshake_message(HANDSHAKE_ENCRYPTED_EXTENSIONS, &[0x00, 0x09, 0x00, 0x10, 0x00, 0x05, 0x00, 0x03, 0x02, b'h', b'2']),
+        handshake_message(HANDSHAKE_CERTIFICATE, &body),
+    ];
+    messages.iter().for_each(|m| transcript.update(m));
+
+    let signature = key_pair.sign(&certificate_verify_content(transcript.clone().finish().as_ref()));
+    let mut body = SIGNATURE_ED25519.to_be_bytes().to_vec();
+    body.extend_from_slice(&(signature.as_ref().len() as u16).to_be_bytes());
+    body.extend_from_slice(signature.as_ref());
+    let certificate_verify = handshake_message(HANDSHAKE_CERTIFICATE_VERIFY, &body);
+    transcript.update(&certificate_verify);
+    messages.push(certificate_verify);
+    let finished = suite.finished(&server_secret, transcript.clone().finish().as_ref());
+    let finished = handshake_message(HANDSHAKE_FINISHED, &finished);
+    transcript.update(&finished);
+    messages.push(finished);
+
+    let mut send = RecordCipher::new(&suite, &server_secret);
+    for message in &messages {
+        send.seal(CONTENT_HANDSHAKE, message, &mut flight);
+    }
+    socket.write_all(&flight).await?;
+
+    // ChangeCipherSpec (اختیاری) و Finished کلاینت
+    let hash = transcript.finish();
+    let mut record = Record::read(socket).await?;
+    if record.content_type == CONTENT_CHANGE_CIPHER_SPEC {
+        record = Record::read(socket).await?;
+    }
+    let (content_type, client_finished) = RecordCipher::new(&suite, &client_secret).open(&record.payload)?;
+    let expected = handshake_message(HANDSHAKE_FINISHED, &suite.finished(&client_secret, hash.as_ref()));
+    if content_type != CONTENT_HANDSHAKE || client_finished != expected {
+        bail!("Client Finished verification failed");
+    }
+
+    let master_secret = suite.master_secret(&handshake_secret);
+    Ok(Tls13Codec {
+        send: RecordCipher::new(&suite, &suite.derive_secret(&master_secret, "s ap traffic", hash.as_ref())),
+        recv: RecordCipher::new(&suite, &suite.derive_secret(&master_secret, "c ap traffic", hash.as_ref())),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_hello_shape() {
+        let hello = ClientHello::new("ebanking.bmi.ir", [7u8; 32]);
+        let record = hello.encode();
+
+        assert_eq!(record[0], CONTENT_HANDSHAKE);
+        assert_eq!(u16::from_be_bytes([record[3], record[4]]) as usize, record.len() - RECORD_HEADER_LEN);
+        assert_eq!(record[5], HANDSHAKE_CLIENT_HELLO);
+        assert_eq!(record[SESSION_ID_OFFSET - 1], 32);
+        assert_eq!(&record[SESSION_ID_OFFSET..SESSION_ID_OFFSET + 32], &hello.session_id);
+        // padding تا ۵۱۲ بایت پیام handshake
+        assert_eq!(record.len() - RECORD_HEADER_LEN, 512);
+        assert!(record.windows(15).any(|w| w == b"ebanking.bmi.ir"));
+        assert!(record.windows(32).any(|w| w == [7u8; 32]));
+    }
+}
@@ -0,0 +1,441 @@
+//! Transparent Proxy Listener
+//!
+//! listener بومی TPROXY روی `TproxyConfig.listen_port`: قوانین
+//! `TproxyManager::generate_setup_script` ترافیک TCP/UDP دستگاه‌های LAN را به
+//! این پورت می‌فرستند. مقصد اصلی از آدرس محلی سوکت (TCP) یا
+//! `IP_RECVORIGDSTADDR` (UDP) بازیابی و جریان از طریق زنجیره موتور ارسال می‌شود.
+//!
+//! UDP طبق `[network] udp_over_tcp` مستقیم روی پروتکل UDP (Hysteria2/TUIC) یا
+//! با UoT v2 روی زنجیره TCP منتقل و پاسخ‌ها از سوکتی که به آدرس مقصد اصلی bind
+//! شده به کلاینت برگردانده می‌شوند.
+
+use std::{
+    collections::HashMap,
+    io::IoSliceMut,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    os::fd::{AsFd, AsRawFd},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use nix::sys::socket::{
+    recvmsg, setsockopt, sockopt, ControlMessageOwned, MsgFlags, SockaddrStorage,
+};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::{
+    io::Interest,
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::{mpsc::{self, error::TrySendError}, Mutex},
+    time::timeout,
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    engine::{TaskScope, TunnelDialer},
+    inbound,
+    router_manager::TproxyConfig,
+    transport::DatagramSocket,
+};
+
+/// بستن نشست UDP پس از این مدت بی‌فعالیتی
+const UDP_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// حداکثر اندازه datagram
+const MAX_DATAGRAM: usize = 65535;
+
+/// نشست‌های UDP فعال: (کلاینت، مقصد اصلی) → صف پکت‌ها
+type UdpSessions = Arc<Mutex<HashMap<(SocketAddr, SocketAddr), mpsc::Sender<Vec<u8>>>>>;
+
+/// سرور TPROXY
+pub struct TproxyServer {
+    config: TproxyConfig,
+    dialer: TunnelDialer,
+}
+
+impl TproxyServer {
+    pub fn new(config: TproxyConfig, dialer: TunnelDialer) -> Self {
+        Self { config, dialer }
+    }
+
+    /// راه‌اندازی listenerهای TCP/UDP (IPv4 و در صورت فعال بودن IPv6)
+    pub fn spawn(self, scope: &TaskScope) -> Result<()> {
+        let port = self.config.listen_port;
+        let mut addrs = vec![SocketAddr::from((Ipv4Addr::UNSPECIFIED, port))];
+        if self.config.enable_ipv6 {
+            addrs.push(SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)));
+        }
+
+        for addr in addrs {
+            let bound = transparent_tcp(addr).and_then(|tcp| Ok((tcp, transparent_udp(addr)?)));
+            let (tcp, udp) = match bound {
+                Ok(sockets) => sockets,
+                // IPv6 ممکن است روی روتر غیرفعال باشد
+                Err(e) if addr.is_ipv6() => {
+                    warn!("⚠️ TPROXY IPv6 غیرفعال شد: {:#}", e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            info!("🪤 TPROXY listener: {} (TCP/UDP)", addr);
+            scope.spawn_loop(serve_tcp(tcp, self.dialer.clone(), scope.clone()));
+            scope.spawn_loop(serve_udp(udp, self.dialer.clone(), scope.clone()));
+        }
+        Ok(())
+    }
+}
+
+// ==================== SOCKETS ====================
+
+fn domain(addr: SocketAddr) -> Domain {
+    if addr.is_ipv4() {
+        Domain::IPV4
+    } else {
+        Domain::IPV6
+    }
+}
+
+/// IP_TRANSPARENT / IPV6_TRANSPARENT (نیازمند CAP_NET_ADMIN)
+fn set_transparent(socket: &Socket, ipv6: bool) -> Result<()> {
+    if ipv6 {
+        let enable: libc::c_int = 1;
+        // SAFETY: valid fd and a correctly sized c_int option value
+        let ret = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::IPPROTO_IPV6,
+                libc::IPV6_TRANSPARENT,
+                &enable as *const _ as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error()).context("IPV6_TRANSPARENT");
+        }
+    } else {
+        socket.set_ip_transparent(true).context("IP_TRANSPARENT")?;
+    }
+    Ok(())
+}
+
+fn transparent_socket(addr: SocketAddr, ty: Type, protocol: Protocol) -> Result<Socket> {
+    let socket = Socket::new(domain(addr), ty, Some(protocol))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    set_transparent(&socket, addr.is_ipv6())?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+fn transparent_tcp(addr: SocketAddr) -> Result<TcpListener> {
+    let socket = transparent_socket(addr, Type::STREAM, Protocol::TCP)?;
+    socket
+        .bind(&addr.into())
+        .with_context(|| format!("Failed to bind TPROXY TCP on {}", addr))?;
+    socket.listen(1024)?;
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+/// IP_RECVORIGDSTADDR / IPV6_RECVORIGDSTADDR: مقصد اصلی هر datagram در cmsg
+fn enable_original_dst(socket: &Socket, ipv6: bool) -> Result<()> {
+    if ipv6 {
+        setsockopt(&socket.as_fd(), sockopt::Ipv6OrigDstAddr, &true).context("IPV6_RECVORIGDSTADDR")?;
+    } else {
+        setsockopt(&socket.as_fd(), sockopt::Ipv4OrigDstAddr, &true).context("IP_RECVORIGDSTADDR")?;
+    }
+    Ok(())
+}
+
+fn transparent_udp(addr: SocketAddr) -> Result<UdpSocket> {
+    let socket = transparent_socket(addr, Type::DGRAM, Protocol::UDP)?;
+    enable_original_dst(&socket, addr.is_ipv6())?;
+    socket
+        .bind(&addr.into())
+        .with_context(|| format!("Failed to bind TPROXY UDP on {}", addr))?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+/// سوکت پاسخ UDP که از طرف مقصد اصلی به کلاینت ارسال می‌کند
+fn reply_socket(origin: SocketAddr) -> Result<UdpSocket> {
+    let socket = transparent_socket(origin, Type::DGRAM, Protocol::UDP)?;
+    socket
+        .bind(&origin.into())
+        .with_context(|| format!("Failed to bind reply socket on {}", origin))?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+// ==================== TCP ====================
+
+async fn serve_tcp(listener: TcpListener, dialer: TunnelDialer, scope: TaskScope) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("⚠️ خطای TPROXY TCP: {}", e);
+                continue;
+            }
+        };
+        let dialer = dialer.clone();
+        scope.spawn_connection(async move {
+            let origin = match original_destination(&stream) {
+                Ok(origin) => origin,
+                Err(e) => return debug!("TPROXY {}: {}", peer, e),
+            };
+            let upstream = match dialer.dial(&origin.ip().to_string(), origin.port()).await {
+                Ok(upstream) => upstream,
+                Err(e) => {
+                    dialer.record_connection(true).await;
+                    return debug!("TPROXY {} → {} dial failed: {:#}", peer, origin, e);
+                }
+            };
+            dialer.record_connection(false).await;
+            debug!("🪤 TPROXY TCP {} → {}", peer, origin);
+            if let Err(e) = inbound::relay(stream, upstream).await {
+                debug!("TPROXY {} → {}: {:#}", peer, origin, e);
+            }
+        });
+    }
+}
+
+/// با IP_TRANSPARENT آدرس محلی اتصال پذیرفته‌شده همان مقصد اصلی است
+fn original_destination(stream: &TcpStream) -> std::io::Result<SocketAddr> {
+    stream.local_addr()
+}
+
+// ==================== UDP ====================
+
+async fn serve_udp(socket: UdpSocket, dialer: TunnelDialer, scope: TaskScope) {
+    let sessions: UdpSessions = Arc::default();
+    let mut buf = vec![0u8; MAX_DATAGRAM];
+
+    loop {
+        let (n, client, origin) = match recv_original(&socket, &mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("⚠️ خطای TPROXY UDP: {:#}", e);
+                continue;
+            }
+        };
+        let packet = buf[..n].to_vec();
+
+        let mut table = sessions.lock().await;
+        let key = (client, origin);
+        let packet = match table.get(&key) {
+            None => packet,
+            Some(tx) => match tx.try_send(packet) {
+                Ok(()) => continue,
+                // صف پر: مثل هر مسیر UDP شلوغ، پکت دور ریخته می‌شود
+                Err(TrySendError::Full(_)) => continue,
+                // نشست بسته شده: نشست جدید
+                Err(TrySendError::Closed(packet)) => packet,
+            },
+        };
+        let (tx, rx) = mpsc::channel(64);
+        let _ = tx.try_send(packet);
+        table.insert(key, tx.clone());
+        scope.spawn_connection(udp_session(client, origin, (tx, rx), dialer.clone(), sessions.clone()));
+    }
+}
+
+/// دریافت datagram به همراه آدرس کلاینت و مقصد اصلی
+async fn recv_original(socket: &UdpSocket, buf: &mut [u8]) -> Result<(usize, SocketAddr, SocketAddr)> {
+    socket
+        .async_io(Interest::READABLE, || {
+            let mut cmsg = nix::cmsg_space!(libc::sockaddr_in6);
+            let mut iov = [IoSliceMut::new(buf)];
+            let msg = recvmsg::<SockaddrStorage>(
+                socket.as_raw_fd(),
+                &mut iov,
+                Some(&mut cmsg),
+                MsgFlags::empty(),
+            )
+            .map_err(std::io::Error::from)?;
+
+            let client = msg.address.and_then(|a| storage_to_addr(&a));
+            let origin = msg.cmsgs().find_map(|c| match c {
+                ControlMessageOwned::Ipv4OrigDstAddr(a) => Some(SocketAddr::V4(SocketAddrV4::new(
+                    Ipv4Addr::from(u32::from_be(a.sin_addr.s_addr)),
+                    u16::from_be(a.sin_port),
+                ))),
+                ControlMessageOwned::Ipv6OrigDstAddr(a) => Some(SocketAddr::V6(SocketAddrV6::new(
+                    Ipv6Addr::from(a.sin6_addr.s6_addr),
+                    u16::from_be(a.sin6_port),
+                    a.sin6_flowinfo,
+                    a.sin6_scope_id,
+                ))),
+                _ => None,
+            });
+            match (client, origin) {
+                (Some(client), Some(origin)) => Ok((msg.bytes, client, origin)),
+                _ => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "datagram without original destination",
+                )),
+            }
+        })
+        .await
+        .context("recvmsg failed")
+}
+
+fn storage_to_addr(storage: &SockaddrStorage) -> Option<SocketAddr> {
+    if let Some(v4) = storage.as_sockaddr_in() {
+        return Some(SocketAddr::V4(SocketAddrV4::new(v4.ip().into(), v4.port())));
+    }
+    storage
+        .as_sockaddr_in6()
+        .map(|v6| SocketAddr::V6(SocketAddrV6::new(v6.ip(), v6.port(), v6.flowinfo(), v6.scope_id())))
+}
+
+/// یک جریان UDP (کلاینت ↔ مقصد اصلی) روی یک سوکت [`TunnelDialer::dial_udp`]
+async fn udp_session(
+    client: SocketAddr,
+    origin: SocketAddr,
+    (tx, mut rx): (mpsc::Sender<Vec<u8>>, mpsc::Receiver<Vec<u8>>),
+    dialer: TunnelDialer,
+    sessions: UdpSessions,
+) {
+    let result = async {
+        let reply = reply_socket(origin)?;
+        let mut target = dialer.watch_target();
+        loop {
+            let upstream = dialer.dial_udp(&origin.ip().to_string(), origin.port()).await;
+            dialer.record_connection(upstream.is_err()).await;
+            let upstream = upstream?;
+            debug!("🪤 TPROXY UDP {} → {}", client, origin);
+
+            let upload = async {
+                while let Ok(Some(packet)) = timeout(UDP_IDLE_TIMEOUT, rx.recv()).await {
+                    upstream.send(&packet).await?;
+                }
+                Ok::<_, anyhow::Error>(())
+            };
+            let download = async {
+                let mut buf = vec![0u8; MAX_DATAGRAM];
+                loop {
+                    let n = upstream.recv(&mut buf).await?;
+                    reply.send_to(&buf[..n], client).await?;
+                }
+            };
+
+            // هر طرف که تمام شود (بی‌فعالیتی یا بسته شدن سرور) نشست بسته می‌شود؛
+            // با تعویض سرور، نشست روی سرور جدید ادامه پیدا می‌کند
+            tokio::select! {
+                r = upload => break r,
+                r = download => break r,
+                Ok(()) = target.changed() => {
+                    debug!("🪤 TPROXY UDP {} → {} به سرور جدید منتقل شد", client, origin);
+                }
+            }
+        }
+    }
+    .await;
+
+    if let Err(e) = result {
+        debug!("TPROXY UDP {} → {}: {:#}", client, origin, e);
+    }
+    // فقط اگر جای این نشست را نشست جدیدی نگرفته باشد
+    let mut table = sessions.lock().await;
+    if table.get(&(client, origin)).is_some_and(|t| t.same_channel(&tx)) {
+        table.remove(&(client, origin));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router_manager::{RouterProfile, TproxyManager};
+
+    /// سوکت UDP معمولی (بدون IP_TRANSPARENT) که مقصد اصلی را گزارش می‌کند
+    fn udp_with_original_dst(addr: SocketAddr) -> Result<UdpSocket> {
+        let socket = Socket::new(domain(addr), Type::DGRAM, Some(Protocol::UDP))?;
+        enable_original_dst(&socket, addr.is_ipv6())?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&addr.into())?;
+        Ok(UdpSocket::from_std(socket.into())?)
+    }
+
+    #[tokio::test]
+    async fn test_udp_original_destination() {
+        for local in ["127.0.0.1:0", "[::1]:0"] {
+            let listener = match udp_with_original_dst(local.parse().unwrap()) {
+                Ok(listener) => listener,
+                // IPv6 ممکن است در محیط تست غیرفعال باشد
+                Err(_) if local.starts_with('[') => continue,
+                Err(e) => panic!("{:#}", e),
+            };
+            let origin = listener.local_addr().unwrap();
+            let client = UdpSocket::bind(SocketAddr::new(origin.ip(), 0)).await.unwrap();
+            client.send_to(b"query", origin).await.unwrap();
+
+            let mut buf = [0u8; 64];
+            let (n, from, dst) = timeout(Duration::from_secs(1), recv_original(&listener, &mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(&buf[..n], b"query");
+            assert_eq!(from, client.local_addr().unwrap());
+            assert_eq!(dst, origin);
+        }
+
+        // بدون IP_RECVORIGDSTADDR مقصد قابل بازیابی نیست و datagram رد می‌شود
+        let plain = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(b"query", plain.local_addr().unwrap()).await.unwrap();
+        let mut buf = [0u8; 64];
+        let error = recv_original(&plain, &mut buf).await.unwrap_err();
+        assert!(format!("{:#}", error).contains("without original destination"), "{:#}", error);
+    }
+
+    #[tokio::test]
+    async fn test_tcp_original_destination() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _client = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        assert_eq!(original_destination(&stream).unwrap(), addr);
+    }
+
+    #[test]
+    fn test_setup_script_follows_config() {
+        let config = TproxyConfig {
+            listen_port: 7000,
+            dns_port: 7001,
+            mark: 3,
+            routing_mark: 200,
+            table_id: 123,
+            bypass_cidrs: vec!["10.8.0.0/16".into(), "2001:db8::/32".into(), "bogus/99".into()],
+            ..Default::default()
+        };
+        let script = TproxyManager::new(config, RouterProfile::default()).generate_setup_script();
+
+        for line in [
+            "PROXY_PORT=7000",
+            "DNS_PORT=7001",
+            "MARK=3",
+            "ROUTING_MARK=200",
+            "TABLE=123",
+            "iptables -t mangle -A GHOST_TP -d 10.8.0.0/16 -j RETURN",
+            "iptables -t mangle -A GHOST_LOCAL -d 10.8.0.0/16 -j RETURN",
+            "ip6tables -t mangle -A GHOST_TP6 -d 2001:db8::/32 -j RETURN",
+        ] {
+            assert!(script.lines().any(|l| l.trim() == line), "missing `{}`", line);
+        }
+        assert!(!script.contains("bogus"));
+        // بایپس باید قبل از قانون TPROXY بیاید
+        assert!(script.find("-d 10.8.0.0/16").unwrap() < script.find("-j TPROXY").unwrap());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs CAP_NET_ADMIN for IP_TRANSPARENT"]
+    async fn test_transparent_sockets() {
+        let tcp = transparent_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+        let udp = transparent_udp(tcp.local_addr().unwrap()).unwrap();
+        assert_eq!(udp.local_addr().unwrap(), tcp.local_addr().unwrap());
+
+        // پاسخ از طرف یک آدرس غیرمحلی فقط با IP_TRANSPARENT ممکن است
+        let origin: SocketAddr = "203.0.113.7:53".parse().unwrap();
+        assert_eq!(reply_socket(origin).unwrap().local_addr().unwrap(), origin);
+    }
+}
@@ -0,0 +1,288 @@
+//! حسابداری ترافیک
+//!
+//! هر جریان تانل در [`CountingStream`] پیچیده می‌شود که بایت‌های خوانده/نوشته‌شده
+//! را هم‌زمان به شمارنده کل، شمارنده پروتکل و شمارنده IP سرور اضافه می‌کند.
+//! [`TrafficMeter`] هر ثانیه نمونه می‌گیرد و نرخ‌های ۱، ۱۰ و ۶۰ ثانیه‌ای را
+//! برای `TrafficStats` و داشبورد حساب می‌کند.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+    io,
+    net::IpAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::{
+    transport::{BoxedDatagram, DatagramSocket},
+    types::{ByteRate, ByteTotals, TrafficRates, TrafficStats},
+};
+
+/// تعداد نمونه‌های نگه‌داشته‌شده (یک نمونه در ثانیه، برای بازه ۶۰ ثانیه)
+const MAX_SAMPLES: usize = 61;
+
+/// شمارنده بایت دریافتی/ارسالی
+#[derive(Debug, Default)]
+pub struct ByteCounter {
+    rx: AtomicU64,
+    tx: AtomicU64,
+}
+
+impl ByteCounter {
+    pub fn totals(&self) -> ByteTotals {
+        ByteTotals {
+            rx_bytes: self.rx.load(Ordering::Relaxed),
+            tx_bytes: self.tx.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// شمارنده‌های یک جریان: کل، پروتکل و IP سرور
+#[derive(Debug, Clone)]
+pub struct TrafficTap {
+    counters: [Arc<ByteCounter>; 3],
+}
+
+impl TrafficTap {
+    /// شمارنده مستقل از [`TrafficMeter`] (مثلاً برای سربار لایه‌ها در trace-chain)
+    pub fn detached() -> Self {
+        Self { counters: Default::default() }
+    }
+
+    /// بایت‌های ثبت‌شده در این tap
+    pub fn totals(&self) -> ByteTotals {
+        self.counters[0].totals()
+    }
+
+    pub fn record_rx(&self, bytes: u64) {
+        for counter in &self.counters {
+            counter.rx.fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_tx(&self, bytes: u64) {
+        for counter in &self.counters {
+            counter.tx.fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+}
+
+/// جریانی که بایت‌های خوانده‌شده را rx و نوشته‌شده را tx حساب می‌کند
+#[derive(Debug)]
+pub struct CountingStream<S> {
+    inner: S,
+    tap: TrafficTap,
+}
+
+impl<S> CountingStream<S> {
+    pub fn new(inner: S, tap: TrafficTap) -> Self {
+        Self { inner, tap }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CountingStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            this.tap.record_rx((buf.filled().len() - before) as u64);
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CountingStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = result {
+            this.tap.record_tx(n as u64);
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// سوکت پکتی که پکت‌های دریافتی را rx و ارسالی را tx حساب می‌کند
+pub struct CountingDatagram {
+    inner: BoxedDatagram,
+    tap: TrafficTap,
+}
+
+impl CountingDatagram {
+    pub fn new(inner: BoxedDatagram, tap: TrafficTap) -> Self {
+        Self { inner, tap }
+    }
+}
+
+#[async_trait]
+impl DatagramSocket for CountingDatagram {
+    async fn send(&self, packet: &[u8]) -> Result<()> {
+        self.inner.send(packet).await?;
+        self.tap.record_tx(packet.len() as u64);
+        Ok(())
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.recv(buf).await?;
+        self.tap.record_rx(n as u64);
+        Ok(n)
+    }
+}
+
+/// شمارنده‌های ترافیک موتور و نمونه‌های نرخ
+#[derive(Debug, Default)]
+pub struct TrafficMeter {
+    total: Arc<ByteCounter>,
+    by_protocol: Mutex<HashMap<String, Arc<ByteCounter>>>,
+    by_ip: Mutex<HashMap<IpAddr, Arc<ByteCounter>>>,
+    /// (زمان، rx کل، tx کل) — قدیمی‌ترین اول
+    samples: Mutex<VecDeque<(Instant, ByteTotals)>>,
+}
+
+impl TrafficMeter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// شمارنده‌های یک جریان جدید روی `protocol` به سرور `ip`
+    pub fn tap(&self, protocol: &str, ip: IpAddr) -> TrafficTap {
+        TrafficTap {
+            counters: [
+                self.total.clone(),
+                counter(&self.by_protocol, protocol.to_string()),
+                counter(&self.by_ip, ip),
+            ],
+        }
+    }
+
+    pub fn totals(&self) -> ByteTotals {
+        self.total.totals()
+    }
+
+    /// ثبت یک نمونه (هر ثانیه صدا زده می‌شود)
+    pub fn sample(&self) {
+        self.sample_at(Instant::now());
+    }
+
+    fn sample_at(&self, at: Instant) {
+        let mut samples = self.samples.lock().unwrap();
+        samples.push_back((at, self.total.totals()));
+        while samples.len() > MAX_SAMPLES {
+            samples.pop_front();
+        }
+    }
+
+    /// نرخ‌های ۱، ۱۰ و ۶۰ ثانیه‌ای از روی نمونه‌ها
+    pub fn rates(&self) -> TrafficRates {
+        let samples = self.samples.lock().unwrap();
+        let rate = |secs: usize| -> ByteRate {
+            let Some(&(now, latest)) = samples.back() else {
+                return ByteRate::default();
+            };
+            let (then, earlier) = samples[samples.len().saturating_sub(secs + 1)];
+            let elapsed = (now - then).as_secs_f64();
+            if elapsed == 0.0 {
+                return ByteRate::default();
+            }
+            ByteRate {
+                rx: (latest.rx_bytes - earlier.rx_bytes) as f64 / elapsed,
+                tx: (latest.tx_bytes - earlier.tx_bytes) as f64 / elapsed,
+            }
+        };
+        TrafficRates { last_1s: rate(1), last_10s: rate(10), last_60s: rate(60) }
+    }
+
+    /// کپی شمارنده‌ها و نرخ‌ها در `TrafficStats`
+    pub fn apply_to(&self, stats: &mut TrafficStats) {
+        let totals = self.totals();
+        stats.rx_bytes = totals.rx_bytes;
+        stats.tx_bytes = totals.tx_bytes;
+        stats.rates = self.rates();
+        stats.by_protocol = snapshot(&self.by_protocol);
+        stats.by_ip = snapshot(&self.by_ip);
+    }
+}
+
+fn counter<K: Eq + Hash>(map: &Mutex<HashMap<K, Arc<ByteCounter>>>, key: K) -> Arc<ByteCounter> {
+    map.lock().unwrap().entry(key).or_default().clone()
+}
+
+fn snapshot<K: Ord + Clone, M: FromIterator<(K, ByteTotals)>>(
+    map: &Mutex<HashMap<K, Arc<ByteCounter>>>,
+) -> M {
+    map.lock()
+        .unwrap()
+        .iter()
+        .map(|(key, counter)| (key.clone(), counter.totals()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_counting_stream_and_rates() {
+        let meter = TrafficMeter::new();
+        let ip: IpAddr = "104.16.1.1".parse().unwrap();
+        let (client, mut server) = tokio::io::duplex(1024);
+        let mut stream = CountingStream::new(client, meter.tap("Reality", ip));
+
+        let start = Instant::now();
+        meter.sample_at(start);
+
+        stream.write_all(&[0u8; 300]).await.unwrap();
+        server.write_all(&[0u8; 100]).await.unwrap();
+        let mut buf = [0u8; 100];
+        stream.read_exact(&mut buf).await.unwrap();
+        meter.sample_at(start + Duration::from_secs(2));
+
+        let mut stats = TrafficStats::default();
+        meter.apply_to(&mut stats);
+        assert_eq!((stats.rx_bytes, stats.tx_bytes), (100, 300));
+        assert_eq!(stats.by_protocol["Reality"], ByteTotals { rx_bytes: 100, tx_bytes: 300 });
+        assert_eq!(stats.by_ip[&ip].tx_bytes, 300);
+        assert_eq!(stats.rates.last_1s, ByteRate { rx: 50.0, tx: 150.0 });
+        assert_eq!(stats.rates.last_60s, stats.rates.last_1s);
+
+        // کلیدهای IP باید از JSON وضعیت (سوکت کنترل) برگردند
+        let json = serde_json::to_value(&stats).unwrap();
+        let back: TrafficStats = serde_json::from_value(json).unwrap();
+        assert_eq!(back.by_ip[&ip].rx_bytes, 100);
+    }
+}
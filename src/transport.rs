@@ -0,0 +1,491 @@
+//! لایه‌های انتقال قابل انباشت
+//!
+//! هر کلاینت پروتکل یک [`Transport`] است: روی جریان لایه زیرین handshake
+//! می‌کند و جریان تازه‌ای (`AsyncRead + AsyncWrite`) برمی‌گرداند که لایه بعدی
+//! روی آن سوار می‌شود. [`TcpDialer`] پایه زنجیره است و [`TransportStack`]
+//! لایه‌ها را به ترتیب روی آن می‌چیند. پروتکل‌های مبتنی بر UDP (Hysteria2،
+//! TUIC و MASQUE) همین کار را با [`DatagramTransport`] روی سوکت پکتی می‌کنند.
+//!
+//! لایه‌هایی که داده را در فریم می‌پیچند (WebSocket، HTTP/2، gRPC، SMUX) فقط
+//! یک [`FrameCodec`] تعریف می‌کنند و [`FramedStream`] بقیه کار را انجام می‌دهد.
+
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{ready, Context as TaskContext, Poll},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf},
+    net::{TcpSocket, UdpSocket},
+    time::timeout,
+};
+use tracing::debug;
+
+/// زمان مجاز برای اتصال پایه
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// حداکثر اندازه هدر پاسخ HTTP در handshakeها
+const MAX_HTTP_HEAD: usize = 8192;
+
+// ==================== STREAM ====================
+
+/// جریان دوطرفه‌ای که لایه‌ها روی آن سوار می‌شوند
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + ?Sized> AsyncStream for T {}
+
+pub type BoxedStream = Box<dyn AsyncStream>;
+
+/// یک لایه جریانی (ShadowTLS، Reality، WebSocket، ...)
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// نام لایه (برای لاگ و خطا)
+    fn name(&self) -> &'static str;
+
+    /// handshake روی جریان لایه زیرین و تحویل جریان این لایه
+    async fn connect_over(&self, lower: BoxedStream) -> Result<BoxedStream>;
+
+    /// مثل [`Transport::connect_over`] به همراه پارامترهای توافق‌شده با سرور
+    /// (مثلاً `tls=1.3 cipher=... alpn=h2`) برای `trace-chain`
+    async fn connect_traced(&self, lower: BoxedStream) -> Result<(BoxedStream, Vec<String>)> {
+        Ok((self.connect_over(lower).await?, Vec::new()))
+    }
+}
+
+/// سازنده جریان پایه یک زنجیره
+#[async_trait]
+pub trait Dialer: Send + Sync {
+    async fn dial(&self) -> Result<BoxedStream>;
+}
+
+/// اتصال TCP مستقیم (با SO_MARK اختیاری)
+#[derive(Debug, Clone)]
+pub struct TcpDialer {
+    addr: SocketAddr,
+    mark: Option<u32>,
+}
+
+impl TcpDialer {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self { addr, mark: None }
+    }
+
+    /// SO_MARK سوکت خروجی (تا قوانین TPROXY آن را دوباره نگیرند)
+    pub fn with_mark(mut self, mark: Option<u32>) -> Self {
+        self.mark = mark;
+        self
+    }
+}
+
+#[async_trait]
+impl Dialer for TcpDialer {
+    async fn dial(&self) -> Result<BoxedStream> {
+        let socket = match self.addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        if let Some(mark) = self.mark {
+            socket2::SockRef::from(&socket)
+                .set_mark(mark)
+                .context("Failed to set SO_MARK")?;
+        }
+        let stream = timeout(CONNECT_TIMEOUT, socket.connect(self.addr))
+            .await
+            .context("TCP connection timeout")?
+            .context("TCP connection failed")?;
+        Ok(Box::new(stream))
+    }
+}
+
+/// زنجیره لایه‌ها روی یک جریان پایه؛ هر لایه روی خروجی لایه قبلی dial می‌کند
+pub struct TransportStack {
+    base: Box<dyn Dialer>,
+    layers: Vec<Box<dyn Transport>>,
+}
+
+impl TransportStack {
+    pub fn new(base: impl Dialer + 'static) -> Self {
+        Self { base: Box::new(base), layers: Vec::new() }
+    }
+
+    /// افزودن یک لایه روی لایه‌های فعلی
+    pub fn layer(mut self, layer: impl Transport + 'static) -> Self {
+        self.layers.push(Box::new(layer));
+        self
+    }
+
+    pub fn push(&mut self, layer: Box<dyn Transport>) {
+        self.layers.push(layer);
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+}
+
+#[async_trait]
+impl Dialer for TransportStack {
+    async fn dial(&self) -> Result<BoxedStream> {
+        let mut stream = self.base.dial().await?;
+        for layer in &self.layers {
+            stream = layer
+                .connect_over(stream)
+                .await
+                .with_context(|| format!("{} layer failed", layer.name()))?;
+            debug!("✅ {} layer applied", layer.name());
+        }
+        Ok(stream)
+    }
+}
+
+// ==================== DATAGRAM ====================
+
+/// سوکت پکتی متصل (UDP یا لایه‌ای روی آن)
+#[async_trait]
+pub trait DatagramSocket: Send + Sync {
+    async fn send(&self, packet: &[u8]) -> Result<()>;
+
+    async fn recv(&self, buf: &mut [u8]) -> Result<usize>;
+}
+
+pub type BoxedDatagram = Box<dyn DatagramSocket>;
+
+#[async_trait]
+impl DatagramSocket for UdpSocket {
+    async fn send(&self, packet: &[u8]) -> Result<()> {
+        UdpSocket::send(self, packet).await.context("UDP send failed")?;
+        Ok(())
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> Result<usize> {
+        UdpSocket::recv(self, buf).await.context("UDP recv failed")
+    }
+}
+
+/// یک لایه پکتی (Hysteria2، TUIC، MASQUE)
+#[async_trait]
+pub trait DatagramTransport: Send + Sync {
+    /// نام لایه (برای لاگ و خطا)
+    fn name(&self) -> &'static str;
+
+    /// handshake روی سوکت لایه زیرین و تحویل سوکت این لایه
+    async fn connect_over(&self, lower: BoxedDatagram) -> Result<BoxedDatagram>;
+}
+
+/// سازنده سوکت پکتی پایه
+#[async_trait]
+pub trait DatagramDialer: Send + Sync {
+    async fn dial(&self) -> Result<BoxedDatagram>;
+}
+
+/// سوکت UDP متصل به سرور (با SO_MARK اختیاری)
+#[derive(Debug, Clone)]
+pub struct UdpDialer {
+    addr: SocketAddr,
+    mark: Option<u32>,
+}
+
+impl UdpDialer {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self { addr, mark: None }
+    }
+
+    pub fn with_mark(mut self, mark: Option<u32>) -> Self {
+        self.mark = mark;
+        self
+    }
+}
+
+#[async_trait]
+impl DatagramDialer for UdpDialer {
+    async fn dial(&self) -> Result<BoxedDatagram> {
+        let bind: SocketAddr = match self.addr {
+            SocketAddr::V4(_) => "0.0.0.0:0".parse()?,
+            SocketAddr::V6(_) => "[::]:0".parse()?,
+        };
+        let socket = UdpSocket::bind(bind).await.context("UDP bind failed")?;
+        if let Some(mark) = self.mark {
+            socket2::SockRef::from(&socket)
+                .set_mark(mark)
+                .context("Failed to set SO_MARK")?;
+        }
+        socket.connect(self.addr).await.context("UDP connect failed")?;
+        Ok(Box::new(socket))
+    }
+}
+
+/// زنجیره لایه‌های پکتی روی یک سوکت پایه
+pub struct DatagramStack {
+    base: Box<dyn DatagramDialer>,
+    layers: Vec<Box<dyn DatagramTransport>>,
+}
+
+impl DatagramStack {
+    pub fn new(base: impl DatagramDialer + 'static) -> Self {
+        Self { base: Box::new(base), layers: Vec::new() }
+    }
+
+    pub fn layer(mut self, layer: impl DatagramTransport + 'static) -> Self {
+        self.layers.push(Box::new(layer));
+        self
+    }
+}
+
+#[async_trait]
+impl DatagramDialer for DatagramStack {
+    async fn dial(&self) -> Result<BoxedDatagram> {
+        let mut socket = self.base.dial().await?;
+        for layer in &self.layers {
+            socket = layer
+                .connect_over(socket)
+                .await
+                .with_context(|| format!("{} layer failed", layer.name()))?;
+        }
+        Ok(socket)
+    }
+}
+
+// ==================== FRAMING ====================
+
+/// فریم decode‌شده از جریان زیرین
+#[derive(Debug, PartialEq, Eq)]
+pub enum Frame {
+    /// داده برای لایه بالا
+    Data(Vec<u8>),
+    /// فریم کنترلی بدون داده (ping، settings، ...)
+    Control,
+    /// سرور جریان را بست
+    Close,
+}
+
+/// قالب فریم یک لایه
+pub trait FrameCodec: Send + Unpin {
+    /// حداکثر داده در هر فریم
+    const MAX_PAYLOAD: usize = 16 * 1024;
+
+    fn encode(&mut self, payload: &[u8], dst: &mut Vec<u8>);
+
+    /// برداشتن فریم کامل بعدی از ابتدای `src` (`None` = هنوز کامل نرسیده)
+    ///
+    /// پاسخ فریم‌های کنترلی (pong، ACK) در `reply` نوشته می‌شود.
+    fn decode(&mut self, src: &mut Vec<u8>, reply: &mut Vec<u8>) -> io::Result<Option<Frame>>;
+
+    /// فریم پایان اتصال (هنگام shutdown)
+    fn close(&mut self, _dst: &mut Vec<u8>) {}
+}
+
+/// جریانی که نوشته‌ها را با `C` در فریم می‌پیچد و از خوانده‌ها فریم را برمی‌دارد
+pub struct FramedStream<C> {
+    inner: BoxedStream,
+    codec: C,
+    /// بایت‌های خامی که هنوز فریم کامل نشده‌اند
+    raw: Vec<u8>,
+    /// داده decode‌شده منتظر خواندن
+    readable: Vec<u8>,
+    /// فریم‌های encode‌شده منتظر نوشتن
+    outgoing: Vec<u8>,
+    eof: bool,
+    closing: bool,
+}
+
+impl<C: FrameCodec> FramedStream<C> {
+    pub fn new(inner: BoxedStream, codec: C) -> Self {
+        Self {
+            inner,
+            codec,
+            raw: Vec::new(),
+            readable: Vec::new(),
+            outgoing: Vec::new(),
+            eof: false,
+            closing: false,
+        }
+    }
+
+    fn poll_drain(&mut self, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        while !self.outgoing.is_empty() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.outgoing))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.outgoing.drain(..n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<C: FrameCodec> AsyncRead for FramedStream<C> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if !this.readable.is_empty() {
+                let n = this.readable.len().min(buf.remaining());
+                buf.put_slice(&this.readable[..n]);
+                this.readable.drain(..n);
+                return Poll::Ready(Ok(()));
+            }
+            if this.eof {
+                return Poll::Ready(Ok(()));
+            }
+            match this.codec.decode(&mut this.raw, &mut this.outgoing)? {
+                Some(Frame::Data(data)) => this.readable = data,
+                Some(Frame::Control) => {
+                    // پاسخ کنترلی اگر سوکت آماده نباشد با نوشتن بعدی می‌رود
+                    if let Poll::Ready(Err(e)) = this.poll_drain(cx) {
+                        return Poll::Ready(Err(e));
+                    }
+                }
+                Some(Frame::Close) => this.eof = true,
+                None => {
+                    let mut chunk = [0u8; 8192];
+                    let mut chunk_buf = ReadBuf::new(&mut chunk);
+                    ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf))?;
+                    if chunk_buf.filled().is_empty() {
+                        this.eof = true;
+                    }
+                    this.raw.extend_from_slice(chunk_buf.filled());
+                }
+            }
+        }
+    }
+}
+
+impl<C: FrameCodec> AsyncWrite for FramedStream<C> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let n = buf.len().min(C::MAX_PAYLOAD);
+        this.codec.encode(&buf[..n], &mut this.outgoing);
+        // فریم پذیرفته شد؛ اگر سوکت پر باشد در poll بعدی یا flush نوشته می‌شود
+        if let Poll::Ready(Err(e)) = this.poll_drain(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.closing {
+            this.closing = true;
+            this.codec.close(&mut this.outgoing);
+        }
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+// ==================== HELPERS ====================
+
+/// خواندن هدر پاسخ HTTP تا `\r\n\r\n` بدون مصرف بایت‌های بعد از آن
+pub async fn read_http_head(stream: &mut BoxedStream) -> Result<String> {
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HTTP_HEAD {
+            anyhow::bail!("HTTP response header too large");
+        }
+        if stream.read(&mut byte).await? == 0 {
+            anyhow::bail!("Connection closed during HTTP handshake");
+        }
+        head.push(byte[0]);
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::websocket_transport::{
+        ws_accept_key, GrpcTransport, GrpcTransportConfig, WsFrame, WsTransport, WsTransportConfig,
+    };
+
+    /// gRPC روی WebSocket روی یک جریان حافظه‌ای: هر لایه روی خروجی لایه زیرین
+    #[tokio::test]
+    async fn test_layers_stack_over_lower_stream() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let ip = "127.0.0.1".parse().unwrap();
+        let ws = WsTransport::new(ip, 443, WsTransportConfig::default());
+        let grpc = GrpcTransport::new(ip, 443, GrpcTransportConfig::default());
+
+        let server = tokio::spawn(async move {
+            let mut server: BoxedStream = Box::new(server);
+            let request = read_http_head(&mut server).await.unwrap();
+            let key = request
+                .lines()
+                .find_map(|l| l.strip_prefix("Sec-WebSocket-Key: "))
+                .unwrap()
+                .to_string();
+            let response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                ws_accept_key(&key)
+            );
+            server.write_all(response.as_bytes()).await.unwrap();
+
+            // پیش‌درآمد HTTP/2 و پیام gRPC داخل فریم‌های WebSocket
+            let mut raw = Vec::new();
+            let mut payload = Vec::new();
+            while payload.len() < 24 + 5 + 5 {
+                let mut chunk = [0u8; 1024];
+                let n = server.read(&mut chunk).await.unwrap();
+                raw.extend_from_slice(&chunk[..n]);
+                while let Some((frame, used)) = WsFrame::decode(&raw) {
+                    assert!(frame.masked);
+                    payload.extend(frame.payload);
+                    raw.drain(..used);
+                }
+            }
+            assert!(payload.starts_with(b"PRI * HTTP/2.0"));
+            assert_eq!(GrpcTransport::decode_grpc_frame(&payload[24..]).unwrap(), b"hello");
+
+            // یک ping قبل از داده باید بی‌صدا پاسخ داده شود
+            let mut frame = WsFrame::ping();
+            frame.masked = false;
+            let mut reply = frame.encode();
+            let mut data = WsFrame::binary(GrpcTransport::encode_grpc_frame(b"world"));
+            data.masked = false;
+            reply.extend(data.encode());
+            server.write_all(&reply).await.unwrap();
+
+            let mut pong = [0u8; 6];
+            server.read_exact(&mut pong).await.unwrap();
+            let (frame, _) = WsFrame::decode(&pong).unwrap();
+            assert_eq!(frame.opcode, 0x0A);
+        });
+
+        let stream = ws.connect_over(Box::new(client)).await.unwrap();
+        let mut stream = grpc.connect_over(stream).await.unwrap();
+        stream.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"world");
+        stream.flush().await.unwrap();
+        server.await.unwrap();
+    }
+}
@@ -2,11 +2,13 @@
 //! پروتکل QUIC-based با QUIC Multiplexing و Zero-RTT
 
 use anyhow::{Context, Result};
+use async_trait::async_trait;
 use rand::{RngCore, thread_rng};
-use tokio::net::UdpSocket;
 use tokio::time::{timeout, Duration};
 use tracing::{debug, info};
 
+use crate::transport::{BoxedDatagram, DatagramSocket, DatagramTransport};
+
 // ── TUIC v5 Constants ────────────────────────────────────────────
 const TUIC_VERSION: u8 = 0x05;
 // Commands
@@ -70,7 +72,6 @@ impl Default for TuicConfig {
 
 /// TUIC v5 Client
 pub struct Tuic {
-    pub socket: Option<UdpSocket>,
     config: TuicConfig,
     token: [u8; 32],
 }
@@ -78,33 +79,52 @@ pub struct Tuic {
 impl Tuic {
     pub fn new(config: TuicConfig) -> Self {
         let mut token = [0u8; 32]; thread_rng().fill_bytes(&mut token);
-        Self { socket: None, config, token }
-    }
-
-    pub async fn connect(&mut self, server: &str) -> Result<()> {
-        info!("⚡ اتصال TUIC v5 به {}", server);
-        let socket = UdpSocket::bind("0.0.0.0:0").await.context("UDP bind failed")?;
-        socket.connect(server).await.context("UDP connect failed")?;
-        self.socket = Some(socket);
-        self.authenticate().await?;
-        info!("✅ TUIC v5 authenticated");
-        Ok(())
+        Self { config, token }
     }
 
-    /// ارسال AUTHENTICATE command
-    async fn authenticate(&mut self) -> Result<()> {
+    /// ارسال AUTHENTICATE روی سوکت زیرین و تحویل سوکت TUIC
+    pub async fn open(&self, lower: BoxedDatagram) -> Result<TuicSocket> {
         let mut pkt = Vec::new();
         pkt.extend_from_slice(&TuicHeader::new(CMD_AUTHENTICATE).to_bytes());
         pkt.extend_from_slice(&self.config.uuid);
         // Token = HMAC-SHA256(UUID, password) — simplified
         pkt.extend_from_slice(&self.token);
-        self.raw_send(&pkt).await?;
+        timeout(Duration::from_secs(5), lower.send(&pkt)).await??;
         debug!("🔐 TUIC AUTHENTICATE sent");
-        Ok(())
+        info!("✅ TUIC v5 authenticated");
+        Ok(TuicSocket { inner: lower, assoc_id: rand::random(), destination: None })
+    }
+}
+
+#[async_trait]
+impl DatagramTransport for Tuic {
+    fn name(&self) -> &'static str {
+        "TUIC"
+    }
+
+    async fn connect_over(&self, lower: BoxedDatagram) -> Result<BoxedDatagram> {
+        Ok(Box::new(self.open(lower).await?))
+    }
+}
+
+/// سوکت TUIC پس از احراز هویت؛ هر پکت در یک PACKET command با association ثابت
+pub struct TuicSocket {
+    inner: BoxedDatagram,
+    /// Association ID (random per session)
+    assoc_id: u16,
+    /// مقصد پکت‌ها (`None` = 0.0.0.0:0)
+    destination: Option<(String, u16)>,
+}
+
+impl TuicSocket {
+    /// مقصد UDP که در هر PACKET فرستاده می‌شود
+    pub fn with_destination(mut self, host: &str, port: u16) -> Self {
+        self.destination = Some((host.to_string(), port));
+        self
     }
 
     /// ارسال CONNECT command
-    pub async fn send_connect(&mut self, host: &str, port: u16) -> Result<()> {
+    pub async fn send_connect(&self, host: &str, port: u16) -> Result<()> {
         let mut pkt = Vec::new();
         pkt.extend_from_slice(&TuicHeader::new(CMD_CONNECT).to_bytes());
         // Address
@@ -117,41 +137,70 @@ impl Tuic {
         Ok(())
     }
 
+    /// HEARTBEAT
+    pub async fn heartbeat(&self) -> Result<()> {
+        let pkt = TuicHeader::new(CMD_HEARTBEAT).to_bytes().to_vec();
+        self.raw_send(&pkt).await
+    }
+
+    async fn raw_send(&self, data: &[u8]) -> Result<()> {
+        timeout(Duration::from_secs(5), self.inner.send(data)).await?
+    }
+}
+
+/// ابتدای payload در یک PACKET command (بعد از هدر و آدرس)
+fn packet_payload(pkt: &[u8]) -> Option<usize> {
+    // Header (2) + assoc id (2) + fragment id/total (2) + size (2) + address type (1)
+    let addr_type = *pkt.get(8)?;
+    let addr_len = match addr_type {
+        ADDR_IPV4 => 4 + 2,
+        ADDR_IPV6 => 16 + 2,
+        ADDR_DOMAIN => 1 + *pkt.get(9)? as usize + 2,
+        _ => 0,
+    };
+    let start = 9 + addr_len;
+    (start <= pkt.len()).then_some(start)
+}
+
+#[async_trait]
+impl DatagramSocket for TuicSocket {
     /// ارسال UDP PACKET
-    pub async fn send_protected(&mut self, data: &[u8]) -> Result<()> {
+    async fn send(&self, data: &[u8]) -> Result<()> {
         let mut pkt = Vec::new();
         pkt.extend_from_slice(&TuicHeader::new(CMD_PACKET).to_bytes());
-        // Association ID (random per session)
-        let assoc_id: u16 = rand::random();
-        pkt.extend_from_slice(&assoc_id.to_be_bytes());
+        pkt.extend_from_slice(&self.assoc_id.to_be_bytes());
         // Fragment info: fragment_id=0, fragment_total=1
         pkt.push(0x00); pkt.push(0x01);
         // Size
         let size = data.len() as u16;
         pkt.extend_from_slice(&size.to_be_bytes());
-        // Target address (0.0.0.0:0 for relay)
-        pkt.push(ADDR_IPV4);
-        pkt.extend_from_slice(&[0u8; 4]); // 0.0.0.0
-        pkt.extend_from_slice(&[0u8; 2]); // port 0
+        // Target address
+        match &self.destination {
+            Some((host, port)) => {
+                pkt.push(ADDR_DOMAIN);
+                pkt.push(host.len() as u8);
+                pkt.extend_from_slice(host.as_bytes());
+                pkt.extend_from_slice(&port.to_be_bytes());
+            }
+            None => {
+                pkt.push(ADDR_IPV4);
+                pkt.extend_from_slice(&[0u8; 4]); // 0.0.0.0
+                pkt.extend_from_slice(&[0u8; 2]); // port 0
+            }
+        }
         pkt.extend_from_slice(data);
         self.raw_send(&pkt).await
     }
 
-    /// HEARTBEAT
-    pub async fn heartbeat(&mut self) -> Result<()> {
-        let pkt = TuicHeader::new(CMD_HEARTBEAT).to_bytes().to_vec();
-        self.raw_send(&pkt).await
-    }
-
-    async fn raw_send(&mut self, data: &[u8]) -> Result<()> {
-        let sock = self.socket.as_ref().context("No socket")?;
-        timeout(Duration::from_secs(5), sock.send(data)).await??;
-        Ok(())
-    }
-
-    pub async fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let sock = self.socket.as_ref().context("No socket")?;
-        let n = timeout(Duration::from_secs(10), sock.recv(buf)).await??;
+    /// دریافت؛ از پکت‌های PACKET فقط payload برگردانده می‌شود
+    async fn recv(&self, buf: &mut [u8]) -> Result<usize> {
+        let n = timeout(Duration::from_secs(10), self.inner.recv(buf)).await??;
+        if n >= 2 && buf[1] == CMD_PACKET {
+            if let Some(start) = packet_payload(&buf[..n]) {
+                buf.copy_within(start..n, 0);
+                return Ok(n - start);
+            }
+        }
         Ok(n)
     }
 }
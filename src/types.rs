@@ -1,9 +1,11 @@
 //! Shared types and constants for Network Ghost v5
 
-use std::net::IpAddr;
+use std::{collections::BTreeMap, net::IpAddr};
 
 use serde::{Deserialize, Serialize};
 
+use crate::{matryoshka::ChainSpec, port_hopper::PortStrategy};
+
 // ==================== CONSTANTS ====================
 
 /// Cloudflare IP ranges
@@ -41,15 +43,20 @@ pub const IRANIAN_SNI_WHITELIST: &[&str] = &[
 // ==================== PROTOCOL ENUMS ====================
 
 /// Tunnel protocol type
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ProtocolType {
     /// ShadowTLS v3
+    #[serde(alias = "shadowtls3")]
     ShadowTls,
     /// Reality with VLESS
+    #[default]
     Reality,
     /// TUIC v5
+    #[serde(alias = "tuic5")]
     Tuic,
     /// Hysteria2
+    #[serde(alias = "hysteria")]
     Hysteria2,
     /// MASQUE
     Masque,
@@ -61,6 +68,11 @@ pub enum ProtocolType {
     Vless,
     /// Warp
     Warp,
+    /// Shadowsocks
+    #[serde(alias = "ss")]
+    Shadowsocks,
+    /// Probe the candidates against the clean IP and use the fastest one
+    Auto,
     /// Cascade (layered)
     Cascade {
         /// Outer layer
@@ -70,16 +82,12 @@ pub enum ProtocolType {
     },
 }
 
-impl Default for ProtocolType {
-    fn default() -> Self {
-        Self::Reality
-    }
-}
-
 /// CDN type
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum CdnType {
     /// Cloudflare
+    #[default]
     Cloudflare,
     /// GCore
     Gcore,
@@ -91,12 +99,6 @@ pub enum CdnType {
     Direct,
 }
 
-impl Default for CdnType {
-    fn default() -> Self {
-        Self::Cloudflare
-    }
-}
-
 // ==================== CORE STRUCTS ====================
 
 /// IP scan result
@@ -125,7 +127,8 @@ pub struct ScanResult {
 }
 
 /// Full proxy configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ProxyConfig {
     /// Server address
     pub server: String,
@@ -135,7 +138,7 @@ pub struct ProxyConfig {
     pub protocol: ProtocolType,
     /// SNI for ShadowTLS
     pub sni: String,
-    /// UUID for Reality/VLESS
+    /// UUID for Reality/VLESS (empty = generated once at engine startup)
     pub uuid: String,
     /// Private key
     pub private_key: Option<String>,
@@ -159,6 +162,17 @@ pub struct ProxyConfig {
     pub enable_matryoshka: bool,
     /// Enable port hopping
     pub enable_port_hopping: bool,
+    /// Switch to another clean IP when the circuit breaker trips
+    pub auto_switch: bool,
+    /// Ports probed by the scanner and used for hopping
+    pub alternative_ports: Vec<u16>,
+    /// Port hopping strategy
+    pub port_strategy: PortStrategy,
+    /// Protocol-specific settings (passwords, transport, obfuscation)
+    pub settings: Option<ProtocolSettings>,
+    /// Matryoshka layer chain, e.g. `tcp > ws(cdn.example.com,/ws) > shadowtls > reality > smux`
+    /// (unset = ShadowTLS → Reality → SMUX)
+    pub chain: Option<ChainSpec>,
 }
 
 impl Default for ProxyConfig {
@@ -168,7 +182,7 @@ impl Default for ProxyConfig {
             port: 443,
             protocol: ProtocolType::Reality,
             sni: "ebanking.bmi.ir".to_string(),
-            uuid: uuid::Uuid::new_v4().to_string(),
+            uuid: String::new(),
             private_key: None,
             public_key: None,
             short_id: None,
@@ -180,10 +194,148 @@ impl Default for ProxyConfig {
             enable_anti_ai: true,
             enable_matryoshka: true,
             enable_port_hopping: true,
+            auto_switch: true,
+            alternative_ports: ALTERNATIVE_PORTS.to_vec(),
+            port_strategy: PortStrategy::default(),
+            settings: None,
+            chain: None,
         }
     }
 }
 
+// ==================== PROTOCOL SETTINGS ====================
+
+/// Settings that only apply to one protocol (`[proxy.settings]`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ProtocolSettings {
+    /// VLESS (plain, TLS or Reality)
+    Vless(VlessSettings),
+    /// Trojan
+    Trojan(TrojanSettings),
+    /// Hysteria2
+    Hysteria2(Hysteria2Settings),
+    /// TUIC v5
+    Tuic(TuicSettings),
+    /// Shadowsocks
+    Shadowsocks(ShadowsocksSettings),
+}
+
+/// Stream transport shared by VLESS and Trojan
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StreamSettings {
+    /// Transport network (`tcp`, `ws`, `grpc`, `http`, `httpupgrade`, `xhttp`)
+    pub network: String,
+    /// Security layer (`none`, `tls`, `reality`)
+    pub security: String,
+    /// HTTP path for ws/http transports
+    pub path: Option<String>,
+    /// HTTP Host header
+    pub host: Option<String>,
+    /// gRPC service name
+    pub service_name: Option<String>,
+    /// TCP header obfuscation type
+    pub header_type: Option<String>,
+    /// TLS ALPN
+    pub alpn: Vec<String>,
+    /// Skip certificate verification
+    pub allow_insecure: bool,
+}
+
+impl Default for StreamSettings {
+    fn default() -> Self {
+        Self {
+            network: "tcp".to_string(),
+            security: "none".to_string(),
+            path: None,
+            host: None,
+            service_name: None,
+            header_type: None,
+            alpn: Vec::new(),
+            allow_insecure: false,
+        }
+    }
+}
+
+/// VLESS settings (the UUID lives in [`ProxyConfig::uuid`])
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VlessSettings {
+    /// XTLS flow (e.g. `xtls-rprx-vision`)
+    pub flow: Option<String>,
+    /// Reality spider path
+    pub spider_x: Option<String>,
+    /// Stream transport
+    pub transport: StreamSettings,
+}
+
+/// Trojan settings
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrojanSettings {
+    /// Password
+    pub password: String,
+    /// Stream transport
+    pub transport: StreamSettings,
+}
+
+/// Hysteria2 settings
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Hysteria2Settings {
+    /// Auth password
+    pub password: String,
+    /// Obfuscation type (`salamander`)
+    pub obfs: Option<String>,
+    /// Obfuscation password
+    pub obfs_password: Option<String>,
+    /// Skip certificate verification
+    pub insecure: bool,
+    /// Pinned certificate SHA-256
+    pub pin_sha256: Option<String>,
+}
+
+/// TUIC v5 settings (the UUID lives in [`ProxyConfig::uuid`])
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TuicSettings {
+    /// Password
+    pub password: String,
+    /// Congestion control (`bbr`, `cubic`, `new_reno`)
+    pub congestion_control: String,
+    /// UDP relay mode (`native`, `quic`)
+    pub udp_relay_mode: String,
+    /// TLS ALPN
+    pub alpn: Vec<String>,
+    /// Skip certificate verification
+    pub allow_insecure: bool,
+}
+
+impl Default for TuicSettings {
+    fn default() -> Self {
+        Self {
+            password: String::new(),
+            congestion_control: "bbr".to_string(),
+            udp_relay_mode: "native".to_string(),
+            alpn: vec!["h3".to_string()],
+            allow_insecure: false,
+        }
+    }
+}
+
+/// Shadowsocks settings
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShadowsocksSettings {
+    /// Cipher (e.g. `2022-blake3-aes-128-gcm`, `chacha20-ietf-poly1305`)
+    pub method: String,
+    /// Password
+    pub password: String,
+    /// SIP003 plugin with its options (`name;opt=val`)
+    pub plugin: Option<String>,
+}
+
 /// Tunnel state
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TunnelState {
@@ -241,6 +393,58 @@ pub struct TrafficStats {
     pub errors: u64,
     /// Packet loss percentage
     pub packet_loss_pct: f32,
+    /// Rolling throughput
+    #[serde(default)]
+    pub rates: TrafficRates,
+    /// Bytes per protocol
+    #[serde(default)]
+    pub by_protocol: BTreeMap<String, ByteTotals>,
+    /// Bytes per server IP
+    #[serde(default)]
+    pub by_ip: BTreeMap<IpAddr, ByteTotals>,
+}
+
+impl TrafficStats {
+    /// Fold one latency probe into the running averages (`None` = lost)
+    pub fn record_probe(&mut self, latency_ms: Option<u64>) {
+        const WEIGHT: f64 = 0.2;
+
+        let lost = if latency_ms.is_some() { 0.0 } else { 100.0 };
+        self.packet_loss_pct =
+            ((1.0 - WEIGHT) * self.packet_loss_pct as f64 + WEIGHT * lost) as f32;
+
+        if let Some(ms) = latency_ms {
+            self.avg_latency_ms = if self.avg_latency_ms == 0.0 {
+                ms as f64
+            } else {
+                (1.0 - WEIGHT) * self.avg_latency_ms + WEIGHT * ms as f64
+            };
+        }
+    }
+}
+
+/// Throughput in bytes per second
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ByteRate {
+    /// Received bytes/sec
+    pub rx: f64,
+    /// Transmitted bytes/sec
+    pub tx: f64,
+}
+
+/// Throughput averaged over the last 1, 10 and 60 seconds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrafficRates {
+    pub last_1s: ByteRate,
+    pub last_10s: ByteRate,
+    pub last_60s: ByteRate,
+}
+
+/// Byte totals of one protocol or server IP
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ByteTotals {
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
 }
 
 /// Engine events
@@ -266,4 +470,29 @@ pub enum EngineEvent {
     LayerAdded { layer: String },
     /// Matryoshka chain complete
     MatryoshkaChainComplete { layers: usize },
+    /// Failed over to another subscription node
+    NodeSwitched {
+        /// Previous server (`host:port`)
+        from: String,
+        /// New server (`host:port`)
+        to: String,
+        /// Node name from the subscription
+        name: String,
+    },
+    /// Protocol picked by `protocol = "auto"`
+    ProtocolSelected {
+        /// Winning candidate (`reality`, `hysteria2`, `tuic`, `xhttp`, `websocket`)
+        protocol: String,
+        /// Handshake latency
+        latency_ms: u64,
+        /// Clean IP the probe ran against
+        ip: IpAddr,
+    },
+    /// Config file reloaded into the running engine
+    ConfigReloaded {
+        /// Settings applied live
+        applied: Vec<String>,
+        /// Changed settings that only take effect after a restart
+        restart_required: Vec<String>,
+    },
 }
@@ -0,0 +1,166 @@
+//! UDP-over-TCP (UoT v2)
+//!
+//! وقتی پروتکل‌های UDP (Hysteria2/TUIC) مسدود باشند، پکت‌های DNS، QUIC و
+//! بازی/VoIP روی همان زنجیره TCP (Matryoshka) منتقل می‌شوند. قالب سازگار با
+//! UoT نسخه ۲ در sing-box است:
+//!
+//! - اتصال TCP به آدرس جادویی [`MAGIC_ADDRESS`]
+//! - درخواست: `[is_connect u8][ATYP addr port]`
+//! - در حالت connect هر پکت: `[length u16][data]`
+//!
+//! آدرس‌ها در قالب SOCKS5 هستند (`0x01` IPv4، `0x03` دامنه، `0x04` IPv6) و
+//! [`encode_address`]/[`decode_address`] برای هدر UDP در SOCKS5 هم استفاده می‌شوند.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf},
+    sync::Mutex,
+};
+
+use crate::transport::{BoxedStream, DatagramSocket};
+
+/// مقصد جادویی UoT v2 (سرور به جای اتصال TCP، پکت‌ها را به UDP تبدیل می‌کند)
+pub const MAGIC_ADDRESS: &str = "sp.v2.udp-over-tcp.arpa";
+
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// نوشتن آدرس در قالب SOCKS5: `[ATYP][addr][port u16]`
+pub fn encode_address(host: &str, port: u16, out: &mut Vec<u8>) {
+    match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            out.push(ATYP_IPV4);
+            out.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            out.push(ATYP_IPV6);
+            out.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            let name = &host.as_bytes()[..host.len().min(255)];
+            out.push(ATYP_DOMAIN);
+            out.push(name.len() as u8);
+            out.extend_from_slice(name);
+        }
+    }
+    out.extend_from_slice(&port.to_be_bytes());
+}
+
+/// خواندن آدرس SOCKS5 از ابتدای `buf`؛ (host, port, طول خوانده‌شده)
+pub fn decode_address(buf: &[u8]) -> Result<(String, u16, usize)> {
+    let (host, len) = match buf.first() {
+        Some(&ATYP_IPV4) if buf.len() >= 5 => {
+            let octets: [u8; 4] = buf[1..5].try_into()?;
+            (Ipv4Addr::from(octets).to_string(), 5)
+        }
+        Some(&ATYP_IPV6) if buf.len() >= 17 => {
+            let octets: [u8; 16] = buf[1..17].try_into()?;
+            (Ipv6Addr::from(octets).to_string(), 17)
+        }
+        Some(&ATYP_DOMAIN) if buf.len() >= 2 && buf.len() >= 2 + buf[1] as usize => {
+            let end = 2 + buf[1] as usize;
+            let name = String::from_utf8(buf[2..end].to_vec()).context("Invalid UTF-8 in domain")?;
+            (name, end)
+        }
+        Some(&atyp @ (ATYP_IPV4 | ATYP_IPV6 | ATYP_DOMAIN)) => bail!("Truncated address (type {})", atyp),
+        Some(atyp) => bail!("Unsupported address type {}", atyp),
+        None => bail!("Empty address"),
+    };
+    let port = buf.get(len..len + 2).context("Truncated address port")?;
+    Ok((host, u16::from_be_bytes([port[0], port[1]]), len + 2))
+}
+
+/// لایه UoT v2 روی یک جریان TCP (حالت connect: یک مقصد برای هر جریان)
+#[derive(Debug, Clone)]
+pub struct UotClient {
+    host: String,
+    port: u16,
+}
+
+impl UotClient {
+    pub fn new(host: &str, port: u16) -> Self {
+        Self { host: host.to_string(), port }
+    }
+
+    /// ارسال درخواست UoT روی جریانی که به [`MAGIC_ADDRESS`] رسیده است
+    pub async fn open(&self, mut lower: BoxedStream) -> Result<UotSocket> {
+        let mut request = vec![1u8];
+        encode_address(&self.host, self.port, &mut request);
+        lower.write_all(&request).await.context("UoT request failed")?;
+
+        let (reader, writer) = tokio::io::split(lower);
+        Ok(UotSocket { reader: Mutex::new(reader), writer: Mutex::new(writer) })
+    }
+}
+
+/// سوکت پکتی روی جریان UoT
+pub struct UotSocket {
+    reader: Mutex<ReadHalf<BoxedStream>>,
+    writer: Mutex<WriteHalf<BoxedStream>>,
+}
+
+#[async_trait]
+impl DatagramSocket for UotSocket {
+    async fn send(&self, packet: &[u8]) -> Result<()> {
+        if packet.len() > u16::MAX as usize {
+            bail!("UDP packet of {} bytes is too large for UoT", packet.len());
+        }
+        let mut frame = Vec::with_capacity(packet.len() + 2);
+        frame.extend_from_slice(&(packet.len() as u16).to_be_bytes());
+        frame.extend_from_slice(packet);
+
+        let mut writer = self.writer.lock().await;
+        writer.write_all(&frame).await.context("UoT write failed")?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    /// دریافت یک پکت؛ پکت بزرگ‌تر از `buf` کوتاه می‌شود (مثل UDP)
+    async fn recv(&self, buf: &mut [u8]) -> Result<usize> {
+        let mut reader = self.reader.lock().await;
+        let mut len = [0u8; 2];
+        reader.read_exact(&mut len).await.context("UoT stream closed")?;
+        let len = u16::from_be_bytes(len) as usize;
+
+        let mut packet = vec![0u8; len];
+        reader.read_exact(&mut packet).await.context("UoT stream closed")?;
+        let n = len.min(buf.len());
+        buf[..n].copy_from_slice(&packet[..n]);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_uot_v2_framing() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        let socket = UotClient::new("1.1.1.1", 53).open(Box::new(client)).await.unwrap();
+        socket.send(b"dns query").await.unwrap();
+
+        // سمت سرور: درخواست connect و سپس پکت با پیشوند طول
+        let mut head = [0u8; 1 + 1 + 4 + 2 + 2 + 9];
+        server.read_exact(&mut head).await.unwrap();
+        assert_eq!(head[0], 1);
+        assert_eq!(decode_address(&head[1..]).unwrap(), ("1.1.1.1".to_string(), 53, 7));
+        assert_eq!(&head[8..10], &9u16.to_be_bytes());
+        assert_eq!(&head[10..], b"dns query");
+
+        server.write_all(&[0, 5]).await.unwrap();
+        server.write_all(b"reply").await.unwrap();
+        let mut buf = [0u8; 64];
+        let n = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"reply");
+
+        let mut domain = Vec::new();
+        encode_address("example.com", 443, &mut domain);
+        assert_eq!(decode_address(&domain).unwrap(), ("example.com".to_string(), 443, domain.len()));
+        assert!(decode_address(&domain[..5]).is_err());
+    }
+}
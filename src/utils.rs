@@ -2,12 +2,55 @@
 
 use std::net::IpAddr;
 
-/// بررسی آیا IP در رنج است
-pub fn is_ip_in_range(_ip: IpAddr, _range: &str) -> bool {
-    true
+use anyhow::{anyhow, Context, Result};
+
+/// بررسی آیا IP در رنج است (رنج نامعتبر یعنی خیر)
+pub fn is_ip_in_range(ip: IpAddr, range: &str) -> bool {
+    let Ok((network, prefix)) = parse_cidr(range) else {
+        return false;
+    };
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+/// خواندن رنج CIDR (`10.0.0.0/8`، `2001:db8::/32`)؛ IP تنها یعنی /32 یا /128
+pub fn parse_cidr(range: &str) -> Result<(IpAddr, u8)> {
+    let (ip, prefix) = match range.split_once('/') {
+        Some((ip, prefix)) => (ip, Some(prefix)),
+        None => (range, None),
+    };
+    let ip: IpAddr = ip.trim().parse().with_context(|| format!("`{}` is not an IP address", ip))?;
+    let max = if ip.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix
+            .trim()
+            .parse::<u8>()
+            .ok()
+            .filter(|p| *p <= max)
+            .ok_or_else(|| anyhow!("prefix `/{}` must be between 0 and {}", prefix, max))?,
+        None => max,
+    };
+    Ok((ip, prefix))
 }
 
 /// تبدیل bytes به hex
 pub fn bytes_to_hex(bytes: &[u8]) -> String {
     hex::encode(bytes)
 }
+
+/// بار CPU: میانگین load یک دقیقه‌ای به ازای هر هسته (`None` اگر `/proc/loadavg` نباشد)
+pub fn cpu_load() -> Option<f64> {
+    let loadavg = std::fs::read_to_string("/proc/loadavg").ok()?;
+    let load: f64 = loadavg.split_whitespace().next()?.parse().ok()?;
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    Some(load / cores as f64)
+}
@@ -38,9 +38,11 @@ const WARP_ENDPOINTS: &[&str] = &[
 // ── WARP Configuration ─────────────────────────────────────────────────────
 
 /// نوع اکانت WARP
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum WarpAccountType {
     /// رایگان (WARP)
+    #[default]
     Free,
     /// WARP+ (پریمیوم)
     Plus,
@@ -48,12 +50,9 @@ pub enum WarpAccountType {
     ZeroTrust,
 }
 
-impl Default for WarpAccountType {
-    fn default() -> Self { Self::Free }
-}
-
 /// پیکربندی WARP
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct WarpConfig {
     pub account_type: WarpAccountType,
     /// License Key برای WARP+
@@ -298,7 +297,7 @@ impl WarpClient {
         let reg = self.register_or_load().await?;
         
         let endpoint = c_endpoint
-            .or_else(|| b_endpoint)
+            .or(b_endpoint)
             .unwrap_or_else(|| WARP_ENDPOINT_V4.to_string());
 
         let dns = dns_val;
@@ -327,9 +326,7 @@ PersistentKeepalive = 25
 
         // اضافه کردن تنظیمات fake-packets اگر فعال بود
         if self.config.fake_packets {
-            config.push_str(&format!(
-                "# Fake Packets for DPI bypass\n# PostUp = ...\n"
-            ));
+            config.push_str("# Fake Packets for DPI bypass\n# PostUp = ...\n");
         }
 
         Ok(config)
@@ -460,9 +457,11 @@ pub struct DoubleWarpConfig {
 
 impl DoubleWarpConfig {
     pub fn new() -> Self {
-        let mut outer = WarpConfig::default();
-        outer.double_warp = true;
-        
+        let outer = WarpConfig {
+            double_warp: true,
+            ..WarpConfig::default()
+        };
+
         let inner = WarpConfig::default();
         
         Self { outer, inner }
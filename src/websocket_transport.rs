@@ -7,19 +7,26 @@
 #![allow(unused_imports)]
 #![allow(unused_variables)]
 
-use std::net::{IpAddr, SocketAddr};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+};
 use anyhow::{Context, Result};
+use async_trait::async_trait;
 use base64::Engine as _;
 use rand::{Rng, RngCore};
 use serde::{Deserialize, Serialize};
 use sha1::{Sha1, Digest};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
+    io::AsyncWriteExt,
     time::{timeout, Duration},
 };
 use tracing::{debug, info};
 
+use crate::transport::{
+    read_http_head, BoxedStream, Dialer, Frame, FrameCodec, FramedStream, TcpDialer, Transport,
+};
+
 // ── WebSocket Frame ────────────────────────────────────────────────────────
 
 const WS_FIN: u8 = 0x80;
@@ -120,7 +127,7 @@ impl WsFrame {
             raw_payload.to_vec()
         };
 
-        Some((Self { fin, opcode, masked: masked, payload }, offset + payload_len))
+        Some((Self { fin, opcode, masked, payload }, offset + payload_len))
     }
 }
 
@@ -174,34 +181,51 @@ pub struct WsTransport {
     server: IpAddr,
     port: u16,
     config: WsTransportConfig,
-    stream: Option<TcpStream>,
-    connected: bool,
 }
 
 impl WsTransport {
     pub fn new(server: IpAddr, port: u16, config: WsTransportConfig) -> Self {
-        Self { server, port, config, stream: None, connected: false }
+        Self { server, port, config }
     }
 
-    /// اتصال و WebSocket Handshake
-    pub async fn connect(&mut self) -> Result<()> {
-        info!("🔌 اتصال WebSocket به {}:{}{}", self.server, self.port, self.config.path);
-        
-        let addr = SocketAddr::new(self.server, self.port);
-        let mut stream = timeout(Duration::from_secs(10), TcpStream::connect(addr))
-            .await
-            .context("WebSocket connection timeout")??;
+    /// ساخت HTTP Upgrade request
+    fn build_upgrade_request(&self, key: &str) -> String {
+        let mut req = format!(
+            "GET {} HTTP/1.1\r\n\
+             Host: {}\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Key: {}\r\n\
+             Sec-WebSocket-Version: 13\r\n",
+            self.config.path, self.config.host, key
+        );
 
+        for (name, value) in &self.config.headers {
+            req.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        req.push_str("\r\n");
+        req
+    }
+}
+
+#[async_trait]
+impl Transport for WsTransport {
+    fn name(&self) -> &'static str {
+        "WebSocket"
+    }
+
+    /// WebSocket Handshake روی جریان زیرین
+    async fn connect_over(&self, mut lower: BoxedStream) -> Result<BoxedStream> {
         // ارسال HTTP Upgrade request
         let ws_key = generate_ws_key();
         let upgrade_req = self.build_upgrade_request(&ws_key);
-        stream.write_all(upgrade_req.as_bytes()).await
+        lower.write_all(upgrade_req.as_bytes()).await
             .context("WebSocket upgrade request failed")?;
 
         // دریافت و تأیید پاسخ
-        let mut buf = vec![0u8; 4096];
-        let n = timeout(Duration::from_secs(10), stream.read(&mut buf)).await??;
-        let response = String::from_utf8_lossy(&buf[..n]);
+        let response = timeout(Duration::from_secs(10), read_http_head(&mut lower))
+            .await
+            .context("WebSocket handshake timeout")??;
 
         if !response.contains("101 Switching Protocols") {
             return Err(anyhow::anyhow!("WebSocket handshake rejected: {}", &response[..response.len().min(200)]));
@@ -213,75 +237,50 @@ impl WsTransport {
             debug!("⚠️ WebSocket accept key mismatch (ignored in Ghost mode)");
         }
 
-        self.stream = Some(stream);
-        self.connected = true;
         info!("✅ WebSocket connected ({}{})", self.config.host, self.config.path);
-        Ok(())
+        Ok(Box::new(FramedStream::new(lower, WsCodec)))
     }
+}
 
-    /// ساخت HTTP Upgrade request
-    fn build_upgrade_request(&self, key: &str) -> String {
-        let mut req = format!(
-            "GET {} HTTP/1.1\r\n\
-             Host: {}\r\n\
-             Upgrade: websocket\r\n\
-             Connection: Upgrade\r\n\
-             Sec-WebSocket-Key: {}\r\n\
-             Sec-WebSocket-Version: 13\r\n",
-            self.config.path, self.config.host, key
-        );
-
-        for (name, value) in &self.config.headers {
-            req.push_str(&format!("{}: {}\r\n", name, value));
-        }
-        req.push_str("\r\n");
-        req
+/// اتصال مستقیم به سرور
+#[async_trait]
+impl Dialer for WsTransport {
+    async fn dial(&self) -> Result<BoxedStream> {
+        info!("🔌 اتصال WebSocket به {}:{}{}", self.server, self.port, self.config.path);
+        let tcp = TcpDialer::new(SocketAddr::new(self.server, self.port)).dial().await?;
+        self.connect_over(tcp).await
     }
+}
+
+/// فریم‌های Binary (کلاینت همیشه mask می‌کند)؛ به Ping با Pong پاسخ می‌دهد
+struct WsCodec;
 
-    /// ارسال داده در قالب WebSocket binary frame
-    pub async fn send(&mut self, data: &[u8]) -> Result<()> {
-        let frame = WsFrame::binary(data.to_vec());
-        let encoded = frame.encode();
-        let stream = self.stream.as_mut().context("WebSocket not connected")?;
-        stream.write_all(&encoded).await.context("WebSocket send failed")?;
-        Ok(())
+impl FrameCodec for WsCodec {
+    fn encode(&mut self, payload: &[u8], dst: &mut Vec<u8>) {
+        dst.extend(WsFrame::binary(payload.to_vec()).encode());
     }
 
-    /// دریافت داده از WebSocket
-    pub async fn recv(&mut self) -> Result<Vec<u8>> {
-        let stream = self.stream.as_mut().context("WebSocket not connected")?;
-        let mut buf = vec![0u8; 65536];
-        let n = stream.read(&mut buf).await.context("WebSocket recv failed")?;
-        
-        if let Some((frame, _)) = WsFrame::decode(&buf[..n]) {
-            match frame.opcode {
-                WS_OPCODE_PING => {
-                    // پاسخ به Ping
-                    let pong = WsFrame::pong(frame.payload).encode();
-                    stream.write_all(&pong).await.ok();
-                    return Ok(vec![]);
-                }
-                WS_OPCODE_CLOSE => {
-                    self.connected = false;
-                    return Err(anyhow::anyhow!("WebSocket closed by server"));
-                }
-                _ => return Ok(frame.payload),
+    fn decode(&mut self, src: &mut Vec<u8>, reply: &mut Vec<u8>) -> io::Result<Option<Frame>> {
+        let Some((frame, used)) = WsFrame::decode(src) else {
+            return Ok(None);
+        };
+        src.drain(..used);
+        Ok(Some(match frame.opcode {
+            WS_OPCODE_PING => {
+                let pong = WsFrame { masked: true, ..WsFrame::pong(frame.payload) };
+                reply.extend(pong.encode());
+                Frame::Control
             }
-        }
-        
-        Ok(buf[..n].to_vec())
+            WS_OPCODE_PONG => Frame::Control,
+            WS_OPCODE_CLOSE => Frame::Close,
+            _ => Frame::Data(frame.payload),
+        }))
     }
 
-    /// ارسال Ping برای نگه‌داشتن اتصال
-    pub async fn ping(&mut self) -> Result<()> {
-        let frame = WsFrame::ping().encode();
-        if let Some(stream) = self.stream.as_mut() {
-            stream.write_all(&frame).await.ok();
-        }
-        Ok(())
+    fn close(&mut self, dst: &mut Vec<u8>) {
+        let close = WsFrame { fin: true, opcode: WS_OPCODE_CLOSE, masked: true, payload: vec![] };
+        dst.extend(close.encode());
     }
-
-    pub fn is_connected(&self) -> bool { self.connected }
 }
 
 // ── HTTP/2 gRPC Transport ──────────────────────────────────────────────────
@@ -311,12 +310,11 @@ pub struct GrpcTransport {
     server: IpAddr,
     port: u16,
     config: GrpcTransportConfig,
-    stream: Option<TcpStream>,
 }
 
 impl GrpcTransport {
     pub fn new(server: IpAddr, port: u16, config: GrpcTransportConfig) -> Self {
-        Self { server, port, config, stream: None }
+        Self { server, port, config }
     }
 
     /// ارسال داده در قالب gRPC frame
@@ -336,17 +334,45 @@ impl GrpcTransport {
         if data.len() < 5 + msg_len { return None; }
         Some(data[5..5 + msg_len].to_vec())
     }
+}
 
-    pub async fn connect(&mut self) -> Result<()> {
-        info!("🔌 اتصال gRPC به {}:{}", self.server, self.port);
-        let addr = SocketAddr::new(self.server, self.port);
-        let mut stream = timeout(Duration::from_secs(10), TcpStream::connect(addr)).await??;
+#[async_trait]
+impl Transport for GrpcTransport {
+    fn name(&self) -> &'static str {
+        "gRPC"
+    }
 
+    async fn connect_over(&self, mut lower: BoxedStream) -> Result<BoxedStream> {
         let preface = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
-        stream.write_all(preface).await?;
+        lower.write_all(preface).await.context("gRPC preface failed")?;
+        info!("✅ gRPC transport connected ({}/{})", self.config.host, self.config.service_name);
+        Ok(Box::new(FramedStream::new(lower, GrpcCodec)))
+    }
+}
 
-        self.stream = Some(stream);
-        info!("✅ gRPC transport connected");
-        Ok(())
+/// اتصال مستقیم به سرور
+#[async_trait]
+impl Dialer for GrpcTransport {
+    async fn dial(&self) -> Result<BoxedStream> {
+        info!("🔌 اتصال gRPC به {}:{}", self.server, self.port);
+        let tcp = TcpDialer::new(SocketAddr::new(self.server, self.port)).dial().await?;
+        self.connect_over(tcp).await
+    }
+}
+
+/// پیام‌های gRPC با پیشوند طول
+struct GrpcCodec;
+
+impl FrameCodec for GrpcCodec {
+    fn encode(&mut self, payload: &[u8], dst: &mut Vec<u8>) {
+        dst.extend(GrpcTransport::encode_grpc_frame(payload));
+    }
+
+    fn decode(&mut self, src: &mut Vec<u8>, _reply: &mut Vec<u8>) -> io::Result<Option<Frame>> {
+        let Some(message) = GrpcTransport::decode_grpc_frame(src) else {
+            return Ok(None);
+        };
+        src.drain(..5 + message.len());
+        Ok(Some(Frame::Data(message)))
     }
 }
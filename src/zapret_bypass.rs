@@ -38,7 +38,8 @@ const OOB_BYTE: u8 = 0x00;
 // ── Strategy Enum ──────────────────────────────────────────────────────────
 
 /// استراتژی bypass
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ZapretStrategy {
     /// تکه‌تکه کردن ClientHello در مرز SNI
     Fragment,
@@ -55,15 +56,12 @@ pub enum ZapretStrategy {
     /// حالت کامل (همه تکنیک‌ها)
     FullBypass,
     /// bypass خودکار بر اساس نوع ترافیک
+    #[default]
     Auto,
 }
 
-impl Default for ZapretStrategy {
-    fn default() -> Self { Self::Auto }
-}
-
 /// نوع جریان برای bypass
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub enum StreamType {
     /// جریان HTTPS (TLS)
     Https,
@@ -72,23 +70,23 @@ pub enum StreamType {
     /// جریان QUIC/HTTP3
     Quic,
     /// نامشخص
+    #[default]
     Unknown,
 }
 
-impl Default for StreamType {
-    fn default() -> Self { Self::Unknown }
-}
-
 // ── Configuration ──────────────────────────────────────────────────────────
 
 /// تنظیمات موتور Zapret
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ZapretConfig {
     /// استراتژی bypass
     pub strategy: ZapretStrategy,
     /// اندازه فراگمنت (بایت) — None برای حالت خودکار
+    #[serde(alias = "fragment_size_https")]
     pub fragment_size: Option<usize>,
     /// فعال‌سازی fake packet
+    #[serde(alias = "enable_fake_packets")]
     pub enable_fake: bool,
     /// TTL برای fake packet
     pub fake_ttl: u8,
@@ -174,17 +172,19 @@ pub fn analyze_packet(data: &[u8]) -> PacketAnalysis {
     }
 
     // بررسی QUIC Initial Packet
-    if !data.is_empty() && (data[0] & 0xC0) == 0xC0 && data.len() > 5 {
-        if data.get(1..5) == Some(&[0x00, 0x00, 0x00, 0x01]) {
-            return PacketAnalysis {
-                stream_type: StreamType::Quic,
-                is_client_hello: true,
-                sni_offset: None,
-                sni_length: None,
-                sni_value: None,
-                http_host_offset: None,
-            };
-        }
+    if !data.is_empty()
+        && (data[0] & 0xC0) == 0xC0
+        && data.len() > 5
+        && data.get(1..5) == Some(&[0x00, 0x00, 0x00, 0x01])
+    {
+        return PacketAnalysis {
+            stream_type: StreamType::Quic,
+            is_client_hello: true,
+            sni_offset: None,
+            sni_length: None,
+            sni_value: None,
+            http_host_offset: None,
+        };
     }
 
     PacketAnalysis {
@@ -273,6 +273,8 @@ fn find_http_host(data: &[u8]) -> Option<usize> {
 /// موتور اصلی Zapret/ByeDPI
 pub struct ZapretEngine {
     config: ZapretConfig,
+    /// استراتژی فعلی (قابل تغییر در حین اجرا، مثلاً با تعویض پروفایل)
+    strategy: std::sync::RwLock<ZapretStrategy>,
     stats: std::sync::Mutex<ZapretStats>,
 }
 
@@ -282,11 +284,23 @@ impl ZapretEngine {
         info!("🛡️ Zapret/ByeDPI Engine v5.0 راه‌اندازی شد");
         info!("   استراتژی: {:?}", config.strategy);
         Self {
+            strategy: std::sync::RwLock::new(config.strategy),
             config,
             stats: std::sync::Mutex::new(ZapretStats::default()),
         }
     }
 
+    /// استراتژی فعلی
+    pub fn strategy(&self) -> ZapretStrategy {
+        *self.strategy.read().unwrap()
+    }
+
+    /// تغییر استراتژی در حین اجرا
+    pub fn set_strategy(&self, strategy: ZapretStrategy) {
+        info!("🛡️ استراتژی Zapret: {:?}", strategy);
+        *self.strategy.write().unwrap() = strategy;
+    }
+
     /// پردازش و bypass یک پکت
     pub fn process_packet(&self, data: &[u8]) -> Vec<Vec<u8>> {
         let analysis = analyze_packet(data);
@@ -426,8 +440,9 @@ impl ZapretEngine {
 
     /// تعیین استراتژی بر اساس تنظیمات و تحلیل
     fn determine_strategy(&self, analysis: &PacketAnalysis) -> ZapretStrategy {
-        if self.config.strategy != ZapretStrategy::Auto {
-            return self.config.strategy;
+        let strategy = self.strategy();
+        if strategy != ZapretStrategy::Auto {
+            return strategy;
         }
         
         // انتخاب خودکار